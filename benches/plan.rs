@@ -0,0 +1,120 @@
+//! Restore planning against synthetic records, without a desktop: the matching, DPI scaling,
+//! work-area clamping and batching the app plans live windows with. The `bench` command measures
+//! the same counts on the live desktop, including capture.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use persistentwin::{
+    geometry::Rect,
+    plan::{plan, Monitor, Record, Window},
+};
+
+/// The record counts measured, as in the `bench` command.
+const COUNTS: &[usize] = &[100, 500, 1000];
+
+/// The number of applications the records are spread over.
+const APPS: usize = 20;
+
+/// A laptop panel and a scaled external monitor, both with a taskbar.
+fn monitors() -> Vec<Monitor> {
+    vec![
+        Monitor {
+            rect: Rect {
+                left: 0,
+                top: 0,
+                right: 1920,
+                bottom: 1080,
+            },
+            work: Rect {
+                left: 0,
+                top: 0,
+                right: 1920,
+                bottom: 1040,
+            },
+            dpi: 96,
+        },
+        Monitor {
+            rect: Rect {
+                left: 1920,
+                top: 0,
+                right: 4480,
+                bottom: 1440,
+            },
+            work: Rect {
+                left: 1920,
+                top: 0,
+                right: 4480,
+                bottom: 1392,
+            },
+            dpi: 120,
+        },
+    ]
+}
+
+fn exe(i: usize) -> Option<String> {
+    Some(format!(r"c:\program files\app{}\app.exe", i % APPS))
+}
+
+/// `count` records of documents spread over the applications and monitors, and a window for
+/// each in reverse order. Some window titles changed since they were captured, so every match
+/// tier is exercised, and some records were captured at another scale factor.
+fn scenario(count: usize) -> (Vec<Window>, Vec<Record>) {
+    let records = (0..count)
+        .map(|i| {
+            let x = (i % 2) as i32 * 1920 + (i % 17) as i32 * 40;
+            let y = (i % 13) as i32 * 30;
+            Record {
+                id: i as i64 + 1,
+                exe: exe(i),
+                class: "AppWindow".to_string(),
+                title: format!("Document {i} - App {}", i % APPS),
+                rect: Rect {
+                    left: x,
+                    top: y,
+                    right: x + 1200,
+                    bottom: y + 800,
+                },
+                dpi: Some(if i % 3 == 0 { 144 } else { 96 }),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let windows = (0..count)
+        .rev()
+        .map(|i| Window {
+            exe: exe(i),
+            class: "AppWindow".to_string(),
+            title: match i % 4 {
+                // An unread count
+                1 => format!("(3) Document {i} - App {}", i % APPS),
+                // Another document
+                2 => format!("Report {i} - App {}", i % APPS),
+                _ => format!("Document {i} - App {}", i % APPS),
+            },
+            exact_only: false,
+        })
+        .collect();
+
+    (windows, records)
+}
+
+fn bench_plan(c: &mut Criterion) {
+    let monitors = monitors();
+
+    let mut group = c.benchmark_group("plan");
+    for &count in COUNTS {
+        let (windows, records) = scenario(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| {
+                plan(
+                    black_box(&monitors),
+                    black_box(&windows),
+                    black_box(&records),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_plan);
+criterion_main!(benches);
@@ -0,0 +1,158 @@
+use crate::{
+    db, ipc,
+    monitor::{self, HMonitorExt},
+    process::ProcessExt,
+    soak,
+    window::HwndExt,
+    workspace_dir, workspace_path_in, App, CaptureTrigger, Rect, RestoreScope,
+};
+
+use anyhow::Context;
+use nwg::NativeUi;
+use windows::Win32::{
+    Foundation::{HWND, POINT},
+    System::Threading::GetCurrentProcess,
+    UI::WindowsAndMessaging::{
+        DestroyWindow, SetWindowPos, EVENT_SYSTEM_MOVESIZEEND, SWP_NOACTIVATE, SWP_NOSIZE,
+        SWP_NOZORDER,
+    },
+};
+
+use std::time::{Duration, Instant};
+
+/// The workspace measured against. It is emptied before every run, so runs are comparable.
+const WORKSPACE: &str = "Bench";
+
+/// The window counts measured by default.
+pub const DEFAULT_COUNTS: &[usize] = &[100, 500, 1000];
+
+/// Developer command: measure capture and restore throughput with growing numbers of synthetic
+/// windows on the live desktop, and print a table of the timings:
+///
+/// - a full capture of every window, as done before the layout is disturbed;
+/// - capturing a single window after it moved, which is the cost of each window event;
+/// - planning a restore of every window, without executing it.
+///
+/// Windows already open on the desktop are part of every measurement. The app runs against a
+/// `Bench` workspace, with no event hooks, so it does not need to be stopped.
+pub fn bench(counts: &[usize]) -> anyhow::Result<()> {
+    nwg::init().context("Failed to init NWG")?;
+
+    let dir = workspace_dir()?;
+    let path = workspace_path_in(&dir, WORKSPACE)?;
+    match std::fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => Err(e).context("failed to empty bench workspace")?,
+    }
+
+    let elevated = unsafe { GetCurrentProcess() }
+        .is_elevated()
+        .context("failed to query process elevation")?;
+    let instance = ipc::instance_name(elevated)?;
+    let db = db::open(&path, &instance).context("failed to open bench workspace")?;
+    let app = App::build_ui(App::new(db, WORKSPACE.to_string(), dir, elevated, instance))
+        .context("Failed to build UI")?;
+
+    let (topology, origin) = app
+        .capture_topology()
+        .context("failed to capture topology")?;
+    app.set_active_topology(topology, origin, "bench")?;
+
+    // The primary monitor holds the origin of the virtual screen.
+    let work = monitor::from_point(POINT { x: 0, y: 0 })
+        .info()
+        .context("failed to query monitor info")?
+        .work;
+
+    println!("windows\tcapture\tper event (avg/max)\tplan");
+
+    let mut windows = Vec::new();
+    let mut res = Ok(());
+    for &count in counts {
+        while windows.len() < count {
+            let rect = cascade(&work, windows.len());
+            match soak::create_window(&format!("Bench #{}", windows.len() + 1), &rect) {
+                Ok(hwnd) => windows.push(hwnd),
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            }
+        }
+        if res.is_err() {
+            break;
+        }
+
+        // Let the windows finish appearing.
+        soak::pump(Duration::from_secs(1));
+
+        let start = Instant::now();
+        app.capture_now();
+        let capture = start.elapsed();
+
+        let (event_avg, event_max) = measure_events(&app, &windows);
+
+        let start = Instant::now();
+        let plan = app.plan_restore("bench", &RestoreScope::default());
+        let planning = start.elapsed();
+        if let Err(e) = plan {
+            res = Err(e.context("failed to plan restore"));
+            break;
+        }
+
+        println!("{count}\t{capture:.2?}\t{event_avg:.2?} / {event_max:.2?}\t{planning:.2?}");
+    }
+
+    for hwnd in windows {
+        unsafe { DestroyWindow(hwnd) };
+    }
+
+    res
+}
+
+/// Move every window a little and capture it, returning the average and longest capture.
+fn measure_events(app: &App, windows: &[HWND]) -> (Duration, Duration) {
+    let mut total = Duration::ZERO;
+    let mut max = Duration::ZERO;
+    for hwnd in windows {
+        let Ok(rect) = hwnd.rect() else {
+            continue;
+        };
+        unsafe {
+            SetWindowPos(
+                *hwnd,
+                None,
+                rect.left + 1,
+                rect.top,
+                0,
+                0,
+                SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+        };
+
+        let start = Instant::now();
+        let _ = app.capture_window_now(*hwnd, CaptureTrigger::Event(EVENT_SYSTEM_MOVESIZEEND));
+        let elapsed = start.elapsed();
+
+        total += elapsed;
+        max = max.max(elapsed);
+    }
+
+    let avg = total / windows.len().max(1) as u32;
+    (avg, max)
+}
+
+/// A rectangle for the `n`th window, cascading down the work area.
+fn cascade(work: &Rect, n: usize) -> Rect {
+    let steps = ((work.height() as i32 - 300) / 20).max(1);
+    let left = work.left + (n as i32 % steps) * 20;
+    let top = work.top + (n as i32 % steps) * 20;
+
+    Rect {
+        left,
+        top,
+        right: left + 400,
+        bottom: top + 300,
+    }
+}
@@ -0,0 +1,130 @@
+use windows::{core::Error, Win32::Foundation::ERROR_INSUFFICIENT_BUFFER};
+
+type Result<R> = core::result::Result<R, Error>;
+
+/// The length of the first buffer tried by `fill_string`, in characters.
+const INITIAL_LEN: usize = 256;
+
+/// How a call filling a string buffer went.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Fill {
+    /// The whole string fit, with this length (excluding the terminator)
+    Fits(usize),
+    /// The buffer was too small for the string
+    TooSmall,
+}
+
+/// Read a UTF-16 string with `fill`, a call that fills a buffer (e.g. a Win32 API taking one).
+/// The buffer starts at 256 characters and doubles whenever it is too small, up to `max`
+/// characters; a string that does not fit even then fails with `ERROR_INSUFFICIENT_BUFFER`.
+pub fn fill_string(max: usize, mut fill: impl FnMut(&mut [u16]) -> Result<Fill>) -> Result<String> {
+    let mut buf = vec![0u16; INITIAL_LEN.min(max)];
+
+    loop {
+        match fill(&mut buf)? {
+            Fill::Fits(len) => return Ok(String::from_utf16_lossy(&buf[..len])),
+            Fill::TooSmall if buf.len() < max => buf.resize((buf.len() * 2).min(max), 0u16),
+            Fill::TooSmall => return Err(ERROR_INSUFFICIENT_BUFFER.to_hresult().into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use windows::Win32::Foundation::ERROR_ACCESS_DENIED;
+
+    const MAX: usize = 32768;
+
+    fn string(len: usize) -> Vec<u16> {
+        (0..len).map(|i| b'a' as u16 + (i % 26) as u16).collect()
+    }
+
+    /// Fill like `GetClassNameW`: the string is silently truncated to fit with its terminator,
+    /// so a string filling the buffer may have been cut off.
+    fn truncating(s: &[u16]) -> impl FnMut(&mut [u16]) -> Result<Fill> + '_ {
+        |buf| {
+            let n = s.len().min(buf.len() - 1);
+            buf[..n].copy_from_slice(&s[..n]);
+            buf[n] = 0;
+            Ok(match n >= buf.len() - 1 {
+                true => Fill::TooSmall,
+                false => Fill::Fits(n),
+            })
+        }
+    }
+
+    /// Fill like `QueryFullProcessImageNameW`: nothing is written unless the string fits with
+    /// its terminator.
+    fn reporting(s: &[u16]) -> impl FnMut(&mut [u16]) -> Result<Fill> + '_ {
+        |buf| {
+            if s.len() + 1 > buf.len() {
+                return Ok(Fill::TooSmall);
+            }
+            buf[..s.len()].copy_from_slice(s);
+            buf[s.len()] = 0;
+            Ok(Fill::Fits(s.len()))
+        }
+    }
+
+    #[test]
+    fn reads_strings_longer_than_the_first_buffer() {
+        for len in [0, 1, 255, 256, 257, 300, 511, 512, 4096, 20000] {
+            let s = string(len);
+            let expected = String::from_utf16(&s).unwrap();
+            assert_eq!(
+                fill_string(MAX, truncating(&s)).unwrap(),
+                expected,
+                "length {len}"
+            );
+            assert_eq!(
+                fill_string(MAX, reporting(&s)).unwrap(),
+                expected,
+                "length {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_strings_that_exactly_fit_the_largest_buffer() {
+        let s = string(MAX - 1);
+        assert_eq!(
+            fill_string(MAX, reporting(&s)).unwrap(),
+            String::from_utf16(&s).unwrap()
+        );
+    }
+
+    #[test]
+    fn fails_on_strings_longer_than_the_largest_buffer() {
+        for len in [MAX, MAX + 1, 40000] {
+            let s = string(len);
+            for err in [
+                fill_string(MAX, truncating(&s)).unwrap_err(),
+                fill_string(MAX, reporting(&s)).unwrap_err(),
+            ] {
+                assert_eq!(err.code(), ERROR_INSUFFICIENT_BUFFER.to_hresult());
+            }
+        }
+
+        // A truncated string filling the largest buffer cannot be told apart from a longer one.
+        let s = string(MAX - 1);
+        assert!(fill_string(MAX, truncating(&s)).is_err());
+    }
+
+    #[test]
+    fn grows_the_buffer_by_doubling_up_to_the_limit() {
+        let mut lens = Vec::new();
+        let _ = fill_string(1000, |buf| {
+            lens.push(buf.len());
+            Ok(Fill::TooSmall)
+        });
+        assert_eq!(lens, [256, 512, 1000]);
+    }
+
+    #[test]
+    fn passes_failures_through() {
+        let err = fill_string(MAX, |_| Err(ERROR_ACCESS_DENIED.to_hresult().into())).unwrap_err();
+        assert_eq!(err.code(), ERROR_ACCESS_DENIED.to_hresult());
+    }
+}
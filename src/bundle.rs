@@ -0,0 +1,274 @@
+use crate::{config, layout, unix_time};
+
+use anyhow::Context;
+use log::info;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use windows::{
+    core::{Error, PSTR},
+    Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        Security::Cryptography::{
+            szOID_PKIX_KP_CODE_SIGNING, CertFreeCertificateChain, CertFreeCertificateContext,
+            CertGetCertificateChain, CertGetCertificateContextProperty,
+            CertVerifyCertificateChainPolicy, CryptVerifyDetachedMessageSignature,
+            CERT_CHAIN_CONTEXT, CERT_CHAIN_PARA, CERT_CHAIN_POLICY_BASE, CERT_CHAIN_POLICY_PARA,
+            CERT_CHAIN_POLICY_STATUS, CERT_CONTEXT, CERT_SHA1_HASH_PROP_ID, CERT_USAGE_MATCH,
+            CRYPT_VERIFY_MESSAGE_PARA, CTL_USAGE, HCERTCHAINENGINE, PKCS_7_ASN_ENCODING,
+            USAGE_MATCH_TYPE_AND, X509_ASN_ENCODING,
+        },
+        Storage::FileSystem::FILE_SHARE_READ,
+        System::Memory::{
+            CreateFileMappingW, MapViewOfFile, UnmapViewOfFile, FILE_MAP_READ,
+            MEMORYMAPPEDVIEW_HANDLE, PAGE_READONLY,
+        },
+    },
+};
+
+use std::{
+    fs::{File, OpenOptions},
+    os::windows::{fs::OpenOptionsExt, io::AsRawHandle},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+/// File name of the layout bundle, next to the executable.
+const BUNDLE_NAME: &str = "layouts.json";
+
+/// Extension appended to the bundle name for its detached PKCS #7 signature.
+const SIGNATURE_EXT: &str = "p7s";
+
+/// Merge the layout bundle shipped alongside the executable into a workspace database, if there
+/// is one that has not been merged yet. The bundle holds standard layouts (e.g. for the
+/// standardized hardware of an organization) in the format written by `export`, and goes under
+/// the user's own captures.
+///
+/// The bundle must come with a detached signature (`layouts.json.p7s`, e.g. from
+/// `signtool sign /p7 ... /p7ce DetachedSignedData`) by the certificate pinned with the
+/// `BundleSigner` policy, which must be trusted by the machine for code signing.
+pub fn seed(db: &Connection) -> anyhow::Result<()> {
+    let path = std::env::current_exe()
+        .context("failed to query exe name")?
+        .with_file_name(BUNDLE_NAME);
+
+    let meta = match std::fs::metadata(&path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e).context("failed to query layout bundle")?,
+    };
+
+    // A bundle is told apart by its size and modification time, so a new release of it is
+    // merged once more.
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let id = format!("{}:{modified}", meta.len());
+
+    let seeded = db
+        .query_row("SELECT 1 FROM seed WHERE bundle=?1", [&id], |_| Ok(()))
+        .optional()
+        .context("failed to query database")?
+        .is_some();
+    if seeded {
+        return Ok(());
+    }
+
+    let Some(signer) = config::with(|c| c.policy.bundle_signer.clone()) else {
+        anyhow::bail!("no layout bundle signer is set by policy");
+    };
+
+    let bundle = MappedFile::open(&path).context("failed to map layout bundle")?;
+    let mut signature_path = path.clone().into_os_string();
+    signature_path.push(".");
+    signature_path.push(SIGNATURE_EXT);
+    let signature =
+        std::fs::read(&signature_path).context("failed to read layout bundle signature")?;
+    verify(bundle.bytes(), &signature, &signer).context("layout bundle signature is not valid")?;
+
+    let count = layout::seed(db, bundle.bytes())?;
+    db.execute(
+        "INSERT INTO seed (bundle, time) VALUES (:bundle, :time)",
+        named_params! { ":bundle": id, ":time": unix_time()? },
+    )
+    .context("failed to record layout bundle")?;
+
+    info!(
+        "merged {count} windows from layout bundle {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Check a detached signature over `data`, and that it was made with the certificate with the
+/// SHA-1 thumbprint `signer`, chaining up to a trusted root for code signing.
+fn verify(data: &[u8], signature: &[u8], signer: &str) -> anyhow::Result<()> {
+    let para = CRYPT_VERIFY_MESSAGE_PARA {
+        cbSize: std::mem::size_of::<CRYPT_VERIFY_MESSAGE_PARA>() as u32,
+        dwMsgAndCertEncodingType: (PKCS_7_ASN_ENCODING | X509_ASN_ENCODING).0,
+        ..Default::default()
+    };
+
+    let ptr = data.as_ptr();
+    let len = data.len() as u32;
+    let mut cert: *mut CERT_CONTEXT = std::ptr::null_mut();
+    if !unsafe {
+        CryptVerifyDetachedMessageSignature(&para, 0, signature, 1, &ptr, &len, Some(&mut cert))
+    }
+    .as_bool()
+    {
+        Err(Error::from_win32()).context("signature does not match")?;
+    }
+
+    let res = thumbprint(cert).and_then(|thumbprint| match thumbprint == signer {
+        true => verify_chain(cert),
+        false => Err(anyhow::anyhow!(
+            "signed by certificate {thumbprint}, not the pinned {signer}"
+        )),
+    });
+    unsafe { CertFreeCertificateContext(Some(cert)) };
+    res
+}
+
+/// The SHA-1 thumbprint of a certificate, in upper-case hex.
+fn thumbprint(cert: *const CERT_CONTEXT) -> anyhow::Result<String> {
+    let mut hash = [0u8; 20];
+    let mut len = hash.len() as u32;
+    if !unsafe {
+        CertGetCertificateContextProperty(
+            cert,
+            CERT_SHA1_HASH_PROP_ID,
+            Some(hash.as_mut_ptr().cast()),
+            &mut len,
+        )
+    }
+    .as_bool()
+    {
+        Err(Error::from_win32()).context("failed to query certificate thumbprint")?;
+    }
+
+    Ok(hash[..len as usize]
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect())
+}
+
+fn verify_chain(cert: *const CERT_CONTEXT) -> anyhow::Result<()> {
+    // The certificate must be valid for code signing, all along the chain.
+    let mut usage = [PSTR(szOID_PKIX_KP_CODE_SIGNING.0 as *mut u8)];
+    let para = CERT_CHAIN_PARA {
+        cbSize: std::mem::size_of::<CERT_CHAIN_PARA>() as u32,
+        RequestedUsage: CERT_USAGE_MATCH {
+            dwType: USAGE_MATCH_TYPE_AND,
+            Usage: CTL_USAGE {
+                cUsageIdentifier: usage.len() as u32,
+                rgpszUsageIdentifier: usage.as_mut_ptr(),
+            },
+        },
+    };
+
+    // Intermediate certificates come with the signature.
+    let mut chain: *mut CERT_CHAIN_CONTEXT = std::ptr::null_mut();
+    if !unsafe {
+        CertGetCertificateChain(
+            HCERTCHAINENGINE::default(),
+            cert,
+            None,
+            (*cert).hCertStore,
+            &para,
+            0,
+            None,
+            &mut chain,
+        )
+    }
+    .as_bool()
+    {
+        Err(Error::from_win32()).context("failed to build certificate chain")?;
+    }
+
+    let policy = CERT_CHAIN_POLICY_PARA {
+        cbSize: std::mem::size_of::<CERT_CHAIN_POLICY_PARA>() as u32,
+        ..Default::default()
+    };
+    let mut status = CERT_CHAIN_POLICY_STATUS {
+        cbSize: std::mem::size_of::<CERT_CHAIN_POLICY_STATUS>() as u32,
+        ..Default::default()
+    };
+    let checked = unsafe {
+        CertVerifyCertificateChainPolicy(CERT_CHAIN_POLICY_BASE, chain, &policy, &mut status)
+    }
+    .as_bool();
+    unsafe { CertFreeCertificateChain(chain) };
+
+    match (checked, status.dwError) {
+        (false, _) => Err(Error::from_win32()).context("failed to check certificate chain"),
+        (true, 0) => Ok(()),
+        (true, code) => Err(Error::from(windows::core::HRESULT(code as i32)))
+            .context("signing certificate is not trusted"),
+    }
+}
+
+/// A file mapped read-only into memory. Bundles can be large, and are only ever read.
+///
+/// The file is kept open without sharing write access, so it cannot change between its signature
+/// being checked and it being merged.
+struct MappedFile {
+    _file: File,
+    mapping: HANDLE,
+    view: MEMORYMAPPEDVIEW_HANDLE,
+    len: usize,
+}
+
+impl MappedFile {
+    fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .share_mode(FILE_SHARE_READ.0)
+            .open(path)
+            .context("failed to open file")?;
+        let len = file.metadata().context("failed to query file size")?.len() as usize;
+        if len == 0 {
+            anyhow::bail!("file is empty");
+        }
+
+        let mapping = unsafe {
+            CreateFileMappingW(
+                HANDLE(file.as_raw_handle() as isize),
+                None,
+                PAGE_READONLY,
+                0,
+                0,
+                None,
+            )
+        }
+        .context("failed to create file mapping")?;
+
+        let view = match unsafe { MapViewOfFile(mapping, FILE_MAP_READ, 0, 0, 0) } {
+            Ok(view) => view,
+            Err(e) => {
+                unsafe { CloseHandle(mapping) };
+                Err(e).context("failed to map file")?
+            }
+        };
+
+        Ok(Self {
+            _file: file,
+            mapping,
+            view,
+            len,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.view.0 as *const u8, self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe {
+            UnmapViewOfFile(self.view);
+            CloseHandle(self.mapping);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{
+    active_workspace, bench, db, ipc, layout, msgwindow, simulate, soak, store::LayoutStore,
+    toggle_autorun, topology_label, window::HwndExt, workspace_path, Topology, WindowDisplay,
+    WindowKey,
+};
+
+use anyhow::Context;
+use rusqlite::Connection;
+use windows::Win32::{
+    Foundation::HWND,
+    System::Console::{AttachConsole, ATTACH_PARENT_PROCESS},
+};
+
+const USAGE: &str = "\
+usage: persistentwin [--workspace <name>]
+       persistentwin <command> [args]
+
+commands:
+  capture                            capture all windows in the running instance
+  restore                            restore all windows in the running instance
+  status                             show the state of the running instance
+  switch-workspace <name>            switch the running instance to another workspace
+  list-topologies [--workspace <name>]
+                                     list the saved display topologies
+  list-windows [--workspace <name>] [--topology <id>]
+                                     list the windows saved in a topology (the active one
+                                     by default)
+  list-tracked                       list the windows the running instance is tracking
+  export <file> [workspace]          write the saved layouts to a JSON file
+  import <file> [workspace]          load the saved layouts from a JSON file
+  autorun                            toggle starting the app at login
+  help                               show this message";
+
+/// How long a soak runs by default, in hours.
+const SOAK_HOURS: u64 = 4;
+
+/// A utility command, run instead of the app.
+pub enum Command {
+    Help,
+    Autorun(Vec<String>),
+    Send(ipc::Request),
+    Status,
+    ListTracked,
+    ListTopologies {
+        workspace: Option<String>,
+    },
+    ListWindows {
+        workspace: Option<String>,
+        topology: Option<usize>,
+    },
+    Export {
+        path: String,
+        workspace: Option<String>,
+    },
+    Import {
+        path: String,
+        workspace: Option<String>,
+    },
+    /// Hidden developer command to preview restore plans for a synthetic topology.
+    Simulate(String),
+    /// Hidden developer command to stress the running instance for a number of hours.
+    Soak(u64),
+    /// Hidden developer command to measure capture and restore throughput for numbers of
+    /// windows.
+    Bench(Vec<usize>),
+}
+
+impl Command {
+    /// Parse a command from the arguments following the executable name. Returns `None` if the
+    /// arguments are launch options rather than a command.
+    pub fn parse(args: &[String]) -> anyhow::Result<Option<Self>> {
+        let Some((name, args)) = args.split_first() else {
+            return Ok(None);
+        };
+        if name.starts_with("--") {
+            return Ok(None);
+        }
+
+        let mut workspace = None;
+        let mut topology = None;
+        let mut positional = Vec::new();
+
+        // `autorun` passes the launch options through as they are.
+        if name != "autorun" {
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--workspace" => {
+                        workspace =
+                            Some(args.next().context("--workspace requires a name")?.clone())
+                    }
+                    "--topology" => {
+                        let id = args.next().context("--topology requires an ID")?;
+                        topology = Some(id.parse().context("invalid topology ID")?);
+                    }
+                    _ => positional.push(arg.clone()),
+                }
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let command = match name.as_str() {
+            "help" | "-h" | "/?" => Self::Help,
+            "autorun" => Self::Autorun(args.to_vec()),
+            "capture" => Self::Send(ipc::Request::Capture),
+            "restore" => Self::Send(ipc::Request::Restore),
+            "status" => Self::Status,
+            "switch-workspace" => Self::Send(ipc::Request::SwitchWorkspace {
+                name: positional
+                    .next()
+                    .context("switch-workspace requires a name")?,
+            }),
+            "list-tracked" => Self::ListTracked,
+            "list-topologies" => Self::ListTopologies { workspace },
+            "list-windows" => Self::ListWindows {
+                workspace,
+                topology,
+            },
+            "export" => Self::Export {
+                path: positional.next().context("export requires a file")?,
+                workspace: positional.next().or(workspace),
+            },
+            "import" => Self::Import {
+                path: positional.next().context("import requires a file")?,
+                workspace: positional.next().or(workspace),
+            },
+            "simulate" => Self::Simulate(positional.next().context("simulate requires a file")?),
+            "bench" => Self::Bench(
+                positional
+                    .by_ref()
+                    .map(|count| count.parse().context("invalid window count"))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            "soak" => Self::Soak(match positional.next() {
+                Some(hours) => hours.parse().context("invalid number of hours")?,
+                None => SOAK_HOURS,
+            }),
+            _ => anyhow::bail!("unknown command \"{name}\"\n\n{USAGE}"),
+        };
+
+        if let Some(arg) = positional.next() {
+            anyhow::bail!("unexpected argument \"{arg}\"\n\n{USAGE}");
+        }
+
+        Ok(Some(command))
+    }
+
+    /// Whether the command is run from the UI rather than from a console, and so reports errors
+    /// with a dialog.
+    pub fn is_interactive(&self) -> bool {
+        matches!(self, Self::Autorun(_))
+    }
+
+    pub fn run(self) -> anyhow::Result<()> {
+        match self {
+            Self::Help => {
+                println!("{USAGE}");
+                Ok(())
+            }
+            Self::Autorun(args) => toggle_autorun(&args),
+            Self::Send(request) => ipc::call(&request).map(|_| ()),
+            Self::Status => status(),
+            Self::ListTracked => list_tracked(),
+            Self::ListTopologies { workspace } => list_topologies(workspace),
+            Self::ListWindows {
+                workspace,
+                topology,
+            } => list_windows(workspace, topology),
+            Self::Export { path, workspace } => layout::export(&path, workspace.as_deref()),
+            Self::Import { path, workspace } => layout::import(&path, workspace.as_deref()),
+            Self::Simulate(path) => simulate::simulate(&path),
+            Self::Soak(hours) => soak::soak(hours),
+            Self::Bench(counts) if counts.is_empty() => bench::bench(bench::DEFAULT_COUNTS),
+            Self::Bench(counts) => bench::bench(&counts),
+        }
+    }
+}
+
+/// Write output to the console the command was started from, if any. The app is built for the
+/// Windows subsystem, so it does not get a console of its own.
+pub fn attach_console() {
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
+
+/// What a running instance publishes on its event window: the active topology ID and the name
+/// of the open workspace.
+fn published(hwnd: HWND) -> (Option<usize>, Option<String>) {
+    let topology = match msgwindow::property(hwnd, "PersistentWindows.Topology") {
+        0 => None,
+        id => Some(id as usize),
+    };
+
+    // The title reads `<id>: <monitor sizes> [<workspace>]`.
+    let workspace = hwnd.title().ok().and_then(|title| {
+        let (_, rest) = title.rsplit_once('[')?;
+        Some(rest.strip_suffix(']')?.to_string())
+    });
+
+    (topology, workspace)
+}
+
+fn open(workspace: Option<String>) -> anyhow::Result<(String, Connection)> {
+    // Prefer the workspace a running instance has open over the one last saved as active.
+    let workspace = workspace
+        .or_else(|| {
+            msgwindow::instances()
+                .into_iter()
+                .find_map(|hwnd| published(hwnd).1)
+        })
+        .unwrap_or_else(active_workspace);
+
+    let db = db::open_read_only(&workspace_path(&workspace)?)
+        .with_context(|| format!("failed to open workspace \"{workspace}\""))?;
+
+    Ok((workspace, db))
+}
+
+fn status() -> anyhow::Result<()> {
+    if msgwindow::instances().is_empty() {
+        println!("not running");
+        return Ok(());
+    }
+
+    let status = ipc::call(&ipc::Request::Status)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&status).context("failed to format status")?
+    );
+
+    Ok(())
+}
+
+fn list_tracked() -> anyhow::Result<()> {
+    let windows = ipc::call(&ipc::Request::ListWindows)?;
+
+    for window in windows.as_array().into_iter().flatten() {
+        let rect = &window["rect"];
+        println!(
+            "{}\t{}\t{}\t{},{} - {},{}",
+            window["exe"].as_str().unwrap_or("(unknown)"),
+            window["class"].as_str().unwrap_or_default(),
+            window["title"].as_str().unwrap_or_default(),
+            rect["left"],
+            rect["top"],
+            rect["right"],
+            rect["bottom"],
+        );
+    }
+
+    Ok(())
+}
+
+fn list_topologies(workspace: Option<String>) -> anyhow::Result<()> {
+    let (workspace, db) = open(workspace)?;
+    println!("workspace \"{workspace}\":");
+
+    for id in db.topologies()?.into_iter().rev() {
+        let topology =
+            bson::from_slice::<Topology>(&db.topology(id)?).context("failed to decode topology")?;
+        let windows = db.records(id)?.len();
+
+        let location = topology
+            .location
+            .as_deref()
+            .map(|l| format!(" at {l}"))
+            .unwrap_or_default();
+        println!(
+            "{id}: {}{location}, {windows} windows",
+            topology_label(&topology.monitors)
+        );
+    }
+
+    Ok(())
+}
+
+fn list_windows(workspace: Option<String>, topology: Option<usize>) -> anyhow::Result<()> {
+    let topology = match topology {
+        Some(id) => id,
+        None => msgwindow::instances()
+            .into_iter()
+            .find_map(|hwnd| published(hwnd).0)
+            .context("the app is not running, so pass --topology")?,
+    };
+
+    let (_, db) = open(workspace)?;
+    let mut records = db.records(topology)?;
+    records.sort_by(|a, b| {
+        (&a.key.exe, &a.key.class, &a.key.title).cmp(&(&b.key.exe, &b.key.class, &b.key.title))
+    });
+
+    for record in records {
+        let WindowKey { exe, class, title } = &record.key;
+        let rect = WindowDisplay::decode(record.placement())
+            .map(|d| format!("{:?}", d.rect))
+            .unwrap_or_else(|_| "<invalid>".to_string());
+        println!(
+            "{}\t{class}\t{title}\t{rect}",
+            exe.as_deref().unwrap_or("(unknown)")
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,357 @@
+use crate::{
+    quirks::QuirkRule,
+    rules::{Rule, TitleRule},
+    DEFAULT_RESTORE_DELAY, DISPLAY_SETTLE_DELAY, KIOSK_MIN_INTERVAL, STABLE_READ_INTERVAL,
+};
+
+use anyhow::Context;
+use log::{warn, LevelFilter};
+use serde::Deserialize;
+use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
+
+use std::{cell::RefCell, path::PathBuf, str::FromStr, time::Duration};
+
+/// Options read from `config.toml`. Every option is optional, and missing ones keep their
+/// built-in defaults.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Interval between placement reads while waiting for a window to stop moving before
+    /// capturing it, in milliseconds
+    pub capture_debounce_ms: u64,
+    /// Delay between restoring each batch of windows, in milliseconds
+    pub restore_delay_ms: u64,
+    /// How long the display topology must stay unchanged after a display change before
+    /// windows are restored, in milliseconds. Docks that fire many display changes in a row
+    /// may need longer.
+    pub display_settle_ms: u64,
+    /// Delays after a restore pass, in milliseconds, at which restored windows are checked and
+    /// put back if they moved away again. Empty disables the checks.
+    pub restore_retry_ms: Vec<u64>,
+    /// Applications that are neither captured nor restored, by image file name (e.g.
+    /// "notepad.exe") or full path. They are added to `exclude_rules` once loaded.
+    pub exclude: Vec<String>,
+    /// Windows that are neither captured nor restored, by image, class or title pattern
+    pub exclude_rules: Vec<Rule>,
+    /// Toolkit quirks of windows by class pattern, in addition to the built-in ones
+    pub quirks: Vec<QuirkRule>,
+    /// Rewrites of window titles before windows are matched to their records, for applications
+    /// whose titles change with the open page or document. The first matching rule applies.
+    pub title_rules: Vec<TitleRule>,
+    /// Records no window has matched for this many days are deleted. Zero keeps them forever.
+    pub retention_days: u64,
+    /// Most records kept per display topology, dropping the least recently matched ones. Zero
+    /// keeps them all.
+    pub max_topology_records: usize,
+    /// Whether windows created after a restore pass (e.g. by an application started after
+    /// docking) are moved to their saved placement when they are first shown
+    pub place_new_windows: bool,
+    /// Most verbose messages logged ("off", "error", "warn", "info", "debug" or "trace"),
+    /// unless overridden with `RUST_LOG`
+    pub log_level: String,
+    /// Directory holding the workspace databases. Changes apply on the next start.
+    pub db_path: Option<PathBuf>,
+    /// Enforce a predefined layout and lock the settings, for shared machines
+    pub kiosk: Option<Kiosk>,
+    /// Workspaces to switch to when conditions of the device start to hold. The first trigger
+    /// for a condition wins.
+    pub workspace_triggers: Vec<WorkspaceTrigger>,
+    /// Administrative overrides, already merged into the options above
+    #[serde(skip)]
+    pub policy: Policy,
+}
+
+/// A condition of the device that can switch the active workspace.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Condition {
+    /// A convertible in tablet posture (keyboard detached or folded away)
+    Tablet,
+    /// A convertible in laptop posture
+    Laptop,
+    /// Running on battery
+    Battery,
+    /// Running on AC power
+    Ac,
+}
+
+/// Switch to a workspace when a condition starts to hold.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceTrigger {
+    pub when: Condition,
+    pub workspace: String,
+}
+
+/// Administrative overrides read from `HKLM\SOFTWARE\Policies\PersistentWindows`, so
+/// settings can be deployed with Group Policy.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    /// Users cannot toggle autorun (`DisableAutorunToggle`, DWORD)
+    pub disable_autorun_toggle: bool,
+    /// Applications that are always excluded (`Exclude`, multi-string)
+    pub exclude: Vec<String>,
+    /// Directory holding the workspace databases (`DataDirectory`, string)
+    pub db_path: Option<PathBuf>,
+    /// SHA-1 thumbprint of the certificate layout bundles must be signed with (`BundleSigner`,
+    /// string)
+    pub bundle_signer: Option<String>,
+}
+
+/// Registry key holding the administrative policy.
+const POLICY_KEY: &str = "SOFTWARE\\Policies\\PersistentWindows";
+
+impl Policy {
+    /// Read the policy. A missing key or value means the setting is not managed.
+    pub fn read() -> Self {
+        let Ok(key) = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(POLICY_KEY) else {
+            return Self::default();
+        };
+
+        Self {
+            disable_autorun_toggle: key
+                .get_value::<u32, _>("DisableAutorunToggle")
+                .is_ok_and(|v| v != 0),
+            exclude: key
+                .get_value::<Vec<String>, _>("Exclude")
+                .unwrap_or_default(),
+            db_path: key
+                .get_value::<String, _>("DataDirectory")
+                .ok()
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from),
+            bundle_signer: key
+                .get_value::<String, _>("BundleSigner")
+                .ok()
+                .map(|t| t.replace(' ', "").to_ascii_uppercase())
+                .filter(|t| !t.is_empty()),
+        }
+    }
+
+    /// Merge the overrides into a configuration.
+    fn apply(self, config: &mut Config) {
+        config.exclude.extend(self.exclude.iter().cloned());
+        if let Some(path) = &self.db_path {
+            config.db_path = Some(path.clone());
+        }
+
+        config.policy = self;
+    }
+}
+
+/// Options of kiosk mode.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Kiosk {
+    /// Name of the checkpoint holding the enforced layout
+    pub checkpoint: String,
+    /// Interval between enforcing the layout, in seconds. With 0 (the default), windows are
+    /// put back as soon as they are noticed out of place.
+    #[serde(default)]
+    pub interval_secs: u64,
+}
+
+impl Kiosk {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs).max(KIOSK_MIN_INTERVAL)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            capture_debounce_ms: STABLE_READ_INTERVAL.as_millis() as u64,
+            restore_delay_ms: DEFAULT_RESTORE_DELAY.as_millis() as u64,
+            display_settle_ms: DISPLAY_SETTLE_DELAY.as_millis() as u64,
+            restore_retry_ms: vec![1000, 5000, 30000],
+            exclude: Vec::new(),
+            exclude_rules: Vec::new(),
+            quirks: Vec::new(),
+            title_rules: Vec::new(),
+            retention_days: 365,
+            max_topology_records: 2000,
+            place_new_windows: true,
+            log_level: "info".to_string(),
+            db_path: None,
+            kiosk: None,
+            workspace_triggers: Vec::new(),
+            policy: Policy::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Whether a window matches any of the exclusion rules.
+    pub fn is_window_excluded(&self, exe: Option<&str>, class: &str, title: &str) -> bool {
+        self.exclude_rules
+            .iter()
+            .any(|rule| rule.matches(exe, class, title))
+    }
+
+    /// The title a window is known by: its title as rewritten by the first title rule for its
+    /// image and class, if any.
+    pub fn rewrite_title(&self, exe: Option<&str>, class: &str, title: &str) -> String {
+        self.title_rules
+            .iter()
+            .find(|rule| rule.matches(exe, class))
+            .map_or_else(|| title.to_string(), |rule| rule.apply(title))
+    }
+
+    pub fn capture_debounce(&self) -> Duration {
+        Duration::from_millis(self.capture_debounce_ms)
+    }
+
+    pub fn restore_delay(&self) -> Duration {
+        Duration::from_millis(self.restore_delay_ms)
+    }
+
+    pub fn display_settle(&self) -> Duration {
+        Duration::from_millis(self.display_settle_ms)
+    }
+
+    /// The restore retry delays, in ascending order.
+    pub fn restore_retries(&self) -> Vec<Duration> {
+        let mut delays = self.restore_retry_ms.clone();
+        delays.sort_unstable();
+        delays.into_iter().map(Duration::from_millis).collect()
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        LevelFilter::from_str(&self.log_level).unwrap_or(LevelFilter::Info)
+    }
+
+    /// Describe the options in effect, noting the ones set by policy.
+    pub fn describe(&self) -> String {
+        let managed = |managed: bool| match managed {
+            true => " (policy)",
+            false => "",
+        };
+
+        let mut lines = vec![
+            format!("Capture debounce: {} ms", self.capture_debounce_ms),
+            format!("Restore delay: {} ms", self.restore_delay_ms),
+            format!("Display settle delay: {} ms", self.display_settle_ms),
+            format!("Restore retries: {:?} ms", self.restore_retry_ms),
+            format!("Log level: {}", self.log_level),
+            format!(
+                "Data directory: {}{}",
+                self.db_path
+                    .as_ref()
+                    .map_or("default".to_string(), |p| p.display().to_string()),
+                managed(self.policy.db_path.is_some())
+            ),
+            format!(
+                "Excluded applications: {}{}",
+                match self.exclude.is_empty() {
+                    true => "none".to_string(),
+                    false => self.exclude.join(", "),
+                },
+                managed(!self.policy.exclude.is_empty())
+            ),
+            format!("Exclusion rules: {}", self.exclude_rules.len()),
+            format!("Toolkit quirk rules: {}", self.quirks.len()),
+            format!("Title rules: {}", self.title_rules.len()),
+            format!("Place new windows: {}", self.place_new_windows),
+            format!(
+                "Retention: {} days, {} records per topology",
+                self.retention_days, self.max_topology_records
+            ),
+        ];
+
+        if self.policy.disable_autorun_toggle {
+            lines.push("Autorun: locked (policy)".to_string());
+        }
+        if let Some(kiosk) = &self.kiosk {
+            lines.push(format!("Kiosk checkpoint: {}", kiosk.checkpoint));
+        }
+
+        lines.join("\n")
+    }
+}
+
+// The configuration in effect, loaded on first use.
+thread_local! {
+    static CURRENT: RefCell<Option<Config>> = const { RefCell::new(None) };
+}
+
+/// Location of the configuration file: `%APPDATA%\PersistentWindows\config.toml`.
+pub fn path() -> anyhow::Result<PathBuf> {
+    let appdata = std::env::var_os("APPDATA").context("APPDATA is not set")?;
+    Ok(PathBuf::from(appdata)
+        .join("PersistentWindows")
+        .join("config.toml"))
+}
+
+/// Location of the machine-wide configuration file: `%ProgramData%\PersistentWindows\config.toml`.
+pub fn machine_path() -> anyhow::Result<PathBuf> {
+    let data = std::env::var_os("ProgramData").context("ProgramData is not set")?;
+    Ok(PathBuf::from(data)
+        .join("PersistentWindows")
+        .join("config.toml"))
+}
+
+/// Read the configuration file, with the administrative policy applied over it. A missing file
+/// is the same as an empty one.
+///
+/// If the machine-wide configuration file exists, it is used instead of the user's, so
+/// administrators can lock down the configuration of shared machines.
+pub fn load() -> anyhow::Result<Config> {
+    let path = match machine_path() {
+        Ok(path) if path.exists() => path,
+        _ => path()?,
+    };
+    let mut config = match std::fs::read_to_string(&path) {
+        Ok(text) => {
+            toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display()))?,
+    };
+
+    finish(&mut config);
+    Ok(config)
+}
+
+/// Complete a configuration with what is not read from its file: the administrative policy is
+/// applied, and the excluded applications become exclusion rules on their image.
+fn finish(config: &mut Config) {
+    Policy::read().apply(config);
+
+    let rules = config
+        .exclude
+        .iter()
+        .map(|exe| Rule::exe(exe))
+        .collect::<Vec<_>>();
+    config.exclude_rules.extend(rules);
+}
+
+/// Run `f` with the configuration in effect. If the file cannot be loaded, the defaults are used
+/// instead, still subject to the administrative policy.
+pub fn with<R>(f: impl FnOnce(&Config) -> R) -> R {
+    CURRENT.with(|c| {
+        let mut current = c.borrow_mut();
+        let config = current.get_or_insert_with(|| {
+            load().unwrap_or_else(|e| {
+                warn!("{e:?}");
+                let mut config = Config::default();
+                finish(&mut config);
+                config
+            })
+        });
+
+        f(config)
+    })
+}
+
+/// A copy of the configuration in effect.
+pub fn get() -> Config {
+    with(Config::clone)
+}
+
+/// Load the configuration file again and put it into effect. On failure, the configuration in
+/// effect is kept.
+pub fn reload() -> anyhow::Result<Config> {
+    let config = load()?;
+    CURRENT.with(|c| *c.borrow_mut() = Some(config.clone()));
+    Ok(config)
+}
@@ -0,0 +1,260 @@
+use anyhow::Context;
+use log::info;
+use rusqlite::{
+    named_params, Connection, OpenFlags, OptionalExtension, Transaction, TransactionBehavior,
+};
+
+use std::{path::Path, time::Duration};
+
+/// How long a connection waits for another to release the database, e.g. a command run while
+/// the app is writing to it.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Schema migrations, in order. The schema version of a database is the number of migrations
+/// applied to it, so existing steps must never change: append a new step instead.
+///
+/// Databases created before versioning was introduced have version 0, and the first step
+/// only creates what is missing so they adopt version 1 as they are.
+const MIGRATIONS: &[&str] = &[
+    // 1: Initial schema.
+    "CREATE TABLE IF NOT EXISTS appwindow (
+            id          INTEGER PRIMARY KEY,
+            topology    INTEGER NOT NULL,
+            exe         TEXT,
+            class       TEXT NOT NULL,
+            title       TEXT NOT NULL,
+            disp        BLOB NOT NULL,
+            user_disp   BLOB,
+            trigger     TEXT,
+            updated     INTEGER NOT NULL,
+            FOREIGN KEY (topology) REFERENCES topology(id)
+        );
+        CREATE INDEX IF NOT EXISTS appwindow_key ON appwindow (topology, exe, class, title);
+        CREATE TABLE IF NOT EXISTS topology (
+            id          INTEGER PRIMARY KEY,
+            data        BLOB UNIQUE NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS topology_policy (
+            topology    INTEGER PRIMARY KEY,
+            policy      TEXT NOT NULL,
+            FOREIGN KEY (topology) REFERENCES topology(id)
+        );
+        CREATE TABLE IF NOT EXISTS quarantine (
+            id          INTEGER NOT NULL,
+            topology    INTEGER NOT NULL,
+            disp        BLOB NOT NULL,
+            reason      TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS journal (
+            id          INTEGER PRIMARY KEY,
+            time        INTEGER NOT NULL,
+            prev        INTEGER,
+            next        INTEGER NOT NULL,
+            trigger     TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS history (
+            id          INTEGER PRIMARY KEY,
+            time        INTEGER NOT NULL,
+            record      INTEGER NOT NULL,
+            topology    INTEGER NOT NULL,
+            action      TEXT NOT NULL,
+            trigger     TEXT NOT NULL,
+            disp        BLOB NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS history_record ON history (record);
+        CREATE TABLE IF NOT EXISTS checkpoint (
+            id          INTEGER PRIMARY KEY,
+            name        TEXT UNIQUE NOT NULL,
+            topology    INTEGER NOT NULL,
+            time        INTEGER NOT NULL,
+            FOREIGN KEY (topology) REFERENCES topology(id)
+        );
+        CREATE TABLE IF NOT EXISTS checkpoint_window (
+            id          INTEGER PRIMARY KEY,
+            checkpoint  INTEGER NOT NULL,
+            exe         TEXT,
+            class       TEXT NOT NULL,
+            title       TEXT NOT NULL,
+            disp        BLOB NOT NULL,
+            FOREIGN KEY (checkpoint) REFERENCES checkpoint(id)
+        );
+        CREATE TABLE IF NOT EXISTS rule (
+            id          INTEGER PRIMARY KEY,
+            exe         TEXT NOT NULL,
+            action      TEXT NOT NULL,
+            UNIQUE (exe, action)
+        );
+        CREATE TABLE IF NOT EXISTS stat (
+            topology    INTEGER NOT NULL,
+            exe         TEXT NOT NULL,
+            restored    INTEGER NOT NULL,
+            failed      INTEGER NOT NULL,
+            PRIMARY KEY (topology, exe),
+            FOREIGN KEY (topology) REFERENCES topology(id)
+        );
+        CREATE TABLE IF NOT EXISTS mru (
+            hwnd        INTEGER NOT NULL,
+            topology    INTEGER NOT NULL,
+            seq         INTEGER NOT NULL,
+            PRIMARY KEY (hwnd, topology),
+            FOREIGN KEY (topology) REFERENCES topology(id)
+        );",
+    // 2: The virtual desktop of each window.
+    "ALTER TABLE appwindow ADD COLUMN desktop TEXT;",
+    // 3: How each application was last started, to launch it again.
+    "CREATE TABLE IF NOT EXISTS launch (
+            exe             TEXT PRIMARY KEY,
+            command_line    TEXT NOT NULL,
+            directory       TEXT,
+            updated         INTEGER NOT NULL
+        );",
+    // 4: The layout bundles already merged into the workspace.
+    "CREATE TABLE IF NOT EXISTS seed (
+            bundle      TEXT PRIMARY KEY,
+            time        INTEGER NOT NULL
+        );",
+    // 5: When the window of each record was closed, if it is.
+    "ALTER TABLE appwindow ADD COLUMN closed INTEGER;",
+    // 6: How often new windows of each application placed themselves.
+    "CREATE TABLE IF NOT EXISTS self_restore (
+            exe         TEXT PRIMARY KEY,
+            shows       INTEGER NOT NULL,
+            hits        INTEGER NOT NULL
+        );",
+    // 7: When a window last matched each record.
+    "ALTER TABLE appwindow ADD COLUMN seen INTEGER;
+        UPDATE appwindow SET seen=updated;",
+    // 8: Where each of the app's own dialogs was last left, per topology.
+    "CREATE TABLE IF NOT EXISTS dialog (
+            topology    INTEGER NOT NULL,
+            name        TEXT NOT NULL,
+            x           INTEGER NOT NULL,
+            y           INTEGER NOT NULL,
+            width       INTEGER NOT NULL,
+            height      INTEGER NOT NULL,
+            PRIMARY KEY (topology, name),
+            FOREIGN KEY (topology) REFERENCES topology(id)
+        );",
+    // 9: The tag stamped on the window of each record, for the session it was captured in.
+    "ALTER TABLE appwindow ADD COLUMN tag TEXT;
+        CREATE INDEX IF NOT EXISTS appwindow_tag ON appwindow (topology, tag);",
+    // 10: The instance that recorded each MRU entry, as an elevated and an unelevated instance
+    // may share the database.
+    "ALTER TABLE mru ADD COLUMN owner TEXT NOT NULL DEFAULT '';",
+];
+
+/// Open the database at `path` for the app instance named `instance`, creating it if needed and
+/// upgrading its schema to the current version.
+pub fn open(path: &Path, instance: &str) -> anyhow::Result<Connection> {
+    let db = open_shared(path)?;
+
+    // Window handles do not survive a restart, so the recency order is only kept per session.
+    // The entries of the other instance sharing the database are still in use.
+    db.execute(
+        "DELETE FROM mru WHERE owner=:owner",
+        named_params! { ":owner": instance },
+    )
+    .context("failed to clear MRU order")?;
+
+    Ok(db)
+}
+
+/// Open a database that only lives in memory, with the current schema.
+#[cfg(test)]
+pub fn open_in_memory() -> anyhow::Result<Connection> {
+    let mut db = Connection::open_in_memory().context("Failed to open DB")?;
+    migrate(&mut db)?;
+    Ok(db)
+}
+
+/// Open the database at `path` to write to it from a command, alongside the app if it is
+/// running. Unlike `open`, this leaves the state of the app's session alone.
+///
+/// Writers follow one protocol: every write goes through a transaction from `begin`.
+pub fn open_shared(path: &Path) -> anyhow::Result<Connection> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("failed to create workspace directory")?;
+    }
+
+    let mut db = Connection::open(path).context("Failed to open DB")?;
+    db.busy_timeout(BUSY_TIMEOUT)
+        .context("failed to set busy timeout")?;
+
+    // With a write-ahead log, readers neither block the writer nor wait for it.
+    db.pragma_update_and_check(None, "journal_mode", "WAL", |r| r.get::<usize, String>(0))
+        .context("failed to enable write-ahead log")?;
+
+    migrate(&mut db)?;
+    Ok(db)
+}
+
+/// Open the database at `path` for queries only. The database is never created or upgraded,
+/// so the app can keep using it while it is read.
+pub fn open_read_only(path: &Path) -> anyhow::Result<Connection> {
+    let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .context("Failed to open DB")?;
+    db.busy_timeout(BUSY_TIMEOUT)
+        .context("failed to set busy timeout")?;
+
+    Ok(db)
+}
+
+/// Begin a write transaction. It takes the write lock up front (waiting up to `BUSY_TIMEOUT`
+/// for other writers), as a transaction that reads first and then finds another connection
+/// writing fails right away rather than waiting.
+pub fn begin(db: &Connection) -> anyhow::Result<Transaction<'_>> {
+    Transaction::new_unchecked(db, TransactionBehavior::Immediate)
+        .context("failed to begin transaction")
+}
+
+/// The schema version of a database, i.e. the number of migrations applied to it.
+fn version(db: &Connection) -> anyhow::Result<usize> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        [],
+    )
+    .context("failed to create schema version table")?;
+
+    let version = db
+        .query_row("SELECT version FROM schema_version", [], |r| {
+            r.get::<usize, i64>(0)
+        })
+        .optional()
+        .context("failed to query schema version")?;
+
+    Ok(version.unwrap_or(0) as usize)
+}
+
+/// Apply the migrations a database is missing. Each step runs in its own transaction along
+/// with the version bump, so an interrupted upgrade resumes where it left off.
+fn migrate(db: &mut Connection) -> anyhow::Result<()> {
+    let current = version(db)?;
+    if current > MIGRATIONS.len() {
+        anyhow::bail!(
+            "database schema version {current} is newer than supported ({})",
+            MIGRATIONS.len()
+        );
+    }
+
+    for (i, step) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = i + 1;
+
+        let tx = db
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("failed to begin migration")?;
+        tx.execute_batch(step)
+            .with_context(|| format!("failed to migrate schema to version {version}"))?;
+        tx.execute("DELETE FROM schema_version", [])
+            .context("failed to update schema version")?;
+        tx.execute(
+            "INSERT INTO schema_version (version) VALUES (?1)",
+            [version as i64],
+        )
+        .context("failed to update schema version")?;
+        tx.commit().context("failed to commit migration")?;
+
+        info!("migrated database schema to version {version}");
+    }
+
+    Ok(())
+}
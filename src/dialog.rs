@@ -0,0 +1,122 @@
+use crate::{
+    monitor::{self, HMonitorExt},
+    window::{self, HwndExt},
+    Point, Rect,
+};
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use windows::Win32::Foundation::{HWND, POINT};
+
+/// Space left between a dialog and the point it is opened next to, in pixels.
+const ANCHOR_GAP: i32 = 12;
+
+/// Where one of the app's own windows (dialogs, and any overlays to come) is shown, and where it
+/// was last left in each display topology.
+pub struct Dialog<'a> {
+    pub hwnd: HWND,
+    /// Name the dialog is remembered by
+    pub name: &'a str,
+}
+
+impl Dialog<'_> {
+    /// Move the dialog to where it was last left in a topology (`origin` being the top-left
+    /// corner of its virtual screen), or else next to `anchor`, e.g. where the tray icon was
+    /// clicked. Either way, it is kept within the work area of a monitor.
+    pub fn place(
+        &self,
+        db: &Connection,
+        topology: Option<usize>,
+        origin: &Point,
+        anchor: POINT,
+    ) -> anyhow::Result<()> {
+        let remembered = match topology {
+            Some(topology) => self.remembered(db, topology)?,
+            None => None,
+        };
+
+        let rect = match remembered {
+            Some(rect) => rect.offset(origin.x, origin.y),
+            None => self.near(anchor)?,
+        };
+
+        // The monitor the dialog was left on may be gone, or have a smaller work area now.
+        let work = monitor::from_rect(&rect)
+            .info()
+            .context("failed to query monitor info")?
+            .work;
+
+        window::set_positions(&[(self.hwnd, rect.clamp_within(&work))])
+            .context("failed to move dialog")
+    }
+
+    /// Remember where the dialog is in a topology, for the next time it is shown.
+    pub fn remember(&self, db: &Connection, topology: usize, origin: &Point) -> anyhow::Result<()> {
+        let rect = self
+            .hwnd
+            .rect()
+            .context("failed to query dialog rect")?
+            .offset(-origin.x, -origin.y);
+
+        db.execute(
+            "REPLACE INTO dialog (topology, name, x, y, width, height)
+                VALUES (:topology, :name, :x, :y, :width, :height)",
+            named_params! {
+                ":topology": topology,
+                ":name": self.name,
+                ":x": rect.left,
+                ":y": rect.top,
+                ":width": rect.width(),
+                ":height": rect.height(),
+            },
+        )
+        .context("failed to remember dialog placement")?;
+
+        Ok(())
+    }
+
+    fn remembered(&self, db: &Connection, topology: usize) -> anyhow::Result<Option<Rect>> {
+        db.query_row(
+            "SELECT x, y, width, height FROM dialog WHERE topology=:topology AND name=:name",
+            named_params! { ":topology": topology, ":name": self.name },
+            |r| {
+                let (left, top) = (r.get::<usize, i32>(0)?, r.get::<usize, i32>(1)?);
+                Ok(Rect {
+                    left,
+                    top,
+                    right: left + r.get::<usize, i32>(2)?,
+                    bottom: top + r.get::<usize, i32>(3)?,
+                })
+            },
+        )
+        .optional()
+        .context("failed to query dialog placement")
+    }
+
+    /// The dialog at its current size, next to `anchor` on the side facing the rest of its
+    /// monitor (e.g. above and to the left of a tray icon in the bottom-right corner).
+    fn near(&self, anchor: POINT) -> anyhow::Result<Rect> {
+        let size = self.hwnd.rect().context("failed to query dialog rect")?;
+        let (width, height) = (size.width() as i32, size.height() as i32);
+        let work = monitor::from_point(anchor)
+            .info()
+            .context("failed to query monitor info")?
+            .work;
+
+        let left = match anchor.x < work.left + work.width() as i32 / 2 {
+            true => anchor.x + ANCHOR_GAP,
+            false => anchor.x - ANCHOR_GAP - width,
+        };
+        let top = match anchor.y < work.top + work.height() as i32 / 2 {
+            true => anchor.y + ANCHOR_GAP,
+            false => anchor.y - ANCHOR_GAP - height,
+        };
+
+        Ok(Rect {
+            left,
+            top,
+            right: left + width,
+            bottom: top + height,
+        })
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Eq, Debug)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Point {
+    pub fn offset(&self, dx: i32, dy: i32) -> Point {
+        Point {
+            x: self.x + dx,
+            y: self.y + dy,
+        }
+    }
+
+    /// Map a point within `from` to the same relative position within `to`.
+    pub fn rescale(&self, from: &Rect, to: &Rect) -> Point {
+        let scale = |v: i32, a0: i32, a_len: u32, b0: i32, b_len: u32| {
+            b0 + ((v - a0) as i64 * b_len as i64 / a_len.max(1) as i64) as i32
+        };
+
+        Point {
+            x: scale(self.x, from.left, from.width(), to.left, to.width()),
+            y: scale(self.y, from.top, from.height(), to.top, to.height()),
+        }
+    }
+
+    /// Scale a point on a monitor whose top-left corner is `origin` from `from` DPI to `to`.
+    pub fn scale_dpi(&self, origin: &Point, from: u32, to: u32) -> Point {
+        let scale = |v: i32, o: i32| o + ((v - o) as i64 * to as i64 / from.max(1) as i64) as i32;
+
+        Point {
+            x: scale(self.x, origin.x),
+            y: scale(self.y, origin.y),
+        }
+    }
+
+    /// Whether this is a real position rather than one of the sentinel values the system uses
+    /// for unset or off-screen (minimized) positions.
+    pub fn is_set(&self) -> bool {
+        (self.x, self.y) != (-1, -1) && self.x > -32000 && self.y > -32000
+    }
+}
+
+impl From<windows::Win32::Foundation::POINT> for Point {
+    fn from(r: windows::Win32::Foundation::POINT) -> Self {
+        Self { x: r.x, y: r.y }
+    }
+}
+
+impl Into<windows::Win32::Foundation::POINT> for Point {
+    fn into(self) -> windows::Win32::Foundation::POINT {
+        windows::Win32::Foundation::POINT {
+            x: self.x,
+            y: self.y,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn width(&self) -> u32 {
+        (self.right - self.left).abs() as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.bottom - self.top).abs() as u32
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32) -> Rect {
+        Rect {
+            left: self.left + dx,
+            top: self.top + dy,
+            right: self.right + dx,
+            bottom: self.bottom + dy,
+        }
+    }
+
+    /// Map a rectangle within `from` to the same relative position and size within `to`.
+    pub fn rescale(&self, from: &Rect, to: &Rect) -> Rect {
+        let tl = Point {
+            x: self.left,
+            y: self.top,
+        }
+        .rescale(from, to);
+        let br = Point {
+            x: self.right,
+            y: self.bottom,
+        }
+        .rescale(from, to);
+
+        Rect {
+            left: tl.x,
+            top: tl.y,
+            right: br.x,
+            bottom: br.y,
+        }
+    }
+
+    /// Scale a rectangle on `monitor` from `from` DPI to `to`, keeping its logical size and its
+    /// position relative to the top-left corner of the monitor.
+    pub fn scale_dpi(&self, monitor: &Rect, from: u32, to: u32) -> Rect {
+        let origin = Point {
+            x: monitor.left,
+            y: monitor.top,
+        };
+        let tl = Point {
+            x: self.left,
+            y: self.top,
+        }
+        .scale_dpi(&origin, from, to);
+        let br = Point {
+            x: self.right,
+            y: self.bottom,
+        }
+        .scale_dpi(&origin, from, to);
+
+        Rect {
+            left: tl.x,
+            top: tl.y,
+            right: br.x,
+            bottom: br.y,
+        }
+    }
+
+    /// The area of the intersection of two rectangles.
+    pub fn intersection_area(&self, other: &Rect) -> u64 {
+        let w = self.right.min(other.right) - self.left.max(other.left);
+        let h = self.bottom.min(other.bottom) - self.top.max(other.top);
+
+        match w > 0 && h > 0 {
+            true => w as u64 * h as u64,
+            false => 0,
+        }
+    }
+
+    /// Shrink and move this rectangle so that it lies entirely within `bounds`.
+    pub fn clamp_within(&self, bounds: &Rect) -> Rect {
+        let width = self.width().min(bounds.width()) as i32;
+        let height = self.height().min(bounds.height()) as i32;
+        let left = self.left.clamp(bounds.left, bounds.right - width);
+        let top = self.top.clamp(bounds.top, bounds.bottom - height);
+
+        Rect {
+            left,
+            top,
+            right: left + width,
+            bottom: top + height,
+        }
+    }
+}
+
+impl From<windows::Win32::Foundation::RECT> for Rect {
+    fn from(r: windows::Win32::Foundation::RECT) -> Self {
+        Self {
+            top: r.top,
+            bottom: r.bottom,
+            right: r.right,
+            left: r.left,
+        }
+    }
+}
+
+impl Into<windows::Win32::Foundation::RECT> for Rect {
+    fn into(self) -> windows::Win32::Foundation::RECT {
+        windows::Win32::Foundation::RECT {
+            top: self.top,
+            bottom: self.bottom,
+            right: self.right,
+            left: self.left,
+        }
+    }
+}
@@ -1,90 +1,161 @@
-use windows::Win32::{
-    Foundation::{HMODULE, HWND},
-    System::LibraryLoader::GetModuleHandleW,
-    UI::{
-        Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK},
-        WindowsAndMessaging::{WINEVENT_OUTOFCONTEXT, WINEVENT_SKIPOWNPROCESS},
+use crate::timers::Stamp;
+
+use windows::{
+    core::{Error, HSTRING},
+    Win32::{
+        Foundation::{E_FAIL, HMODULE, HWND},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK},
+            WindowsAndMessaging::{
+                CHILDID_SELF, OBJID_WINDOW, WINEVENT_OUTOFCONTEXT, WINEVENT_SKIPOWNPROCESS,
+            },
+        },
     },
 };
 
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
 
-type CallbackFn = dyn Fn(u32, HWND);
+type Result<R> = core::result::Result<R, Error>;
+pub type CallbackFn = dyn Fn(u32, HWND);
 
 // Note that per MSDN, events are dispatched to the same thread that registered them.
 thread_local! {
     static EVENT_TABLE: RefCell<HashMap<isize, EventHook>> = RefCell::new(HashMap::new());
+    static LAST_EVENT: Cell<Option<Stamp>> = const { Cell::new(None) };
 }
 
 fn current_module() -> HMODULE {
     unsafe { GetModuleHandleW(None) }.expect("failed to query current module")
 }
 
-pub struct EventHandle(HWINEVENTHOOK);
+/// An installed event hook. The hook is removed when the handle is dropped.
+pub struct EventHandle {
+    hook: HWINEVENTHOOK,
+    /// The range of events the hook was registered for
+    range: (u32, u32),
+}
+
+impl EventHandle {
+    /// Install a hook for a range of events, dispatching them to `cb`.
+    fn install(evt_min: u32, evt_max: u32, cb: Rc<CallbackFn>) -> Result<EventHandle> {
+        let hnd = EventHandle {
+            hook: hook_range(evt_min, evt_max)?,
+            range: (evt_min, evt_max),
+        };
+
+        // N.B: Despite us registering the event hook internally _after_ registering the callback with
+        // the system, there is no race condition here because the callback is not invoked asynchronously.
+        EVENT_TABLE.with(|tab| {
+            tab.borrow_mut().insert(hnd.hook.0, EventHook { cb });
+        });
+
+        // Count the hook as healthy from the moment it is installed.
+        LAST_EVENT.with(|t| t.set(Some(Stamp::now())));
+
+        Ok(hnd)
+    }
+
+    /// The range of events the hook was registered for.
+    pub fn range(&self) -> (u32, u32) {
+        self.range
+    }
+}
+
+impl std::ops::Drop for EventHandle {
+    fn drop(&mut self) {
+        unsafe { UnhookWinEvent(self.hook) };
+
+        EVENT_TABLE.with(|tab| {
+            tab.borrow_mut().remove(&self.hook.0);
+        });
+    }
+}
 
 pub struct EventHook {
     cb: Rc<CallbackFn>,
 }
 
+/// Install a hook for a range of events. `SetWinEventHook` does not report why it failed, so
+/// failures surface as a generic error.
+fn hook_range(evt_min: u32, evt_max: u32) -> Result<HWINEVENTHOOK> {
+    let hook = unsafe {
+        SetWinEventHook(
+            evt_min,
+            evt_max,
+            current_module(),
+            Some(event_cb),
+            0,
+            0,
+            WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
+        )
+    };
+
+    match hook.0 {
+        0 => Err(Error::new(
+            E_FAIL,
+            HSTRING::from(format!(
+                "SetWinEventHook failed for events {evt_min:#x}..={evt_max:#x}"
+            )),
+        )),
+        _ => Ok(hook),
+    }
+}
+
 #[allow(dead_code)]
 impl EventHook {
+    /// Install hooks for several ranges of events sharing one callback. Either all of the hooks
+    /// are installed, or none are.
     pub fn register_ranges(
         ranges: &[(u32, u32)],
         cb: impl Fn(u32, HWND) + 'static,
-    ) -> Vec<EventHandle> {
-        let ranges = ranges
-            .into_iter()
-            .map(|(min, max)| {
-                EventHandle(unsafe {
-                    SetWinEventHook(
-                        *min,
-                        *max,
-                        current_module(),
-                        Some(event_cb),
-                        0,
-                        0,
-                        WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
-                    )
-                })
-            })
-            .collect::<Vec<_>>();
-
-        let cb = Rc::new(cb);
-        EVENT_TABLE.with(|tab| {
-            for hnd in &ranges {
-                tab.borrow_mut()
-                    .insert(hnd.0 .0, EventHook { cb: cb.clone() });
-            }
-        });
+    ) -> Result<Vec<EventHandle>> {
+        let cb: Rc<CallbackFn> = Rc::new(cb);
 
+        // N.B: On failure, the hooks installed so far are removed as their handles drop.
         ranges
+            .iter()
+            .map(|&(min, max)| EventHandle::install(min, max, cb.clone()))
+            .collect()
     }
 
-    pub fn register(evt_min: u32, evt_max: u32, cb: impl Fn(u32, HWND) + 'static) -> EventHandle {
-        let hnd = EventHandle(unsafe {
-            SetWinEventHook(
-                evt_min,
-                evt_max,
-                current_module(),
-                Some(event_cb),
-                0,
-                0,
-                WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
-            )
-        });
+    pub fn register(
+        evt_min: u32,
+        evt_max: u32,
+        cb: impl Fn(u32, HWND) + 'static,
+    ) -> Result<EventHandle> {
+        EventHandle::install(evt_min, evt_max, Rc::new(cb))
+    }
 
-        // N.B: Despite us registering the event hook internally _after_ registering the callback with
-        // the system, there is no race condition here because the callback is not invoked asynchronously.
-        EVENT_TABLE.with(|tab| {
-            tab.borrow_mut()
-                .insert(hnd.0 .0, EventHook { cb: Rc::new(cb) });
-        });
+    /// Install a hook sharing a callback with other hooks, so hooks can be added to an existing
+    /// set at runtime.
+    pub fn register_shared(evt_min: u32, evt_max: u32, cb: Rc<CallbackFn>) -> Result<EventHandle> {
+        EventHandle::install(evt_min, evt_max, cb)
+    }
+
+    /// Replace a hook with a freshly installed one for the same events and callback, for hooks
+    /// that have stopped delivering events. The old hook is kept if the new one cannot be
+    /// installed.
+    pub fn reregister(handle: &mut EventHandle) -> Result<()> {
+        let cb = EVENT_TABLE.with(|tab| tab.borrow().get(&handle.hook.0).map(|h| h.cb.clone()));
+        let Some(cb) = cb else {
+            return Ok(());
+        };
 
-        hnd
+        let (min, max) = handle.range;
+
+        // N.B: The old handle drops here, removing the old hook and its table entry.
+        *handle = EventHandle::install(min, max, cb)?;
+        Ok(())
     }
 
-    pub fn unregister(handle: EventHandle) {
-        unsafe { UnhookWinEvent(handle.0) };
+    /// When any hook last received an event (or was installed), whether or not it was dispatched.
+    pub fn last_event() -> Option<Stamp> {
+        LAST_EVENT.with(|t| t.get())
     }
 }
 
@@ -92,14 +163,23 @@ extern "system" fn event_cb(
     hook: HWINEVENTHOOK,
     event: u32,
     hwnd: HWND,
-    _idobject: i32,
-    _idchild: i32,
+    idobject: i32,
+    idchild: i32,
     _ideventthread: u32,
     _dwmseventtime: u32,
 ) {
-    EVENT_TABLE.with(|tab| {
-        if let Some(hook) = tab.borrow().get(&hook.0) {
-            (hook.cb)(event, hwnd);
-        }
-    });
+    LAST_EVENT.with(|t| t.set(Some(Stamp::now())));
+
+    // Only dispatch events about windows themselves, rather than objects within them (e.g. a
+    // caret or scrollbar), which are reported against the handle of the containing window.
+    if idobject != OBJID_WINDOW.0 || idchild != CHILDID_SELF as i32 {
+        return;
+    }
+
+    // N.B: Clone the callback out of the table so it may add or remove hooks without the table
+    // being borrowed.
+    let cb = EVENT_TABLE.with(|tab| tab.borrow().get(&hook.0).map(|h| h.cb.clone()));
+    if let Some(cb) = cb {
+        (cb)(event, hwnd);
+    }
 }
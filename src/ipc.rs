@@ -0,0 +1,478 @@
+use crate::process::{self, ProcessExt};
+
+use anyhow::Context;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use windows::{
+    core::{Error, PCWSTR},
+    Win32::{
+        Foundation::{
+            CloseHandle, ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, HANDLE, HLOCAL,
+            INVALID_HANDLE_VALUE,
+        },
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+        Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+        System::{
+            Memory::LocalFree,
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe,
+                GetNamedPipeClientProcessId, GetNamedPipeClientSessionId, PeekNamedPipe,
+                WaitNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+                PIPE_WAIT,
+            },
+            RemoteDesktop::ProcessIdToSessionId,
+            Threading::{
+                GetCurrentProcess, GetCurrentProcessId, PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+        },
+    },
+};
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    os::windows::io::{AsRawHandle, FromRawHandle},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The prefix of the pipes running instances listen on.
+const PIPE_PREFIX: &str = r"\\.\pipe\";
+
+/// Access to the pipe of an elevated instance: full access for the system and administrators,
+/// and read/write access for the interactive user, whose unelevated instance has to reach it.
+/// The medium integrity label lets unelevated processes write to it at all.
+const ELEVATED_PIPE_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;IU)S:(ML;;NW;;;ME)";
+
+/// How long a client waits for the instance to handle a request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a client has to send its request once connected. There is a single pipe instance,
+/// so a client that stalls would otherwise lock out every other one.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client waits for the pipe while the instance serves another client: long enough
+/// for it to take the request and the answer of the one before.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How often the pipe is polled for the rest of a request.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Largest request accepted, in bytes.
+const MAX_REQUEST: usize = 64 * 1024;
+
+/// A request to the running instance. Requests and responses are sent as a single line of JSON
+/// each, e.g. `{"command": "switch-workspace", "name": "Home"}`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Capture all windows
+    Capture,
+    /// Restore all windows in the active topology
+    Restore,
+    /// The state of the instance
+    Status,
+    /// The windows tracked in the active topology
+    ListWindows,
+    /// Switch to another workspace, creating it if needed
+    SwitchWorkspace { name: String },
+    /// Developer request: go through a topology transition, as if the displays changed, with
+    /// the location set for this session only
+    SimulateTransition { location: Option<String> },
+    /// Sent by an instance to the one of the other elevation as it starts, switches workspace
+    /// or exits, so they can tell which of them maintains a database both have open. The answer
+    /// is the receiver's own announcement.
+    Announce(Announcement),
+    /// Sent by the instance maintaining a shared database after it changed the topologies in
+    /// it, for the other one to settle the display topology again
+    Settle,
+}
+
+impl Request {
+    /// Whether the request only coordinates two instances. These are the only requests an
+    /// elevated instance takes from unelevated processes.
+    fn is_coordination(&self) -> bool {
+        matches!(self, Self::Announce(_) | Self::Settle)
+    }
+}
+
+/// What an instance tells the instance of the other elevation in the same session about itself.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Announcement {
+    pub elevated: bool,
+    /// The workspace open in the instance, or `None` once it exits
+    pub workspace: Option<String>,
+}
+
+/// The announcements of an instance and of its peer (the instance of the other elevation), as
+/// shared between the UI and pipe threads. Announcements are answered on the pipe thread, so two
+/// instances announcing themselves to each other at once do not wait on each other.
+pub struct Peers {
+    own: Mutex<Announcement>,
+    peer: Mutex<Option<Announcement>>,
+}
+
+impl Peers {
+    pub fn new(own: Announcement) -> Arc<Self> {
+        Arc::new(Self {
+            own: Mutex::new(own),
+            peer: Mutex::new(None),
+        })
+    }
+
+    /// The last announcement of the peer, if it made one.
+    pub fn peer(&self) -> Option<Announcement> {
+        self.peer.lock().unwrap().clone()
+    }
+
+    /// Announce the workspace this instance has open (or `None` as it exits) to its peer, if
+    /// one is running, and learn the peer's in return.
+    pub fn announce(&self, workspace: Option<String>) -> anyhow::Result<()> {
+        let own = {
+            let mut own = self.own.lock().unwrap();
+            own.workspace = workspace;
+            own.clone()
+        };
+
+        let peer = match call_instance(!own.elevated, &Request::Announce(own.clone())) {
+            Ok(answer) => Some(
+                serde_json::from_value::<Announcement>(answer).context("invalid announcement")?,
+            ),
+            // The peer is not running.
+            Err(e) if is_not_running(&e) => None,
+            Err(e) => return Err(e.context("failed to reach the other instance")),
+        };
+        *self.peer.lock().unwrap() = peer.filter(|p| p.elevated != own.elevated);
+
+        Ok(())
+    }
+
+    /// Take the announcement of the peer, and answer with this instance's.
+    fn answer(&self, peer: Announcement) -> Value {
+        let own = self.own.lock().unwrap().clone();
+        *self.peer.lock().unwrap() = Some(peer).filter(|p| p.elevated != own.elevated);
+        serde_json::to_value(own).unwrap_or(Value::Null)
+    }
+}
+
+/// The answer to a request: `{"ok": true, "result": ...}` or `{"ok": false, "error": "..."}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<anyhow::Result<Value>> for Response {
+    fn from(res: anyhow::Result<Value>) -> Self {
+        match res {
+            Ok(result) => Self {
+                ok: true,
+                result: Some(result).filter(|r| !r.is_null()),
+                error: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                result: None,
+                error: Some(format!("{e:#}")),
+            },
+        }
+    }
+}
+
+/// A request handed to the UI thread, and where to send its response.
+pub struct Call {
+    pub request: Request,
+    pub reply: mpsc::Sender<Response>,
+}
+
+/// The session this process runs in.
+fn session() -> anyhow::Result<u32> {
+    let mut session = 0;
+    unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session) }
+        .ok()
+        .context("failed to query session")?;
+
+    Ok(session)
+}
+
+/// The name an instance of the given elevation goes by. Like the instance mutex, it is scoped to
+/// the session, so instances in other sessions (and of the other elevation) have their own.
+///
+/// N.B: This is why the pipe is not simply `\\.\pipe\persistentwin`. A single name would be
+/// taken by whichever instance of any session started first, so the others could not be reached,
+/// and an unelevated process could take the name before an elevated instance and answer its
+/// clients.
+pub fn instance_name(elevated: bool) -> anyhow::Result<String> {
+    let session = session()?;
+    Ok(match elevated {
+        true => format!("persistentwin-{session}-elevated"),
+        false => format!("persistentwin-{session}"),
+    })
+}
+
+/// The pipe an instance of the given elevation listens on.
+pub fn pipe_name(elevated: bool) -> anyhow::Result<String> {
+    Ok(format!("{PIPE_PREFIX}{}", instance_name(elevated)?))
+}
+
+/// Start serving requests on the pipe of an instance of the given elevation. Requests are
+/// handled one at a time: each is handed to the UI thread through `calls`, which is woken up with
+/// `notice`, except announcements, which `peers` answers right away.
+///
+/// Fails if another instance already owns the pipe.
+pub fn serve(
+    elevated: bool,
+    peers: Arc<Peers>,
+    notice: nwg::NoticeSender,
+    calls: mpsc::Sender<Call>,
+) -> anyhow::Result<()> {
+    let pipe_name = pipe_name(elevated)?;
+    let name = widestring::WideCString::from_str_truncate(&pipe_name);
+
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    if elevated {
+        let sddl = widestring::WideCString::from_str_truncate(ELEVATED_PIPE_SDDL);
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                PCWSTR(sddl.as_ptr()),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                None,
+            )
+        }
+        .ok()
+        .context("failed to build pipe security")?;
+    }
+    let attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: descriptor.0,
+        bInheritHandle: false.into(),
+    };
+
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            PCWSTR(name.as_ptr()),
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            4096,
+            4096,
+            0,
+            Some(&attributes),
+        )
+    };
+    if !descriptor.0.is_null() {
+        let _ = unsafe { LocalFree(HLOCAL(descriptor.0 as isize)) };
+    }
+    if pipe == INVALID_HANDLE_VALUE {
+        Err(Error::from_win32()).context("failed to create pipe")?;
+    }
+
+    // N.B: The same pipe instance is reused for every client, so no other process can create
+    // one under the same name in between.
+    let pipe = unsafe { File::from_raw_handle(pipe.0 as _) };
+
+    std::thread::Builder::new()
+        .name("ipc".to_string())
+        .spawn(move || loop {
+            let handle = HANDLE(pipe.as_raw_handle() as isize);
+            if !unsafe { ConnectNamedPipe(handle, None) }.as_bool() {
+                let err = Error::from_win32();
+                if err.code() != ERROR_PIPE_CONNECTED.to_hresult() {
+                    warn!("failed to accept pipe client: {err}");
+                    std::thread::sleep(Duration::from_secs(1));
+                    continue;
+                }
+            }
+
+            if let Err(e) = handle_client(&pipe, elevated, &peers, &notice, &calls) {
+                warn!("{:?}", e.context("failed to serve pipe client"));
+            }
+
+            unsafe { DisconnectNamedPipe(handle) };
+        })
+        .context("failed to start pipe thread")?;
+
+    info!("listening on {pipe_name}");
+    Ok(())
+}
+
+fn handle_client(
+    pipe: &File,
+    elevated: bool,
+    peers: &Peers,
+    notice: &nwg::NoticeSender,
+    calls: &mpsc::Sender<Call>,
+) -> anyhow::Result<()> {
+    let line = read_request(pipe)?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) if elevated && !request.is_coordination() && !client_elevated(pipe)? => {
+            Response::from(Err(anyhow::anyhow!("request needs an elevated client")))
+        }
+        Ok(Request::Announce(peer)) => Response::from(Ok(peers.answer(peer))),
+        Ok(request) => {
+            let (reply, response) = mpsc::channel();
+            calls
+                .send(Call { request, reply })
+                .context("app is shutting down")?;
+            notice.notice();
+
+            response
+                .recv_timeout(REPLY_TIMEOUT)
+                .context("app did not answer in time")?
+        }
+        Err(e) => Response::from(Err(anyhow::anyhow!("invalid request: {e}"))),
+    };
+
+    let mut text = serde_json::to_string(&response).context("failed to encode response")?;
+    text.push('\n');
+
+    let mut pipe = pipe;
+    pipe.write_all(text.as_bytes())
+        .context("failed to write response")?;
+    pipe.flush().context("failed to flush response")?;
+
+    Ok(())
+}
+
+/// Whether the client of the pipe runs elevated, in the same session. Other clients only get to
+/// coordinate with an elevated instance.
+fn client_elevated(pipe: &File) -> anyhow::Result<bool> {
+    let handle = HANDLE(pipe.as_raw_handle() as isize);
+
+    let mut session = 0;
+    unsafe { GetNamedPipeClientSessionId(handle, &mut session) }
+        .ok()
+        .context("failed to query client session")?;
+    if session != self::session()? {
+        return Ok(false);
+    }
+
+    let mut pid = 0;
+    unsafe { GetNamedPipeClientProcessId(handle, &mut pid) }
+        .ok()
+        .context("failed to query client process")?;
+    let client = process::open(PROCESS_QUERY_LIMITED_INFORMATION.0, pid)
+        .context("failed to open client process")?;
+    let elevated = client.is_elevated();
+    unsafe { CloseHandle(client) };
+
+    elevated.context("failed to query client elevation")
+}
+
+/// Read the request line of a client, giving up on clients that stall. The pipe is only read
+/// once data is available, as a blocking read cannot be abandoned.
+fn read_request(pipe: &File) -> anyhow::Result<String> {
+    let handle = HANDLE(pipe.as_raw_handle() as isize);
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut request = Vec::new();
+
+    while !request.contains(&b'\n') {
+        let mut available = 0u32;
+        if !unsafe { PeekNamedPipe(handle, None, 0, None, Some(&mut available), None) }.as_bool() {
+            Err(Error::from_win32()).context("failed to poll pipe")?;
+        }
+
+        if available == 0 {
+            if Instant::now() >= deadline {
+                anyhow::bail!("client did not send a request in time");
+            }
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        let mut buf = vec![0u8; available as usize];
+        let n = (&*pipe).read(&mut buf).context("failed to read request")?;
+        request.extend_from_slice(&buf[..n]);
+        if request.len() > MAX_REQUEST {
+            anyhow::bail!("request is too large");
+        }
+    }
+
+    let end = request
+        .iter()
+        .position(|b| *b == b'\n')
+        .unwrap_or(request.len());
+    request.truncate(end);
+    String::from_utf8(request).context("request is not valid UTF-8")
+}
+
+/// Send a request to the instance running in this session and wait for its response. The
+/// instance of the caller's own elevation is preferred, if both are running.
+pub fn call(request: &Request) -> anyhow::Result<Value> {
+    let elevated = unsafe { GetCurrentProcess() }
+        .is_elevated()
+        .context("failed to query process elevation")?;
+
+    let pipe = connect(elevated).or_else(|_| connect(!elevated))?;
+    exchange(pipe, request)
+}
+
+/// Send a request to the instance of the given elevation running in this session, and wait for
+/// its response.
+pub fn call_instance(elevated: bool, request: &Request) -> anyhow::Result<Value> {
+    exchange(connect(elevated)?, request)
+}
+
+/// Connect to the pipe of the instance of the given elevation. The instance serves one client at
+/// a time, so while it is busy with another, wait for its turn.
+fn connect(elevated: bool) -> anyhow::Result<File> {
+    let pipe_name = pipe_name(elevated)?;
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+
+    loop {
+        let err = match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&pipe_name)
+        {
+            Ok(pipe) => return Ok(pipe),
+            Err(e) => e,
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if err.raw_os_error() != Some(ERROR_PIPE_BUSY.0 as i32) || remaining.is_zero() {
+            return Err(err).context("failed to connect to the app, is it running?");
+        }
+
+        // Fails if the pipe is gone or the wait times out; either way, opening it again tells.
+        let name = widestring::WideCString::from_str_truncate(&pipe_name);
+        unsafe { WaitNamedPipeW(PCWSTR(name.as_ptr()), remaining.as_millis() as u32) };
+    }
+}
+
+/// Whether connecting failed because no instance is listening on the pipe.
+fn is_not_running(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+fn exchange(mut pipe: File, request: &Request) -> anyhow::Result<Value> {
+    let mut text = serde_json::to_string(request).context("failed to encode request")?;
+    text.push('\n');
+    pipe.write_all(text.as_bytes())
+        .context("failed to send request")?;
+
+    let mut line = String::new();
+    BufReader::new(pipe)
+        .read_line(&mut line)
+        .context("failed to read response")?;
+    let response: Response = serde_json::from_str(&line).context("invalid response")?;
+
+    match response.ok {
+        true => Ok(response.result.unwrap_or(Value::Null)),
+        false => Err(anyhow::anyhow!(response
+            .error
+            .unwrap_or_else(|| "request failed".to_string()))),
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{
+    active_workspace, db,
+    store::{LayoutStore, RecordData},
+    workspace_path, Topology, WindowDisplay, WindowKey,
+};
+
+use anyhow::Context;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use std::{collections::HashSet, path::Path};
+
+/// The saved layouts of a workspace, as written by `export`.
+#[derive(Serialize, Deserialize)]
+struct LayoutFile {
+    topologies: Vec<TopologyEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TopologyEntry {
+    #[serde(flatten)]
+    topology: Topology,
+    windows: Vec<WindowEntry>,
+}
+
+/// A window record. Placements are relative to the origin of the topology.
+#[derive(Serialize, Deserialize)]
+struct WindowEntry {
+    exe: Option<String>,
+    class: String,
+    title: String,
+    disp: WindowDisplay,
+    user_disp: Option<WindowDisplay>,
+    trigger: Option<String>,
+    /// Unix time of the last capture
+    updated: u64,
+    desktop: Option<String>,
+}
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut data = Vec::new();
+    bson::to_document(value)
+        .unwrap()
+        .to_writer(&mut data)
+        .unwrap();
+    data
+}
+
+/// Open a workspace (the active one by default) with `open`, one of the `db` openers.
+fn open(
+    workspace: Option<&str>,
+    open: fn(&Path) -> anyhow::Result<Connection>,
+) -> anyhow::Result<(String, Connection)> {
+    let workspace = workspace.map_or_else(active_workspace, |w| w.to_string());
+    let db = open(&workspace_path(&workspace)?)
+        .with_context(|| format!("failed to open workspace \"{workspace}\""))?;
+
+    Ok((workspace, db))
+}
+
+/// Command: write every topology and window record of a workspace (the active one by default)
+/// to a JSON file, to back layouts up or move them to another machine.
+pub fn export(path: &str, workspace: Option<&str>) -> anyhow::Result<()> {
+    let (workspace, db) = open(workspace, db::open_read_only)?;
+
+    let mut topologies = Vec::new();
+    for id in db.topologies()?.into_iter().rev() {
+        let topology =
+            bson::from_slice::<Topology>(&db.topology(id)?).context("failed to decode topology")?;
+
+        let windows = db
+            .records(id)?
+            .into_iter()
+            .map(|record| {
+                Ok(WindowEntry {
+                    exe: record.key.exe,
+                    class: record.key.class,
+                    title: record.key.title,
+                    disp: WindowDisplay::decode(&record.disp)?,
+                    user_disp: record
+                        .user_disp
+                        .map(|d| WindowDisplay::decode(&d))
+                        .transpose()?,
+                    trigger: record.trigger,
+                    updated: record.updated,
+                    desktop: record.desktop,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        topologies.push(TopologyEntry { topology, windows });
+    }
+
+    let text = serde_json::to_string_pretty(&LayoutFile { topologies })
+        .context("failed to serialize layouts")?;
+    std::fs::write(path, text).context("failed to write layout file")?;
+
+    println!("exported the layouts of workspace \"{workspace}\" to {path}");
+    Ok(())
+}
+
+/// Command: load the layouts of a file written by `export` into a workspace (the active one by
+/// default). Records of the same window in the same topology are replaced, and all others are
+/// kept.
+///
+/// N.B: A running instance keeps its own view of the records, so it should be restarted.
+pub fn import(path: &str, workspace: Option<&str>) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(path).context("failed to read layout file")?;
+    let file: LayoutFile = serde_json::from_str(&text).context("failed to parse layout file")?;
+
+    let (workspace, db) = open(workspace, db::open_shared)?;
+    let tx = db.begin()?;
+    let imported = merge(&db, &file, Merge::Replace)?;
+    tx.commit()?;
+
+    println!(
+        "imported {imported} windows in {} topologies into workspace \"{workspace}\"",
+        file.topologies.len()
+    );
+    Ok(())
+}
+
+/// Load the layouts of a bundle shipped alongside the app (in the format written by `export`)
+/// into a layout store. The user's own records always win: only windows without a record of
+/// their own in a topology are added. Returns the number of windows added.
+pub fn seed(store: &dyn LayoutStore, data: &[u8]) -> anyhow::Result<usize> {
+    let file: LayoutFile = serde_json::from_slice(data).context("failed to parse layout bundle")?;
+
+    let tx = store.begin()?;
+    let seeded = merge(store, &file, Merge::Under)?;
+    tx.commit()?;
+
+    Ok(seeded)
+}
+
+/// How records from a layout file are merged with the records already in a topology.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Merge {
+    /// Replace the records of the same window
+    Replace,
+    /// Keep the records of the same window, and skip the one from the file
+    Under,
+}
+
+/// Add the records of a layout file to a store, returning the number added.
+///
+/// N.B: The caller is expected to hold a transaction on the store.
+fn merge(store: &dyn LayoutStore, file: &LayoutFile, mode: Merge) -> anyhow::Result<usize> {
+    // Several windows may share a key, so only replace the records that were there before.
+    let mut merged = HashSet::new();
+    for entry in &file.topologies {
+        let topology = store.register_topology(&encode(&entry.topology))?;
+
+        for window in &entry.windows {
+            window.disp.validate()?;
+            if let Some(disp) = &window.user_disp {
+                disp.validate()?;
+            }
+
+            let key = WindowKey {
+                exe: window.exe.clone(),
+                class: window.class.clone(),
+                title: window.title.clone(),
+            };
+
+            let existing = store
+                .candidates(topology, key.exe.as_deref(), &key.class)?
+                .into_iter()
+                .filter(|(id, title)| *title == key.title && !merged.contains(id))
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            match mode {
+                Merge::Replace => {
+                    for id in existing {
+                        store.delete_record(id)?;
+                    }
+                }
+                Merge::Under if !existing.is_empty() => continue,
+                Merge::Under => {}
+            }
+
+            let disp = encode(&window.disp);
+            let user_disp = window.user_disp.as_ref().map(encode);
+            let id = store.insert_record(
+                topology,
+                &RecordData {
+                    key: &key,
+                    disp: &disp,
+                    user_disp: user_disp.as_deref(),
+                    trigger: match mode {
+                        Merge::Replace => window.trigger.as_deref().unwrap_or("import"),
+                        Merge::Under => "bundle",
+                    },
+                    updated: window.updated,
+                    desktop: window.desktop.as_deref(),
+                    tag: None,
+                },
+            )?;
+            merged.insert(id);
+        }
+    }
+
+    Ok(merged.len())
+}
@@ -0,0 +1,6 @@
+//! Window matching and restore planning, which work without a desktop. The app builds on these,
+//! and the benchmarks measure them against synthetic records.
+
+pub mod geometry;
+pub mod matching;
+pub mod plan;
@@ -1,45 +1,110 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{mpsc, Arc},
+    time::Duration,
+};
 
 use anyhow::Context;
 use log::{error, info, warn};
-use mutex::GlobalMutex;
+use mutex::{Acquired, GlobalMutex};
 use nwd::NwgUi;
 use nwg::{NativeUi, TrayNotificationFlags};
-use rusqlite::{named_params, Connection, OptionalExtension};
+use persistentwin::{
+    geometry::{Point, Rect},
+    matching::{self, MatchTier},
+    plan,
+};
+use rusqlite::{named_params, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use store::LayoutStore;
 use widestring::widecstr;
 use windows::{
     core::PCWSTR,
     Win32::{
-        Foundation::{ERROR_ALREADY_EXISTS, HWND, LPARAM, LRESULT, WPARAM},
-        System::Threading::{GetExitCodeProcess, WaitForSingleObject},
+        Foundation::{
+            CloseHandle, ERROR_ALREADY_EXISTS, FILETIME, HANDLE, HWND, LPARAM, LRESULT, POINT,
+            SYSTEMTIME, WPARAM,
+        },
+        Graphics::Gdi::HMONITOR,
+        System::Com::{CoInitializeEx, COINIT_APARTMENTTHREADED},
+        System::Power::POWERBROADCAST_SETTING,
+        System::SystemInformation::GetTickCount,
+        System::SystemServices::GUID_ACDC_POWER_SOURCE,
+        System::Threading::{
+            GetCurrentProcess, GetExitCodeProcess, OpenProcess, TerminateProcess,
+            WaitForSingleObject, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION,
+            PROCESS_TERMINATE, PROCESS_VM_READ,
+        },
+        System::Time::{FileTimeToSystemTime, SystemTimeToTzSpecificLocalTime},
         UI::{
+            Input::KeyboardAndMouse::{
+                GetLastInputInfo, LASTINPUTINFO, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT,
+            },
             Shell::ShellExecuteExW,
             WindowsAndMessaging::{
-                EVENT_OBJECT_NAMECHANGE, EVENT_SYSTEM_MINIMIZEEND, EVENT_SYSTEM_MOVESIZESTART,
-                SHOW_WINDOW_CMD, SW_MAXIMIZE, SW_SHOWNORMAL, WINDOWPLACEMENT, WM_DISPLAYCHANGE,
-                WM_WTSSESSION_CHANGE, WPF_ASYNCWINDOWPLACEMENT,
+                FindWindowW, GetSystemMetrics, SendMessageTimeoutW, EVENT_OBJECT_CREATE,
+                EVENT_OBJECT_DESTROY, EVENT_OBJECT_NAMECHANGE, EVENT_OBJECT_SHOW,
+                EVENT_SYSTEM_FOREGROUND, EVENT_SYSTEM_MINIMIZEEND, EVENT_SYSTEM_MINIMIZESTART,
+                EVENT_SYSTEM_MOVESIZEEND, EVENT_SYSTEM_MOVESIZESTART, PBT_APMRESUMEAUTOMATIC,
+                PBT_APMSUSPEND, PBT_POWERSETTINGCHANGE, SHOW_WINDOW_CMD, SMTO_ABORTIFHUNG,
+                SM_CONVERTIBLESLATEMODE, SPI_SETWORKAREA, SW_MAX, SW_MAXIMIZE, SW_SHOWNOACTIVATE,
+                SW_SHOWNORMAL, USER_DEFAULT_SCREEN_DPI, WINDOWPLACEMENT, WM_DISPLAYCHANGE,
+                WM_DWMCOMPOSITIONCHANGED, WM_HOTKEY, WM_NULL, WM_POWERBROADCAST, WM_SETTINGCHANGE,
+                WM_WTSSESSION_CHANGE, WPF_ASYNCWINDOWPLACEMENT, WPF_RESTORETOMAXIMIZED,
+                WPF_SETMINPOSITION, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WTS_CONSOLE_CONNECT,
+                WTS_CONSOLE_DISCONNECT, WTS_REMOTE_CONNECT, WTS_REMOTE_DISCONNECT,
+                WTS_SESSION_LOCK, WTS_SESSION_UNLOCK,
             },
         },
     },
 };
 
+mod bench;
+mod buffer;
+mod bundle;
+mod cli;
+mod config;
+mod db;
+mod dialog;
 mod hook;
+mod ipc;
+mod layout;
 mod monitor;
+mod msgwindow;
 mod mutex;
+mod picker;
 mod process;
+mod quirks;
+mod rules;
+mod scaling;
+mod shell;
+mod simulate;
+mod soak;
+mod store;
+mod timers;
+mod vdesktop;
 mod window;
 
-use hook::EventHook;
-use monitor::{HMonitorExt, MonitorDpi};
+use dialog::Dialog;
+use hook::{CallbackFn, EventHandle, EventHook};
+use monitor::{HMonitorExt, MonitorDpi, MonitorDpiType};
+use msgwindow::MessageWindow;
+use process::ProcessExt;
+use timers::Stamp;
 use window::HwndExt;
 use winreg::enums::HKEY_CURRENT_USER;
 
 const HKCU: winreg::RegKey = winreg::RegKey::predef(HKEY_CURRENT_USER);
 const STARTUP_KEY: &str = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run";
 const STARTUP_NAME: &str = "PersistentWindows";
+const SETTINGS_KEY: &str = "SOFTWARE\\PersistentWindows";
+const DEFAULT_WORKSPACE: &str = "Default";
+const INSTANCE_GUID: &str = "{D1905271-98BC-4888-BC9D-B05810AA21CB}";
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 struct Monitor {
@@ -47,95 +112,717 @@ struct Monitor {
     dpi: MonitorDpi,
 }
 
+/// A display topology, identified by the geometry, identity, scale and rotation of its monitors.
+///
+/// Two monitors with the same resolution swapped between ports (or a different monitor docked in
+/// the same spot) produce the same rectangles, so the EDID identity of each monitor is part of
+/// the key. Likewise, changing the scale factor of a monitor changes the logical size of
+/// everything on it, so it is treated as a different topology.
+///
+/// Topologies captured before these were recorded are adopted by the first matching topology
+/// seen with them.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 struct Topology {
+    /// Monitor rectangles relative to the virtual screen origin, sorted by position
     monitors: Vec<Rect>,
+    /// Identities of the monitors, in the same order, if they could be queried
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ids: Vec<String>,
+    /// Effective DPI of the monitors, in the same order, if it could be queried
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dpis: Vec<u32>,
+    /// Clockwise rotation of the monitors in degrees, in the same order, if it could be queried
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rotations: Vec<u32>,
+    /// The location the user declared they are at, to tell apart identical setups at several
+    /// desks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct WindowDisplay {
     show: u32,
-    min: Point,
-    max: Point,
+    /// Position of the window while minimized, if explicitly set
+    min: Option<Point>,
+    /// Position of the window while maximized, if explicitly set
+    max: Option<Point>,
     rect: Rect,
+    /// Whether a minimized window is restored to the maximized state
+    #[serde(default)]
+    restore_maximized: bool,
+    /// Whether the window is always on top, if known. This is not part of the placement, so it
+    /// is only set when capturing (and missing from records captured before it was).
+    #[serde(default)]
+    topmost: Option<bool>,
+    /// Effective DPI of the monitor holding the window when it was captured, if known
+    #[serde(default)]
+    dpi: Option<u32>,
+    /// Where `rect` was read from. Records captured before this was recorded read it from the
+    /// placement.
+    #[serde(default)]
+    source: CaptureSource,
+}
+
+/// Where the normal rectangle of a window is read from when capturing it, and so how it is
+/// applied when restoring it.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+enum CaptureSource {
+    /// The normal position of the window placement, applied with `SetWindowPlacement`
+    #[default]
+    Placement,
+    /// The window rectangle, applied with `SetWindowPos`, for applications whose placement does
+    /// not match where the window is. Only read while the window is in its normal state.
+    WindowRect,
+}
+
+impl WindowDisplay {
+    /// Largest coordinate magnitude accepted in a record. Window coordinates are limited to
+    /// 16-bit values by the system.
+    const MAX_COORD: i32 = i16::MAX as i32;
+
+    /// Decode a persisted record, rejecting anything that could not have been captured from a
+    /// real window.
+    fn decode(disp: &[u8]) -> anyhow::Result<Self> {
+        let disp: Self = bson::from_reader(disp).context("failed to decode record")?;
+        disp.validate()?;
+
+        Ok(disp)
+    }
+
+    /// Translate the placement by the given offset.
+    fn offset(mut self, dx: i32, dy: i32) -> Self {
+        self.rect = self.rect.offset(dx, dy);
+        self.min = self.min.map(|p| p.offset(dx, dy));
+        self.max = self.max.map(|p| p.offset(dx, dy));
+        self
+    }
+
+    /// Map the placement from one monitor to the same relative position on another.
+    fn rescale(mut self, from: &Rect, to: &Rect) -> Self {
+        self.rect = self.rect.rescale(from, to);
+        self.min = self.min.map(|p| p.rescale(from, to));
+        self.max = self.max.map(|p| p.rescale(from, to));
+        self
+    }
+
+    /// Scale the placement on `monitor` from the DPI it was captured at to `dpi`, so the window
+    /// keeps its logical size and position after the scale factor of the monitor changed.
+    fn scale_dpi(mut self, monitor: &Rect, dpi: u32) -> Self {
+        let Some(from) = self.dpi.filter(|from| *from != dpi && *from != 0) else {
+            return self;
+        };
+
+        let origin = Point {
+            x: monitor.left,
+            y: monitor.top,
+        };
+        self.rect = self.rect.scale_dpi(monitor, from, dpi);
+        self.min = self.min.map(|p| p.scale_dpi(&origin, from, dpi));
+        self.max = self.max.map(|p| p.scale_dpi(&origin, from, dpi));
+        self.dpi = Some(dpi);
+        self
+    }
+
+    /// Convert the record back into a placement that can be applied to a window.
+    fn placement(&self) -> WINDOWPLACEMENT {
+        // (-1, -1) lets the system pick the minimized/maximized position.
+        let unset = Point { x: -1, y: -1 };
+
+        let mut flags = WPF_ASYNCWINDOWPLACEMENT;
+        if self.min.is_some() {
+            flags |= WPF_SETMINPOSITION;
+        }
+        if self.restore_maximized {
+            flags |= WPF_RESTORETOMAXIMIZED;
+        }
+
+        WINDOWPLACEMENT {
+            length: core::mem::size_of::<WINDOWPLACEMENT>() as u32,
+            flags,
+            showCmd: SHOW_WINDOW_CMD(self.show),
+            ptMinPosition: self.min.clone().unwrap_or(unset.clone()).into(),
+            ptMaxPosition: self.max.clone().unwrap_or(unset).into(),
+            rcNormalPosition: self.rect.clone().into(),
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.show > SW_MAX.0 {
+            anyhow::bail!("unknown show command {}", self.show);
+        }
+
+        let r = &self.rect;
+        if r.right <= r.left || r.bottom <= r.top {
+            anyhow::bail!("empty or inverted rect {r:?}");
+        }
+
+        let mut coords = [r.left, r.top, r.right, r.bottom].into_iter().chain(
+            self.min
+                .iter()
+                .chain(self.max.iter())
+                .flat_map(|p| [p.x, p.y]),
+        );
+        if coords.any(|c| c.unsigned_abs() > Self::MAX_COORD as u32) {
+            anyhow::bail!("coordinates out of range {self:?}");
+        }
+
+        Ok(())
+    }
 }
 
 impl From<WINDOWPLACEMENT> for WindowDisplay {
     fn from(wp: WINDOWPLACEMENT) -> Self {
+        // The minimized and maximized positions are frequently garbage (or sentinel values such
+        // as (-1, -1) and (-32000, -32000)) unless the application explicitly set them, so only
+        // keep them if they look like real coordinates.
+        let min = Some(Point::from(wp.ptMinPosition)).filter(Point::is_set);
+        let max = Some(Point::from(wp.ptMaxPosition)).filter(Point::is_set);
+
         Self {
             show: wp.showCmd.0,
-            min: wp.ptMinPosition.into(),
-            max: wp.ptMaxPosition.into(),
+            min,
+            max,
             rect: wp.rcNormalPosition.into(),
+            restore_maximized: (wp.flags & WPF_RESTORETOMAXIMIZED).0 != 0,
+            topmost: None,
+            dpi: None,
+            source: CaptureSource::Placement,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
-pub struct Point {
-    pub x: i32,
-    pub y: i32,
+/// Number of windows captured per tick of a capture pass.
+const CAPTURE_CHUNK: usize = 16;
+
+/// Maximum number of threads querying processes ahead of a capture pass.
+const CAPTURE_THREADS: usize = 4;
+
+/// Shortest interval between enforcing the layout in kiosk mode, which is also how quickly
+/// windows are put back when enforcing continuously.
+const KIOSK_MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Time to wait after the session is unlocked before resuming capture, so monitors that were
+/// detached while locked have reattached.
+const UNLOCK_SETTLE_DELAY: Duration = Duration::from_secs(3);
+
+/// How long the work area must stay unchanged before windows are fitted to it. Toggling taskbar
+/// auto-hide or docking an appbar changes it several times in quick succession.
+const WORK_AREA_SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// How far (in pixels) the edges of a maximized window may stray from the work area before it
+/// is considered to no longer fit it. Maximized windows overhang by their resize borders.
+const WORK_AREA_TOLERANCE: i32 = 32;
+
+/// How long the display topology must stay unchanged after a display change before it is made
+/// active and windows are restored, so flapping connections (a bad cable, a dock negotiating)
+/// do not thrash between layouts. This is the default for `display_settle_ms`.
+const DISPLAY_SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// Interval between placement reads when waiting for a window to stop moving before capturing
+/// it. Maximize, minimize and snap animations take a few hundred milliseconds, during which
+/// intermediate placements can be read.
+const STABLE_READ_INTERVAL: Duration = Duration::from_millis(150);
+
+/// Number of placement reads after which a window that keeps moving is captured anyway.
+const STABLE_READ_LIMIT: u32 = 10;
+
+/// How long after a restore to check that the window actually moved. Placements are applied
+/// asynchronously, so the window's own thread needs a moment to act on them.
+const PLACEMENT_VERIFY_DELAY: Duration = Duration::from_millis(500);
+
+/// Number of placement history entries kept per record.
+const HISTORY_LIMIT: usize = 100;
+
+/// Number of records of closed windows kept per application and class in a topology.
+/// Applications opening many windows with distinct titles (e.g. one per document) would
+/// otherwise grow the database without bound over long sessions.
+const CLOSED_RECORD_LIMIT: usize = 10;
+
+/// How often records past the retention limits are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Fraction of the database that must be free pages before it is compacted after pruning.
+const VACUUM_FREE_RATIO: f64 = 0.25;
+
+/// Number of new windows of an application observed before telling whether it places its
+/// windows itself.
+const SELF_RESTORE_MIN_SHOWS: u32 = 3;
+
+/// How long after a window is destroyed a new window with the same key is treated as its
+/// replacement.
+const RECREATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How long a window must have existed before it is persisted, so short-lived windows such as
+/// splash screens never make it into the database.
+const MIN_WINDOW_AGE: Duration = Duration::from_secs(3);
+
+/// Classes of transient windows that are never persisted (tooltips, menus, IME candidates and
+/// drag images).
+const TRANSIENT_CLASSES: &[&str] = &[
+    "tooltips_class32",
+    "#32768",
+    "IME",
+    "MSCTFIME UI",
+    "MSCTFIME Composition",
+    "SysDragImage",
+];
+
+/// Classes of the taskbar and its overflow windows.
+const SHELL_CLASSES: &[&str] = &[
+    "Shell_TrayWnd",
+    "Shell_SecondaryTrayWnd",
+    "NotifyIconOverflowWindow",
+    "TopLevelWindowForOverflowXamlIsland",
+];
+
+/// Identifier of the hotkey toggling whether the foreground application is tracked.
+const HOTKEY_TOGGLE_TRACKING: i32 = 1;
+
+/// Default delay between restoring each batch of windows.
+const DEFAULT_RESTORE_DELAY: Duration = Duration::from_millis(50);
+
+/// How long after startup to report the applications from the previous session that have not
+/// come back. Applications started at login can take a while to show their windows.
+const STARTUP_REPORT_DELAY: Duration = Duration::from_secs(60);
+
+/// Applications with windows captured within this long before the end of the previous session
+/// are expected to come back after startup.
+const EXPECTED_APP_AGE: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How often the applications launched by a session restore are checked for windows.
+const SESSION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many consecutive polls an application launched by a session restore must have shown a
+/// window for before its windows are restored, so it has finished opening them.
+const SESSION_READY_POLLS: u32 = 2;
+
+/// How long an application launched by a session restore has to show a window.
+const RELAUNCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the event hooks are checked for signs of life.
+const HOOK_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the hooks may go without delivering any event while the user is active before they
+/// are presumed dead. Showing and hiding windows (tooltips, menus) alone generates a steady
+/// stream of events on an active desktop, so a silence this long means nothing is arriving.
+const HOOK_STALL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Events hooked while tracking normally.
+const HOOK_RANGES: &[(u32, u32)] = &[
+    (EVENT_SYSTEM_MOVESIZESTART, EVENT_SYSTEM_MINIMIZEEND),
+    (EVENT_OBJECT_CREATE, EVENT_OBJECT_SHOW),
+    (EVENT_OBJECT_NAMECHANGE, EVENT_OBJECT_NAMECHANGE),
+    (EVENT_SYSTEM_FOREGROUND, EVENT_SYSTEM_FOREGROUND),
+];
+
+/// Events hooked while a fullscreen window is in the foreground. Only window lifetimes and the
+/// end of fullscreen are followed, sparing games and presentations from a flood of callbacks.
+const FULLSCREEN_HOOK_RANGES: &[(u32, u32)] = &[
+    (EVENT_OBJECT_CREATE, EVENT_OBJECT_SHOW),
+    (EVENT_SYSTEM_FOREGROUND, EVENT_SYSTEM_FOREGROUND),
+];
+
+/// Class name prefixes of windows projected by RDP clients.
+const REMOTE_CLASSES: &[&str] = &["RAIL_WINDOW"];
+
+/// Image names (as normalized by `window_image_name`) of processes hosting RemoteApp windows on
+/// the server side of a session.
+const REMOTE_IMAGES: &[&str] = &["rdpshell.exe", "rdpinit.exe"];
+
+/// The identity of a window, used to match it against saved records across sessions.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct WindowKey {
+    /// Normalized image path of the owning process (or the identity of a UWP app, see
+    /// `window_app_name`), if it could be queried
+    exe: Option<String>,
+    class: String,
+    title: String,
 }
 
-impl From<windows::Win32::Foundation::POINT> for Point {
-    fn from(r: windows::Win32::Foundation::POINT) -> Self {
-        Self { x: r.x, y: r.y }
+impl WindowKey {
+    fn from_window(hwnd: HWND) -> anyhow::Result<Self> {
+        let class = hwnd.class_name().context("failed to query class name")?;
+
+        // All Explorer windows share an image and class, and the title is only the folder name,
+        // which collides for same-named folders. Key them by the full location instead.
+        let location = match class.as_str() {
+            shell::EXPLORER_CLASS => shell::explorer_location(hwnd).unwrap_or_else(|e| {
+                warn!("failed to query explorer location: {e}");
+                None
+            }),
+            _ => None,
+        };
+
+        // Not all processes can be opened (e.g. protected processes), so the path is optional.
+        let exe = window_app_name(hwnd).ok();
+        let title = match location {
+            Some(location) => location,
+            None => {
+                let title = hwnd.title().context("failed to query title")?;
+                config::with(|c| c.rewrite_title(exe.as_deref(), &class, &title))
+            }
+        };
+
+        Ok(Self { exe, class, title })
+    }
+
+    /// Whether this is a window projected from a remote session (RemoteApp, or RDP in seamless
+    /// mode, as used by WSLg). Its placement is controlled by the remote side, so it is neither
+    /// captured nor restored locally.
+    fn is_remote(&self) -> bool {
+        let image = self
+            .exe
+            .as_deref()
+            .and_then(|exe| exe.rsplit('\\').next())
+            .unwrap_or_default();
+
+        REMOTE_CLASSES.iter().any(|c| self.class.starts_with(c)) || REMOTE_IMAGES.contains(&image)
     }
 }
 
-impl Into<windows::Win32::Foundation::POINT> for Point {
-    fn into(self) -> windows::Win32::Foundation::POINT {
-        windows::Win32::Foundation::POINT {
-            x: self.x,
-            y: self.y,
+/// Which windows a restore pass applies to, and where their placements come from.
+#[derive(Default)]
+struct RestoreScope {
+    /// Only restore windows whose placement is on this monitor
+    monitor: Option<HMONITOR>,
+    /// Restore placements from this checkpoint rather than the live records
+    checkpoint: Option<i64>,
+    /// Restore placements as the records were captured at this Unix time, from their history
+    as_of: Option<u64>,
+    /// Only restore windows that are off-screen or on a different monitor than saved
+    misplaced_only: bool,
+    /// Only restore windows of these applications, by normalized image path
+    apps: Option<HashSet<String>>,
+    /// Skip windows that are already at their saved placement
+    moved_only: bool,
+    /// Only restore this window
+    window: Option<HWND>,
+}
+
+/// How a planned restore is applied to its window.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RestoreStrategy {
+    /// Moved together with the other windows of its batch (`DeferWindowPos`), for windows in
+    /// their normal state that are restored to it
+    Deferred,
+    /// Given a full placement (`SetWindowPlacement`)
+    Placement,
+    /// Given a full placement while brought to the foreground, per the application's rule
+    Foreground,
+}
+
+/// A restore of one window, planned but not yet applied.
+#[derive(Clone)]
+struct PlannedRestore {
+    hwnd: HWND,
+    /// Where the window was when the plan was made, in workspace coordinates
+    current: Rect,
+    /// The placement the window is restored to
+    target: WindowDisplay,
+    /// The virtual desktop the window was last captured on, if known
+    desktop: Option<windows::core::GUID>,
+    strategy: RestoreStrategy,
+}
+
+/// The restores of a restore pass, as pure data. Planning reads the records and the state of the
+/// windows without touching anything, so a plan can be inspected before (or instead of) being
+/// executed.
+struct RestorePlan {
+    /// What triggered the pass
+    trigger: &'static str,
+    /// Restores batched by the monitor they restore to, so each monitor is settled in one go
+    batches: Vec<Vec<PlannedRestore>>,
+}
+
+/// Where the app is in a display topology transition. Transitions are serialized: a display
+/// change cancels the restore in flight, waits for the topology to settle, and only then plans
+/// restores against the final topology. Nothing is captured until the transition is over, so
+/// captures never interleave with restores of the same windows.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+enum Transition {
+    #[default]
+    Idle,
+    /// The display topology is changing and has yet to settle
+    Settling,
+    /// A restore plan is being executed
+    Restoring,
+}
+
+/// How windows are restored automatically after a display change, chosen per topology.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum RestorePolicy {
+    /// Restore every window
+    #[default]
+    Full,
+    /// Only restore windows that are badly out of place
+    MissingOnly,
+    /// Never restore automatically, only from the tray
+    Manual,
+}
+
+impl RestorePolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RestorePolicy::Full => "full",
+            RestorePolicy::MissingOnly => "missing",
+            RestorePolicy::Manual => "manual",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "full" => Some(RestorePolicy::Full),
+            "missing" => Some(RestorePolicy::MissingOnly),
+            "manual" => Some(RestorePolicy::Manual),
+            _ => None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
-pub struct Rect {
-    pub left: i32,
-    pub top: i32,
-    pub right: i32,
-    pub bottom: i32,
+/// A saved topology, as listed in the layout copy dialog.
+#[derive(Default)]
+struct TopologyItem {
+    id: usize,
+    label: String,
 }
 
-impl Rect {
-    pub fn width(&self) -> u32 {
-        (self.right - self.left).abs() as u32
+impl std::fmt::Display for TopologyItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.id, self.label)
     }
+}
 
-    pub fn height(&self) -> u32 {
-        (self.bottom - self.top).abs() as u32
-    }
+/// Per-application behaviors, keyed by the normalized image path.
+#[derive(Clone, Copy)]
+enum RuleAction {
+    /// Bring windows to the foreground while restoring them, for applications that ignore
+    /// placement changes while in the background
+    Foreground,
+    /// Neither capture nor restore windows
+    Exclude,
+    /// Only match windows to records with exactly the same title, for applications whose
+    /// windows differ only slightly in title
+    ExactMatch,
+    /// Capture the window rectangle rather than the placement, for applications whose
+    /// placement does not match where their windows are
+    WindowRect,
+    /// Place new windows even if the application seems to put them back where they were on
+    /// its own
+    AlwaysRestore,
 }
 
-impl From<windows::Win32::Foundation::RECT> for Rect {
-    fn from(r: windows::Win32::Foundation::RECT) -> Self {
-        Self {
-            top: r.top,
-            bottom: r.bottom,
-            right: r.right,
-            left: r.left,
+impl RuleAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RuleAction::Foreground => "foreground",
+            RuleAction::Exclude => "exclude",
+            RuleAction::ExactMatch => "exact-match",
+            RuleAction::WindowRect => "window-rect",
+            RuleAction::AlwaysRestore => "always-restore",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "foreground" => Some(RuleAction::Foreground),
+            "exclude" => Some(RuleAction::Exclude),
+            "exact-match" => Some(RuleAction::ExactMatch),
+            "window-rect" => Some(RuleAction::WindowRect),
+            "always-restore" => Some(RuleAction::AlwaysRestore),
+            _ => None,
         }
     }
 }
 
-impl Into<windows::Win32::Foundation::RECT> for Rect {
-    fn into(self) -> windows::Win32::Foundation::RECT {
-        windows::Win32::Foundation::RECT {
-            top: self.top,
-            bottom: self.bottom,
-            right: self.right,
-            left: self.left,
+/// Rules and preferences without any layout data, as exported for sharing between machines or
+/// keeping in a dotfiles repository.
+#[derive(Serialize, Deserialize, Default)]
+struct SettingsFile {
+    #[serde(default)]
+    settings: Preferences,
+    #[serde(default)]
+    rules: Vec<RuleEntry>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Preferences {
+    capture_scope: Option<String>,
+    max_record_age_days: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RuleEntry {
+    exe: String,
+    action: String,
+}
+
+/// Which windows are captured automatically.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum CaptureScope {
+    /// All windows
+    #[default]
+    Everything,
+    /// Only windows on the monitor the user is working on
+    Monitor,
+    /// Only windows on the current virtual desktop
+    Desktop,
+}
+
+impl CaptureScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CaptureScope::Everything => "everything",
+            CaptureScope::Monitor => "monitor",
+            CaptureScope::Desktop => "desktop",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "everything" => Some(CaptureScope::Everything),
+            "monitor" => Some(CaptureScope::Monitor),
+            "desktop" => Some(CaptureScope::Desktop),
+            _ => None,
         }
     }
 }
 
+/// What the user is picking a point on the screen for.
+#[derive(Clone, Copy)]
+enum PickAction {
+    /// Inspect or forget the window under the point
+    Window,
+    /// Restore only the windows belonging to the monitor under the point
+    RestoreMonitor,
+}
+
 #[derive(Default)]
 pub struct AppData {
     /// The current display topology index
     active_topology: Option<usize>,
+    /// The top-left corner of the virtual screen in the current topology. Topologies and
+    /// window placements are stored relative to this point.
+    origin: Point,
+    /// Records claimed by windows this session, keyed by window handle and topology
+    records: HashMap<(isize, usize), i64>,
+    /// Records passed over this session for one a window was stamped with, though they carry
+    /// the title of that window, for `merge_duplicate_windows` to remove
+    superseded: HashSet<i64>,
+    /// Windows still to be captured in the current capture pass
+    capture_queue: VecDeque<HWND>,
+    /// Number of windows in the current capture pass
+    capture_total: usize,
+    /// Pending restores, batched by the monitor they are restored to
+    restore_queue: VecDeque<Vec<PlannedRestore>>,
+    /// Delay between restoring each batch of windows
+    restore_delay: Duration,
+    /// What triggered the pending restores
+    restore_trigger: &'static str,
+    /// Whether captures are suspended because the session is locked or disconnected (or just
+    /// unlocked or reconnected)
+    frozen: bool,
+    /// Whether the session is locked
+    locked: bool,
+    /// What resumed the session, once `unlock_timer` fires
+    resume_trigger: &'static str,
+    /// Whether the system runs on battery, once reported
+    on_battery: Option<bool>,
+    /// What started the display transition in progress, once `display_timer` fires
+    settle_trigger: &'static str,
+    /// A newly seen topology and the known one it may be, with its monitors under other
+    /// identities, until the user is asked whether to merge them
+    merge_offer: Option<(usize, usize)>,
+    /// The display topology transition in progress, if any
+    transition: Transition,
+    /// Whether window animations were turned off for the restore in progress, and need to be
+    /// turned back on when it is done
+    animations_suppressed: bool,
+    /// The checkpoint shown in the application selection dialog
+    apps_checkpoint: Option<String>,
+    /// Where the tray icon was last clicked, in screen coordinates
+    tray_point: Option<POINT>,
+    /// Recently destroyed windows whose state may be carried over to a recreated window
+    destroyed: Vec<DestroyedWindow>,
+    /// The window the user was last working with, other than the taskbar
+    last_foreground: Option<HWND>,
+    /// The key and claimed records of the window shown in the pick dialog
+    picked_window: Option<(WindowKey, Vec<i64>)>,
+    /// When windows shown this session appeared
+    shown: HashMap<isize, Stamp>,
+    /// Top-level windows created since the hooks were installed that have not been shown yet
+    created: HashSet<isize>,
+    /// New windows placed since the last retry check, to tell whether their application moves
+    /// them away again on its own
+    placed_new: HashSet<isize>,
+    /// Captures deferred until the window has existed for `MIN_WINDOW_AGE`, with their trigger
+    deferred_captures: HashMap<isize, CaptureTrigger>,
+    /// Captures waiting for the window's placement to stop changing, with their trigger, the
+    /// last placement read and the number of reads so far
+    unstable_captures: HashMap<isize, (CaptureTrigger, WindowDisplay, u32)>,
+    /// Restored windows waiting to be checked for having moved, with their normal rect before
+    /// the restore and the rect they were restored to
+    unverified_restores: HashMap<isize, (Rect, Rect)>,
+    /// Windows restored by the last restore pass, with their restored placement, checked again
+    /// by `retry_timer` in case the application moved them back
+    restored: HashMap<isize, WindowDisplay>,
+    /// The number of retry checks done since the last restore pass
+    retry_pass: usize,
+    /// The name of the workspace whose database is open
+    workspace: String,
+    /// The directory workspace databases are stored in, as configured at startup. A change to
+    /// the setting takes effect once the app is restarted.
+    workspace_dir: PathBuf,
+    /// The location the user declared they are at, if any
+    location: Option<String>,
+    /// Unix time the app started at
+    started: u64,
+    /// Whether tracking is paused from the tray, with all event hooks removed
+    paused: bool,
+    /// Whether a fullscreen window is in the foreground
+    fullscreen: bool,
+    /// Which windows are captured automatically
+    capture_scope: CaptureScope,
+    /// Saved placements last captured longer ago than this many days are not restored
+    max_record_age: Option<u64>,
+    /// Processes whose command line was recorded this session
+    launches_seen: HashSet<u32>,
+    /// Applications launched by a session restore whose windows are still to be restored, with
+    /// when they were launched and the number of polls they have shown a window for
+    relaunched: HashMap<String, (Stamp, u32)>,
+}
+
+/// How an application was last started.
+struct Launch {
+    /// Normalized image path
+    exe: String,
+    command_line: String,
+    directory: Option<String>,
+}
+
+impl Launch {
+    /// Start the application again, with the arguments and working directory it had.
+    fn spawn(&self) -> std::io::Result<()> {
+        use std::os::windows::process::CommandExt;
+
+        let mut command = std::process::Command::new(&self.exe);
+        command.raw_arg(command_args(&self.command_line));
+        if let Some(dir) = self.directory.as_deref().filter(|d| Path::new(d).is_dir()) {
+            command.current_dir(dir);
+        }
+
+        command.spawn().map(|_| ())
+    }
+}
+
+/// A destroyed window that was tracking records this session.
+struct DestroyedWindow {
+    time: Stamp,
+    hwnd: HWND,
+    key: WindowKey,
+    /// Records claimed by the window, by topology
+    records: Vec<(usize, i64)>,
 }
 
 #[derive(NwgUi)]
@@ -144,6 +831,74 @@ pub struct App {
     #[nwg_events( OnInit: [App::on_init] )]
     window: nwg::Window,
 
+    #[nwg_control(parent: window, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_restore_tick])]
+    restore_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: Duration::from_millis(1), active: false)]
+    #[nwg_events(OnTimerTick: [App::on_capture_tick])]
+    capture_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: Duration::from_secs(1), active: false)]
+    #[nwg_events(OnTimerTick: [App::on_deferred_capture_tick])]
+    deferred_capture_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: HOOK_CHECK_INTERVAL, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_hook_check])]
+    hook_check_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: STARTUP_REPORT_DELAY, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_startup_report])]
+    startup_report_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: DISPLAY_SETTLE_DELAY, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_display_settled])]
+    display_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: STABLE_READ_INTERVAL, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_stable_capture_tick])]
+    stable_capture_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: WORK_AREA_SETTLE_DELAY, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_work_area_settled])]
+    work_area_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: PLACEMENT_VERIFY_DELAY, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_verify_restores])]
+    verify_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_retry_tick])]
+    retry_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: PRUNE_INTERVAL, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_prune_tick])]
+    prune_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: SESSION_POLL_INTERVAL, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_session_tick])]
+    session_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: KIOSK_MIN_INTERVAL, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_kiosk_tick])]
+    kiosk_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window, interval: UNLOCK_SETTLE_DELAY, active: false)]
+    #[nwg_events(OnTimerTick: [App::on_unlock_settled])]
+    unlock_timer: nwg::AnimationTimer,
+
+    #[nwg_control(parent: window)]
+    #[nwg_events(OnNotice: [App::on_picked])]
+    pick_notice: nwg::Notice,
+
+    #[nwg_control(parent: window)]
+    #[nwg_events(OnNotice: [App::on_ipc])]
+    ipc_notice: nwg::Notice,
+
+    #[nwg_control(parent: window)]
+    #[nwg_events(OnNotice: [App::on_scaling_changed])]
+    scaling_notice: nwg::Notice,
+
     #[nwg_resource]
     embed: nwg::EmbedResource,
 
@@ -161,6 +916,144 @@ pub struct App {
     #[nwg_events(OnMenuItemSelected: [App::on_about])]
     tray_menu_about: nwg::MenuItem,
 
+    #[nwg_control(parent: tray_menu, text: "Topology Journal")]
+    #[nwg_events(OnMenuItemSelected: [App::on_journal])]
+    tray_menu_journal: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Statistics")]
+    #[nwg_events(OnMenuItemSelected: [App::on_statistics])]
+    tray_menu_statistics: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Pick Window...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_pick_window])]
+    tray_menu_pick: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Restore Monitor...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_restore_monitor])]
+    tray_menu_restore_monitor: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Checkpoints...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_checkpoints])]
+    tray_menu_checkpoints: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Copy Layout...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_copy_layout])]
+    tray_menu_copy_layout: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Workspaces...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_workspaces])]
+    tray_menu_workspaces: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Locations...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_locations])]
+    tray_menu_locations: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Stop Tracking Current App")]
+    #[nwg_events(OnMenuItemSelected: [App::on_stop_tracking])]
+    tray_menu_stop_tracking: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Resume Tracking Current App")]
+    #[nwg_events(OnMenuItemSelected: [App::on_resume_tracking])]
+    tray_menu_resume_tracking: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Restore Windows")]
+    #[nwg_events(OnMenuItemSelected: [App::on_restore_now])]
+    tray_menu_restore_now: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Restore Session...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_restore_session])]
+    tray_menu_restore_session: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Roll Back Layout")]
+    tray_menu_rollback: nwg::Menu,
+
+    #[nwg_control(parent: tray_menu_rollback, text: "5 Minutes Ago")]
+    #[nwg_events(OnMenuItemSelected: [App::on_rollback_minutes])]
+    tray_menu_rollback_minutes: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_rollback, text: "1 Hour Ago")]
+    #[nwg_events(OnMenuItemSelected: [App::on_rollback_hour])]
+    tray_menu_rollback_hour: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_rollback, text: "1 Day Ago")]
+    #[nwg_events(OnMenuItemSelected: [App::on_rollback_day])]
+    tray_menu_rollback_day: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Restore Policy")]
+    tray_menu_policy: nwg::Menu,
+
+    #[nwg_control(parent: tray_menu_policy, text: "Restore All Windows", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_policy_full])]
+    tray_menu_policy_full: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_policy, text: "Restore Misplaced Windows Only", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_policy_missing])]
+    tray_menu_policy_missing: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_policy, text: "Manual Only", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_policy_manual])]
+    tray_menu_policy_manual: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Ignore Placements Older Than")]
+    tray_menu_age: nwg::Menu,
+
+    #[nwg_control(parent: tray_menu_age, text: "Never Ignore", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_age_never])]
+    tray_menu_age_never: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_age, text: "7 Days", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_age_week])]
+    tray_menu_age_week: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_age, text: "30 Days", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_age_month])]
+    tray_menu_age_month: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_age, text: "90 Days", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_age_quarter])]
+    tray_menu_age_quarter: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Capture Scope")]
+    tray_menu_scope: nwg::Menu,
+
+    #[nwg_control(parent: tray_menu_scope, text: "Everything", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_scope_everything])]
+    tray_menu_scope_everything: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_scope, text: "Current Monitor", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_scope_monitor])]
+    tray_menu_scope_monitor: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu_scope, text: "Current Virtual Desktop", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_scope_desktop])]
+    tray_menu_scope_desktop: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Export Settings...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_export_settings])]
+    tray_menu_export: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Import Settings...")]
+    #[nwg_events(OnMenuItemSelected: [App::on_import_settings])]
+    tray_menu_import: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Reload Config")]
+    #[nwg_events(OnMenuItemSelected: [App::on_reload_config])]
+    tray_menu_reload_config: nwg::MenuItem,
+
+    #[nwg_resource(title: "Export Settings", action: nwg::FileDialogAction::Save, filters: "Settings (*.toml)|All files (*.*)")]
+    export_dialog: nwg::FileDialog,
+
+    #[nwg_resource(title: "Import Settings", action: nwg::FileDialogAction::Open, filters: "Settings (*.toml)|All files (*.*)")]
+    import_dialog: nwg::FileDialog,
+
+    #[nwg_control(parent: tray_menu, text: "Pause Tracking", check: false)]
+    #[nwg_events(OnMenuItemSelected: [App::on_pause_toggle])]
+    tray_menu_pause: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Cancel Capture", disabled: true)]
+    #[nwg_events(OnMenuItemSelected: [App::on_cancel_capture])]
+    tray_menu_cancel_capture: nwg::MenuItem,
+
     #[nwg_control(parent: tray_menu)]
     tray_menu_sep: nwg::MenuSeparator,
 
@@ -172,27 +1065,300 @@ pub struct App {
     #[nwg_events(OnMenuItemSelected: [App::on_exit])]
     tray_menu_exit: nwg::MenuItem,
 
+    #[nwg_control(size: (360, 330), position: (300, 300), title: "Checkpoints", flags: "WINDOW")]
+    #[nwg_events(OnWindowClose: [App::on_checkpoints_close(SELF, EVT_DATA)])]
+    checkpoints_window: nwg::Window,
+
+    #[nwg_control(parent: checkpoints_window, position: (10, 10), size: (340, 210))]
+    checkpoints_list: nwg::ListBox<String>,
+
+    #[nwg_control(parent: checkpoints_window, position: (10, 230), size: (340, 25))]
+    checkpoints_name: nwg::TextInput,
+
+    #[nwg_control(parent: checkpoints_window, text: "Create", position: (10, 265), size: (80, 30))]
+    #[nwg_events(OnButtonClick: [App::on_checkpoint_create])]
+    checkpoints_create: nwg::Button,
+
+    #[nwg_control(parent: checkpoints_window, text: "Restore", position: (97, 265), size: (80, 30))]
+    #[nwg_events(OnButtonClick: [App::on_checkpoint_restore])]
+    checkpoints_restore: nwg::Button,
+
+    #[nwg_control(parent: checkpoints_window, text: "Apps...", position: (184, 265), size: (80, 30))]
+    #[nwg_events(OnButtonClick: [App::on_checkpoint_apps])]
+    checkpoints_apps: nwg::Button,
+
+    #[nwg_control(parent: checkpoints_window, text: "Delete", position: (271, 265), size: (80, 30))]
+    #[nwg_events(OnButtonClick: [App::on_checkpoint_delete])]
+    checkpoints_delete: nwg::Button,
+
+    #[nwg_control(size: (360, 330), position: (300, 300), title: "Restore Applications", flags: "WINDOW")]
+    #[nwg_events(OnWindowClose: [App::on_checkpoint_apps_close(SELF, EVT_DATA)])]
+    checkpoint_apps_window: nwg::Window,
+
+    #[nwg_control(parent: checkpoint_apps_window, text: "Restore only the windows of the selected applications:", position: (10, 10), size: (340, 20))]
+    checkpoint_apps_label: nwg::Label,
+
+    #[nwg_control(parent: checkpoint_apps_window, flags: "VISIBLE | MULTI_SELECT", position: (10, 35), size: (340, 220))]
+    checkpoint_apps_list: nwg::ListBox<String>,
+
+    #[nwg_control(parent: checkpoint_apps_window, text: "Restore", position: (245, 265), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_checkpoint_apps_restore])]
+    checkpoint_apps_restore: nwg::Button,
+
+    #[nwg_control(size: (400, 290), position: (300, 300), title: "Pick Window", flags: "WINDOW")]
+    #[nwg_events(OnWindowClose: [App::on_picked_close(SELF, EVT_DATA)])]
+    picked_window: nwg::Window,
+
+    #[nwg_control(parent: picked_window, position: (10, 10), size: (380, 100))]
+    picked_label: nwg::Label,
+
+    #[nwg_control(parent: picked_window, text: "Bring to the foreground while restoring", position: (10, 120), size: (380, 25))]
+    #[nwg_events(OnButtonClick: [App::on_picked_foreground])]
+    picked_foreground: nwg::CheckBox,
+
+    #[nwg_control(parent: picked_window, text: "Only match windows with the exact same title", position: (10, 145), size: (380, 25))]
+    #[nwg_events(OnButtonClick: [App::on_picked_exact_match])]
+    picked_exact_match: nwg::CheckBox,
+
+    #[nwg_control(parent: picked_window, text: "Capture the window rectangle instead of the placement", position: (10, 170), size: (380, 25))]
+    #[nwg_events(OnButtonClick: [App::on_picked_window_rect])]
+    picked_window_rect: nwg::CheckBox,
+
+    #[nwg_control(parent: picked_window, text: "Place new windows even if the application does itself", position: (10, 195), size: (380, 25))]
+    #[nwg_events(OnButtonClick: [App::on_picked_always_restore])]
+    picked_always_restore: nwg::CheckBox,
+
+    #[nwg_control(parent: picked_window, text: "History", position: (10, 245), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_picked_history])]
+    picked_history: nwg::Button,
+
+    #[nwg_control(parent: picked_window, text: "Forget", position: (127, 245), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_picked_forget])]
+    picked_forget: nwg::Button,
+
+    #[nwg_control(size: (360, 300), position: (300, 300), title: "Copy Layout", flags: "WINDOW")]
+    #[nwg_events(OnWindowClose: [App::on_copy_layout_close(SELF, EVT_DATA)])]
+    copy_layout_window: nwg::Window,
+
+    #[nwg_control(parent: copy_layout_window, text: "Copy the layout of a display setup with the same number of monitors to the current one:", position: (10, 10), size: (340, 40))]
+    copy_layout_label: nwg::Label,
+
+    #[nwg_control(parent: copy_layout_window, position: (10, 55), size: (340, 185))]
+    copy_layout_list: nwg::ListBox<TopologyItem>,
+
+    #[nwg_control(parent: copy_layout_window, text: "Copy", position: (245, 255), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_copy_layout_copy])]
+    copy_layout_copy: nwg::Button,
+
+    #[nwg_control(size: (360, 330), position: (300, 300), title: "Workspaces", flags: "WINDOW")]
+    #[nwg_events(OnWindowClose: [App::on_workspaces_close(SELF, EVT_DATA)])]
+    workspaces_window: nwg::Window,
+
+    #[nwg_control(parent: workspaces_window, position: (10, 10), size: (340, 210))]
+    workspaces_list: nwg::ListBox<String>,
+
+    #[nwg_control(parent: workspaces_window, position: (10, 230), size: (340, 25))]
+    workspaces_name: nwg::TextInput,
+
+    #[nwg_control(parent: workspaces_window, text: "Create", position: (10, 265), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_workspace_create])]
+    workspaces_create: nwg::Button,
+
+    #[nwg_control(parent: workspaces_window, text: "Switch", position: (245, 265), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_workspace_switch])]
+    workspaces_switch: nwg::Button,
+
+    #[nwg_control(size: (360, 330), position: (300, 300), title: "Locations", flags: "WINDOW")]
+    #[nwg_events(OnWindowClose: [App::on_locations_close(SELF, EVT_DATA)])]
+    locations_window: nwg::Window,
+
+    #[nwg_control(parent: locations_window, position: (10, 10), size: (340, 210))]
+    locations_list: nwg::ListBox<String>,
+
+    #[nwg_control(parent: locations_window, position: (10, 230), size: (340, 25))]
+    locations_name: nwg::TextInput,
+
+    #[nwg_control(parent: locations_window, text: "Set", position: (10, 265), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_location_set])]
+    locations_set: nwg::Button,
+
+    #[nwg_control(parent: locations_window, text: "Clear", position: (245, 265), size: (105, 30))]
+    #[nwg_events(OnButtonClick: [App::on_location_clear])]
+    locations_clear: nwg::Button,
+
     data: RefCell<AppData>,
-    db: rusqlite::Connection,
+    /// The point chosen with the picker and what it was picked for, handed over by `pick_notice`
+    picked: Rc<Cell<Option<(PickAction, POINT)>>>,
+    /// The database of the active workspace, holding its auxiliary data
+    db: RefCell<Rc<rusqlite::Connection>>,
+    /// The saved layouts of the active workspace, kept in its database
+    store: RefCell<Box<dyn LayoutStore>>,
+    /// The window event hooks currently installed
+    hooks: RefCell<Vec<EventHandle>>,
+    /// The callback shared by all event hooks, set once the UI is up
+    hook_cb: RefCell<Option<Rc<CallbackFn>>>,
+    /// The window receiving system notifications, created once the UI is up
+    msg_wnd: RefCell<Option<MessageWindow>>,
+    /// The message broadcast when the shell restarts
+    taskbar_created: u32,
+    /// Requests received on the pipe, handed over by `ipc_notice`
+    ipc_calls: RefCell<Option<mpsc::Receiver<ipc::Call>>>,
+    /// Whether this instance is running elevated
+    elevated: bool,
+    /// The name this instance goes by in the session, to tell its rows apart in a database
+    /// shared with the instance of the other elevation
+    instance: String,
+    /// What this instance and the instance of the other elevation know of each other
+    peers: Arc<ipc::Peers>,
 }
 
 impl App {
-    fn new(conn: rusqlite::Connection) -> Self {
+    fn new(
+        conn: rusqlite::Connection,
+        workspace: String,
+        workspace_dir: PathBuf,
+        elevated: bool,
+        instance: String,
+    ) -> Self {
+        let peers = ipc::Peers::new(ipc::Announcement {
+            elevated,
+            workspace: Some(workspace.clone()),
+        });
+        let conn = Rc::new(conn);
+
         Self {
             window: Default::default(),
+            restore_timer: Default::default(),
+            capture_timer: Default::default(),
+            work_area_timer: Default::default(),
+            verify_timer: Default::default(),
+            retry_timer: Default::default(),
+            prune_timer: Default::default(),
+            session_timer: Default::default(),
+            kiosk_timer: Default::default(),
+            unlock_timer: Default::default(),
+            deferred_capture_timer: Default::default(),
+            hook_check_timer: Default::default(),
+            startup_report_timer: Default::default(),
+            display_timer: Default::default(),
+            stable_capture_timer: Default::default(),
+            pick_notice: Default::default(),
+            ipc_notice: Default::default(),
+            scaling_notice: Default::default(),
             embed: Default::default(),
             icon: Default::default(),
             tray: Default::default(),
             tray_menu: Default::default(),
             tray_menu_about: Default::default(),
+            tray_menu_journal: Default::default(),
+            tray_menu_statistics: Default::default(),
+            tray_menu_pick: Default::default(),
+            tray_menu_restore_monitor: Default::default(),
+            tray_menu_checkpoints: Default::default(),
+            tray_menu_copy_layout: Default::default(),
+            tray_menu_workspaces: Default::default(),
+            tray_menu_locations: Default::default(),
+            tray_menu_stop_tracking: Default::default(),
+            tray_menu_resume_tracking: Default::default(),
+            tray_menu_restore_now: Default::default(),
+            tray_menu_restore_session: Default::default(),
+            tray_menu_rollback: Default::default(),
+            tray_menu_rollback_minutes: Default::default(),
+            tray_menu_rollback_hour: Default::default(),
+            tray_menu_rollback_day: Default::default(),
+            tray_menu_policy: Default::default(),
+            tray_menu_policy_full: Default::default(),
+            tray_menu_policy_missing: Default::default(),
+            tray_menu_policy_manual: Default::default(),
+            tray_menu_age: Default::default(),
+            tray_menu_age_never: Default::default(),
+            tray_menu_age_week: Default::default(),
+            tray_menu_age_month: Default::default(),
+            tray_menu_age_quarter: Default::default(),
+            tray_menu_scope: Default::default(),
+            tray_menu_scope_everything: Default::default(),
+            tray_menu_scope_monitor: Default::default(),
+            tray_menu_scope_desktop: Default::default(),
+            tray_menu_export: Default::default(),
+            tray_menu_import: Default::default(),
+            tray_menu_reload_config: Default::default(),
+            export_dialog: Default::default(),
+            import_dialog: Default::default(),
+            tray_menu_pause: Default::default(),
+            tray_menu_cancel_capture: Default::default(),
             tray_menu_sep: Default::default(),
             tray_menu_autorun: Default::default(),
             tray_menu_exit: Default::default(),
-            data: RefCell::new(Default::default()),
-            db: conn,
+            checkpoints_window: Default::default(),
+            checkpoints_list: Default::default(),
+            checkpoints_name: Default::default(),
+            checkpoints_create: Default::default(),
+            checkpoints_restore: Default::default(),
+            checkpoints_apps: Default::default(),
+            checkpoints_delete: Default::default(),
+            checkpoint_apps_window: Default::default(),
+            checkpoint_apps_label: Default::default(),
+            checkpoint_apps_list: Default::default(),
+            checkpoint_apps_restore: Default::default(),
+            picked_window: Default::default(),
+            picked_label: Default::default(),
+            picked_foreground: Default::default(),
+            picked_exact_match: Default::default(),
+            picked_window_rect: Default::default(),
+            picked_always_restore: Default::default(),
+            picked_history: Default::default(),
+            picked_forget: Default::default(),
+            copy_layout_window: Default::default(),
+            copy_layout_label: Default::default(),
+            copy_layout_list: Default::default(),
+            copy_layout_copy: Default::default(),
+            workspaces_window: Default::default(),
+            workspaces_list: Default::default(),
+            workspaces_name: Default::default(),
+            workspaces_create: Default::default(),
+            workspaces_switch: Default::default(),
+            locations_window: Default::default(),
+            locations_list: Default::default(),
+            locations_name: Default::default(),
+            locations_set: Default::default(),
+            locations_clear: Default::default(),
+            data: RefCell::new(AppData {
+                restore_delay: config::get().restore_delay(),
+                workspace,
+                workspace_dir,
+                location: read_setting("Location").filter(|l| !l.is_empty()),
+                started: unix_time().unwrap_or_default(),
+                capture_scope: read_setting("CaptureScope")
+                    .and_then(|s| CaptureScope::from_str(&s))
+                    .unwrap_or_default(),
+                max_record_age: read_setting("MaxRecordAgeDays")
+                    .and_then(|s| s.parse().ok())
+                    .filter(|days| *days > 0),
+                ..Default::default()
+            }),
+            picked: Default::default(),
+            db: RefCell::new(conn.clone()),
+            store: RefCell::new(Box::new(conn)),
+            hooks: Default::default(),
+            hook_cb: Default::default(),
+            msg_wnd: Default::default(),
+            taskbar_created: msgwindow::taskbar_created_message(),
+            ipc_calls: Default::default(),
+            elevated,
+            instance,
+            peers,
         }
     }
 
+    fn db(&self) -> std::cell::Ref<'_, rusqlite::Connection> {
+        std::cell::Ref::map(self.db.borrow(), |db| &**db)
+    }
+
+    /// The store holding saved layouts.
+    fn store(&self) -> std::cell::Ref<'_, dyn LayoutStore> {
+        std::cell::Ref::map(self.store.borrow(), |store| &**store)
+    }
+
     fn has_autostart() -> std::io::Result<bool> {
         // Determine if we are already set to automatically start.
         let key = HKCU.open_subkey(STARTUP_KEY)?;
@@ -207,273 +1373,4939 @@ impl App {
         if let Ok(r) = Self::has_autostart() {
             self.tray_menu_autorun.set_checked(r);
         }
-    }
 
-    fn on_tray_click(&self) {
-        let (x, y) = nwg::GlobalCursor::position();
-        self.tray_menu.popup(x, y);
+        if let Err(e) = refresh_autorun() {
+            warn!("failed to refresh auto-start entry: {e:?}");
+        }
+
+        self.check_capture_scope();
+        self.check_max_record_age();
+        self.apply_config(&config::get());
     }
 
-    fn on_autorun_toggle(&self) {
-        match runas_admin("autorun") {
-            Ok(0) => {
-                self.tray_menu_autorun
-                    .set_checked(!self.tray_menu_autorun.checked());
+    fn on_reload_config(&self) {
+        match config::reload() {
+            Ok(config) => {
+                info!("reloaded configuration");
+                self.apply_config(&config);
             }
-            Ok(_) => {}
             Err(e) => {
                 nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
-                return;
             }
-        };
+        }
     }
 
-    fn on_about(&self) {
-        self.tray.show(
-            &format!(
-                "Persistent Windows {}\n{}",
-                env!("VERGEN_BUILD_SEMVER"),
-                env!("VERGEN_GIT_SHA_SHORT")
-            ),
-            Some("About"),
-            Some(TrayNotificationFlags::LARGE_ICON),
-            Some(&self.icon),
-        );
-    }
+    /// Put the options of a (re)loaded configuration into effect. Excluded applications are
+    /// looked up as needed, and the database path only applies on the next start.
+    fn apply_config(&self, config: &config::Config) {
+        self.data.borrow_mut().restore_delay = config.restore_delay();
+        self.stable_capture_timer
+            .set_interval(config.capture_debounce());
+        self.display_timer.set_interval(config.display_settle());
 
-    fn on_exit(&self) {
-        nwg::stop_thread_dispatch();
-    }
+        if std::env::var_os("RUST_LOG").is_none() {
+            log::set_max_level(config.log_level());
+        }
 
-    fn find_window(&self, hwnd: HWND, topology: usize) -> Option<WindowDisplay> {
-        if let Some(disp) = self
-            .db
-            .query_row(
-                "SELECT disp FROM appwindow WHERE hwnd=:hwnd AND topology=:topology",
-                named_params! { ":hwnd": hwnd.0, ":topology": topology },
-                |r| r.get::<usize, Vec<u8>>(0),
-            )
-            .optional()
-            .unwrap()
-        {
-            bson::from_reader(&*disp).unwrap()
-        } else {
-            None
+        // Kiosk mode locks everything that changes what is captured or how it is restored.
+        let locked = config.kiosk.is_some();
+        for item in [
+            &self.tray_menu_pick,
+            &self.tray_menu_checkpoints,
+            &self.tray_menu_copy_layout,
+            &self.tray_menu_workspaces,
+            &self.tray_menu_locations,
+            &self.tray_menu_stop_tracking,
+            &self.tray_menu_resume_tracking,
+            &self.tray_menu_export,
+            &self.tray_menu_import,
+            &self.tray_menu_pause,
+        ] {
+            item.set_enabled(!locked);
+        }
+        self.tray_menu_autorun
+            .set_enabled(!locked && !config.policy.disable_autorun_toggle);
+        for menu in [
+            &self.tray_menu_rollback,
+            &self.tray_menu_policy,
+            &self.tray_menu_age,
+            &self.tray_menu_scope,
+        ] {
+            menu.set_enabled(!locked);
+        }
+
+        match &config.kiosk {
+            Some(kiosk) => {
+                info!(
+                    "kiosk mode: enforcing checkpoint \"{}\" every {:?}",
+                    kiosk.checkpoint,
+                    kiosk.interval()
+                );
+                self.kiosk_timer.set_interval(kiosk.interval());
+                self.kiosk_timer.start();
+            }
+            None => self.kiosk_timer.stop(),
         }
     }
 
-    fn restore_windows(&self) -> anyhow::Result<()> {
+    /// Put windows that moved back to the layout enforced in kiosk mode.
+    fn on_kiosk_tick(&self) {
+        let Some(kiosk) = config::with(|c| c.kiosk.clone()) else {
+            self.kiosk_timer.stop();
+            return;
+        };
+
+        // Leave windows alone while the system is moving them or a restore is underway.
+        {
+            let data = self.data.borrow();
+            if data.frozen || data.transition != Transition::Idle {
+                return;
+            }
+        }
+
+        if let Err(e) = self.enforce_checkpoint(&kiosk.checkpoint) {
+            warn!("failed to enforce kiosk layout: {e:?}");
+        }
+    }
+
+    fn on_tray_click(&self) {
+        let (x, y) = nwg::GlobalCursor::position();
+        self.data.borrow_mut().tray_point = Some(POINT { x, y });
+
+        // Message boxes open on the monitor of their owner, so move the hidden owner along.
+        self.place_dialog(&self.window, None);
+        self.tray_menu.popup(x, y);
+    }
+
+    /// Move one of the app's dialogs to where it was last left in the active topology, or else
+    /// next to where the tray icon was last clicked (or the cursor), rather than wherever it was
+    /// created. Dialogs without a `name` are not remembered.
+    fn place_dialog(&self, dialog: &nwg::Window, name: Option<&str>) {
+        let Some(hwnd) = dialog.handle.hwnd().map(|h| HWND(h as isize)) else {
+            return;
+        };
+
+        let (topology, origin, anchor) = {
+            let data = self.data.borrow();
+            let anchor = data.tray_point.unwrap_or_else(|| {
+                let (x, y) = nwg::GlobalCursor::position();
+                POINT { x, y }
+            });
+            (
+                data.active_topology.filter(|_| name.is_some()),
+                data.origin.clone(),
+                anchor,
+            )
+        };
+
+        let dialog = Dialog {
+            hwnd,
+            name: name.unwrap_or_default(),
+        };
+        if let Err(e) = dialog.place(&self.db(), topology, &origin, anchor) {
+            warn!("{:?}", e.context("failed to place dialog"));
+        }
+    }
+
+    /// Hide one of the app's dialogs, remembering where it was left in the active topology.
+    fn hide_dialog(&self, dialog: &nwg::Window, name: &str) {
+        let (topology, origin) = {
+            let data = self.data.borrow();
+            (data.active_topology, data.origin.clone())
+        };
+
+        if let (Some(hwnd), Some(topology)) = (dialog.handle.hwnd(), topology) {
+            let dialog = Dialog {
+                hwnd: HWND(hwnd as isize),
+                name,
+            };
+            if let Err(e) = dialog.remember(&self.db(), topology, &origin) {
+                warn!("{:?}", e.context("failed to remember dialog"));
+            }
+        }
+
+        dialog.set_visible(false);
+    }
+
+    fn on_autorun_toggle(&self) {
+        if config::with(|c| c.policy.disable_autorun_toggle) {
+            return;
+        }
+
+        // Pass along our launch arguments, so that they are used at login too.
+        let launch_args = std::env::args().skip(1).collect::<Vec<_>>();
+        match runas_admin(&format!("autorun {}", join_args(&launch_args))) {
+            Ok(0) => {
+                self.tray_menu_autorun
+                    .set_checked(!self.tray_menu_autorun.checked());
+            }
+            Ok(_) => {}
+            Err(e) => {
+                nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+                return;
+            }
+        };
+    }
+
+    fn on_about(&self) {
+        let text = format!(
+            "Persistent Windows {}\n{}\n\n{}",
+            env!("VERGEN_BUILD_SEMVER"),
+            env!("VERGEN_GIT_SHA_SHORT"),
+            config::with(config::Config::describe)
+        );
+
+        nwg::modal_info_message(&self.window, "About", &text);
+    }
+
+    fn on_journal(&self) {
+        let text = match self.journal(20) {
+            Ok(entries) if entries.is_empty() => "No topology transitions recorded.".to_string(),
+            Ok(entries) => entries.join("\n"),
+            Err(e) => {
+                nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+                return;
+            }
+        };
+
+        nwg::modal_info_message(&self.window, "Topology Journal", &text);
+    }
+
+    fn on_statistics(&self) {
+        match self.statistics() {
+            Ok(text) => {
+                nwg::modal_info_message(&self.window, "Statistics", &text);
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_checkpoints(&self) {
+        self.refresh_checkpoints();
+        self.place_dialog(&self.checkpoints_window, Some("checkpoints"));
+        self.checkpoints_window.set_visible(true);
+        self.checkpoints_window.set_focus();
+    }
+
+    fn on_checkpoints_close(&self, data: &nwg::EventData) {
+        // Keep the dialog around to be shown again.
+        if let nwg::EventData::OnWindowClose(data) = data {
+            data.close(false);
+        }
+
+        self.hide_dialog(&self.checkpoints_window, "checkpoints");
+    }
+
+    fn on_checkpoint_create(&self) {
+        let name = self.checkpoints_name.text();
+        let name = name.trim();
+        if name.is_empty() {
+            nwg::modal_info_message(
+                &self.checkpoints_window,
+                "Checkpoints",
+                "Enter a name for the checkpoint.",
+            );
+            return;
+        }
+
+        match self.create_checkpoint(name) {
+            Ok(_) => self.checkpoints_name.set_text(""),
+            Err(e) => {
+                nwg::modal_error_message(&self.checkpoints_window, "Error", &format!("{e:?}"));
+            }
+        }
+
+        self.refresh_checkpoints();
+    }
+
+    fn on_checkpoint_restore(&self) {
+        if let Some(name) = self.checkpoints_list.selection_string() {
+            if let Err(e) = self.restore_checkpoint(&name, None) {
+                nwg::modal_error_message(&self.checkpoints_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    /// Let the user pick which applications to restore from the selected checkpoint.
+    fn on_checkpoint_apps(&self) {
+        let Some(name) = self.checkpoints_list.selection_string() else {
+            return;
+        };
+
+        match self.checkpoint_apps(&name) {
+            Ok(apps) => {
+                self.checkpoint_apps_list.set_collection(apps);
+                self.checkpoint_apps_window
+                    .set_text(&format!("Restore Applications ({name})"));
+                self.data.borrow_mut().apps_checkpoint = Some(name);
+
+                self.place_dialog(&self.checkpoint_apps_window, Some("checkpoint-apps"));
+                self.checkpoint_apps_window.set_visible(true);
+                self.checkpoint_apps_window.set_focus();
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.checkpoints_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_checkpoint_apps_close(&self, data: &nwg::EventData) {
+        // Keep the dialog around to be shown again.
+        if let nwg::EventData::OnWindowClose(data) = data {
+            data.close(false);
+        }
+
+        self.hide_dialog(&self.checkpoint_apps_window, "checkpoint-apps");
+        self.data.borrow_mut().apps_checkpoint = None;
+    }
+
+    fn on_checkpoint_apps_restore(&self) {
+        let Some(name) = self.data.borrow().apps_checkpoint.clone() else {
+            return;
+        };
+
+        let apps = {
+            let collection = self.checkpoint_apps_list.collection();
+            self.checkpoint_apps_list
+                .multi_selection()
+                .into_iter()
+                .filter_map(|i| collection.get(i).cloned())
+                .collect::<HashSet<_>>()
+        };
+        if apps.is_empty() {
+            return;
+        }
+
+        match self.restore_checkpoint(&name, Some(apps)) {
+            Ok(_) => self.hide_dialog(&self.checkpoint_apps_window, "checkpoint-apps"),
+            Err(e) => {
+                nwg::modal_error_message(&self.checkpoint_apps_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_checkpoint_delete(&self) {
+        if let Some(name) = self.checkpoints_list.selection_string() {
+            if let Err(e) = self.delete_checkpoint(&name) {
+                nwg::modal_error_message(&self.checkpoints_window, "Error", &format!("{e:?}"));
+            }
+
+            self.refresh_checkpoints();
+        }
+    }
+
+    fn refresh_checkpoints(&self) {
+        match self.checkpoints() {
+            Ok(names) => {
+                self.checkpoints_list.set_collection(names);
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.checkpoints_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_workspaces(&self) {
+        self.refresh_workspaces();
+        self.place_dialog(&self.workspaces_window, Some("workspaces"));
+        self.workspaces_window.set_visible(true);
+        self.workspaces_window.set_focus();
+    }
+
+    fn on_workspaces_close(&self, data: &nwg::EventData) {
+        // Keep the dialog around to be shown again.
+        if let nwg::EventData::OnWindowClose(data) = data {
+            data.close(false);
+        }
+
+        self.hide_dialog(&self.workspaces_window, "workspaces");
+    }
+
+    fn on_workspace_create(&self) {
+        let name = self.workspaces_name.text();
+        let name = name.trim();
+        if !is_valid_workspace_name(name) {
+            nwg::modal_info_message(
+                &self.workspaces_window,
+                "Workspaces",
+                "Enter a name for the workspace. Names cannot contain path separators.",
+            );
+            return;
+        }
+
+        // A new workspace starts out empty, so switching to it creates it.
+        match self.switch_workspace(name) {
+            Ok(_) => self.workspaces_name.set_text(""),
+            Err(e) => {
+                nwg::modal_error_message(&self.workspaces_window, "Error", &format!("{e:?}"));
+            }
+        }
+
+        self.refresh_workspaces();
+    }
+
+    fn on_workspace_switch(&self) {
+        if let Some(name) = self.workspaces_list.selection_string() {
+            if let Err(e) = self.switch_workspace(&name) {
+                nwg::modal_error_message(&self.workspaces_window, "Error", &format!("{e:?}"));
+            }
+
+            self.refresh_workspaces();
+        }
+    }
+
+    fn refresh_workspaces(&self) {
+        let dir = self.data.borrow().workspace_dir.clone();
+        match workspaces(&dir) {
+            Ok(names) => {
+                let active = self.data.borrow().workspace.clone();
+                let selected = names.iter().position(|n| *n == active);
+
+                self.workspaces_list.set_collection(names);
+                self.workspaces_list.set_selection(selected);
+                self.workspaces_window
+                    .set_text(&format!("Workspaces (active: {active})"));
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.workspaces_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_locations(&self) {
+        self.refresh_locations();
+        self.place_dialog(&self.locations_window, Some("locations"));
+        self.locations_window.set_visible(true);
+        self.locations_window.set_focus();
+    }
+
+    fn on_locations_close(&self, data: &nwg::EventData) {
+        // Keep the dialog around to be shown again.
+        if let nwg::EventData::OnWindowClose(data) = data {
+            data.close(false);
+        }
+
+        self.hide_dialog(&self.locations_window, "locations");
+    }
+
+    fn on_location_set(&self) {
+        let name = self.locations_name.text();
+        let name = match name.trim() {
+            "" => self.locations_list.selection_string().unwrap_or_default(),
+            name => name.to_string(),
+        };
+        if name.is_empty() {
+            nwg::modal_info_message(
+                &self.locations_window,
+                "Locations",
+                "Select a location, or enter the name of a new one.",
+            );
+            return;
+        }
+
+        self.set_location(Some(name));
+        self.locations_name.set_text("");
+    }
+
+    fn on_location_clear(&self) {
+        self.set_location(None);
+    }
+
+    fn refresh_locations(&self) {
+        match self.locations() {
+            Ok(names) => {
+                let active = self.data.borrow().location.clone();
+                let selected = names.iter().position(|n| Some(n) == active.as_ref());
+
+                self.locations_list.set_collection(names);
+                self.locations_list.set_selection(selected);
+                self.locations_window.set_text(&format!(
+                    "Locations (current: {})",
+                    active.as_deref().unwrap_or("none")
+                ));
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.locations_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    /// Declare the location the user is at. While a location is set, layouts are saved and
+    /// restored for it separately, even on identical displays.
+    fn set_location(&self, location: Option<String>) {
+        if let Err(e) = write_setting("Location", location.as_deref().unwrap_or_default()) {
+            warn!("failed to remember the location: {e}");
+        }
+
+        info!("location set to {location:?}");
+        self.data.borrow_mut().location = location;
+        self.refresh_locations();
+
+        // The displays now map to a different topology.
+        self.settle("location");
+    }
+
+    /// The locations topologies are bound to, sorted by name.
+    fn locations(&self) -> anyhow::Result<Vec<String>> {
+        let mut names = BTreeSet::new();
+        for id in self.store().topologies()? {
+            let topology = bson::from_slice::<Topology>(&self.store().topology(id)?)
+                .context("failed to decode topology")?;
+            names.extend(topology.location);
+        }
+
+        names.extend(self.data.borrow().location.clone());
+        Ok(names.into_iter().collect())
+    }
+
+    /// Let the user know when the current displays are bound to more than one location, as
+    /// the one in use may not be where they are.
+    fn check_location(&self, topology: usize) -> anyhow::Result<()> {
+        let current = bson::from_slice::<Topology>(&self.store().topology(topology)?)
+            .context("failed to decode topology")?;
+        let unbound = |t: Topology| Topology {
+            location: None,
+            ..t
+        };
+        let location = current.location.clone();
+        let displays = unbound(current);
+
+        let mut names = BTreeSet::new();
+        for id in self.store().topologies()? {
+            let other = bson::from_slice::<Topology>(&self.store().topology(id)?)
+                .context("failed to decode topology")?;
+            if let Some(name) = other.location.clone() {
+                if unbound(other) == displays {
+                    names.insert(name);
+                }
+            }
+        }
+
+        if names.len() < 2 {
+            return Ok(());
+        }
+
+        let text = format!(
+            "These displays are used at {}. Restoring the layout of {}; choose \"Locations...\" \
+             in the tray menu if you are elsewhere.",
+            names.into_iter().collect::<Vec<_>>().join(", "),
+            location.as_deref().unwrap_or("no location"),
+        );
+        self.tray.show(
+            &text,
+            Some("Persistent Windows"),
+            Some(TrayNotificationFlags::LARGE_ICON),
+            Some(&self.icon),
+        );
+
+        Ok(())
+    }
+
+    /// Switch to the workspace of the first configured trigger for a condition of the device
+    /// that just started to hold.
+    fn apply_workspace_triggers(&self, condition: config::Condition) {
+        let target = config::with(|c| {
+            c.workspace_triggers
+                .iter()
+                .find(|t| t.when == condition)
+                .map(|t| t.workspace.clone())
+        });
+
+        if let Some(name) = target {
+            info!("device is now in condition {condition:?}");
+            if let Err(e) = self.switch_workspace(&name) {
+                warn!("{e:?}");
+            }
+        }
+    }
+
+    /// Close the database of the active workspace and continue with another one.
+    ///
+    /// Everything tracked this session belongs to the old database, so it is dropped and the
+    /// current windows are restored from the new workspace as if the app had just started.
+    fn switch_workspace(&self, name: &str) -> anyhow::Result<()> {
+        if self.data.borrow().workspace == name {
+            return Ok(());
+        }
+
+        let dir = self.data.borrow().workspace_dir.clone();
+        let conn = db::open(&workspace_path_in(&dir, name)?, &self.instance)
+            .with_context(|| format!("failed to open workspace \"{name}\""))?;
+        if let Err(e) = bundle::seed(&conn) {
+            warn!("{:?}", e.context("failed to merge layout bundle"));
+        }
+
+        info!("switching to workspace \"{name}\"");
+
+        // Abandon any pass still running against the old database.
+        self.cancel_restore();
+        self.deferred_capture_timer.stop();
+        self.stable_capture_timer.stop();
+        self.verify_timer.stop();
+        self.finish_capture();
+
+        let (prev_workspace, prev_topology, prev_origin) = {
+            let mut data = self.data.borrow_mut();
+            data.records.clear();
+            data.superseded.clear();
+            data.capture_queue.clear();
+            data.capture_total = 0;
+            data.destroyed.clear();
+            data.picked_window = None;
+            data.deferred_captures.clear();
+            data.unstable_captures.clear();
+            data.unverified_restores.clear();
+            (
+                std::mem::replace(&mut data.workspace, name.to_string()),
+                data.active_topology.take(),
+                data.origin.clone(),
+            )
+        };
+
+        let conn = Rc::new(conn);
+        let prev_db = std::mem::replace(&mut *self.db.borrow_mut(), conn.clone());
+        let prev_store = std::mem::replace(&mut *self.store.borrow_mut(), Box::new(conn));
+
+        let switched = (|| {
+            // Coordinating with the peer is best effort: without it, the peer keeps assuming the
+            // workspace it was last told about.
+            if let Err(e) = self.peers.announce(Some(name.to_string())) {
+                warn!("{:?}", e.context("failed to announce workspace"));
+            }
+            self.sanitize_windows()
+                .context("failed to validate saved windows")?;
+
+            let (topo_id, origin) = self
+                .capture_topology()
+                .context("failed to capture topology")?;
+            self.set_active_topology(topo_id, origin, "workspace")
+        })();
+
+        // Go back to the old workspace rather than run without an active topology.
+        if let Err(e) = switched {
+            *self.db.borrow_mut() = prev_db;
+            *self.store.borrow_mut() = prev_store;
+            {
+                let mut data = self.data.borrow_mut();
+                data.workspace = prev_workspace;
+                data.active_topology = prev_topology;
+                data.origin = prev_origin;
+            }
+            if let Err(e) = self
+                .peers
+                .announce(Some(self.data.borrow().workspace.clone()))
+            {
+                warn!("{:?}", e.context("failed to announce workspace"));
+            }
+            self.publish_topology();
+            return Err(e);
+        }
+
+        if let Err(e) = set_active_workspace(name) {
+            warn!("failed to remember the active workspace: {e}");
+        }
+
+        // N.B: Windows are not captured first, as that would overwrite the layout being
+        // switched to with the current one. Windows without a saved placement are picked up
+        // by the next event on them.
+        self.restore_windows("workspace", Default::default())
+    }
+
+    fn on_copy_layout(&self) {
+        match self.compatible_topologies() {
+            Ok(items) => {
+                self.copy_layout_list.set_collection(items);
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+                return;
+            }
+        }
+
+        self.place_dialog(&self.copy_layout_window, Some("copy-layout"));
+        self.copy_layout_window.set_visible(true);
+        self.copy_layout_window.set_focus();
+    }
+
+    fn on_copy_layout_close(&self, data: &nwg::EventData) {
+        // Keep the dialog around to be shown again.
+        if let nwg::EventData::OnWindowClose(data) = data {
+            data.close(false);
+        }
+
+        self.hide_dialog(&self.copy_layout_window, "copy-layout");
+    }
+
+    fn on_copy_layout_copy(&self) {
+        let source = match self.copy_layout_list.selection() {
+            Some(i) => self.copy_layout_list.collection()[i].id,
+            None => return,
+        };
+
+        let res = self
+            .copy_layout(source)
+            .and_then(|_| self.restore_windows("copy layout", Default::default()));
+        match res {
+            Ok(_) => self.hide_dialog(&self.copy_layout_window, "copy-layout"),
+            Err(e) => {
+                nwg::modal_error_message(&self.copy_layout_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_pick_window(&self) {
+        self.start_pick(PickAction::Window);
+    }
+
+    fn on_restore_monitor(&self) {
+        self.start_pick(PickAction::RestoreMonitor);
+    }
+
+    fn start_pick(&self, action: PickAction) {
+        let picked = self.picked.clone();
+        let sender = self.pick_notice.sender();
+
+        if let Err(e) = picker::pick(move |pt| {
+            picked.set(pt.map(|pt| (action, pt)));
+            sender.notice();
+        }) {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+    }
+
+    fn on_picked(&self) {
+        match self.picked.take() {
+            Some((PickAction::Window, pt)) => {
+                if let Some(hwnd) = window::from_point(pt) {
+                    self.on_window_picked(hwnd);
+                }
+            }
+            Some((PickAction::RestoreMonitor, pt)) => {
+                let _ = run_fallible(|| {
+                    self.restore_windows(
+                        "restore monitor",
+                        RestoreScope {
+                            monitor: Some(monitor::from_point(pt)),
+                            ..Default::default()
+                        },
+                    )
+                    .context("failed to restore windows")
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Show how a picked window is identified, and let the user choose what to do with it.
+    fn on_window_picked(&self, hwnd: HWND) {
+        let (key, ids) = match self.window_records(hwnd) {
+            Ok(r) => r,
+            Err(e) => {
+                nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+                return;
+            }
+        };
+
+        let self_restoring = key
+            .exe
+            .as_deref()
+            .is_some_and(|exe| self.is_self_restoring(exe));
+        self.picked_label.set_text(&format!(
+            "Title: {}\r\nClass: {}\r\nImage: {}\r\nRecords: {ids:?}\r\nPlaces its own windows: {}",
+            key.title,
+            key.class,
+            key.exe.as_deref().unwrap_or("<unknown>"),
+            if self_restoring { "yes" } else { "no" },
+        ));
+
+        // Rules apply to an application as a whole, so need its image.
+        for (check, action) in [
+            (&self.picked_foreground, RuleAction::Foreground),
+            (&self.picked_exact_match, RuleAction::ExactMatch),
+            (&self.picked_window_rect, RuleAction::WindowRect),
+            (&self.picked_always_restore, RuleAction::AlwaysRestore),
+        ] {
+            let enabled = match key.exe.as_deref() {
+                Some(exe) => {
+                    check.set_enabled(true);
+                    self.has_rule(exe, action).unwrap_or(false)
+                }
+                None => {
+                    check.set_enabled(false);
+                    false
+                }
+            };
+            check.set_check_state(match enabled {
+                true => nwg::CheckBoxState::Checked,
+                false => nwg::CheckBoxState::Unchecked,
+            });
+        }
+
+        self.data.borrow_mut().picked_window = Some((key, ids));
+        self.place_dialog(&self.picked_window, Some("pick"));
+        self.picked_window.set_visible(true);
+        self.picked_window.set_focus();
+    }
+
+    fn on_picked_close(&self, data: &nwg::EventData) {
+        // Keep the dialog around to be shown again.
+        if let nwg::EventData::OnWindowClose(data) = data {
+            data.close(false);
+        }
+
+        self.hide_dialog(&self.picked_window, "pick");
+        self.data.borrow_mut().picked_window = None;
+    }
+
+    fn on_picked_history(&self) {
+        let ids = match &self.data.borrow().picked_window {
+            Some((_, ids)) => ids.clone(),
+            None => return,
+        };
+
+        match self.window_history(&ids, 20) {
+            Ok(entries) if entries.is_empty() => {
+                nwg::modal_info_message(
+                    &self.picked_window,
+                    "Placement History",
+                    "No placement history recorded.",
+                );
+            }
+            Ok(entries) => {
+                nwg::modal_info_message(
+                    &self.picked_window,
+                    "Placement History",
+                    &entries.join("\n"),
+                );
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.picked_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_picked_forget(&self) {
+        let ids = match &self.data.borrow().picked_window {
+            Some((_, ids)) => ids.clone(),
+            None => return,
+        };
+
+        match self.delete_records(&ids) {
+            Ok(_) => self.hide_dialog(&self.picked_window, "pick"),
+            Err(e) => {
+                nwg::modal_error_message(&self.picked_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_picked_foreground(&self) {
+        self.set_picked_rule(&self.picked_foreground, RuleAction::Foreground);
+    }
+
+    fn on_picked_exact_match(&self) {
+        self.set_picked_rule(&self.picked_exact_match, RuleAction::ExactMatch);
+    }
+
+    fn on_picked_window_rect(&self) {
+        self.set_picked_rule(&self.picked_window_rect, RuleAction::WindowRect);
+    }
+
+    fn on_picked_always_restore(&self) {
+        self.set_picked_rule(&self.picked_always_restore, RuleAction::AlwaysRestore);
+    }
+
+    /// Set a rule for the application of the picked window from the state of its checkbox.
+    fn set_picked_rule(&self, check: &nwg::CheckBox, action: RuleAction) {
+        let exe = match &self.data.borrow().picked_window {
+            Some((key, _)) => key.exe.clone(),
+            None => return,
+        };
+
+        if let Some(exe) = exe {
+            let enabled = check.check_state() == nwg::CheckBoxState::Checked;
+            if let Err(e) = self.set_rule(&exe, action, enabled) {
+                nwg::modal_error_message(&self.picked_window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    fn on_stop_tracking(&self) {
+        let hwnd = self.data.borrow().last_foreground;
+        if let Some(hwnd) = hwnd {
+            self.set_tracking(hwnd, false);
+        }
+    }
+
+    fn on_resume_tracking(&self) {
+        let hwnd = self.data.borrow().last_foreground;
+        if let Some(hwnd) = hwnd {
+            self.set_tracking(hwnd, true);
+        }
+    }
+
+    /// Exclude the application owning a window from tracking, or track it again.
+    fn set_tracking(&self, hwnd: HWND, tracked: bool) {
+        let res = window_app_name(hwnd).and_then(|exe| {
+            self.set_rule(&exe, RuleAction::Exclude, !tracked)?;
+            Ok(exe)
+        });
+
+        match res {
+            Ok(exe) => {
+                let name = exe.rsplit('\\').next().unwrap_or(&exe).to_string();
+                let text = match tracked {
+                    true => format!("Resumed tracking {name}"),
+                    false => format!("Stopped tracking {name}"),
+                };
+
+                self.tray.show(
+                    &text,
+                    Some("Persistent Windows"),
+                    Some(TrayNotificationFlags::LARGE_ICON),
+                    Some(&self.icon),
+                );
+            }
+            Err(e) => {
+                nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+            }
+        }
+    }
+
+    /// Report the applications that had windows at the end of the previous session, but have
+    /// not shown any since startup, so the user knows what they still need to launch.
+    fn on_startup_report(&self) {
+        self.startup_report_timer.stop();
+
+        let missing = match self.missing_apps() {
+            Ok(missing) => missing,
+            Err(e) => {
+                warn!(
+                    "{:?}",
+                    e.context("failed to check for missing applications")
+                );
+                return;
+            }
+        };
+
+        if missing.is_empty() {
+            info!("startup report: every application from the previous session is back");
+            return;
+        }
+
+        info!("startup report: not running since startup: {missing:?}");
+
+        let names = missing
+            .iter()
+            .map(|exe| exe.rsplit('\\').next().unwrap_or(exe))
+            .collect::<Vec<_>>();
+        let text = match names.len() {
+            n if n > 5 => format!("{} and {} more", names[..5].join(", "), n - 5),
+            _ => names.join(", "),
+        };
+
+        self.tray.show(
+            &text,
+            Some("Not running since startup"),
+            Some(TrayNotificationFlags::LARGE_ICON),
+            Some(&self.icon),
+        );
+    }
+
+    /// The applications with windows saved in the active topology towards the end of the
+    /// previous session that have no window open now, by normalized image path.
+    fn missing_apps(&self) -> anyhow::Result<Vec<String>> {
+        let (topology, started) = {
+            let data = self.data.borrow();
+            (
+                data.active_topology.expect("no active topology"),
+                data.started,
+            )
+        };
+
+        let records = self.store().records(topology)?;
+        let Some(last) = records
+            .iter()
+            .map(|r| r.updated)
+            .filter(|updated| *updated < started)
+            .max()
+        else {
+            return Ok(Vec::new());
+        };
+        let expected = records
+            .into_iter()
+            .filter(|r| r.updated >= last.saturating_sub(EXPECTED_APP_AGE.as_secs()))
+            .filter_map(|r| r.key.exe)
+            .collect::<BTreeSet<_>>();
+
+        let running = window::windows()
+            .context("failed to query windows")?
+            .into_iter()
+            .filter(|hwnd| hwnd.is_visible() && hwnd.is_top_level())
+            .filter_map(|hwnd| window_app_name(hwnd).ok())
+            .collect::<HashSet<_>>();
+
+        Ok(expected
+            .into_iter()
+            .filter(|exe| !running.contains(exe))
+            .filter(|exe| !self.has_rule(exe, RuleAction::Exclude).unwrap_or(false))
+            .collect())
+    }
+
+    fn on_exit(&self) {
+        // Put the user's animation setting back if a restore is in flight.
+        self.cancel_restore();
+
+        // Hand the maintenance of a shared database over to the other instance.
+        if let Err(e) = self.peers.announce(None) {
+            warn!("{:?}", e.context("failed to announce exit"));
+        }
+        nwg::stop_thread_dispatch();
+    }
+
+    /// Find the record tracking a window in a topology.
+    ///
+    /// Within a session, windows are tracked by handle so a record follows its window even as
+    /// the title changes. Windows not yet seen this session are matched by their tag, if an
+    /// earlier run stamped them, or else by their key against the records not already claimed
+    /// by another window.
+    fn find_record(
+        &self,
+        hwnd: HWND,
+        key: &WindowKey,
+        topology: usize,
+    ) -> anyhow::Result<Option<i64>> {
+        if let Some(id) = self.data.borrow().records.get(&(hwnd.0, topology)) {
+            return Ok(Some(*id));
+        }
+
+        let claimed = self
+            .data
+            .borrow()
+            .records
+            .iter()
+            .filter(|((_, t), _)| *t == topology)
+            .map(|(_, id)| *id)
+            .collect::<HashSet<_>>();
+
+        // Windows stamped by an earlier run of the app keep their record, whatever their title is
+        // now and however many windows share it.
+        let tagged = match hwnd.tag() {
+            Some(tag) => self.store().tagged_record(topology, &format!("{tag:?}"))?,
+            None => None,
+        }
+        .filter(|id| !claimed.contains(id));
+
+        let candidates = self
+            .store()
+            .candidates(topology, key.exe.as_deref(), &key.class)?
+            .into_iter()
+            .filter(|(id, _)| !claimed.contains(id))
+            .collect::<Vec<_>>();
+
+        let exact_only = self.key_has_rule(key, RuleAction::ExactMatch);
+        let id = match plan::find_match(&key.title, tagged, &candidates, exact_only) {
+            Some((id, MatchTier::Exact | MatchTier::Tagged)) => Some(id),
+            Some((id, tier)) => {
+                info!(
+                    "matched {:#010X} ({}) to record {id} by {tier:?} tier",
+                    hwnd.0, key.title
+                );
+                Some(id)
+            }
+            None => None,
+        };
+
+        if let Some(id) = id {
+            let mut data = self.data.borrow_mut();
+            data.records.insert((hwnd.0, topology), id);
+            // The stamped record now stands for the window, so any other record under its
+            // current title is left over from an earlier run.
+            if tagged.is_some() {
+                data.superseded.extend(
+                    candidates
+                        .iter()
+                        .filter(|(c, title)| *c != id && *title == key.title)
+                        .map(|(c, _)| *c),
+                );
+            }
+            drop(data);
+            self.store().touch_record(id, unix_time()?)?;
+        }
+
+        Ok(id)
+    }
+
+    fn find_window(&self, hwnd: HWND, topology: usize) -> anyhow::Result<Option<WindowDisplay>> {
+        let key = WindowKey::from_window(hwnd)?;
+        if key.is_remote() || self.is_excluded(&key) {
+            return Ok(None);
+        }
+
+        let id = match self.find_record(hwnd, &key, topology)? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let disp = self.store().record_display(id)?;
+
+        // Placements that have not been seen in a long time likely no longer reflect how the
+        // user works with the window, so let the window keep its current placement.
+        let max_age = self.data.borrow().max_record_age;
+        if let (Some(days), Some((_, updated))) = (max_age, &disp) {
+            if unix_time()?.saturating_sub(*updated) > days * 24 * 60 * 60 {
+                return Ok(None);
+            }
+        }
+
+        match disp.map(|(disp, _)| WindowDisplay::decode(&disp)) {
+            Some(Ok(disp)) => Ok(Some(disp)),
+            Some(Err(e)) => {
+                warn!("{e:?}");
+                self.quarantine_window(id, &format!("{e}"))?;
+
+                Ok(None)
+            }
+            None => {
+                // The record was removed since this window claimed it.
+                self.data.borrow_mut().records.remove(&(hwnd.0, topology));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Move a record that failed validation out of `appwindow` so it is never considered again.
+    fn quarantine_window(&self, id: i64, reason: &str) -> anyhow::Result<()> {
+        {
+            let store = self.store();
+            let tx = store.begin()?;
+            store.quarantine_record(id, reason)?;
+            tx.commit()?;
+        }
+
+        self.data.borrow_mut().records.retain(|_, r| *r != id);
+        Ok(())
+    }
+
+    /// Validate every persisted record, quarantining any that are invalid.
+    fn sanitize_windows(&self) -> anyhow::Result<()> {
+        if !self.maintains_database() {
+            return Ok(());
+        }
+
+        let mut invalid = Vec::new();
+        for topology in self.store().topologies()? {
+            for record in self.store().records(topology)? {
+                if let Err(e) = WindowDisplay::decode(record.placement()) {
+                    invalid.push((record.id, format!("{e}")));
+                }
+            }
+        }
+
+        for (id, reason) in invalid {
+            warn!("quarantining record {id}: {reason}");
+            self.quarantine_window(id, &reason)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove records left behind by windows that changed their title.
+    ///
+    /// Records of the same application (image path and class) in the same topology whose
+    /// titles are the same once normalized are duplicates: the most recently updated one is
+    /// kept and the rest are deleted, along with those `find_record` passed over for a stamped
+    /// record. Records placed by the user, of closed windows or claimed by a window this session
+    /// are always kept.
+    fn merge_duplicate_windows(&self) -> anyhow::Result<()> {
+        let (claimed, superseded) = {
+            let data = self.data.borrow();
+            let claimed = data.records.values().copied().collect::<HashSet<_>>();
+            (claimed, data.superseded.clone())
+        };
+
+        let mut rows = Vec::new();
+        for topology in self.store().topologies()? {
+            for record in self.store().records(topology)? {
+                if record.user_disp.is_some() || record.closed.is_some() {
+                    continue;
+                }
+                let title = matching::normalize_title(&record.key.title);
+                rows.push((
+                    record.id,
+                    (topology, record.key.exe, record.key.class, title),
+                    record.updated,
+                ));
+            }
+        }
+        rows.sort_by(|(_, a, a_updated), (_, b, b_updated)| {
+            a.cmp(b).then(b_updated.cmp(a_updated))
+        });
+
+        // Rows are sorted by group, most recent first, so every row in a group after the first
+        // one not superseded is a duplicate.
+        let mut duplicates = Vec::new();
+        let mut group = None;
+        for (id, key, _) in rows {
+            if superseded.contains(&id) || group.as_ref() == Some(&key) {
+                duplicates.push(id);
+            } else {
+                group = Some(key);
+            }
+        }
+        duplicates.retain(|id| !claimed.contains(id));
+
+        if duplicates.is_empty() {
+            return Ok(());
+        }
+        info!("removing {} duplicate records", duplicates.len());
+
+        let store = self.store();
+        let tx = store.begin()?;
+        for id in &duplicates {
+            store.delete_record(*id)?;
+        }
+        tx.commit()?;
+        drop(store);
+
+        self.data
+            .borrow_mut()
+            .superseded
+            .retain(|id| !duplicates.contains(id));
+
+        Ok(())
+    }
+
+    /// Restore windows to their saved placements in the active topology, limited to the given
+    /// scope. The `trigger` is noted in the history of every restored window.
+    fn restore_windows(&self, trigger: &'static str, scope: RestoreScope) -> anyhow::Result<()> {
+        let plan = self.plan_restore(trigger, &scope)?;
+        self.execute_plan(plan);
+
+        Ok(())
+    }
+
+    /// Plan a restore pass over the windows in `scope`, without touching any of them.
+    fn plan_restore(
+        &self,
+        trigger: &'static str,
+        scope: &RestoreScope,
+    ) -> anyhow::Result<RestorePlan> {
+        let mut handles = window::windows().context("failed to query windows")?;
+
+        // Restore in least-recently-used order, so the windows the user was last working with
+        // are placed last.
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+        let mru = self
+            .mru_windows(topology)
+            .context("failed to query recently used windows")?;
+        handles.sort_by_key(|hwnd| {
+            std::cmp::Reverse(mru.iter().position(|h| h == hwnd).unwrap_or(usize::MAX))
+        });
+
+        let (hmonitors, monitors) = plan_monitors()?;
+        let mut batches = monitors.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+        let mut claimed = HashSet::new();
+        for hwnd in handles {
+            if scope.window.is_some_and(|w| w != hwnd)
+                || quirks::for_window(hwnd).proxy
+                || is_ghost(hwnd)
+            {
+                continue;
+            }
+
+            if let Some(apps) = &scope.apps {
+                if !window_app_name(hwnd).is_ok_and(|exe| apps.contains(&exe)) {
+                    continue;
+                }
+            }
+
+            let disp = match (scope.checkpoint, scope.as_of) {
+                (Some(checkpoint), _) => {
+                    self.find_checkpoint_restore(hwnd, checkpoint, &mut claimed)
+                }
+                (None, Some(time)) => self.find_history_restore(hwnd, time),
+                (None, None) => self.find_restore(hwnd),
+            };
+
+            // Silently ignore any errors for individual windows.
+            let mut disp = match disp {
+                Ok(Some(disp)) => disp,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("{}", e.context("failed to restore window"));
+                    continue;
+                }
+            };
+
+            // Land the placement in screen coordinates, where the monitors are. Maximized windows
+            // are maximized onto the monitor holding their normal rect, so this also decides
+            // which work area they are maximized into.
+            let screen = workspace_to_screen(hwnd, &disp.rect);
+            let landing = plan::land(&monitors, &screen, disp.dpi);
+            if landing.rect != screen {
+                info!("landing {screen:?} at {:?}", landing.rect);
+            }
+            // Scale the minimized and maximized positions along with the normal rect.
+            let mon = &monitors[landing.monitor];
+            disp = disp.scale_dpi(&mon.rect, mon.dpi);
+            disp.rect = screen_to_workspace(hwnd, &landing.rect);
+
+            let mon = hmonitors[landing.monitor];
+            if scope.monitor.is_some_and(|m| m != mon) {
+                continue;
+            }
+
+            if scope.misplaced_only && !is_misplaced(hwnd, mon) {
+                continue;
+            }
+
+            let Ok(placement) = hwnd.placement() else {
+                // The window is gone.
+                continue;
+            };
+
+            if scope.moved_only
+                && placement.showCmd.0 == disp.show
+                && normal_rect(hwnd, &placement, disp.source) == disp.rect
+            {
+                continue;
+            }
+
+            let strategy = if self.window_has_rule(hwnd, RuleAction::Foreground) {
+                RestoreStrategy::Foreground
+            } else if SHOW_WINDOW_CMD(disp.show) == SW_SHOWNORMAL
+                && !hwnd.is_minimized()
+                && !hwnd.is_maximized()
+            {
+                RestoreStrategy::Deferred
+            } else {
+                RestoreStrategy::Placement
+            };
+
+            // Checkpoints do not record virtual desktops.
+            let desktop = match scope.checkpoint {
+                Some(_) => None,
+                None => self.record_desktop(hwnd, topology),
+            };
+
+            let restore = PlannedRestore {
+                hwnd,
+                current: placement.rcNormalPosition.into(),
+                target: disp,
+                desktop,
+                strategy,
+            };
+            batches[landing.monitor].push(restore);
+        }
+
+        Ok(RestorePlan {
+            trigger,
+            batches: batches.into_iter().filter(|b| !b.is_empty()).collect(),
+        })
+    }
+
+    /// Queue the batches of a plan to be applied by `restore_timer`.
+    fn execute_plan(&self, plan: RestorePlan) {
+        // Any restores still queued from a previous pass are stale now.
+        self.cancel_restore();
+        if plan.batches.is_empty() {
+            return;
+        }
+
+        let delay = {
+            let mut data = self.data.borrow_mut();
+            data.restore_queue = plan.batches.into();
+            data.restore_trigger = plan.trigger;
+            data.restore_delay
+        };
+
+        self.set_transition(Transition::Restoring);
+        self.restore_timer.set_interval(delay);
+        self.restore_timer.start();
+    }
+
+    /// Abandon the restore plan in flight, if any.
+    fn cancel_restore(&self) {
+        self.restore_timer.stop();
+        self.retry_timer.stop();
+        self.data.borrow_mut().restore_queue.clear();
+        self.data.borrow_mut().restored.clear();
+
+        if self.data.borrow().transition == Transition::Restoring {
+            self.set_transition(Transition::Idle);
+        }
+    }
+
+    fn set_transition(&self, transition: Transition) {
+        let prev = std::mem::replace(&mut self.data.borrow_mut().transition, transition);
+        if prev != transition {
+            info!("topology transition: {prev:?} -> {transition:?}");
+        }
+
+        // Dozens of windows animating at once out of their minimized or maximized states looks
+        // chaotic and slows the pass down, so animations are off while restoring.
+        let restoring = transition == Transition::Restoring;
+        let suppressed = self.data.borrow().animations_suppressed;
+        if restoring && !suppressed && window::animations().unwrap_or(false) {
+            match window::set_animations(false) {
+                Ok(()) => self.data.borrow_mut().animations_suppressed = true,
+                Err(e) => warn!("failed to turn off window animations: {e}"),
+            }
+        } else if !restoring && suppressed {
+            self.data.borrow_mut().animations_suppressed = false;
+            if let Err(e) = window::set_animations(true) {
+                warn!("failed to turn window animations back on: {e}");
+            }
+        }
+    }
+
+    /// Apply the next queued batch of restores. Batches are paced by `restore_timer` so that
+    /// applications are not flooded with placement changes all at once.
+    fn on_restore_tick(&self) {
+        let batch = self.data.borrow_mut().restore_queue.pop_front();
+        let batch = match batch {
+            Some(batch) => batch,
+            None => {
+                self.restore_timer.stop();
+                self.set_transition(Transition::Idle);
+
+                self.data.borrow_mut().retry_pass = 0;
+                self.schedule_retry(Duration::ZERO);
+                return;
+            }
+        };
+
+        // The window may have been minimized or maximized since the plan was made, in which
+        // case it needs a full placement after all.
+        let (deferred, mut placed): (Vec<_>, Vec<_>) = batch.into_iter().partition(|r| {
+            r.strategy == RestoreStrategy::Deferred
+                && !r.hwnd.is_minimized()
+                && !r.hwnd.is_maximized()
+        });
+
+        let positions = deferred
+            .iter()
+            .map(|r| (r.hwnd, workspace_to_screen(r.hwnd, &r.target.rect)))
+            .collect::<Vec<_>>();
+        match window::set_positions(&positions) {
+            Ok(_) => {
+                for r in &deferred {
+                    info!(
+                        "restoring {:#010X} from {:?} to {:?} (deferred)",
+                        r.hwnd.0, r.current, r.target.rect
+                    );
+                    self.finish_restore(r);
+                }
+            }
+            Err(e) => {
+                warn!("deferred positioning failed, falling back to placement: {e}");
+                placed.extend(deferred);
+            }
+        }
+
+        for r in placed {
+            // Silently ignore any errors for individual windows.
+            match self.restore_window(&r) {
+                Ok(_) => self.finish_restore(&r),
+                Err(e) => {
+                    error!("{}", e.context("failed to restore window"));
+                    self.count_restore(r.hwnd, false);
+                }
+            }
+        }
+    }
+
+    /// Move a window created after the last restore pass (e.g. by an application started after
+    /// docking) to its saved placement as it is first shown. It is checked again by
+    /// `retry_timer`, as applications often position their windows themselves right after.
+    fn place_new_window(&self, hwnd: HWND) -> anyhow::Result<()> {
+        {
+            let data = self.data.borrow();
+            if data.frozen || data.transition != Transition::Idle || data.paused {
+                return Ok(());
+            }
+        }
+
+        let plan = self.plan_restore(
+            "new window",
+            &RestoreScope {
+                window: Some(hwnd),
+                ..Default::default()
+            },
+        )?;
+
+        let Some(restore) = plan.batches.into_iter().flatten().next() else {
+            return Ok(());
+        };
+
+        // A window appearing right where it was saved was put there by its application.
+        let placement = hwnd.placement().context("failed to query placement")?;
+        let in_place = placement.showCmd.0 == restore.target.show
+            && normal_rect(hwnd, &placement, restore.target.source) == restore.target.rect;
+        if let Ok(exe) = window_app_name(hwnd) {
+            if let Err(e) = self.note_self_restore(&exe, true, in_place) {
+                warn!("{:?}", e.context("failed to note self-restoring window"));
+            }
+            if self.is_self_restoring(&exe) {
+                info!(
+                    "leaving new window {:#010X} to {exe}, which places its windows itself",
+                    hwnd.0
+                );
+                return Ok(());
+            }
+        }
+        if in_place {
+            return Ok(());
+        }
+
+        info!(
+            "placing new window {:#010X} from {:?} to {:?}",
+            hwnd.0, restore.current, restore.target.rect
+        );
+        self.data.borrow_mut().restore_trigger = plan.trigger;
+        match self.restore_window(&restore) {
+            Ok(()) => self.finish_restore(&restore),
+            Err(e) => {
+                self.count_restore(hwnd, false);
+                return Err(e);
+            }
+        }
+
+        self.data.borrow_mut().placed_new.insert(hwnd.0);
+        self.data.borrow_mut().retry_pass = 0;
+        self.schedule_retry(Duration::ZERO);
+        Ok(())
+    }
+
+    /// Count a new window of an application towards telling whether it places its windows
+    /// itself: `shown` for every new window with a saved placement, and `hit` when the window
+    /// appeared at that placement, or moved away right after being placed there.
+    fn note_self_restore(&self, exe: &str, shown: bool, hit: bool) -> anyhow::Result<()> {
+        self.db()
+            .execute(
+                "INSERT INTO self_restore (exe, shows, hits) VALUES (:exe, :shows, :hits)
+                    ON CONFLICT (exe) DO UPDATE SET shows=shows+:shows, hits=hits+:hits",
+                named_params! {
+                    ":exe": exe,
+                    ":shows": shown as u32,
+                    ":hits": hit as u32,
+                },
+            )
+            .context("failed to query database")?;
+
+        Ok(())
+    }
+
+    /// Whether an application appears to put its new windows back where they were on its own,
+    /// as most of them were, in which case they are left alone rather than fought over. Its
+    /// windows are still captured, and restored on display changes.
+    fn is_self_restoring(&self, exe: &str) -> bool {
+        if self
+            .has_rule(exe, RuleAction::AlwaysRestore)
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        let counts = self
+            .db()
+            .query_row(
+                "SELECT shows, hits FROM self_restore WHERE exe=:exe",
+                named_params! { ":exe": exe },
+                |r| Ok((r.get::<usize, u32>(0)?, r.get::<usize, u32>(1)?)),
+            )
+            .optional();
+
+        match counts {
+            Ok(Some((shows, hits))) => shows >= SELF_RESTORE_MIN_SHOWS && hits * 2 > shows,
+            Ok(None) => false,
+            Err(e) => {
+                warn!("failed to query self-restoring application: {e}");
+                false
+            }
+        }
+    }
+
+    /// Put back the state a placement does not cover (the virtual desktop and z-band) of a
+    /// restored window, and note the restore.
+    fn finish_restore(&self, restore: &PlannedRestore) {
+        let hwnd = restore.hwnd;
+        let topmost = (hwnd.ex_style() & WS_EX_TOPMOST).0 != 0;
+        if let Some(saved) = restore.target.topmost.filter(|t| *t != topmost) {
+            if let Err(e) = window::set_topmost(hwnd, saved) {
+                warn!("failed to restore z-band of {:#010X}: {e}", hwnd.0);
+            }
+        }
+
+        self.restore_desktop(restore);
+        self.note_restore(hwnd, &restore.target);
+
+        self.data
+            .borrow_mut()
+            .restored
+            .insert(hwnd.0, restore.target.clone());
+    }
+
+    /// Start `retry_timer` for the next retry check, `elapsed` after the restore pass.
+    fn schedule_retry(&self, elapsed: Duration) {
+        let pass = self.data.borrow().retry_pass;
+        let delay = config::with(|c| c.restore_retries().get(pass).copied());
+
+        match delay {
+            Some(delay) if !self.data.borrow().restored.is_empty() => {
+                self.retry_timer
+                    .set_interval(delay.saturating_sub(elapsed).max(Duration::from_millis(1)));
+                self.retry_timer.start();
+            }
+            _ => self.data.borrow_mut().restored.clear(),
+        }
+    }
+
+    /// Some applications re-adjust their own position a few seconds after a display change,
+    /// undoing the restore. Put back the windows of the last restore pass that moved away from
+    /// their restored placement.
+    fn on_retry_tick(&self) {
+        self.retry_timer.stop();
+
+        let placed_new = std::mem::take(&mut self.data.borrow_mut().placed_new);
+        let restored = self.data.borrow().restored.clone();
+        for (h, disp) in restored {
+            let hwnd = HWND(h);
+            let Ok(placement) = hwnd.placement() else {
+                // The window is gone.
+                self.data.borrow_mut().restored.remove(&h);
+                continue;
+            };
+
+            if placement.showCmd.0 == disp.show
+                && normal_rect(hwnd, &placement, disp.source) == disp.rect
+            {
+                continue;
+            }
+
+            // New windows moving away right after they were placed are positioned by their
+            // application.
+            if placed_new.contains(&h) {
+                if let Ok(exe) = window_app_name(hwnd) {
+                    if let Err(e) = self.note_self_restore(&exe, false, true) {
+                        warn!("{:?}", e.context("failed to note self-restoring window"));
+                    }
+                }
+            }
+
+            info!(
+                "{:#010X} moved away after being restored, restoring again",
+                hwnd.0
+            );
+            if let Err(e) = self.apply_placement(hwnd, &disp, disp.placement()) {
+                warn!("{:?}", e.context("failed to restore window again"));
+            }
+        }
+
+        let elapsed = {
+            let mut data = self.data.borrow_mut();
+            data.retry_pass += 1;
+            config::with(|c| c.restore_retries().get(data.retry_pass - 1).copied())
+        };
+        self.schedule_retry(elapsed.unwrap_or_default());
+    }
+
+    /// The virtual desktop saved in the record a window claimed in a topology, if any.
+    fn record_desktop(&self, hwnd: HWND, topology: usize) -> Option<windows::core::GUID> {
+        let id = *self.data.borrow().records.get(&(hwnd.0, topology))?;
+        match self.store().record_desktop(id) {
+            Ok(desktop) => vdesktop::parse_id(&desktop?),
+            Err(e) => {
+                warn!("failed to query virtual desktop: {e}");
+                None
+            }
+        }
+    }
+
+    /// Move a restored window back to the virtual desktop it was captured on.
+    fn restore_desktop(&self, restore: &PlannedRestore) {
+        let Some(desktop) = restore.desktop else {
+            return;
+        };
+
+        let hwnd = restore.hwnd;
+        match vdesktop::desktop_id(hwnd) {
+            Ok(Some(current)) if current == desktop => {}
+            // Windows pinned to all desktops have no desktop of their own.
+            Ok(None) => {}
+            _ => {
+                info!("moving {:#010X} to virtual desktop {desktop:?}", hwnd.0);
+                if let Err(e) = vdesktop::move_to_desktop(hwnd, &desktop) {
+                    warn!(
+                        "failed to move {:#010X} to its virtual desktop: {e}",
+                        hwnd.0
+                    );
+                }
+            }
+        }
+    }
+
+    /// Record a restore applied to a window in the history of the record it was restored from.
+    fn note_restore(&self, hwnd: HWND, disp: &WindowDisplay) {
+        let (topology, trigger) = {
+            let data = self.data.borrow();
+            (
+                data.active_topology.expect("no active topology"),
+                data.restore_trigger,
+            )
+        };
+
+        let id = self.data.borrow().records.get(&(hwnd.0, topology)).copied();
+        if let Some(id) = id {
+            if let Err(e) = self.record_history(id, topology, "restore", trigger, disp) {
+                warn!("{:?}", e.context("failed to record restore"));
+            }
+        }
+
+        self.count_restore(hwnd, true);
+    }
+
+    /// Count a restore attempt towards the usage statistics of the window's application in the
+    /// active topology. Statistics never leave the workspace database.
+    fn count_restore(&self, hwnd: HWND, succeeded: bool) {
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+        let exe = window_app_name(hwnd).unwrap_or_else(|_| "(unknown)".to_string());
+
+        let res = self.db().execute(
+            "INSERT INTO stat (topology, exe, restored, failed) VALUES (:topology, :exe, :restored, :failed)
+                ON CONFLICT (topology, exe) DO UPDATE SET
+                    restored=restored + excluded.restored, failed=failed + excluded.failed",
+            named_params! {
+                ":topology": topology,
+                ":exe": exe,
+                ":restored": succeeded as u32,
+                ":failed": !succeeded as u32,
+            },
+        );
+
+        if let Err(e) = res {
+            warn!("failed to update statistics: {e}");
+        }
+    }
+
+    /// Summarize the usage statistics: restores per topology, the most frequently restored
+    /// applications, and the applications that most often fail to restore.
+    fn statistics(&self) -> anyhow::Result<String> {
+        let db = self.db();
+        let mut lines = Vec::new();
+
+        lines.push("Restores by topology:".to_string());
+        let mut stmt = db
+            .prepare(
+                "SELECT topology, SUM(restored), SUM(failed) FROM stat
+                    GROUP BY topology ORDER BY SUM(restored) DESC",
+            )
+            .context("failed to prepare query")?;
+        for row in stmt
+            .query_map([], |r| {
+                Ok(format!(
+                    "  {}: {} restored, {} failed",
+                    r.get::<usize, usize>(0)?,
+                    r.get::<usize, u64>(1)?,
+                    r.get::<usize, u64>(2)?
+                ))
+            })
+            .context("failed to query database")?
+        {
+            lines.push(row.context("failed to read row")?);
+        }
+
+        lines.push(String::new());
+        lines.push("Most restored applications:".to_string());
+        let mut stmt = db
+            .prepare(
+                "SELECT exe, SUM(restored) AS n FROM stat GROUP BY exe
+                    HAVING n > 0 ORDER BY n DESC LIMIT 10",
+            )
+            .context("failed to prepare query")?;
+        for row in stmt
+            .query_map([], |r| {
+                Ok(format!(
+                    "  {}: {}",
+                    r.get::<usize, String>(0)?,
+                    r.get::<usize, u64>(1)?
+                ))
+            })
+            .context("failed to query database")?
+        {
+            lines.push(row.context("failed to read row")?);
+        }
+
+        lines.push(String::new());
+        lines.push("Restore failures:".to_string());
+        let mut stmt = db
+            .prepare(
+                "SELECT exe, SUM(failed) AS f, SUM(restored) + SUM(failed) FROM stat GROUP BY exe
+                    HAVING f > 0 ORDER BY f * 1.0 / (SUM(restored) + SUM(failed)) DESC LIMIT 10",
+            )
+            .context("failed to prepare query")?;
+        for row in stmt
+            .query_map([], |r| {
+                let failed = r.get::<usize, u64>(1)?;
+                let total = r.get::<usize, u64>(2)?;
+
+                Ok(format!(
+                    "  {}: {failed} of {total} ({:.0}%)",
+                    r.get::<usize, String>(0)?,
+                    failed as f64 * 100.0 / total as f64
+                ))
+            })
+            .context("failed to query database")?
+        {
+            lines.push(row.context("failed to read row")?);
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Reinstall the event hooks if they appear to have died. The system can drop a hook without
+    /// notice, leaving the app running but blind to window changes, so the hooks are expected to
+    /// deliver events for as long as the user is interacting with the desktop.
+    fn on_hook_check(&self) {
+        if self.hooks.borrow().is_empty() {
+            return;
+        }
+
+        let silent = EventHook::last_event().is_none_or(|t| t.elapsed() >= HOOK_STALL_TIMEOUT);
+        let active = idle_time().is_some_and(|idle| idle < HOOK_CHECK_INTERVAL);
+        if !silent || !active {
+            return;
+        }
+
+        warn!("no window events received for {HOOK_STALL_TIMEOUT:?}, reinstalling hooks");
+        self.reinstall_hooks();
+    }
+
+    fn on_restore_now(&self) {
+        if let Err(e) = self.restore_windows("manual", Default::default()) {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+    }
+
+    fn on_restore_session(&self) {
+        if let Err(e) = self.restore_session() {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+    }
+
+    /// Launch the applications from the previous session that are not running again, with the
+    /// command line and working directory they were last started with, and restore their
+    /// windows once they appear.
+    fn restore_session(&self) -> anyhow::Result<()> {
+        let launches = self.session_launches()?;
+        if launches.is_empty() {
+            nwg::modal_info_message(
+                &self.window,
+                "Restore Session",
+                "Every application from the previous session is running, or it is not known how to start it.",
+            );
+            return Ok(());
+        }
+
+        let names = launches
+            .iter()
+            .map(|l| l.exe.rsplit('\\').next().unwrap_or(&l.exe))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let choice = nwg::message(&nwg::MessageParams {
+            title: "Restore Session",
+            content: &format!("Start these applications again?\n\n{names}"),
+            buttons: nwg::MessageButtons::YesNo,
+            icons: nwg::MessageIcons::Question,
+        });
+        if choice != nwg::MessageChoice::Yes {
+            return Ok(());
+        }
+
+        let mut failed = Vec::new();
+        for launch in launches {
+            match launch.spawn() {
+                Ok(()) => {
+                    info!("session restore: started {}", launch.command_line);
+                    self.data
+                        .borrow_mut()
+                        .relaunched
+                        .insert(launch.exe, (Stamp::now(), 0));
+                }
+                Err(e) => {
+                    warn!("session restore: failed to start {}: {e}", launch.exe);
+                    failed.push(format!("{}: {e}", launch.exe));
+                }
+            }
+        }
+
+        if !self.data.borrow().relaunched.is_empty() {
+            self.session_timer.start();
+        }
+
+        if !failed.is_empty() {
+            anyhow::bail!("failed to start:\n{}", failed.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    /// The applications from the previous session that are not running and whose command line
+    /// is known.
+    fn session_launches(&self) -> anyhow::Result<Vec<Launch>> {
+        let missing = self.missing_apps()?;
+
+        let db = self.db();
+        let mut stmt = db
+            .prepare("SELECT command_line, directory FROM launch WHERE exe=?1")
+            .context("failed to prepare query")?;
+
+        let mut launches = Vec::new();
+        for exe in missing {
+            let launch = stmt
+                .query_row([&exe], |r| {
+                    Ok((
+                        r.get::<usize, String>(0)?,
+                        r.get::<usize, Option<String>>(1)?,
+                    ))
+                })
+                .optional()
+                .context("failed to query database")?;
+
+            if let Some((command_line, directory)) = launch {
+                launches.push(Launch {
+                    exe,
+                    command_line,
+                    directory,
+                });
+            }
+        }
+
+        Ok(launches)
+    }
+
+    /// Restore the windows of the applications launched by a session restore once they have
+    /// shown them, and give up on those taking too long.
+    fn on_session_tick(&self) {
+        let shown = match window::windows() {
+            Ok(windows) => windows
+                .into_iter()
+                .filter(|hwnd| hwnd.is_visible() && hwnd.is_top_level())
+                .filter_map(|hwnd| window_app_name(hwnd).ok())
+                .collect::<HashSet<_>>(),
+            Err(e) => {
+                warn!("failed to query windows: {e}");
+                return;
+            }
+        };
+
+        let ready = {
+            let mut data = self.data.borrow_mut();
+            let mut ready = HashSet::new();
+            data.relaunched.retain(|exe, (started, polls)| {
+                if shown.contains(exe) {
+                    *polls += 1;
+                    if *polls >= SESSION_READY_POLLS {
+                        ready.insert(exe.clone());
+                        return false;
+                    }
+                } else if started.elapsed() >= RELAUNCH_TIMEOUT {
+                    warn!(
+                        "session restore: {exe} did not show a window within {RELAUNCH_TIMEOUT:?}"
+                    );
+                    return false;
+                }
+
+                true
+            });
+
+            if data.relaunched.is_empty() {
+                self.session_timer.stop();
+            }
+            ready
+        };
+
+        if ready.is_empty() {
+            return;
+        }
+
+        let scope = RestoreScope {
+            apps: Some(ready),
+            ..Default::default()
+        };
+        if let Err(e) = self.restore_windows("session", scope) {
+            warn!("{:?}", e.context("failed to restore relaunched windows"));
+        }
+    }
+
+    fn on_rollback_minutes(&self) {
+        self.on_rollback(Duration::from_secs(5 * 60));
+    }
+
+    fn on_rollback_hour(&self) {
+        self.on_rollback(Duration::from_secs(60 * 60));
+    }
+
+    fn on_rollback_day(&self) {
+        self.on_rollback(Duration::from_secs(24 * 60 * 60));
+    }
+
+    fn on_rollback(&self, age: Duration) {
+        if let Err(e) = self.rollback(age) {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+    }
+
+    /// Roll the windows of the active topology back to where they were captured `age` ago, and
+    /// make those placements the saved ones again. This undoes bad captures (e.g. taken while
+    /// monitors were half-attached) that replaced a good layout.
+    fn rollback(&self, age: Duration) -> anyhow::Result<()> {
+        let time = unix_time()?.saturating_sub(age.as_secs());
+        let plan = self.plan_restore(
+            "rollback",
+            &RestoreScope {
+                as_of: Some(time),
+                ..Default::default()
+            },
+        )?;
+
+        if plan.batches.iter().all(|b| b.is_empty()) {
+            nwg::modal_info_message(
+                &self.window,
+                "Roll Back Layout",
+                "No window has an earlier placement to roll back to.",
+            );
+            return Ok(());
+        }
+
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+        let origin = self.data.borrow().origin.clone();
+        let updated = unix_time()?;
+        for restore in plan.batches.iter().flatten() {
+            let Some(id) = self
+                .data
+                .borrow()
+                .records
+                .get(&(restore.hwnd.0, topology))
+                .copied()
+            else {
+                continue;
+            };
+            let Some(key) = self.store().record_key(id)? else {
+                continue;
+            };
+
+            let mut disp = Vec::new();
+            bson::to_document(&restore.target.clone().offset(-origin.x, -origin.y))
+                .unwrap()
+                .to_writer(&mut disp)
+                .unwrap();
+
+            // Also saved as the placement the user chose, so incidental captures do not
+            // replace it when restoring.
+            self.store().update_record(
+                id,
+                &store::RecordData {
+                    key: &key,
+                    disp: &disp,
+                    user_disp: Some(&disp),
+                    trigger: "rollback",
+                    updated,
+                    desktop: None,
+                    tag: None,
+                },
+            )?;
+        }
+
+        info!("rolling back windows to their placements as of {time}");
+        self.execute_plan(plan);
+
+        Ok(())
+    }
+
+    fn on_policy_full(&self) {
+        self.on_policy_selected(RestorePolicy::Full);
+    }
+
+    fn on_policy_missing(&self) {
+        self.on_policy_selected(RestorePolicy::MissingOnly);
+    }
+
+    fn on_policy_manual(&self) {
+        self.on_policy_selected(RestorePolicy::Manual);
+    }
+
+    fn on_policy_selected(&self, policy: RestorePolicy) {
+        if let Err(e) = self.set_restore_policy(policy) {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+
+        self.check_restore_policy();
+    }
+
+    fn check_restore_policy(&self) {
+        let policy = self.restore_policy().unwrap_or_default();
+        self.tray_menu_policy_full
+            .set_checked(policy == RestorePolicy::Full);
+        self.tray_menu_policy_missing
+            .set_checked(policy == RestorePolicy::MissingOnly);
+        self.tray_menu_policy_manual
+            .set_checked(policy == RestorePolicy::Manual);
+    }
+
+    /// The restore policy of the active topology.
+    fn restore_policy(&self) -> anyhow::Result<RestorePolicy> {
+        let Some(topology) = self.data.borrow().active_topology else {
+            return Ok(RestorePolicy::default());
+        };
+
+        let policy = self
+            .db()
+            .query_row(
+                "SELECT policy FROM topology_policy WHERE topology=:topology",
+                named_params! { ":topology": topology },
+                |r| r.get::<usize, String>(0),
+            )
+            .optional()
+            .context("failed to query restore policy")?;
+
+        Ok(policy
+            .and_then(|p| RestorePolicy::from_str(&p))
+            .unwrap_or_default())
+    }
+
+    fn set_restore_policy(&self, policy: RestorePolicy) -> anyhow::Result<()> {
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+
+        self.db()
+            .execute(
+                "INSERT OR REPLACE INTO topology_policy (topology, policy)
+                    VALUES (:topology, :policy)",
+                named_params! { ":topology": topology, ":policy": policy.as_str() },
+            )
+            .context("failed to save restore policy")?;
+
+        Ok(())
+    }
+
+    /// Restore windows after a change the user did not ask for, as allowed by the restore policy
+    /// of the active topology.
+    fn restore_automatically(&self, trigger: &'static str) -> anyhow::Result<()> {
+        let scope = match self.restore_policy()? {
+            RestorePolicy::Full => RestoreScope::default(),
+            RestorePolicy::MissingOnly => RestoreScope {
+                misplaced_only: true,
+                ..Default::default()
+            },
+            RestorePolicy::Manual => {
+                info!("{trigger}: automatic restore disabled for this topology");
+                return Ok(());
+            }
+        };
+
+        self.restore_windows(trigger, scope)
+    }
+
+    fn on_age_never(&self) {
+        self.set_max_record_age(None);
+    }
+
+    fn on_age_week(&self) {
+        self.set_max_record_age(Some(7));
+    }
+
+    fn on_age_month(&self) {
+        self.set_max_record_age(Some(30));
+    }
+
+    fn on_age_quarter(&self) {
+        self.set_max_record_age(Some(90));
+    }
+
+    fn set_max_record_age(&self, days: Option<u64>) {
+        self.data.borrow_mut().max_record_age = days;
+        self.check_max_record_age();
+
+        let value = days.map(|d| d.to_string()).unwrap_or_default();
+        if let Err(e) = write_setting("MaxRecordAgeDays", &value) {
+            warn!("failed to save maximum placement age: {e}");
+        }
+    }
+
+    fn check_max_record_age(&self) {
+        let days = self.data.borrow().max_record_age;
+        self.tray_menu_age_never.set_checked(days.is_none());
+        self.tray_menu_age_week.set_checked(days == Some(7));
+        self.tray_menu_age_month.set_checked(days == Some(30));
+        self.tray_menu_age_quarter.set_checked(days == Some(90));
+    }
+
+    fn on_scope_everything(&self) {
+        self.set_capture_scope(CaptureScope::Everything);
+    }
+
+    fn on_scope_monitor(&self) {
+        self.set_capture_scope(CaptureScope::Monitor);
+    }
+
+    fn on_scope_desktop(&self) {
+        self.set_capture_scope(CaptureScope::Desktop);
+    }
+
+    fn set_capture_scope(&self, scope: CaptureScope) {
+        self.data.borrow_mut().capture_scope = scope;
+        self.check_capture_scope();
+
+        if let Err(e) = write_setting("CaptureScope", scope.as_str()) {
+            warn!("failed to save capture scope: {e}");
+        }
+    }
+
+    fn check_capture_scope(&self) {
+        let scope = self.data.borrow().capture_scope;
+        self.tray_menu_scope_everything
+            .set_checked(scope == CaptureScope::Everything);
+        self.tray_menu_scope_monitor
+            .set_checked(scope == CaptureScope::Monitor);
+        self.tray_menu_scope_desktop
+            .set_checked(scope == CaptureScope::Desktop);
+    }
+
+    /// Whether a window falls within the capture scope chosen by the user.
+    fn in_capture_scope(&self, hwnd: HWND) -> bool {
+        let (scope, last_foreground) = {
+            let data = self.data.borrow();
+            (data.capture_scope, data.last_foreground)
+        };
+
+        match scope {
+            CaptureScope::Everything => true,
+            CaptureScope::Monitor => {
+                // The monitor the user is working on is the one with the foreground window, or
+                // failing that the one under the cursor.
+                let active = match last_foreground.map(|w| w.rect()) {
+                    Some(Ok(rect)) => monitor::from_rect(&rect),
+                    _ => {
+                        let (x, y) = nwg::GlobalCursor::position();
+                        monitor::from_point(POINT { x, y })
+                    }
+                };
+
+                hwnd.rect()
+                    .is_ok_and(|rect| monitor::from_rect(&rect) == active)
+            }
+            // Windows whose desktop cannot be determined (e.g. pinned to all desktops by a
+            // shell extension) are captured.
+            CaptureScope::Desktop => vdesktop::is_on_current_desktop(hwnd).unwrap_or(true),
+        }
+    }
+
+    fn on_export_settings(&self) {
+        if !self.export_dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        let res = self
+            .export_dialog
+            .get_selected_item()
+            .map_err(|e| anyhow::anyhow!("{e:?}"))
+            .and_then(|path| {
+                let mut path = PathBuf::from(path);
+                if path.extension().is_none() {
+                    path.set_extension("toml");
+                }
+
+                self.export_settings(&path)
+            });
+
+        if let Err(e) = res {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+    }
+
+    fn on_import_settings(&self) {
+        if !self.import_dialog.run(Some(&self.window)) {
+            return;
+        }
+
+        let res = self
+            .import_dialog
+            .get_selected_item()
+            .map_err(|e| anyhow::anyhow!("{e:?}"))
+            .and_then(|path| self.import_settings(&PathBuf::from(path)));
+
+        if let Err(e) = res {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+    }
+
+    /// Write the rules of the active workspace and the preferences to a file.
+    fn export_settings(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        let rules = {
+            let db = self.db();
+            let mut stmt = db
+                .prepare("SELECT exe, action FROM rule ORDER BY exe, action")
+                .context("failed to prepare query")?;
+
+            let rules = stmt
+                .query_map([], |r| {
+                    Ok(RuleEntry {
+                        exe: r.get(0)?,
+                        action: r.get(1)?,
+                    })
+                })
+                .context("failed to query database")?
+                .collect::<Result<Vec<_>, _>>()
+                .context("failed to read row")?;
+            rules
+        };
+
+        let data = self.data.borrow();
+        let file = SettingsFile {
+            settings: Preferences {
+                capture_scope: Some(data.capture_scope.as_str().to_string()),
+                max_record_age_days: data.max_record_age,
+            },
+            rules,
+        };
+
+        let text = toml::to_string_pretty(&file).context("failed to serialize settings")?;
+        std::fs::write(path, text).context("failed to write settings file")?;
+
+        Ok(())
+    }
+
+    /// Apply the preferences from a settings file, and add its rules to the active workspace.
+    /// Existing rules are kept.
+    fn import_settings(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(path).context("failed to read settings file")?;
+        let file: SettingsFile = toml::from_str(&text).context("failed to parse settings file")?;
+
+        if let Some(scope) = file.settings.capture_scope {
+            match CaptureScope::from_str(&scope) {
+                Some(scope) => self.set_capture_scope(scope),
+                None => warn!("ignoring unknown capture scope \"{scope}\""),
+            }
+        }
+
+        if file.settings.max_record_age_days.is_some() {
+            self.set_max_record_age(file.settings.max_record_age_days.filter(|d| *d > 0));
+        }
+
+        for rule in file.rules {
+            match RuleAction::from_str(&rule.action) {
+                Some(action) => self.set_rule(&rule.exe, action, true)?,
+                None => warn!("ignoring unknown rule action \"{}\"", rule.action),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_pause_toggle(&self) {
+        let paused = !self.data.borrow().paused;
+        self.data.borrow_mut().paused = paused;
+        self.tray_menu_pause.set_checked(paused);
+
+        info!("tracking {}", if paused { "paused" } else { "resumed" });
+
+        // Drop captures already underway, so nothing rearranged in the meantime is saved.
+        if paused {
+            self.deferred_capture_timer.stop();
+            self.stable_capture_timer.stop();
+            {
+                let mut data = self.data.borrow_mut();
+                data.deferred_captures.clear();
+                data.unstable_captures.clear();
+                data.capture_queue.clear();
+            }
+            self.finish_capture();
+        }
+
+        if let Err(e) = self.configure_hooks() {
+            nwg::modal_error_message(&self.window, "Error", &format!("{e:?}"));
+        }
+    }
+
+    /// The event ranges that should be hooked in the current state.
+    fn hook_ranges(&self) -> &'static [(u32, u32)] {
+        let data = self.data.borrow();
+        if data.paused {
+            &[]
+        } else if data.fullscreen {
+            FULLSCREEN_HOOK_RANGES
+        } else {
+            HOOK_RANGES
+        }
+    }
+
+    /// Bring the installed event hooks in line with `hook_ranges`, removing the hooks that are no
+    /// longer wanted and installing the missing ones.
+    fn configure_hooks(&self) -> anyhow::Result<()> {
+        let Some(cb) = self.hook_cb.borrow().clone() else {
+            return Ok(());
+        };
+
+        let wanted = self.hook_ranges();
+        let mut hooks = self.hooks.borrow_mut();
+        hooks.retain(|h| wanted.contains(&h.range()));
+
+        for &(min, max) in wanted {
+            if !hooks.iter().any(|h| h.range() == (min, max)) {
+                hooks.push(
+                    EventHook::register_shared(min, max, cb.clone())
+                        .context("failed to register window event hook")?,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch to the reduced set of hooks while a fullscreen window is in the foreground, and
+    /// back once it is not.
+    fn update_fullscreen(&self, foreground: HWND) {
+        let fullscreen = is_fullscreen(foreground);
+        if self.data.borrow().fullscreen == fullscreen {
+            return;
+        }
+
+        info!(
+            "fullscreen window {}",
+            if fullscreen { "entered" } else { "left" }
+        );
+
+        self.data.borrow_mut().fullscreen = fullscreen;
+        if let Err(e) = self.configure_hooks() {
+            error!("{e:?}");
+        }
+    }
+
+    fn reinstall_hooks(&self) {
+        for hook in self.hooks.borrow_mut().iter_mut() {
+            if let Err(e) = EventHook::reregister(hook) {
+                error!("failed to reinstall event hook: {e}");
+            }
+        }
+    }
+
+    /// Commit to the display topology once it has stopped changing, and restore windows for it.
+    /// Wait for the display topology to settle before reconciling windows with it, after
+    /// `trigger` reported that it may have changed.
+    fn settle(&self, trigger: &'static str) {
+        // Restores planned for the previous topology no longer apply.
+        self.cancel_restore();
+        self.set_transition(Transition::Settling);
+        self.data.borrow_mut().settle_trigger = trigger;
+
+        // Every further change restarts the wait.
+        self.display_timer.stop();
+        self.display_timer.start();
+    }
+
+    fn on_display_settled(&self) {
+        self.display_timer.stop();
+        self.set_transition(Transition::Idle);
+        let trigger = self.data.borrow().settle_trigger;
+
+        let (topo_id, origin) = match self
+            .capture_topology()
+            .context("failed to capture topology")
+        {
+            Ok(topo) => topo,
+            Err(e) => {
+                error!("{e}");
+                return;
+            }
+        };
+
+        // The topology may have flapped back to where it started, in which case there is no
+        // transition to record (but windows may still have been moved around).
+        let (active, prev_origin) = {
+            let data = self.data.borrow();
+            (data.active_topology, data.origin.clone())
+        };
+        if active != Some(topo_id) || prev_origin != origin {
+            if let Err(e) = self.set_active_topology(topo_id, origin, trigger) {
+                error!("{e:?}");
+                return;
+            }
+
+            if let Err(e) = self.check_location(topo_id) {
+                warn!("{:?}", e.context("failed to check location"));
+            }
+        }
+
+        info!("{trigger}: {topo_id}");
+
+        // Windows will be reconciled once the session is unlocked.
+        if self.data.borrow().frozen {
+            return;
+        }
+
+        self.offer_topology_merge();
+
+        let _ = run_fallible(|| {
+            self.restore_automatically(trigger)
+                .context("failed to restore windows")
+        });
+    }
+
+    /// Resume capturing once the session has settled after an unlock, and put back any windows
+    /// the system moved while it was locked.
+    fn on_unlock_settled(&self) {
+        self.unlock_timer.stop();
+        let trigger = {
+            let mut data = self.data.borrow_mut();
+            data.frozen = false;
+            data.resume_trigger
+        };
+
+        let _ = run_fallible(|| {
+            let (topo_id, origin) = self
+                .capture_topology()
+                .context("failed to capture topology")?;
+            self.set_active_topology(topo_id, origin, trigger)?;
+            self.offer_topology_merge();
+
+            self.restore_automatically(trigger)
+                .context("failed to restore windows")
+        });
+    }
+
+    /// Capture every window right away, rather than in chunks, for when the layout is about to
+    /// be disturbed.
+    fn capture_now(&self) {
+        let handles = match window::windows() {
+            Ok(handles) => handles,
+            Err(e) => {
+                warn!("failed to query windows: {e}");
+                return;
+            }
+        };
+
+        prefetch_processes(&handles);
+        for hwnd in handles {
+            if let Err(e) = self.capture_window_now(hwnd, CaptureTrigger::Pass) {
+                warn!("{:?}", e.context("failed to capture window"));
+            }
+        }
+    }
+
+    /// Start a full capture pass. Windows are captured in chunks by `capture_timer`, reporting
+    /// progress in the tray tooltip, and the pass can be cancelled from the tray menu.
+    fn capture_windows(&self) -> anyhow::Result<()> {
         let handles = window::windows().context("failed to query windows")?;
 
-        for hwnd in handles {
-            // Silently ignore any errors for individual windows.
-            match self.restore_window(hwnd) {
-                Ok(_) => {}
-                Err(e) => error!("{}", e.context("failed to restore window")),
+        info!("capturing {} handles", handles.len());
+
+        // Opening the owning processes is the slowest part of capturing a window, so do it for
+        // the whole pass up front on worker threads, rather than spinning them up on every tick.
+        // The captures themselves (and all database writes) stay on this thread.
+        prefetch_processes(&handles);
+
+        let mut data = self.data.borrow_mut();
+        data.capture_total = handles.len();
+        data.capture_queue = handles.into();
+
+        self.tray_menu_cancel_capture.set_enabled(true);
+        self.capture_timer.start();
+
+        Ok(())
+    }
+
+    fn on_capture_tick(&self) {
+        let (chunk, remaining, total) = {
+            let mut data = self.data.borrow_mut();
+            let n = data.capture_queue.len().min(CAPTURE_CHUNK);
+            let chunk = data.capture_queue.drain(..n).collect::<Vec<_>>();
+
+            (chunk, data.capture_queue.len(), data.capture_total)
+        };
+
+        for hwnd in chunk {
+            // Silently ignore any errors for individual windows.
+            match self
+                .capture_window(hwnd, CaptureTrigger::Pass)
+                .context("failed to capture window")
+            {
+                Ok(_) => {
+                    /*
+                    if let Ok(title) = hwnd.title() {
+                        info!("captured {}", title)
+                    }
+                    */
+                }
+                Err(e) => warn!("{e:?}"),
+            }
+        }
+
+        if remaining != 0 {
+            self.tray.set_tip(&format!(
+                "Persistent Windows\nCapturing windows ({}/{total})",
+                total - remaining
+            ));
+            return;
+        }
+
+        self.finish_capture();
+    }
+
+    fn on_cancel_capture(&self) {
+        info!("capture cancelled");
+
+        self.data.borrow_mut().capture_queue.clear();
+        self.finish_capture();
+    }
+
+    fn finish_capture(&self) {
+        PROCESS_CACHE.with(|c| c.borrow_mut().clear());
+
+        self.capture_timer.stop();
+        self.tray_menu_cancel_capture.set_enabled(false);
+        self.tray.set_tip("Persistent Windows");
+    }
+
+    /// Whether this instance maintains its database: prunes, validates and journals it, and
+    /// merges topologies in it. When an elevated and an unelevated instance have the same
+    /// workspace open, the elevated one does. Either way, each saves the windows it owns.
+    fn maintains_database(&self) -> bool {
+        if self.elevated {
+            return true;
+        }
+        let Some(peer) = self.peers.peer() else {
+            return true;
+        };
+
+        // The peer may have exited without announcing it (e.g. it crashed).
+        peer.workspace.as_deref() != Some(self.data.borrow().workspace.as_str())
+            || GlobalMutex::open(&instance_mutex_name(true)).is_err()
+    }
+
+    /// Determine whether this instance is responsible for a window.
+    ///
+    /// An elevated and an unelevated instance may run side by side in the same session. When
+    /// both are present, each one only manages windows whose owning process has the same
+    /// elevation as itself.
+    fn owns_window(&self, hwnd: HWND) -> bool {
+        if GlobalMutex::open(&instance_mutex_name(!self.elevated)).is_err() {
+            // No peer instance running.
+            return true;
+        }
+
+        // Processes that cannot be inspected are assumed to be elevated.
+        is_window_elevated(hwnd).unwrap_or(true) == self.elevated
+    }
+
+    /// Look up the saved placement of a window that should be restored, if any.
+    fn find_restore(&self, hwnd: HWND) -> anyhow::Result<Option<WindowDisplay>> {
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+
+        if hwnd.is_visible() && !is_desktop_widget(hwnd) && self.owns_window(hwnd) {
+            let origin = self.data.borrow().origin.clone();
+
+            Ok(self
+                .find_window(hwnd, topology)?
+                .map(|disp| disp.offset(origin.x, origin.y)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Look up the placement a window was last captured with at or before `time`, from the
+    /// history of the record it claims.
+    fn find_history_restore(&self, hwnd: HWND, time: u64) -> anyhow::Result<Option<WindowDisplay>> {
+        // Claim the record of the window first.
+        if self.find_restore(hwnd)?.is_none() {
+            return Ok(None);
+        }
+
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+        let Some(id) = self.data.borrow().records.get(&(hwnd.0, topology)).copied() else {
+            return Ok(None);
+        };
+
+        let disp = self.store().captured_placement(id, time)?;
+
+        // History entries are already in screen coordinates.
+        disp.map(|disp| WindowDisplay::decode(&disp)).transpose()
+    }
+
+    /// Look up the placement of a window in a checkpoint. Checkpoint entries are matched by key,
+    /// each at most once per pass, as tracked by `claimed`.
+    fn find_checkpoint_restore(
+        &self,
+        hwnd: HWND,
+        checkpoint: i64,
+        claimed: &mut HashSet<i64>,
+    ) -> anyhow::Result<Option<WindowDisplay>> {
+        if !hwnd.is_visible() || is_desktop_widget(hwnd) || !self.owns_window(hwnd) {
+            return Ok(None);
+        }
+
+        let key = WindowKey::from_window(hwnd)?;
+        if key.is_remote() || self.is_excluded(&key) {
+            return Ok(None);
+        }
+
+        let db = self.db();
+
+        let mut stmt = db
+            .prepare(
+                "SELECT id, disp FROM checkpoint_window WHERE checkpoint=:checkpoint
+                    AND exe IS :exe AND class=:class AND title=:title ORDER BY id",
+            )
+            .context("failed to prepare query")?;
+
+        let rows = stmt
+            .query_map(
+                named_params! {
+                    ":checkpoint": checkpoint,
+                    ":exe": key.exe,
+                    ":class": key.class,
+                    ":title": key.title,
+                },
+                |r| Ok((r.get::<usize, i64>(0)?, r.get::<usize, Vec<u8>>(1)?)),
+            )
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        let (id, disp) = match rows.into_iter().find(|(id, _)| !claimed.contains(id)) {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        claimed.insert(id);
+
+        let origin = self.data.borrow().origin.clone();
+        Ok(Some(
+            WindowDisplay::decode(&disp)?.offset(origin.x, origin.y),
+        ))
+    }
+
+    fn restore_window(&self, restore: &PlannedRestore) -> anyhow::Result<()> {
+        let hwnd = restore.hwnd;
+        let restore_placement = &restore.target;
+        let mut wnd_placement = restore_placement.placement();
+
+        // Some applications ignore placement changes while in the background, so bring them to
+        // the foreground for the duration and hand it back to whichever window had it.
+        if restore.strategy == RestoreStrategy::Foreground {
+            // An asynchronous placement would only be applied after the foreground is handed
+            // back, so wait for the window to apply it.
+            wnd_placement.flags &= !WPF_ASYNCWINDOWPLACEMENT;
+
+            let prev = window::foreground();
+            window::set_foreground(hwnd);
+
+            let res = self.apply_placement(hwnd, restore_placement, wnd_placement);
+
+            if prev.0 != 0 {
+                window::set_foreground(prev);
+            }
+            return res;
+        }
+
+        self.apply_placement(hwnd, restore_placement, wnd_placement)
+    }
+
+    fn apply_placement(
+        &self,
+        hwnd: HWND,
+        restore_placement: &WindowDisplay,
+        wnd_placement: WINDOWPLACEMENT,
+    ) -> anyhow::Result<()> {
+        let class_name = hwnd.class_name().context("failed to query class name")?;
+        let placement = hwnd.placement().context("failed to query placement")?;
+
+        if quirks::for_class(&class_name).reposition
+            && SHOW_WINDOW_CMD(restore_placement.show) != SW_SHOWNORMAL
+        {
+            // The toolkit would put the window back to its own normal bounds once it is shown
+            // normally again, so move it there while normal first. It shows briefly.
+            info!(
+                "moving {:#010X} while normal first ({})",
+                hwnd.0,
+                quirks::toolkit_name(&class_name).unwrap_or(&class_name),
+            );
+
+            let mut normal = wnd_placement;
+            normal.showCmd = SW_SHOWNOACTIVATE;
+            hwnd.set_placement(normal)
+                .context("failed to show window normally")?;
+            window::set_positions(&[(hwnd, workspace_to_screen(hwnd, &restore_placement.rect))])
+                .context("failed to move window")?;
+        }
+
+        match SHOW_WINDOW_CMD(restore_placement.show) {
+            SW_MAXIMIZE => {
+                // For some reason, maximized windows ignore SetWindowPlacement calls,
+                // so we have to set the window to normal placement first, and then maximize
+                // it afterwards.
+                let mut wnd_placement = wnd_placement;
+                wnd_placement.showCmd = SW_SHOWNORMAL;
+                hwnd.set_placement(wnd_placement)
+                    .context("failed to restore maximized window placement")?;
+            }
+            _ => info!(
+                "restoring {:#010X} from {:?} to {:?} ({})",
+                hwnd.0, placement.rcNormalPosition, wnd_placement.rcNormalPosition, class_name,
+            ),
+        };
+
+        hwnd.set_placement(wnd_placement)
+            .context("failed to restore window placement")?;
+
+        // The placement of these windows does not match where they are, so the rectangle is
+        // applied directly once the window is in its normal state.
+        if restore_placement.source == CaptureSource::WindowRect
+            && SHOW_WINDOW_CMD(restore_placement.show) == SW_SHOWNORMAL
+        {
+            window::set_positions(&[(hwnd, workspace_to_screen(hwnd, &restore_placement.rect))])
+                .context("failed to move window")?;
+            return Ok(());
+        }
+
+        // Windows with custom frames (e.g. WinUI or frameless Electron) sometimes accept the
+        // placement yet stay put, so check back once it has had time to apply.
+        let before: Rect = placement.rcNormalPosition.into();
+        let target: Rect = wnd_placement.rcNormalPosition.into();
+        if before != target {
+            self.data
+                .borrow_mut()
+                .unverified_restores
+                .insert(hwnd.0, (before, target));
+            self.verify_timer.start();
+        }
+
+        Ok(())
+    }
+
+    /// Check that recently restored windows moved, and move the ones that ignored their
+    /// placement with `SetWindowPos` instead. Which of the two worked is logged per application,
+    /// as a hint for rules.
+    fn on_verify_restores(&self) {
+        self.verify_timer.stop();
+
+        let pending = std::mem::take(&mut self.data.borrow_mut().unverified_restores);
+        for (h, (before, target)) in pending {
+            let hwnd = HWND(h);
+            let Ok(placement) = hwnd.placement() else {
+                // The window is gone.
+                continue;
+            };
+
+            let exe = window_app_name(hwnd).unwrap_or_else(|_| "(unknown)".to_string());
+            let current: Rect = placement.rcNormalPosition.into();
+            if current != before {
+                info!("{exe}: {:#010X} restored with SetWindowPlacement", hwnd.0);
+                continue;
+            }
+
+            // The normal rect of a minimized or maximized window is not where it is shown, so
+            // moving it would only pull it out of that state.
+            if hwnd.is_minimized() || hwnd.is_maximized() {
+                info!("{exe}: {:#010X} ignored SetWindowPlacement", hwnd.0);
+                continue;
+            }
+
+            let screen = workspace_to_screen(hwnd, &target);
+            match window::set_positions(&[(hwnd, screen)]) {
+                Ok(()) => info!(
+                    "{exe}: {:#010X} ignored SetWindowPlacement, restored with SetWindowPos",
+                    hwnd.0
+                ),
+                Err(e) => warn!(
+                    "{exe}: failed to move {:#010X} with SetWindowPos: {e}",
+                    hwnd.0
+                ),
+            }
+        }
+    }
+
+    /// Fit windows to a changed work area (e.g. the taskbar was set to auto-hide, or an appbar
+    /// docked), without treating it as a topology change. Maximized windows that no longer fill
+    /// the work area are maximized again, and windows in their normal state that now extend
+    /// under the taskbar or an appbar are moved back inside.
+    fn on_work_area_settled(&self) {
+        self.work_area_timer.stop();
+
+        let handles = match window::windows() {
+            Ok(handles) => handles,
+            Err(e) => {
+                warn!("failed to query windows: {e}");
+                return;
+            }
+        };
+
+        let mut moves = Vec::new();
+        for hwnd in handles {
+            if !hwnd.is_visible()
+                || !hwnd.is_top_level()
+                || hwnd.is_minimized()
+                || (hwnd.ex_style() & WS_EX_TOOLWINDOW).0 != 0
+                || is_desktop_widget(hwnd)
+                || !self.owns_window(hwnd)
+            {
+                continue;
+            }
+
+            let Ok(rect) = hwnd.rect() else {
+                continue;
+            };
+            let Ok(info) = monitor::from_rect(&rect).info() else {
+                continue;
+            };
+            let work = &info.work;
+
+            if hwnd.is_maximized() {
+                let fits = (rect.left - work.left).abs() <= WORK_AREA_TOLERANCE
+                    && (rect.top - work.top).abs() <= WORK_AREA_TOLERANCE
+                    && (rect.right - work.right).abs() <= WORK_AREA_TOLERANCE
+                    && (rect.bottom - work.bottom).abs() <= WORK_AREA_TOLERANCE;
+                if fits {
+                    continue;
+                }
+
+                let res = hwnd
+                    .placement()
+                    .context("failed to query placement")
+                    .and_then(|p| self.apply_placement(hwnd, &WindowDisplay::from(p), p));
+                if let Err(e) = res {
+                    warn!("{:?}", e.context("failed to refit maximized window"));
+                }
+            } else if rect.clamp_within(&info.rect) == rect {
+                // Only windows that lie on a single monitor are moved: windows spanning
+                // monitors (or partly off-screen) were put there deliberately.
+                let clamped = rect.clamp_within(work);
+                if clamped != rect {
+                    info!("fitting {:#010X} to work area {work:?}", hwnd.0);
+                    moves.push((hwnd, clamped));
+                }
+            }
+        }
+
+        if let Err(e) = window::set_positions(&moves) {
+            warn!("failed to fit windows to the work area: {e}");
+        }
+    }
+
+    /// Capture the placement of a window into its record, noting the `trigger` in its history.
+    /// Windows that were shown only moments ago are captured once they have been around for a
+    /// while instead, in case they are transient.
+    fn capture_window(&self, hwnd: HWND, trigger: CaptureTrigger) -> anyhow::Result<()> {
+        let young = self
+            .data
+            .borrow()
+            .shown
+            .get(&hwnd.0)
+            .is_some_and(|t| t.elapsed() < MIN_WINDOW_AGE);
+        if young {
+            self.data
+                .borrow_mut()
+                .deferred_captures
+                .insert(hwnd.0, trigger);
+            self.deferred_capture_timer.start();
+            return Ok(());
+        }
+
+        self.capture_window_stable(hwnd, trigger)
+    }
+
+    /// Capture a window once two consecutive reads of its placement agree, so intermediate
+    /// placements of an animation are never persisted. Capture passes are not triggered by the
+    /// window changing, so they capture right away.
+    fn capture_window_stable(&self, hwnd: HWND, trigger: CaptureTrigger) -> anyhow::Result<()> {
+        if let CaptureTrigger::Pass = trigger {
+            return self.capture_window_now(hwnd, trigger);
+        }
+
+        let disp = WindowDisplay::from(hwnd.placement().context("failed to query placement")?);
+        self.data
+            .borrow_mut()
+            .unstable_captures
+            .insert(hwnd.0, (trigger, disp, 1));
+        self.stable_capture_timer.start();
+
+        Ok(())
+    }
+
+    /// Capture the windows whose placement has stopped changing since the last read.
+    fn on_stable_capture_tick(&self) {
+        let pending = std::mem::take(&mut self.data.borrow_mut().unstable_captures);
+
+        let mut due = Vec::new();
+        let mut waiting = HashMap::new();
+        for (h, (trigger, prev, reads)) in pending {
+            let hwnd = HWND(h);
+            let Ok(placement) = hwnd.placement() else {
+                // The window is gone.
+                continue;
+            };
+
+            // Toolkits that settle slowly could otherwise be caught pausing mid-adjustment.
+            let min_reads = match quirks::for_window(hwnd).settle {
+                true => quirks::SETTLE_READS,
+                false => 1,
+            };
+
+            let disp = WindowDisplay::from(placement);
+            if (disp == prev && reads >= min_reads) || reads >= STABLE_READ_LIMIT {
+                due.push((hwnd, trigger));
+            } else {
+                waiting.insert(h, (trigger, disp, reads + 1));
+            }
+        }
+
+        {
+            // N.B: Events handled while capturing may queue more windows.
+            let mut data = self.data.borrow_mut();
+            for (h, entry) in waiting {
+                data.unstable_captures.entry(h).or_insert(entry);
+            }
+        }
+
+        for (hwnd, trigger) in due {
+            if let Err(e) = self.capture_window_now(hwnd, trigger) {
+                warn!("{:?}", e.context("failed to capture window"));
+            }
+        }
+
+        if self.data.borrow().unstable_captures.is_empty() {
+            self.stable_capture_timer.stop();
+        }
+    }
+
+    /// Capture windows whose capture was deferred, once they are old enough.
+    fn on_deferred_capture_tick(&self) {
+        let due = {
+            let mut data = self.data.borrow_mut();
+            let AppData {
+                shown,
+                deferred_captures,
+                ..
+            } = &mut *data;
+
+            let due = deferred_captures
+                .iter()
+                .filter(|(h, _)| shown.get(h).is_none_or(|t| t.elapsed() >= MIN_WINDOW_AGE))
+                .map(|(h, trigger)| (HWND(*h), *trigger))
+                .collect::<Vec<_>>();
+            for (hwnd, _) in &due {
+                deferred_captures.remove(&hwnd.0);
+                shown.remove(&hwnd.0);
+            }
+
+            if deferred_captures.is_empty() {
+                self.deferred_capture_timer.stop();
+            }
+
+            due
+        };
+
+        for (hwnd, trigger) in due {
+            if let Err(e) = self.capture_window_stable(hwnd, trigger) {
+                warn!("{:?}", e.context("failed to capture window"));
+            }
+        }
+    }
+
+    fn capture_window_now(&self, hwnd: HWND, trigger: CaptureTrigger) -> anyhow::Result<()> {
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+
+        // Placements are unreliable while the session is locked, as monitors may be detached,
+        // and while the system is still moving windows around for a display change. While
+        // tracking is paused, the user is rearranging windows only temporarily.
+        {
+            let data = self.data.borrow();
+            if data.frozen || data.transition != Transition::Idle || data.paused {
+                return Ok(());
+            }
+        }
+
+        if hwnd.is_visible()
+            && hwnd.is_top_level()
+            && !is_desktop_widget(hwnd)
+            && !is_ghost(hwnd)
+            && self.owns_window(hwnd)
+            && self.in_capture_scope(hwnd)
+        {
+            let placement = hwnd.placement().context("failed to query placement")?;
+            let origin = self.data.borrow().origin.clone();
+
+            let key = WindowKey::from_window(hwnd)?;
+            if key.is_remote()
+                || TRANSIENT_CLASSES.contains(&key.class.as_str())
+                || quirks::for_class(&key.class).proxy
+                || self.is_excluded(&key)
+            {
+                return Ok(());
+            }
+
+            if let Some(exe) = &key.exe {
+                self.record_launch(hwnd, exe);
+            }
+
+            let dpi = monitor::from_rect(&placement.rcNormalPosition.into())
+                .dpi(MonitorDpiType::Effective)
+                .ok()
+                .map(|d| d.x);
+
+            let mut disp = WindowDisplay {
+                topmost: Some((hwnd.ex_style() & WS_EX_TOPMOST).0 != 0),
+                dpi,
+                ..WindowDisplay::from(placement)
+            };
+            if self.key_has_rule(&key, RuleAction::WindowRect)
+                && !hwnd.is_minimized()
+                && !hwnd.is_maximized()
+            {
+                let rect = hwnd.rect().context("failed to query window rect")?;
+                disp.rect = screen_to_workspace(hwnd, &rect);
+                disp.source = CaptureSource::WindowRect;
+            }
+
+            // Windows without any area are not worth restoring.
+            if disp.rect.width() == 0 || disp.rect.height() == 0 {
+                return Ok(());
+            }
+
+            let time = unix_time()?;
+            let desktop = match vdesktop::desktop_id(hwnd) {
+                Ok(desktop) => desktop.map(|id| vdesktop::format_id(&id)),
+                Err(e) => {
+                    warn!("failed to query virtual desktop: {e}");
+                    None
+                }
+            };
+
+            let mut rect = Vec::new();
+            bson::to_document(&disp.clone().offset(-origin.x, -origin.y))
+                .unwrap()
+                .to_writer(&mut rect)
+                .unwrap();
+
+            // Placements the user chose are also kept aside, so incidental captures do not
+            // replace them when restoring.
+            let user_disp = trigger.is_user().then_some(&rect);
+
+            let tag = window_tag(hwnd);
+            let record = store::RecordData {
+                key: &key,
+                disp: &rect,
+                user_disp: user_disp.map(|d| d.as_slice()),
+                trigger: trigger.name(),
+                updated: time,
+                desktop: desktop.as_deref(),
+                tag: tag.as_deref(),
+            };
+
+            // Update the record this window already owns, if it still exists.
+            if let Some(id) = self.find_record(hwnd, &key, topology)? {
+                if self.store().update_record(id, &record)? {
+                    return self.record_history(id, topology, "capture", trigger.name(), &disp);
+                }
+            }
+
+            let id = self.store().insert_record(topology, &record)?;
+            self.data
+                .borrow_mut()
+                .records
+                .insert((hwnd.0, topology), id);
+
+            self.record_history(id, topology, "capture", trigger.name(), &disp)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remember how the process owning a window was started, so a session restore can start its
+    /// application again. Each process is only read once per session, and processes that cannot
+    /// be read (e.g. elevated ones) are skipped.
+    fn record_launch(&self, hwnd: HWND, exe: &str) {
+        let Ok(owner) = hwnd.owner() else {
+            return;
+        };
+        if !self
+            .data
+            .borrow_mut()
+            .launches_seen
+            .insert(owner.process_id)
+        {
+            return;
+        }
+
+        let access = (PROCESS_QUERY_INFORMATION | PROCESS_VM_READ).0;
+        let Ok(process) = process::open(access, owner.process_id) else {
+            return;
+        };
+        let command_line = process.command_line();
+        let directory = process.current_directory().ok();
+        unsafe { CloseHandle(process) };
+
+        let Ok(command_line) = command_line else {
+            return;
+        };
+        let Ok(time) = unix_time() else {
+            return;
+        };
+
+        if let Err(e) = self.db().execute(
+            "INSERT OR REPLACE INTO launch (exe, command_line, directory, updated)
+                VALUES (:exe, :command_line, :directory, :updated)",
+            named_params! {
+                ":exe": exe,
+                ":command_line": command_line,
+                ":directory": directory,
+                ":updated": time,
+            },
+        ) {
+            warn!("failed to record command line of {exe}: {e}");
+        }
+    }
+
+    /// Append an entry to the placement history of a record. `disp` is in screen coordinates.
+    fn record_history(
+        &self,
+        record: i64,
+        topology: usize,
+        action: &str,
+        trigger: &str,
+        disp: &WindowDisplay,
+    ) -> anyhow::Result<()> {
+        let mut data = Vec::new();
+        bson::to_document(disp)
+            .unwrap()
+            .to_writer(&mut data)
+            .unwrap();
+
+        // Only the most recent entries are kept, which is plenty to roll a layout back.
+        self.store().add_history(
+            &store::HistoryEntry {
+                time: unix_time()?,
+                record,
+                topology,
+                action: action.to_string(),
+                trigger: trigger.to_string(),
+                disp: data,
+            },
+            HISTORY_LIMIT,
+        )
+    }
+
+    /// Query the key of a window and the records it has claimed in any topology.
+    fn window_records(&self, hwnd: HWND) -> anyhow::Result<(WindowKey, Vec<i64>)> {
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+
+        // Windows not seen yet this session are matched by key.
+        let key = WindowKey::from_window(hwnd)?;
+        self.find_record(hwnd, &key, topology)?;
+
+        let ids = self
+            .data
+            .borrow()
+            .records
+            .iter()
+            .filter(|((h, _), _)| *h == hwnd.0)
+            .map(|(_, id)| *id)
+            .collect::<Vec<_>>();
+
+        Ok((key, ids))
+    }
+
+    /// Format the placement history of a set of records, newest first.
+    fn window_history(&self, ids: &[i64], count: usize) -> anyhow::Result<Vec<String>> {
+        let rows = self
+            .store()
+            .history(ids, count)?
+            .into_iter()
+            .map(|entry| {
+                let disp = bson::from_slice::<WindowDisplay>(&entry.disp)
+                    .map(|d| format!("{:?}", d.rect))
+                    .unwrap_or_else(|_| "<invalid>".to_string());
+
+                format!(
+                    "{}  {} by {}  record {} in topology {}  {}",
+                    local_time(entry.time),
+                    entry.action,
+                    entry.trigger,
+                    entry.record,
+                    entry.topology,
+                    disp,
+                )
+            })
+            .collect();
+
+        Ok(rows)
+    }
+
+    /// Delete records, so the windows that claimed them start over with a fresh record.
+    fn delete_records(&self, ids: &[i64]) -> anyhow::Result<()> {
+        for id in ids {
+            self.store().delete_record(*id)?;
+        }
+
+        self.data
+            .borrow_mut()
+            .records
+            .retain(|_, id| !ids.contains(id));
+        Ok(())
+    }
+
+    fn on_prune_tick(&self) {
+        if !self.maintains_database() {
+            return;
+        }
+
+        if let Err(e) = self.prune_records() {
+            warn!("{:?}", e.context("failed to prune records"));
+        }
+        if let Err(e) = self.merge_duplicate_windows() {
+            warn!("{:?}", e.context("failed to merge duplicate windows"));
+        }
+    }
+
+    /// Delete the records no window has matched for `retention_days`, and the least recently
+    /// matched ones of topologies holding more than `max_topology_records`, along with their
+    /// history. Records claimed this session are kept. The database is compacted once enough
+    /// of it is free.
+    fn prune_records(&self) -> anyhow::Result<()> {
+        let (days, cap) = config::with(|c| (c.retention_days, c.max_topology_records));
+        let claimed = self
+            .data
+            .borrow()
+            .records
+            .values()
+            .copied()
+            .collect::<HashSet<_>>();
+
+        let mut stale = BTreeSet::new();
+        if days > 0 {
+            let cutoff = unix_time()?.saturating_sub(days * 24 * 60 * 60);
+            stale.extend(self.store().stale_records(cutoff)?);
+        }
+        if cap > 0 {
+            stale.extend(self.store().excess_records(cap)?);
+        }
+        stale.retain(|id| !claimed.contains(id));
+
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        {
+            let store = self.store();
+            let tx = store.begin()?;
+            for id in &stale {
+                store.delete_record(*id)?;
+            }
+            tx.commit()?;
+        }
+
+        info!("pruned {} records past the retention limits", stale.len());
+
+        let db = self.db();
+        let pages = db
+            .query_row("PRAGMA page_count", [], |r| r.get::<usize, u64>(0))
+            .context("failed to query page count")?;
+        let free = db
+            .query_row("PRAGMA freelist_count", [], |r| r.get::<usize, u64>(0))
+            .context("failed to query free pages")?;
+        if pages > 0 && free as f64 / pages as f64 >= VACUUM_FREE_RATIO {
+            info!("compacting database ({free} of {pages} pages free)");
+            db.execute_batch("VACUUM")
+                .context("failed to compact database")?;
+        }
+
+        Ok(())
+    }
+
+    /// Release the records claimed by a destroyed window, remembering them for a while in case
+    /// the window is being recreated.
+    fn forget_window(&self, hwnd: HWND) -> anyhow::Result<()> {
+        let records = {
+            let mut data = self.data.borrow_mut();
+            let records = data
+                .records
+                .iter()
+                .filter(|((h, _), _)| *h == hwnd.0)
+                .map(|((_, t), id)| (*t, *id))
+                .collect::<Vec<_>>();
+
+            data.records.retain(|(h, _), _| *h != hwnd.0);
+            data.shown.remove(&hwnd.0);
+            data.deferred_captures.remove(&hwnd.0);
+            data.destroyed
+                .retain(|d| d.time.elapsed() < RECREATE_WINDOW && d.hwnd != hwnd);
+            records
+        };
+
+        // The window can no longer be queried, so take its key from one of its records.
+        let key = match records.first() {
+            Some((_, id)) => self.store().record_key(*id)?,
+            None => None,
+        };
+
+        if let Some(key) = &key {
+            self.close_records(&records, key)?;
+        }
+
+        if let Some(key) = key {
+            self.data.borrow_mut().destroyed.push(DestroyedWindow {
+                time: Stamp::now(),
+                hwnd,
+                key,
+                records,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Mark the records of a destroyed window as closed, and drop the oldest records of closed
+    /// windows of its application beyond `CLOSED_RECORD_LIMIT`, along with their history. The
+    /// records just closed are the most recent, so they are kept for the window to come back.
+    fn close_records(&self, records: &[(usize, i64)], key: &WindowKey) -> anyhow::Result<()> {
+        let time = unix_time()?;
+        for (topology, id) in records {
+            self.store().close_record(*id, time)?;
+
+            let pruned = self.store().prune_closed(
+                *topology,
+                key.exe.as_deref(),
+                &key.class,
+                CLOSED_RECORD_LIMIT,
+            )?;
+            if !pruned.is_empty() {
+                info!(
+                    "pruned {} records of closed {} windows",
+                    pruned.len(),
+                    key.class
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Some applications destroy and recreate their top-level window (e.g. on theme or DPI
+    /// changes). If a newly shown window matches one destroyed moments ago, carry over the
+    /// state of the old window: its claimed records, its place in the MRU list, and any restores
+    /// still pending for it.
+    fn adopt_recreated_window(&self, hwnd: HWND) -> anyhow::Result<()> {
+        if !hwnd.is_top_level() || self.data.borrow().destroyed.is_empty() {
+            return Ok(());
+        }
+
+        let key = WindowKey::from_window(hwnd)?;
+
+        let old = {
+            let mut data = self.data.borrow_mut();
+            data.destroyed
+                .retain(|d| d.time.elapsed() < RECREATE_WINDOW);
+
+            // Titles may change across recreation, so prefer an exact match but settle for the
+            // same application and class.
+            let pos = data
+                .destroyed
+                .iter()
+                .rposition(|d| d.key == key)
+                .or_else(|| {
+                    data.destroyed
+                        .iter()
+                        .rposition(|d| d.key.exe == key.exe && d.key.class == key.class)
+                });
+
+            match pos {
+                Some(pos) => data.destroyed.remove(pos),
+                None => return Ok(()),
+            }
+        };
+
+        info!("{:#010X} recreated as {:#010X}", old.hwnd.0, hwnd.0);
+
+        {
+            let mut data = self.data.borrow_mut();
+            for (topology, id) in old.records {
+                data.records.insert((hwnd.0, topology), id);
+            }
+
+            for restore in data.restore_queue.iter_mut().flatten() {
+                if restore.hwnd == old.hwnd {
+                    restore.hwnd = hwnd;
+                }
+            }
+        }
+
+        self.db()
+            .execute(
+                "UPDATE OR REPLACE mru SET hwnd=:new WHERE hwnd=:old",
+                named_params! { ":new": hwnd.0, ":old": old.hwnd.0 },
+            )
+            .context("failed to query database")?;
+
+        Ok(())
+    }
+
+    /// Freeze a copy of the layout of the active topology under a name. Captures never modify
+    /// checkpoints, so they can be restored until explicitly deleted.
+    fn create_checkpoint(&self, name: &str) -> anyhow::Result<()> {
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+
+        let db = self.db();
+
+        let tx = db::begin(&db)?;
+
+        tx.execute(
+            "INSERT INTO checkpoint (name, topology, time) VALUES (:name, :topology, :time)",
+            named_params! { ":name": name, ":topology": topology, ":time": unix_time()? },
+        )
+        .context("failed to create checkpoint")?;
+        let id = tx.last_insert_rowid();
+
+        for record in self.store().records(topology)? {
+            tx.execute(
+                "INSERT INTO checkpoint_window (checkpoint, exe, class, title, disp)
+                    VALUES (:checkpoint, :exe, :class, :title, :disp)",
+                named_params! {
+                    ":checkpoint": id,
+                    ":exe": record.key.exe,
+                    ":class": record.key.class,
+                    ":title": record.key.title,
+                    ":disp": record.placement(),
+                },
+            )
+            .context("failed to query database")?;
+        }
+
+        tx.commit().context("failed to commit transaction")?;
+        Ok(())
+    }
+
+    fn delete_checkpoint(&self, name: &str) -> anyhow::Result<()> {
+        let db = self.db();
+        let tx = db::begin(&db)?;
+
+        tx.execute(
+            "DELETE FROM checkpoint_window WHERE checkpoint IN
+                (SELECT id FROM checkpoint WHERE name=:name)",
+            named_params! { ":name": name },
+        )
+        .context("failed to query database")?;
+        tx.execute(
+            "DELETE FROM checkpoint WHERE name=:name",
+            named_params! { ":name": name },
+        )
+        .context("failed to query database")?;
+
+        tx.commit().context("failed to commit transaction")?;
+        Ok(())
+    }
+
+    /// Restore the layout frozen in a checkpoint, optionally only for some applications (by
+    /// normalized image path). The checkpoint must have been taken in the active topology.
+    fn restore_checkpoint(&self, name: &str, apps: Option<HashSet<String>>) -> anyhow::Result<()> {
+        let (id, topology) = self
+            .db()
+            .query_row(
+                "SELECT id, topology FROM checkpoint WHERE name=:name",
+                named_params! { ":name": name },
+                |r| Ok((r.get::<usize, i64>(0)?, r.get::<usize, usize>(1)?)),
+            )
+            .context("failed to query checkpoint")?;
+
+        if Some(topology) != self.data.borrow().active_topology {
+            anyhow::bail!("checkpoint \"{name}\" was taken in a different display topology");
+        }
+
+        self.restore_windows(
+            "checkpoint",
+            RestoreScope {
+                checkpoint: Some(id),
+                apps,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Put the windows saved in a checkpoint that moved since back where they were.
+    fn enforce_checkpoint(&self, name: &str) -> anyhow::Result<()> {
+        let (id, topology) = self
+            .db()
+            .query_row(
+                "SELECT id, topology FROM checkpoint WHERE name=:name",
+                named_params! { ":name": name },
+                |r| Ok((r.get::<usize, i64>(0)?, r.get::<usize, usize>(1)?)),
+            )
+            .context("failed to query checkpoint")?;
+
+        // The layout only applies to the topology it was taken in.
+        if Some(topology) != self.data.borrow().active_topology {
+            return Ok(());
+        }
+
+        let plan = self.plan_restore(
+            "kiosk",
+            &RestoreScope {
+                checkpoint: Some(id),
+                moved_only: true,
+                ..Default::default()
+            },
+        )?;
+
+        if plan.batches.iter().any(|b| !b.is_empty()) {
+            self.execute_plan(plan);
+        }
+
+        Ok(())
+    }
+
+    /// List the applications with windows saved in a checkpoint, by normalized image path.
+    fn checkpoint_apps(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        let db = self.db();
+        let mut stmt = db
+            .prepare(
+                "SELECT DISTINCT w.exe FROM checkpoint_window w
+                    JOIN checkpoint c ON c.id=w.checkpoint
+                    WHERE c.name=:name AND w.exe IS NOT NULL ORDER BY w.exe",
+            )
+            .context("failed to prepare query")?;
+
+        let apps = stmt
+            .query_map(named_params! { ":name": name }, |r| {
+                r.get::<usize, String>(0)
+            })
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(apps)
+    }
+
+    /// List the names of all checkpoints, newest first.
+    fn checkpoints(&self) -> anyhow::Result<Vec<String>> {
+        let db = self.db();
+        let mut stmt = db
+            .prepare("SELECT name FROM checkpoint ORDER BY id DESC")
+            .context("failed to prepare query")?;
+
+        let names = stmt
+            .query_map([], |r| r.get::<usize, String>(0))
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(names)
+    }
+
+    /// Whether a rule is set for an application.
+    fn has_rule(&self, exe: &str, action: RuleAction) -> anyhow::Result<bool> {
+        // Applications excluded in the configuration file behave as if they had a rule.
+        if let RuleAction::Exclude = action {
+            if config::with(|c| c.exclude_rules.iter().any(|r| r.matches_app(exe))) {
+                return Ok(true);
+            }
+        }
+
+        let n = self
+            .db()
+            .query_row(
+                "SELECT COUNT(*) FROM rule WHERE exe=:exe AND action=:action",
+                named_params! { ":exe": exe, ":action": action.as_str() },
+                |r| r.get::<usize, usize>(0),
+            )
+            .context("failed to query database")?;
+
+        Ok(n != 0)
+    }
+
+    /// Whether a rule is set for the application owning a window.
+    fn window_has_rule(&self, hwnd: HWND, action: RuleAction) -> bool {
+        match window_app_name(hwnd) {
+            Ok(exe) => self.has_rule(&exe, action).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether a rule is set for the application of a window key.
+    fn key_has_rule(&self, key: &WindowKey, action: RuleAction) -> bool {
+        match key.exe.as_deref() {
+            Some(exe) => self.has_rule(exe, action).unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Whether a window is excluded from capture and restore, by a rule for its application or
+    /// an exclusion rule in the configuration file.
+    fn is_excluded(&self, key: &WindowKey) -> bool {
+        self.key_has_rule(key, RuleAction::Exclude)
+            || config::with(|c| c.is_window_excluded(key.exe.as_deref(), &key.class, &key.title))
+    }
+
+    fn set_rule(&self, exe: &str, action: RuleAction, enabled: bool) -> anyhow::Result<()> {
+        let sql = match enabled {
+            true => "INSERT OR IGNORE INTO rule (exe, action) VALUES (:exe, :action)",
+            false => "DELETE FROM rule WHERE exe=:exe AND action=:action",
+        };
+
+        self.db()
+            .execute(
+                sql,
+                named_params! { ":exe": exe, ":action": action.as_str() },
+            )
+            .context("failed to query database")?;
+
+        Ok(())
+    }
+
+    /// Move a window to the front of the most-recently-used list of the active topology.
+    fn touch_mru(&self, hwnd: HWND) -> anyhow::Result<()> {
+        let topology = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+
+        if hwnd.is_visible() && hwnd.is_top_level() {
+            self.db()
+                .execute(
+                    "REPLACE INTO mru (hwnd, topology, seq, owner) VALUES (:hwnd, :topology,
+                        (SELECT IFNULL(MAX(seq), 0) + 1 FROM mru WHERE topology=:topology),
+                        :owner)",
+                    named_params! {
+                        ":hwnd": hwnd.0,
+                        ":topology": topology,
+                        ":owner": self.instance,
+                    },
+                )
+                .context("failed to query database")?;
+        }
+
+        Ok(())
+    }
+
+    /// Query the recently used windows of a topology, most recent first.
+    fn mru_windows(&self, topology: usize) -> anyhow::Result<Vec<HWND>> {
+        let db = self.db();
+        let mut stmt = db
+            .prepare("SELECT hwnd FROM mru WHERE topology=:topology ORDER BY seq DESC")
+            .context("failed to prepare query")?;
+
+        let handles = stmt
+            .query_map(named_params! { ":topology": topology }, |r| {
+                r.get::<usize, isize>(0)
+            })
+            .context("failed to query database")?
+            .map(|r| r.map(HWND))
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(handles)
+    }
+
+    /// Switch the active topology, recording the transition in the journal.
+    fn set_active_topology(
+        &self,
+        topology: usize,
+        origin: Point,
+        trigger: &str,
+    ) -> anyhow::Result<()> {
+        let prev = self.data.borrow().active_topology;
+        let time = unix_time()?;
+
+        // Both instances sharing a database see the same transitions.
+        if self.maintains_database() {
+            self.db()
+                .execute(
+                    "INSERT INTO journal (time, prev, next, trigger) VALUES (:time, :prev, :next, :trigger)",
+                    named_params! { ":time": time, ":prev": prev, ":next": topology, ":trigger": trigger },
+                )
+                .context("failed to query database")?;
+        }
+
+        {
+            let mut data = self.data.borrow_mut();
+            data.active_topology = Some(topology);
+            data.origin = origin;
+        }
+
+        self.check_restore_policy();
+        self.publish_topology();
+        Ok(())
+    }
+
+    /// Format the most recent `count` journal entries, newest first.
+    fn journal(&self, count: usize) -> anyhow::Result<Vec<String>> {
+        let db = self.db();
+        let mut stmt = db
+            .prepare(
+                "SELECT datetime(time, 'unixepoch', 'localtime'), prev, next, trigger FROM journal
+                    ORDER BY id DESC LIMIT :count",
+            )
+            .context("failed to prepare query")?;
+
+        let entries = stmt
+            .query_map(named_params! { ":count": count }, |r| {
+                let prev = match r.get::<usize, Option<usize>>(1)? {
+                    Some(prev) => prev.to_string(),
+                    None => "none".to_string(),
+                };
+
+                Ok(format!(
+                    "{}  {} -> {}  ({})",
+                    r.get::<usize, String>(0)?,
+                    prev,
+                    r.get::<usize, usize>(2)?,
+                    r.get::<usize, String>(3)?,
+                ))
+            })
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(entries)
+    }
+
+    /// Load the monitor rectangles of a saved topology, relative to its origin and sorted.
+    fn topology_monitors(&self, topology: usize) -> anyhow::Result<Vec<Rect>> {
+        let data = self.store().topology(topology)?;
+        let topology: Topology = bson::from_slice(&data).context("failed to decode topology")?;
+        Ok(topology.monitors)
+    }
+
+    /// List the saved topologies other than the active one with the same number of monitors.
+    fn compatible_topologies(&self) -> anyhow::Result<Vec<TopologyItem>> {
+        let active = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+        let count = self.topology_monitors(active)?.len();
+
+        let mut items = Vec::new();
+        for id in self.store().topologies()? {
+            if id == active {
+                continue;
+            }
+
+            let monitors = self.topology_monitors(id)?;
+            if monitors.len() != count {
+                continue;
+            }
+
+            items.push(TopologyItem {
+                id,
+                label: topology_label(&monitors),
+            });
+        }
+
+        Ok(items)
+    }
+
+    /// Publish the active topology for other programs (status bar widgets, AutoHotkey scripts)
+    /// on the notification window: its ID as the `PersistentWindows.Topology` property, and
+    /// its ID, monitor sizes and workspace in the window title.
+    fn publish_topology(&self) {
+        let (topology, workspace) = {
+            let data = self.data.borrow();
+            (data.active_topology, data.workspace.clone())
+        };
+        let Some(topology) = topology else {
+            return;
+        };
+
+        let label = match self.topology_monitors(topology) {
+            Ok(monitors) => topology_label(&monitors),
+            Err(e) => {
+                warn!("{:?}", e.context("failed to describe topology"));
+                String::new()
+            }
+        };
+
+        if let Some(msg_wnd) = self.msg_wnd.borrow_mut().as_mut() {
+            let title = format!("{topology}: {label} [{workspace}]");
+            if let Err(e) = msg_wnd.set_title(&title).and_then(|_| {
+                msg_wnd.set_property(widecstr!("PersistentWindows.Topology"), topology as isize)
+            }) {
+                warn!("failed to publish active topology: {e}");
+            }
+        }
+    }
+
+    /// Copy the layout of another topology with the same number of monitors into the active
+    /// topology. Monitors are paired up in their sorted order, and each window is rescaled from
+    /// its monitor to the corresponding one. Records of the same window are paired up oldest
+    /// first, and replace the placement of their pair in the active topology (or are added if
+    /// they have none).
+    fn copy_layout(&self, source: usize) -> anyhow::Result<()> {
+        let target = self
+            .data
+            .borrow()
+            .active_topology
+            .expect("no active topology");
+
+        let from = self.topology_monitors(source)?;
+        let to = self.topology_monitors(target)?;
+        if from.len() != to.len() {
+            anyhow::bail!("topologies have a different number of monitors");
+        }
+
+        let records = self.store().records(source)?;
+        let origin = self.data.borrow().origin.clone();
+
+        let time = unix_time()?;
+        let store = self.store();
+        let tx = store.begin()?;
+
+        let mut paired = HashSet::new();
+        for record in records {
+            let disp = match WindowDisplay::decode(record.placement()) {
+                Ok(disp) => disp,
+                Err(_) => continue,
+            };
+
+            // Pair the window with the monitor holding most of it.
+            let (i, _) = from
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, m)| m.intersection_area(&disp.rect))
+                .expect("topology without monitors");
+
+            // Placements are in workspace coordinates, which start where the work area of the
+            // monitor does. Rescale in screen coordinates so that the offset is not scaled
+            // along with the window. Saved topologies do not record work areas, so the offset
+            // of the live monitor the window is copied to is used for both.
+            let offset = workspace_offset(&to[i].offset(origin.x, origin.y));
+
+            // The window keeps the same share of its monitor, whatever the scale factors.
+            let disp = WindowDisplay {
+                dpi: None,
+                ..disp
+                    .offset(offset.x, offset.y)
+                    .rescale(&from[i], &to[i])
+                    .offset(-offset.x, -offset.y)
+            };
+
+            let mut data = Vec::new();
+            bson::to_document(&disp)
+                .unwrap()
+                .to_writer(&mut data)
+                .unwrap();
+
+            let existing = self
+                .store()
+                .candidates(target, record.key.exe.as_deref(), &record.key.class)?
+                .into_iter()
+                .filter(|(id, title)| *title == record.key.title && !paired.contains(id))
+                .map(|(id, _)| id)
+                .min();
+            let id = match existing {
+                Some(id) => {
+                    self.store()
+                        .replace_placement(id, &data, "copy layout", time)?;
+                    id
+                }
+                None => self.store().insert_record(
+                    target,
+                    &store::RecordData {
+                        key: &record.key,
+                        disp: &data,
+                        user_disp: None,
+                        trigger: "copy layout",
+                        updated: time,
+                        desktop: None,
+                        tag: None,
+                    },
+                )?,
+            };
+            paired.insert(id);
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Capture the current display topology, returning its ID and the virtual screen origin.
+    fn capture_topology(&self) -> anyhow::Result<(usize, Point)> {
+        let monitors = monitor::monitors(None).context("failed to query display topology")?;
+
+        let infos = monitors
+            .into_iter()
+            .map(|(m, _)| Ok((m, m.info()?)))
+            .collect::<Result<Vec<_>, windows::core::Error>>()
+            .context("failed to query monitor info")?;
+
+        // Without identities, fall back to matching by geometry alone rather than failing.
+        let ids = monitor::monitor_ids().unwrap_or_else(|e| {
+            warn!("failed to query monitor identities: {e}");
+            HashMap::new()
+        });
+
+        // Changing the primary monitor moves the virtual screen origin (and the enumeration
+        // order) without anything physically moving. Normalize the monitors so that the same
+        // physical arrangement always produces the same topology.
+        let origin = Point {
+            x: infos.iter().map(|(_, i)| i.rect.left).min().unwrap_or(0),
+            y: infos.iter().map(|(_, i)| i.rect.top).min().unwrap_or(0),
+        };
+        let mut monitors = infos
+            .into_iter()
+            .map(|(m, i)| {
+                (
+                    i.rect.offset(-origin.x, -origin.y),
+                    ids.get(&i.name).cloned(),
+                    m.dpi(MonitorDpiType::Effective).ok().map(|d| d.x),
+                    m.rotation().ok(),
+                )
+            })
+            .collect::<Vec<_>>();
+        monitors.sort_by_key(|(r, ..)| (r.left, r.top, r.right, r.bottom));
+
+        // Only record a property if every monitor has it, so the key is stable.
+        fn all<T: Clone>(v: impl Iterator<Item = Option<T>>) -> Vec<T> {
+            v.collect::<Option<Vec<_>>>().unwrap_or_default()
+        }
+
+        let topology = Topology {
+            monitors: monitors.iter().map(|(r, ..)| r.clone()).collect(),
+            ids: all(monitors.iter().map(|(_, id, ..)| id.clone())),
+            dpis: all(monitors.iter().map(|(_, _, dpi, _)| *dpi)),
+            rotations: all(monitors.iter().map(|(.., rotation)| *rotation)),
+            location: self.data.borrow().location.clone(),
+        };
+
+        let encode = |topology: &Topology| {
+            let mut data = Vec::new();
+            bson::to_document(topology)
+                .unwrap()
+                .to_writer(&mut data)
+                .unwrap();
+            data
+        };
+
+        // Adopt the layouts of a topology captured before monitor identities (or later, scales
+        // and rotations) were recorded, or before a location was declared.
+        let legacy = [
+            Topology {
+                location: None,
+                ..topology.clone()
+            },
+            Topology {
+                dpis: Vec::new(),
+                rotations: Vec::new(),
+                location: None,
+                ..topology.clone()
+            },
+            Topology {
+                monitors: topology.monitors.clone(),
+                ids: Vec::new(),
+                dpis: Vec::new(),
+                rotations: Vec::new(),
+                location: None,
+            },
+        ];
+        let data = encode(&topology);
+        for legacy in legacy.iter().map(encode) {
+            if legacy != data && self.store().replace_topology(&legacy, &data)? {
+                info!("adopted legacy topology for the current monitors");
+                break;
             }
         }
 
-        Ok(())
+        // Monitors can come back under new identities (e.g. after a driver update, or through
+        // another dock port), which makes them look like new displays.
+        let renamed = match self.store().find_topology(&data)? {
+            Some(_) => None,
+            None => self.renamed_topology(&topology)?,
+        };
+
+        let row_id = self.store().register_topology(&data)?;
+        if let Some(known) = renamed {
+            info!("topology {row_id} has the monitors of topology {known} under new identities");
+            self.data.borrow_mut().merge_offer = Some((row_id, known));
+        }
+
+        Ok((row_id, origin))
     }
 
-    fn capture_windows(&self) -> anyhow::Result<()> {
-        let handles = window::windows().context("failed to query windows")?;
+    /// The most recent known topology with the same monitors (and location) as `topology`, but
+    /// other monitor identities.
+    fn renamed_topology(&self, topology: &Topology) -> anyhow::Result<Option<usize>> {
+        if topology.ids.is_empty() {
+            return Ok(None);
+        }
 
-        info!("capturing {} handles", handles.len());
-        for hwnd in handles {
-            // Silently ignore any errors for individual windows.
-            match self
-                .capture_window(hwnd)
-                .context("failed to capture window")
+        for id in self.store().topologies()? {
+            let other = bson::from_slice::<Topology>(&self.store().topology(id)?)
+                .context("failed to decode topology")?;
+            if other.monitors == topology.monitors
+                && other.location == topology.location
+                && !other.ids.is_empty()
+                && other.ids != topology.ids
             {
-                Ok(_) => {
-                    /*
-                    if let Ok(title) = hwnd.title() {
-                        info!("captured {}", title)
+                return Ok(Some(id));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Ask the user whether the active topology is a known one whose monitors changed identity,
+    /// and if so, merge it into the known one so its layout is restored.
+    fn offer_topology_merge(&self) {
+        let Some((topology, known)) = self.data.borrow_mut().merge_offer.take() else {
+            return;
+        };
+        if self.data.borrow().active_topology != Some(topology) || !self.maintains_database() {
+            return;
+        }
+
+        let choice = nwg::message(&nwg::MessageParams {
+            title: "Persistent Windows",
+            content: "These monitors match a display setup seen before, but report different \
+                      identities. Treat them as the same displays and restore their layout?",
+            buttons: nwg::MessageButtons::YesNo,
+            icons: nwg::MessageIcons::Question,
+        });
+        if choice != nwg::MessageChoice::Yes {
+            return;
+        }
+
+        match self.merge_topology(topology, known) {
+            Ok(()) => info!("merged topology {topology} into {known}"),
+            Err(e) => {
+                error!("{:?}", e.context("failed to merge topologies"));
+                return;
+            }
+        }
+
+        // The other instance may be using the topology that is gone.
+        let workspace = self.data.borrow().workspace.clone();
+        if self.peers.peer().and_then(|p| p.workspace) == Some(workspace) {
+            if let Err(e) = ipc::call_instance(!self.elevated, &ipc::Request::Settle) {
+                warn!("{:?}", e.context("failed to notify the other instance"));
+            }
+        }
+    }
+
+    /// Fold the topology `from` into `known`, which takes over its monitor identities. What was
+    /// saved under `from` since it was first seen is dropped in favor of the layout of `known`.
+    fn merge_topology(&self, from: usize, known: usize) -> anyhow::Result<()> {
+        {
+            // With the database store, the transaction also covers the auxiliary data.
+            let store = self.store();
+            let tx = store.begin()?;
+            let db = self.db();
+
+            for table in ["mru", "stat", "dialog", "topology_policy"] {
+                db.execute(
+                    &format!("DELETE FROM {table} WHERE topology=:from"),
+                    named_params! { ":from": from },
+                )
+                .context("failed to query database")?;
+            }
+            for (table, column) in [
+                ("checkpoint", "topology"),
+                ("journal", "prev"),
+                ("journal", "next"),
+            ] {
+                db.execute(
+                    &format!("UPDATE {table} SET {column}=:known WHERE {column}=:from"),
+                    named_params! { ":known": known, ":from": from },
+                )
+                .context("failed to query database")?;
+            }
+            store.merge_topology(from, known)?;
+
+            tx.commit().context("failed to commit merge")?;
+        }
+
+        {
+            let mut data = self.data.borrow_mut();
+            data.records.retain(|(_, topology), _| *topology != from);
+            data.active_topology = Some(known);
+        }
+
+        self.check_restore_policy();
+        self.publish_topology();
+        Ok(())
+    }
+
+    /// This is called when a window event happens in the system
+    fn on_wnd_event(&self, hwnd: HWND, event: u32) {
+        // Interesting system events:
+        // - EVENT_SYSTEM_FOREGROUND (OS window foreground/background)
+        // - EVENT_OBJECT_LOCATIONCHANGE
+        // - EVENT_OBJECT_NAMECHANGE
+        // - EVENT_OBJECT_DESTROY
+        // - EVENT_SYSTEM_MOVESIZESTART
+        // - EVENT_SYSTEM_MOVESIZEEND
+        // - EVENT_SYSTEM_MINIMIZESTART
+        // - EVENT_SYSTEM_MINIMIZEEND
+        match event {
+            EVENT_OBJECT_CREATE => {
+                if hwnd.is_top_level() && config::with(|c| c.place_new_windows) {
+                    self.data.borrow_mut().created.insert(hwnd.0);
+                }
+            }
+            EVENT_OBJECT_DESTROY => {
+                self.data.borrow_mut().created.remove(&hwnd.0);
+                if let Err(e) = self.forget_window(hwnd) {
+                    warn!("{:?}", e.context("failed to forget window"));
+                }
+            }
+            EVENT_OBJECT_SHOW => {
+                if hwnd.is_top_level() {
+                    self.data
+                        .borrow_mut()
+                        .shown
+                        .entry(hwnd.0)
+                        .or_insert_with(Stamp::now);
+                }
+
+                if let Err(e) = self.adopt_recreated_window(hwnd) {
+                    warn!("{:?}", e.context("failed to adopt recreated window"));
+                }
+
+                let created = self.data.borrow_mut().created.remove(&hwnd.0);
+                if created {
+                    if let Err(e) = self.place_new_window(hwnd) {
+                        warn!("{:?}", e.context("failed to place new window"));
                     }
-                    */
                 }
-                Err(e) => warn!("{e:?}"),
+            }
+            EVENT_SYSTEM_FOREGROUND => {
+                let _ = self.touch_mru(hwnd);
+                self.update_fullscreen(hwnd);
+
+                // Clicking the tray icon activates the taskbar, which is not what the user was
+                // working with.
+                let shell = hwnd
+                    .class_name()
+                    .is_ok_and(|c| SHELL_CLASSES.contains(&c.as_str()));
+                if !shell {
+                    self.data.borrow_mut().last_foreground = Some(hwnd);
+                }
+            }
+            _ => {
+                // Where the user puts a window wins over where it was restored to.
+                if CaptureTrigger::Event(event).is_user() {
+                    self.data.borrow_mut().restored.remove(&hwnd.0);
+                }
+
+                let _ = self.capture_window(hwnd, CaptureTrigger::Event(event));
             }
         }
+    }
 
-        Ok(())
+    /// A monitor's scale was overridden in the registry. Some builds of Windows apply it without
+    /// a display change, so the topology is settled just the same.
+    fn on_scaling_changed(&self) {
+        info!("monitor scaling changed, waiting for the topology to settle");
+        self.settle("DpiValue");
     }
 
-    fn restore_window(&self, hwnd: HWND) -> anyhow::Result<()> {
-        let topology = self
-            .data
-            .borrow()
-            .active_topology
-            .expect("no active topology");
+    /// Answer the requests received on the pipe.
+    fn on_ipc(&self) {
+        let calls = match self.ipc_calls.borrow().as_ref() {
+            Some(receiver) => receiver.try_iter().collect::<Vec<_>>(),
+            None => return,
+        };
 
-        if hwnd.is_visible() {
-            let class_name = hwnd.class_name().context("failed to query class name")?;
-            let placement = hwnd.placement().context("failed to query placement")?;
+        for call in calls {
+            info!("pipe request: {:?}", call.request);
+
+            let res = self.handle_ipc(call.request);
+            if let Err(e) = &res {
+                warn!("failed to handle pipe request: {e:?}");
+            }
+
+            // The client may have given up waiting.
+            let _ = call.reply.send(res.into());
+        }
+    }
+
+    fn handle_ipc(&self, request: ipc::Request) -> anyhow::Result<serde_json::Value> {
+        match request {
+            ipc::Request::Capture => self.capture_windows()?,
+            ipc::Request::Restore => self.restore_windows("ipc", Default::default())?,
+            ipc::Request::Status => {
+                let data = self.data.borrow();
+                let label = data
+                    .active_topology
+                    .and_then(|id| self.topology_monitors(id).ok())
+                    .map(|monitors| topology_label(&monitors));
 
-            if let Some(restore_placement) = self.find_window(hwnd, topology) {
-                let wnd_placement = WINDOWPLACEMENT {
-                    length: core::mem::size_of::<WINDOWPLACEMENT>() as u32,
-                    flags: WPF_ASYNCWINDOWPLACEMENT,
-                    showCmd: SHOW_WINDOW_CMD(restore_placement.show),
-                    ptMinPosition: restore_placement.min.into(),
-                    ptMaxPosition: restore_placement.max.into(),
-                    rcNormalPosition: restore_placement.rect.into(),
+                return Ok(serde_json::json!({
+                    "pid": std::process::id(),
+                    "elevated": self.elevated,
+                    "workspace": data.workspace,
+                    "topology": data.active_topology,
+                    "monitors": label,
+                    "location": data.location,
+                    "paused": data.paused,
+                    "transition": format!("{:?}", data.transition),
+                }));
+            }
+            ipc::Request::ListWindows => {
+                let data = self.data.borrow();
+                let Some(topology) = data.active_topology else {
+                    return Ok(serde_json::json!([]));
                 };
 
-                match SHOW_WINDOW_CMD(restore_placement.show) {
-                    SW_MAXIMIZE => {
-                        // For some reason, maximized windows ignore SetWindowPlacement calls,
-                        // so we have to set the window to normal placement first, and then maximize
-                        // it afterwards.
-                        let mut wnd_placement = wnd_placement.clone();
-                        wnd_placement.showCmd = SW_SHOWNORMAL;
-                        hwnd.set_placement(wnd_placement)
-                            .context("failed to restore maximized window placement")?;
+                let mut windows = data
+                    .records
+                    .iter()
+                    .filter(|((_, t), _)| *t == topology)
+                    .filter_map(|((hwnd, _), id)| {
+                        let hwnd = HWND(*hwnd);
+                        let key = WindowKey::from_window(hwnd).ok()?;
+
+                        Some(serde_json::json!({
+                            "hwnd": hwnd.0,
+                            "record": id,
+                            "exe": key.exe,
+                            "class": key.class,
+                            "title": key.title,
+                            "rect": hwnd.rect().ok(),
+                        }))
+                    })
+                    .collect::<Vec<_>>();
+                windows.sort_by_key(|w| w["record"].as_i64());
+
+                return Ok(serde_json::Value::Array(windows));
+            }
+            ipc::Request::SwitchWorkspace { name } => {
+                self.switch_workspace(&name)?;
+                self.refresh_workspaces();
+            }
+            ipc::Request::SimulateTransition { location } => {
+                // Unlike a declared location, this one is not remembered.
+                self.data.borrow_mut().location = location;
+                self.settle("simulated");
+            }
+            ipc::Request::Announce(_) => {
+                anyhow::bail!("announcements are answered by the pipe thread")
+            }
+            ipc::Request::Settle => self.settle("peer"),
+        }
+
+        Ok(serde_json::Value::Null)
+    }
+
+    fn on_raw_event(
+        &self,
+        _hwnd: HWND,
+        msg: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> Option<LRESULT> {
+        if msg == WM_HOTKEY && wparam.0 as i32 == HOTKEY_TOGGLE_TRACKING {
+            let hwnd = window::foreground();
+            if hwnd.0 != 0 {
+                let tracked = window_app_name(hwnd)
+                    .map(|exe| self.has_rule(&exe, RuleAction::Exclude).unwrap_or(false))
+                    .unwrap_or(false);
+                self.set_tracking(hwnd, tracked);
+            }
+
+            return Some(LRESULT(0));
+        }
+
+        // Hooks and notification registrations can silently stop working when the shell or DWM
+        // restarts (e.g. after a display driver reset), so set them all up again.
+        if msg == self.taskbar_created || msg == WM_DWMCOMPOSITIONCHANGED {
+            info!("shell or DWM restarted, reinstalling hooks");
+
+            self.reinstall_hooks();
+            if let Some(msg_wnd) = self.msg_wnd.borrow_mut().as_mut() {
+                if let Err(e) = msg_wnd.reregister() {
+                    error!("failed to re-register for system notifications: {e}");
+                }
+            }
+
+            return None;
+        }
+
+        // Interesting events:
+        // - WM_WTSSESSION_CHANGE (remote/console)
+        // - WM_DISPLAYCHANGE (resolution change)
+        match msg {
+            WM_DISPLAYCHANGE => {
+                info!("display change, waiting for the topology to settle");
+                self.settle("WM_DISPLAYCHANGE");
+            }
+            // Nothing the system does to windows while going to sleep is worth capturing.
+            WM_POWERBROADCAST if wparam.0 as u32 == PBT_APMSUSPEND => {
+                info!("suspending, holding capture until resumed");
+
+                self.cancel_restore();
+                self.display_timer.stop();
+                self.set_transition(Transition::Settling);
+            }
+            // Monitors frequently come back from sleep in a shuffled arrangement (or not at
+            // all for a while), so resuming is handled like a display change.
+            WM_POWERBROADCAST if wparam.0 as u32 == PBT_APMRESUMEAUTOMATIC => {
+                info!("resumed, waiting for the topology to settle");
+                self.settle("PBT_APMRESUMEAUTOMATIC");
+            }
+            // The system reports the power source once on registration, and whenever it changes
+            // after that.
+            WM_POWERBROADCAST if wparam.0 as u32 == PBT_POWERSETTINGCHANGE => {
+                let setting = unsafe { &*(lparam.0 as *const POWERBROADCAST_SETTING) };
+                if setting.PowerSetting == GUID_ACDC_POWER_SOURCE {
+                    // N.B: The data is a `SYSTEM_POWER_CONDITION`, where 0 means AC power.
+                    let on_battery = setting.Data[0] != 0;
+                    let prev = self.data.borrow_mut().on_battery.replace(on_battery);
+                    if prev.is_some_and(|prev| prev != on_battery) {
+                        self.apply_workspace_triggers(match on_battery {
+                            true => config::Condition::Battery,
+                            false => config::Condition::Ac,
+                        });
                     }
-                    _ => info!(
-                        "restoring {:#010X} from {:?} to {:?} ({})",
-                        hwnd.0,
-                        placement.rcNormalPosition,
-                        wnd_placement.rcNormalPosition,
-                        class_name,
-                    ),
-                };
+                }
+            }
+            // Convertibles switching between laptop and tablet posture.
+            WM_SETTINGCHANGE if setting_name(lparam).as_deref() == Some("ConvertibleSlateMode") => {
+                self.apply_workspace_triggers(posture());
+            }
+            // Work area changes that come with a display change are handled once it settles.
+            WM_SETTINGCHANGE
+                if wparam.0 as u32 == SPI_SETWORKAREA.0
+                    && self.data.borrow().transition != Transition::Settling =>
+            {
+                info!("work area changed, waiting for it to settle");
 
-                hwnd.set_placement(wnd_placement)
-                    .context("failed to restore window placement")?;
+                self.work_area_timer.stop();
+                self.work_area_timer.start();
+            }
+            WM_WTSSESSION_CHANGE => match wparam.0 as u32 {
+                WTS_SESSION_LOCK => {
+                    info!("session locked, freezing capture");
+
+                    self.unlock_timer.stop();
+                    let mut data = self.data.borrow_mut();
+                    data.frozen = true;
+                    data.locked = true;
+                }
+                WTS_SESSION_UNLOCK => {
+                    info!("session unlocked, resuming capture shortly");
+
+                    {
+                        let mut data = self.data.borrow_mut();
+                        data.locked = false;
+                        data.resume_trigger = "WTS_SESSION_UNLOCK";
+                    }
+                    self.unlock_timer.stop();
+                    self.unlock_timer.start();
+                }
+                // Connecting over RDP (or back at the console) changes the resolution and
+                // scrambles every window, so capture the layout while it is still intact.
+                WTS_CONSOLE_DISCONNECT | WTS_REMOTE_DISCONNECT => {
+                    info!("session disconnected, capturing layout and freezing capture");
+
+                    self.unlock_timer.stop();
+                    if !self.data.borrow().frozen {
+                        self.capture_now();
+                    }
+                    self.data.borrow_mut().frozen = true;
+                }
+                // Reconnecting to a locked session resumes once it is unlocked instead.
+                WTS_CONSOLE_CONNECT | WTS_REMOTE_CONNECT if !self.data.borrow().locked => {
+                    info!("session connected, restoring layout shortly");
+
+                    self.data.borrow_mut().resume_trigger = match wparam.0 as u32 {
+                        WTS_REMOTE_CONNECT => "WTS_REMOTE_CONNECT",
+                        _ => "WTS_CONSOLE_CONNECT",
+                    };
+                    self.unlock_timer.stop();
+                    self.unlock_timer.start();
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        None
+    }
+}
+
+/// The posture of a convertible, for workspace triggers.
+fn posture() -> config::Condition {
+    // N.B: The metric is zero in tablet posture.
+    match unsafe { GetSystemMetrics(SM_CONVERTIBLESLATEMODE) } {
+        0 => config::Condition::Tablet,
+        _ => config::Condition::Laptop,
+    }
+}
+
+/// The name of the setting a `WM_SETTINGCHANGE` is about, if any.
+fn setting_name(lparam: LPARAM) -> Option<String> {
+    match lparam.0 {
+        0 => None,
+        p => unsafe { PCWSTR(p as *const u16).to_string() }.ok(),
+    }
+}
+
+/// Classes of the windows making up the desktop itself (behind the icons, and the wallpaper
+/// worker windows spawned to host animated wallpapers).
+const DESKTOP_CLASSES: &[&str] = &["Progman", "WorkerW"];
+
+/// Classes of known desktop widget windows.
+const WIDGET_CLASSES: &[&str] = &["RainmeterMeterWindow"];
+
+/// Whether a window is a desktop widget (Rainmeter skins, conky-likes) living in the bottom
+/// z-band. These position themselves, and restoring them can pull them out of their band, so
+/// they are neither captured nor restored.
+fn is_desktop_widget(hwnd: HWND) -> bool {
+    // Widgets pinned to the desktop are owned by one of its windows.
+    if let Some(owner) = hwnd.owner_window() {
+        if let Ok(class) = owner.class_name() {
+            if DESKTOP_CLASSES.contains(&class.as_str()) {
+                return true;
             }
         }
+    }
+
+    match hwnd.class_name() {
+        Ok(class) => WIDGET_CLASSES.contains(&class.as_str()),
+        Err(_) => false,
+    }
+}
+
+/// Whether a window is hidden by DWM for reasons other than being on another virtual desktop,
+/// such as the leftover frames of suspended UWP apps. These report being visible, but are not
+/// on screen, so they are neither captured nor restored.
+fn is_ghost(hwnd: HWND) -> bool {
+    hwnd.is_cloaked() && vdesktop::is_on_current_desktop(hwnd).unwrap_or(true)
+}
+
+/// Whether a window covers its whole monitor, like games and presentations do. Maximized
+/// windows leave the taskbar uncovered, and the desktop itself does not count.
+fn is_fullscreen(hwnd: HWND) -> bool {
+    if hwnd
+        .class_name()
+        .is_ok_and(|c| DESKTOP_CLASSES.contains(&c.as_str()) || SHELL_CLASSES.contains(&c.as_str()))
+    {
+        return false;
+    }
+
+    let Ok(rect) = hwnd.rect() else {
+        return false;
+    };
+
+    monitor::from_rect(&rect).info().is_ok_and(|m| {
+        rect.left <= m.rect.left
+            && rect.top <= m.rect.top
+            && rect.right >= m.rect.right
+            && rect.bottom >= m.rect.bottom
+    })
+}
+
+/// Describe a topology by the sizes of its monitors, e.g. "1920x1080 + 2560x1440".
+fn topology_label(monitors: &[Rect]) -> String {
+    monitors
+        .iter()
+        .map(|m| format!("{}x{}", m.width(), m.height()))
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// Whether a window is badly out of place: entirely off-screen, or on a different monitor than
+/// the one it would be restored to.
+fn is_misplaced(hwnd: HWND, target: HMONITOR) -> bool {
+    // N.B: Use the normal position, so minimized windows are judged by where they will reappear.
+    let Ok(placement) = hwnd.placement() else {
+        return false;
+    };
+    let rect = WindowDisplay::from(placement).rect;
+
+    match monitor::monitors(Some(rect.clone().into())) {
+        Ok(monitors) if monitors.is_empty() => true,
+        _ => monitor::from_rect(&rect) != target,
+    }
+}
+
+/// How long ago the user last provided keyboard or mouse input to the session.
+///
+/// N.B: Unlike a `Stamp`, this includes time the system spent asleep. The input time is only
+/// available in the tick count clock, so it is not mixed with the interrupt time stamps use.
+fn idle_time() -> Option<Duration> {
+    let mut info = LASTINPUTINFO {
+        cbSize: core::mem::size_of::<LASTINPUTINFO>() as u32,
+        ..Default::default()
+    };
+
+    if !unsafe { GetLastInputInfo(&mut info) }.as_bool() {
+        return None;
+    }
+
+    // The input time is in the same wrapping millisecond tick count as `GetTickCount`.
+    let idle = unsafe { GetTickCount() }.wrapping_sub(info.dwTime);
+    Some(Duration::from_millis(idle as u64))
+}
+
+/// Convert a rectangle from the workspace coordinates used by `WINDOWPLACEMENT` into screen
+/// coordinates. Tool windows already use screen coordinates.
+fn workspace_to_screen(hwnd: HWND, rect: &Rect) -> Rect {
+    if (hwnd.ex_style() & WS_EX_TOOLWINDOW).0 != 0 {
+        return rect.clone();
+    }
+
+    let offset = workspace_offset(rect);
+    rect.offset(offset.x, offset.y)
+}
+
+/// Convert a rectangle in screen coordinates to the workspace coordinates of window placements,
+/// the inverse of `workspace_to_screen`.
+fn screen_to_workspace(hwnd: HWND, rect: &Rect) -> Rect {
+    if (hwnd.ex_style() & WS_EX_TOOLWINDOW).0 != 0 {
+        return rect.clone();
+    }
+
+    let offset = workspace_offset(rect);
+    rect.offset(-offset.x, -offset.y)
+}
+
+/// The offset of screen coordinates from the workspace coordinates of window placements on
+/// the monitor nearest `rect`: how far its work area starts from its top-left corner.
+fn workspace_offset(rect: &Rect) -> Point {
+    match monitor::from_rect(rect).info() {
+        Ok(info) => Point {
+            x: info.work.left - info.rect.left,
+            y: info.work.top - info.rect.top,
+        },
+        Err(_) => Point { x: 0, y: 0 },
+    }
+}
+
+/// The normal rectangle of a window in workspace coordinates, read from the same source as a
+/// record captured from `source`.
+fn normal_rect(hwnd: HWND, placement: &WINDOWPLACEMENT, source: CaptureSource) -> Rect {
+    match source {
+        CaptureSource::WindowRect if !hwnd.is_minimized() && !hwnd.is_maximized() => hwnd
+            .rect()
+            .map(|rect| screen_to_workspace(hwnd, &rect))
+            .unwrap_or_else(|_| placement.rcNormalPosition.into()),
+        _ => placement.rcNormalPosition.into(),
+    }
+}
+
+/// The monitors attached to the system, as restores are planned against them, along with their
+/// handles.
+fn plan_monitors() -> anyhow::Result<(Vec<HMONITOR>, Vec<plan::Monitor>)> {
+    let mut handles = Vec::new();
+    let mut monitors = Vec::new();
+    for (hmon, _) in monitor::monitors(None).context("failed to enumerate monitors")? {
+        let info = hmon.info().context("failed to query monitor info")?;
+        let dpi = hmon
+            .dpi(MonitorDpiType::Effective)
+            .map_or(USER_DEFAULT_SCREEN_DPI, |dpi| dpi.x);
+
+        handles.push(hmon);
+        monitors.push(plan::Monitor {
+            rect: info.rect,
+            work: info.work,
+            dpi,
+        });
+    }
+
+    if monitors.is_empty() {
+        anyhow::bail!("no monitors to restore to");
+    }
+
+    Ok((handles, monitors))
+}
+
+/// What caused a window to be captured.
+#[derive(Clone, Copy, Debug)]
+enum CaptureTrigger {
+    /// A window event reported by the event hooks
+    Event(u32),
+    /// A full capture pass over all windows
+    Pass,
+}
+
+impl CaptureTrigger {
+    fn name(&self) -> &'static str {
+        match self {
+            CaptureTrigger::Event(event) => event_name(*event),
+            CaptureTrigger::Pass => "capture pass",
+        }
+    }
+
+    /// Whether the capture follows something the user did to the window (moving, sizing,
+    /// minimizing or restoring it), as opposed to an incidental capture.
+    fn is_user(&self) -> bool {
+        matches!(
+            self,
+            CaptureTrigger::Event(
+                EVENT_SYSTEM_MOVESIZESTART
+                    | EVENT_SYSTEM_MOVESIZEEND
+                    | EVENT_SYSTEM_MINIMIZESTART
+                    | EVENT_SYSTEM_MINIMIZEEND
+            )
+        )
+    }
+}
+
+/// Name of a window event, as recorded in the placement history.
+fn event_name(event: u32) -> &'static str {
+    match event {
+        EVENT_SYSTEM_MOVESIZESTART => "EVENT_SYSTEM_MOVESIZESTART",
+        EVENT_SYSTEM_MOVESIZEEND => "EVENT_SYSTEM_MOVESIZEEND",
+        EVENT_SYSTEM_MINIMIZESTART => "EVENT_SYSTEM_MINIMIZESTART",
+        EVENT_SYSTEM_MINIMIZEEND => "EVENT_SYSTEM_MINIMIZEEND",
+        EVENT_OBJECT_NAMECHANGE => "EVENT_OBJECT_NAMECHANGE",
+        _ => "window event",
+    }
+}
+
+/// Run a fallible function, and show an error message if it fails.
+fn run_fallible<T>(f: impl FnOnce() -> Result<T, anyhow::Error>) -> Result<T, anyhow::Error> {
+    return match f() {
+        Ok(t) => Ok(t),
+        Err(e) => {
+            let e = Into::<anyhow::Error>::into(e);
+            nwg::error_message("Error", &format!("{}", e));
+            Err(e)
+        }
+    };
+}
+
+/// Format a Unix time as local date and time, e.g. `2024-05-01 09:30:00`.
+fn local_time(time: u64) -> String {
+    // FILETIMEs count 100 ns intervals since 1601.
+    let ticks = (time + 11_644_473_600) * 10_000_000;
+    let file_time = FILETIME {
+        dwLowDateTime: ticks as u32,
+        dwHighDateTime: (ticks >> 32) as u32,
+    };
+
+    let mut utc = SYSTEMTIME::default();
+    let mut local = SYSTEMTIME::default();
+    let converted = unsafe {
+        FileTimeToSystemTime(&file_time, &mut utc).as_bool()
+            && SystemTimeToTzSpecificLocalTime(None, &utc, &mut local).as_bool()
+    };
+    if !converted {
+        return format!("@{time}");
+    }
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        local.wYear, local.wMonth, local.wDay, local.wHour, local.wMinute, local.wSecond
+    )
+}
+
+/// The current time, in seconds since the Unix epoch.
+fn unix_time() -> anyhow::Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("system clock is before the epoch")?
+        .as_secs())
+}
+
+/// Name of the singleton mutex held by instances of the given elevation. The name lives in the
+/// session namespace, so an elevated and an unelevated instance can run alongside each other.
+fn instance_mutex_name(elevated: bool) -> String {
+    match elevated {
+        true => format!("Local\\{INSTANCE_GUID}-elevated"),
+        false => format!("Local\\{INSTANCE_GUID}"),
+    }
+}
 
-        Ok(())
+/// Query whether the process owning a window is elevated.
+fn is_window_elevated(hwnd: HWND) -> anyhow::Result<bool> {
+    if let Some(details) = cached_process(hwnd) {
+        return details
+            .elevated
+            .context("failed to query process elevation");
     }
 
-    fn capture_window(&self, hwnd: HWND) -> anyhow::Result<()> {
-        let topology = self
-            .data
-            .borrow()
-            .active_topology
-            .expect("no active topology");
+    with_window_process(hwnd, |process| process.is_elevated())
+        .context("failed to query process elevation")
+}
 
-        if hwnd.is_visible() && hwnd.is_top_level() {
-            let placement = hwnd.placement().context("failed to query placement")?;
+/// The tag of a window, stamping it with a new one if it has none yet. Returns `None` if the
+/// window cannot be stamped.
+fn window_tag(hwnd: HWND) -> Option<String> {
+    let tag = match hwnd.tag() {
+        Some(tag) => tag,
+        None => {
+            let tag = windows::core::GUID::new().ok()?;
+            hwnd.set_tag(tag).ok()?;
+            tag
+        }
+    };
 
-            let mut rect = Vec::new();
-            bson::to_document(&WindowDisplay::from(placement))
-                .unwrap()
-                .to_writer(&mut rect)
-                .unwrap();
+    Some(format!("{tag:?}"))
+}
 
-            self.db
-                .execute(
-                    "REPLACE INTO appwindow (hwnd, topology, disp) VALUES (:hwnd, :topology, :disp)",
-                    named_params! { ":hwnd": hwnd.0, ":topology": topology, ":disp": rect },
-                )
-                .context("failed to query database")?;
+/// Query the name an application is known by, for the application owning a window. This is the
+/// normalized image path of its process, except for UWP apps: their windows all belong to the
+/// same frame host, so they go by their AppUserModelID instead.
+fn window_app_name(hwnd: HWND) -> anyhow::Result<String> {
+    if hwnd.class_name().is_ok_and(|c| c == shell::APP_FRAME_CLASS) {
+        match shell::app_user_model_id(hwnd) {
+            Ok(Some(id)) => return Ok(id),
+            Ok(None) => {}
+            Err(e) => warn!("failed to query app user model ID: {e}"),
         }
-
-        Ok(())
     }
 
-    fn capture_topology(&self) -> anyhow::Result<usize> {
-        let monitors = monitor::monitors(None).context("failed to query display topology")?;
-
-        let rects = monitors
-            .into_iter()
-            .map(|(m, _)| Ok(m.info()?.rect))
-            .collect::<Result<Vec<_>, windows::core::Error>>()
-            .context("failed to query monitor info")?;
+    window_image_name(hwnd)
+}
 
-        let mut topology = Vec::new();
-        bson::to_document(&Topology { monitors: rects })
-            .unwrap()
-            .to_writer(&mut topology)
-            .unwrap();
+/// Query the normalized image path of the process owning a window.
+fn window_image_name(hwnd: HWND) -> anyhow::Result<String> {
+    if let Some(details) = cached_process(hwnd) {
+        return details.image.context("failed to query process image name");
+    }
 
-        // Register the new topology if it is not already in the database.
-        self.db
-            .execute(
-                "INSERT OR IGNORE INTO topology (data) VALUES (:topology)",
-                named_params! { ":topology": topology },
-            )
-            .context("failed to query database")?;
+    let name = with_window_process(hwnd, |process| process.full_image_name())
+        .context("failed to query process image name")?;
 
-        // Retrieve the row ID and save it.
-        let row_id = self
-            .db
-            .query_row(
-                "SELECT rowid FROM topology WHERE data=:topology",
-                named_params! { ":topology": topology },
-                |row| row.get::<usize, usize>(0),
-            )
-            .context("failed to query row id")?;
+    Ok(normalize_image_name(name))
+}
 
-        Ok(row_id)
-    }
+fn normalize_image_name(name: String) -> String {
+    // Fall back to case folding alone if the path cannot be resolved.
+    process::normalize_path(&name).unwrap_or_else(|_| name.to_lowercase())
+}
 
-    /// This is called when a window event happens in the system
-    fn on_wnd_event(&self, hwnd: HWND, _event: u32) {
-        // Interesting system events:
-        // - EVENT_SYSTEM_FOREGROUND (OS window foreground/background)
-        // - EVENT_OBJECT_LOCATIONCHANGE
-        // - EVENT_OBJECT_NAMECHANGE
-        // - EVENT_OBJECT_DESTROY
-        // - EVENT_SYSTEM_MOVESIZESTART
-        // - EVENT_SYSTEM_MOVESIZEEND
-        // - EVENT_SYSTEM_MINIMIZESTART
-        // - EVENT_SYSTEM_MINIMIZEEND
-        let _ = self.capture_window(hwnd);
-    }
+/// Details of a process queried ahead of a capture pass.
+#[derive(Clone)]
+struct ProcessDetails {
+    image: Option<String>,
+    elevated: Option<bool>,
+}
 
-    fn on_raw_event(
-        &self,
-        _hwnd: HWND,
-        msg: u32,
-        _wparam: WPARAM,
-        _lparam: LPARAM,
-    ) -> Option<LRESULT> {
-        // Interesting events:
-        // - WM_WTSSESSION_CHANGE (remote/console)
-        // - WM_DISPLAYCHANGE (resolution change)
-        match msg {
-            WM_DISPLAYCHANGE => {
-                // TODO: Query display topology and resolution, and use it as a key for looking up window layout.
-                // TODO: Enumerate all windows in the active desktop, restore positioning if differs
-                let topo_id = match self
-                    .capture_topology()
-                    .context("failed to capture topology")
-                {
-                    Ok(id) => id,
-                    Err(e) => {
-                        error!("{e}");
-                        return None;
-                    }
-                };
+// Process details prefetched for the running capture pass, keyed by process ID. The cache is
+// cleared once the pass ends, as process IDs are recycled.
+thread_local! {
+    static PROCESS_CACHE: RefCell<HashMap<u32, ProcessDetails>> = RefCell::new(HashMap::new());
+}
 
-                self.data.borrow_mut().active_topology = Some(topo_id);
+fn cached_process(hwnd: HWND) -> Option<ProcessDetails> {
+    let pid = hwnd.owner().ok()?.process_id;
+    PROCESS_CACHE.with(|c| c.borrow().get(&pid).cloned())
+}
 
-                info!("display change: {topo_id}");
-                let _ =
-                    run_fallible(|| self.restore_windows().context("failed to restore windows"));
+fn query_process(pid: u32) -> ProcessDetails {
+    let process = match process::open(PROCESS_QUERY_LIMITED_INFORMATION.0, pid) {
+        Ok(process) => process,
+        Err(_) => {
+            return ProcessDetails {
+                image: None,
+                elevated: None,
             }
-            WM_WTSSESSION_CHANGE => {}
-            _ => {}
         }
+    };
 
-        None
+    let details = ProcessDetails {
+        image: process.full_image_name().ok().map(normalize_image_name),
+        elevated: process.is_elevated().ok(),
+    };
+    unsafe { CloseHandle(process) };
+
+    details
+}
+
+/// Query the processes owning a set of windows on a few worker threads, caching the results
+/// for the running capture pass. Each process is only queried once, however many windows it
+/// owns.
+fn prefetch_processes(handles: &[HWND]) {
+    let pids = PROCESS_CACHE.with(|c| {
+        let cache = c.borrow();
+        handles
+            .iter()
+            .filter_map(|hwnd| hwnd.owner().ok())
+            .map(|owner| owner.process_id)
+            .filter(|pid| !cache.contains_key(pid))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>()
+    });
+
+    if pids.is_empty() {
+        return;
     }
+
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(CAPTURE_THREADS);
+
+    let details = std::thread::scope(|s| {
+        pids.chunks(pids.len().div_ceil(threads))
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|&pid| (pid, query_process(pid)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|t| t.join().unwrap_or_default())
+            .collect::<Vec<_>>()
+    });
+
+    PROCESS_CACHE.with(|c| c.borrow_mut().extend(details));
 }
 
-/// Run a fallible function, and show an error message if it fails.
-fn run_fallible<T>(f: impl FnOnce() -> Result<T, anyhow::Error>) -> Result<T, anyhow::Error> {
-    return match f() {
-        Ok(t) => Ok(t),
-        Err(e) => {
-            let e = Into::<anyhow::Error>::into(e);
-            nwg::error_message("Error", &format!("{}", e));
-            Err(e)
-        }
-    };
+/// Open the process owning a window for the duration of `f`.
+fn with_window_process<T>(
+    hwnd: HWND,
+    f: impl FnOnce(HANDLE) -> windows::core::Result<T>,
+) -> anyhow::Result<T> {
+    let owner = hwnd.owner().context("failed to query window owner")?;
+    let process = process::open(PROCESS_QUERY_LIMITED_INFORMATION.0, owner.process_id)
+        .context("failed to open process")?;
+
+    let res = f(process);
+    unsafe { CloseHandle(process) };
+
+    Ok(res?)
 }
 
 fn runas_admin(params: &str) -> std::result::Result<i32, windows::core::Error> {
@@ -509,7 +6341,116 @@ fn runas_admin(params: &str) -> std::result::Result<i32, windows::core::Error> {
     Ok(code as i32)
 }
 
-fn toggle_autorun() -> anyhow::Result<()> {
+/// Quote a command line argument, if needed, so that it survives `CommandLineToArgvW`.
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes preceding a quote must be escaped, as must the quote itself.
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                backslashes = 0;
+            }
+        }
+
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+
+    // Backslashes preceding the closing quote must be escaped too.
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Join command line arguments, quoting them as needed.
+fn join_args<S: AsRef<str>>(args: &[S]) -> String {
+    args.iter()
+        .map(|a| quote_arg(a.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The command line to start the app at login with, from the (already joined) launch arguments.
+fn autorun_command(args: &str) -> anyhow::Result<String> {
+    let exe = std::env::current_exe().context("failed to query exe name")?;
+    let exe = format!("\"{}\"", exe.display());
+
+    Ok(match args.is_empty() {
+        true => exe,
+        false => format!("{exe} {args}"),
+    })
+}
+
+/// Split a stored auto-start command line into the executable and its (joined) arguments.
+///
+/// N.B: Older versions stored the bare, unquoted path of the executable.
+fn split_autorun_command(cmd: &str) -> (&str, &str) {
+    match cmd.strip_prefix('"').and_then(|c| c.split_once('"')) {
+        Some((exe, args)) => (exe, args.trim_start()),
+        None => (cmd, ""),
+    }
+}
+
+/// The arguments of a command line, without the program name. Like `CommandLineToArgvW`, the
+/// program name ends at the closing quote if it is quoted, or at the first whitespace if not.
+fn command_args(cmd: &str) -> &str {
+    let rest = match cmd.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').map_or("", |(_, rest)| rest),
+        None => cmd.split_once([' ', '\t']).map_or("", |(_, rest)| rest),
+    };
+
+    rest.trim_start()
+}
+
+/// Point an enabled auto-start entry at the running executable (e.g. after the app was moved or
+/// upgraded in a different location), keeping the launch arguments it was saved with.
+fn refresh_autorun() -> anyhow::Result<()> {
+    let key = match HKCU.open_subkey_with_flags(
+        STARTUP_KEY,
+        winreg::enums::KEY_READ | winreg::enums::KEY_WRITE,
+    ) {
+        Ok(key) => key,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e).context("could not open registry key")?,
+    };
+
+    let cmd = match key.get_value::<String, _>(STARTUP_NAME) {
+        Ok(cmd) => cmd,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e).context("failed to read startup value")?,
+    };
+
+    let (_, args) = split_autorun_command(&cmd);
+    let new = autorun_command(args)?;
+    if new != cmd {
+        info!("updating auto-start command line to {new}");
+        key.set_value(STARTUP_NAME, &new)
+            .context("failed to set startup value")?;
+    }
+
+    Ok(())
+}
+
+/// Toggle starting the app at login. When enabling, the app is started with `args` (the launch
+/// arguments of the instance that asked for it). Refused while the DisableAutorunToggle policy
+/// locks it.
+fn toggle_autorun(args: &[String]) -> anyhow::Result<()> {
+    if config::with(|c| c.policy.disable_autorun_toggle) {
+        anyhow::bail!("auto-start is locked by policy");
+    }
+
     let cur_state = App::has_autostart().context("could not determine auto-start state")?;
 
     let key = HKCU
@@ -526,47 +6467,288 @@ fn toggle_autorun() -> anyhow::Result<()> {
         }
         false => {
             // Enable autorun.
-            key.set_value(
-                STARTUP_NAME,
-                &std::env::current_exe()
-                    .context("failed to query exe name")?
-                    .as_os_str(),
-            )
-            .context("failed to set startup value")?;
+            key.set_value(STARTUP_NAME, &autorun_command(&join_args(args))?)
+                .context("failed to set startup value")?;
         }
     };
 
     Ok(())
 }
 
-fn run() -> anyhow::Result<()> {
-    // Attempt to create a global mutex for this process.
-    // If it fails, that means we have another instance running.
-    let _mutex = match GlobalMutex::create("Global\\{D1905271-98BC-4888-BC9D-B05810AA21CB}", true) {
-        Ok(g) => g,
+/// The directory workspace databases are stored in.
+fn workspace_dir() -> anyhow::Result<PathBuf> {
+    if let Some(dir) = config::get().db_path {
+        return Ok(dir);
+    }
+
+    let appdata = std::env::var_os("APPDATA").context("APPDATA is not set")?;
+    Ok(PathBuf::from(appdata)
+        .join("PersistentWindows")
+        .join("workspaces"))
+}
+
+fn is_valid_workspace_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['\\', '/', ':', '*', '?', '"', '<', '>', '|'])
+}
+
+fn workspace_path(name: &str) -> anyhow::Result<PathBuf> {
+    workspace_path_in(&workspace_dir()?, name)
+}
+
+/// The database of a workspace stored in `dir`.
+fn workspace_path_in(dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    if !is_valid_workspace_name(name) {
+        anyhow::bail!("invalid workspace name \"{name}\"");
+    }
+
+    Ok(dir.join(format!("{name}.db")))
+}
+
+/// Names of the workspaces that have a database in `dir`.
+fn workspaces(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let dir = match std::fs::read_dir(dir) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => Err(e).context("failed to list workspaces")?,
+    };
+
+    let mut names = dir
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "db"))
+        .filter_map(|p| Some(p.file_stem()?.to_string_lossy().into_owned()))
+        .collect::<Vec<_>>();
+    names.sort_unstable_by_key(|n| n.to_lowercase());
+
+    Ok(names)
+}
+
+/// Read a setting persisted in the registry.
+fn read_setting(name: &str) -> Option<String> {
+    HKCU.open_subkey(SETTINGS_KEY)
+        .and_then(|key| key.get_value::<String, _>(name))
+        .ok()
+}
+
+fn write_setting(name: &str, value: &str) -> std::io::Result<()> {
+    let (key, _) = HKCU.create_subkey(SETTINGS_KEY)?;
+    key.set_value(name, &value)
+}
+
+/// The workspace that was active when the app last ran.
+fn active_workspace() -> String {
+    read_setting("Workspace")
+        .filter(|name| is_valid_workspace_name(name))
+        .unwrap_or_else(|| DEFAULT_WORKSPACE.to_string())
+}
+
+fn set_active_workspace(name: &str) -> std::io::Result<()> {
+    write_setting("Workspace", name)
+}
+
+/// How often to check whether the shell is ready at startup.
+const SHELL_READY_POLL: Duration = Duration::from_millis(500);
+
+/// How long to wait at most for the shell to be ready at startup.
+const SHELL_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The monitor rectangles, or `None` if they could not be queried.
+fn monitor_rects() -> Option<Vec<Rect>> {
+    monitor::monitors(None)
+        .ok()?
+        .into_iter()
+        .map(|(m, _)| m.info().ok().map(|i| i.rect))
+        .collect()
+}
+
+/// Wait for the shell to be ready: the taskbar must exist (or the tray icon cannot be added)
+/// and the set of monitors must have stopped changing. At login, acting any earlier captures
+/// placements of a desktop that is still being laid out, and placements applied then fail.
+fn wait_for_shell() {
+    let start = std::time::Instant::now();
+    let mut prev = None;
+
+    loop {
+        let tray = unsafe { FindWindowW(PCWSTR(widecstr!("Shell_TrayWnd").as_ptr()), None) };
+        let rects = monitor_rects();
+        if tray.0 != 0 && rects.is_some() && rects == prev {
+            break;
+        }
+
+        if start.elapsed() >= SHELL_READY_TIMEOUT {
+            warn!("shell still not ready after {SHELL_READY_TIMEOUT:?}, starting anyway");
+            return;
+        }
+
+        prev = rects;
+        std::thread::sleep(SHELL_READY_POLL);
+    }
+
+    if start.elapsed() > SHELL_READY_POLL * 2 {
+        info!("waited {:?} for the shell to be ready", start.elapsed());
+    }
+}
+
+/// How long a running instance has to answer before it is considered hung.
+const INSTANCE_PING_TIMEOUT: u32 = 2000;
+
+/// How long to wait for a terminated instance to release the singleton mutex.
+const TAKE_OVER_TIMEOUT: u32 = 5000;
+
+/// Handle another instance of the same elevation holding the singleton mutex. If it is hung,
+/// offer to end it and take over, returning the mutex once it is released.
+fn take_over_instance(elevated: bool) -> anyhow::Result<Option<GlobalMutex>> {
+    let instance = msgwindow::instances()
+        .into_iter()
+        .find(|hwnd| is_window_elevated(*hwnd).unwrap_or(true) == elevated);
+
+    // Without a window to reach it, the instance is still starting up (or shutting down).
+    let Some(hwnd) = instance else {
+        return Ok(None);
+    };
+
+    let responsive = unsafe {
+        SendMessageTimeoutW(
+            hwnd,
+            WM_NULL,
+            WPARAM(0),
+            LPARAM(0),
+            SMTO_ABORTIFHUNG,
+            INSTANCE_PING_TIMEOUT,
+            None,
+        )
+    }
+    .0 != 0;
+    if responsive {
+        return Ok(None);
+    }
+
+    let choice = nwg::message(&nwg::MessageParams {
+        title: "Persistent Windows",
+        content: "Another instance is running but not responding. End it and start a new one?",
+        buttons: nwg::MessageButtons::YesNo,
+        icons: nwg::MessageIcons::Warning,
+    });
+    if choice != nwg::MessageChoice::Yes {
+        return Ok(None);
+    }
+
+    let pid = hwnd
+        .owner()
+        .context("failed to query hung instance")?
+        .process_id;
+    let process = unsafe { OpenProcess(PROCESS_TERMINATE, false, pid) }
+        .context("failed to open hung instance")?;
+    let terminated = unsafe { TerminateProcess(process, 1) }.as_bool();
+    unsafe { CloseHandle(process) };
+    if !terminated {
+        anyhow::bail!("failed to end hung instance");
+    }
+
+    warn!("ended hung instance (process {pid})");
+
+    let (mutex, _) = GlobalMutex::acquire(&instance_mutex_name(elevated), TAKE_OVER_TIMEOUT)
+        .context("hung instance did not release the singleton mutex")?;
+    Ok(Some(mutex))
+}
+
+/// Options the app was launched with, which are kept when it starts at login.
+#[derive(Default)]
+struct LaunchOptions {
+    /// Open this workspace instead of the last active one.
+    workspace: Option<String>,
+}
+
+impl LaunchOptions {
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--workspace" => {
+                    let name = args.next().context("--workspace requires a name")?;
+                    if !is_valid_workspace_name(name) {
+                        anyhow::bail!("invalid workspace name \"{name}\"");
+                    }
+
+                    options.workspace = Some(name.clone());
+                }
+                _ => anyhow::bail!("unknown option \"{arg}\""),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Apply the options in the running instance instead, for a copy launched while one is
+    /// already running.
+    fn forward(&self) -> anyhow::Result<()> {
+        let Some(workspace) = &self.workspace else {
+            anyhow::bail!("app is already running");
+        };
+
+        ipc::call(&ipc::Request::SwitchWorkspace {
+            name: workspace.clone(),
+        })
+        .context("failed to forward the launch options to the running instance")?;
+
+        info!("switched the running instance to workspace \"{workspace}\"");
+        Ok(())
+    }
+}
+
+fn run(options: LaunchOptions) -> anyhow::Result<()> {
+    let elevated = unsafe { GetCurrentProcess() }
+        .is_elevated()
+        .context("failed to query process elevation")?;
+
+    // Attempt to take the mutex for this process.
+    // If it fails, that means we have another instance of the same elevation running.
+    let _mutex = match GlobalMutex::acquire(&instance_mutex_name(elevated), 0) {
+        Ok((g, Acquired::Abandoned)) => {
+            warn!("previous instance exited without releasing the singleton mutex, taking over");
+            g
+        }
+        Ok((g, _)) => g,
         Err(e) => match e.code() {
-            e if e == ERROR_ALREADY_EXISTS.to_hresult() => anyhow::bail!("app is already running"),
+            e if e == ERROR_ALREADY_EXISTS.to_hresult() => match take_over_instance(elevated)? {
+                Some(g) => g,
+                None => return options.forward(),
+            },
             _ => Err(e).context("failed to create singleton mutex")?,
         },
     };
 
-    let db = Connection::open_in_memory().context("Failed to open DB")?;
-    db.execute_batch(
-        "CREATE TABLE appwindow (
-                hwnd        INTEGER NOT NULL,
-                topology    INTEGER NOT NULL,
-                disp        BLOB NOT NULL,
-                PRIMARY KEY (hwnd, topology),
-                FOREIGN KEY (topology) REFERENCES topology(id)
-            );
-            CREATE TABLE topology (
-                id          INTEGER PRIMARY KEY,
-                data        BLOB UNIQUE NOT NULL
-            );",
-    )
-    .unwrap();
+    wait_for_shell();
+
+    // Required to query the shell for Explorer window locations.
+    if let Err(e) = unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) } {
+        warn!("failed to initialize COM: {e}");
+    }
+
+    let workspace = options.workspace.unwrap_or_else(active_workspace);
+    let instance = ipc::instance_name(elevated)?;
+    let dir = workspace_dir()?;
+    let db = db::open(&workspace_path_in(&dir, &workspace)?, &instance)
+        .with_context(|| format!("failed to open workspace \"{workspace}\""))?;
+    if let Err(e) = bundle::seed(&db) {
+        warn!("{:?}", e.context("failed to merge layout bundle"));
+    }
+
+    let app = Rc::new(
+        App::build_ui(App::new(db, workspace.clone(), dir, elevated, instance))
+            .context("Failed to build UI")?,
+    );
 
-    let app = Rc::new(App::build_ui(App::new(db)).context("Failed to build UI")?);
+    // Learn whether the instance of the other elevation has the same workspace open, before
+    // touching the database.
+    if let Err(e) = app.peers.announce(Some(workspace)) {
+        warn!("{:?}", e.context("failed to announce workspace"));
+    }
 
     // This notification is annoying, so only show it on release builds.
     if false {
@@ -578,71 +6760,111 @@ fn run() -> anyhow::Result<()> {
         );
     }
 
-    let topo_id = app
+    app.sanitize_windows()
+        .context("failed to validate saved windows")?;
+
+    let (topo_id, origin) = app
         .capture_topology()
         .context("failed to capture initial topology")?;
-    app.data.borrow_mut().active_topology = Some(topo_id);
+    app.set_active_topology(topo_id, origin, "startup")
+        .context("failed to set initial topology")?;
+    app.offer_topology_merge();
 
     app.capture_windows()
         .context("failed to capture initial window set")?;
 
-    // Handle a few raw events as well.
+    // System notifications are delivered to a dedicated window rather than the UI window.
     let appref = Rc::downgrade(&app);
-    let raw_hook = nwg::bind_raw_event_handler(
-        &app.window.handle,
-        0x13370,
-        move |hwnd, msg, wparam, lparam| {
-            if let Some(app) = appref.upgrade() {
-                app.on_raw_event(HWND(hwnd as isize), msg, WPARAM(wparam), LPARAM(lparam))
-                    .map(|r| r.0)
-            } else {
-                None
-            }
-        },
-    )
-    .context("could not bind raw handler")?;
+    let mut msg_wnd = MessageWindow::create(move |hwnd, msg, wparam, lparam| {
+        if let Some(app) = appref.upgrade() {
+            app.on_raw_event(hwnd, msg, wparam, lparam)
+        } else {
+            None
+        }
+    })
+    .context("could not create notification window")?;
+
+    // Ctrl+Alt+Shift+T toggles tracking of the foreground application. Another program may
+    // already own the combination, which is not worth failing over.
+    if let Err(e) = msg_wnd.register_hotkey(
+        HOTKEY_TOGGLE_TRACKING,
+        MOD_CONTROL | MOD_ALT | MOD_SHIFT | MOD_NOREPEAT,
+        'T' as u32,
+    ) {
+        warn!("failed to register hotkey: {e}");
+    }
 
     let appref = Rc::downgrade(&app);
-    let evt_hooks = EventHook::register_ranges(
-        &[
-            (EVENT_SYSTEM_MOVESIZESTART, EVENT_SYSTEM_MINIMIZEEND),
-            (EVENT_OBJECT_NAMECHANGE, EVENT_OBJECT_NAMECHANGE),
-        ],
-        move |evt, wnd| {
-            if let Some(app) = appref.upgrade() {
-                app.on_wnd_event(wnd, evt);
-            }
-        },
-    );
+    *app.hook_cb.borrow_mut() = Some(Rc::new(move |evt, wnd| {
+        if let Some(app) = appref.upgrade() {
+            app.on_wnd_event(wnd, evt);
+        }
+    }));
+    app.configure_hooks()?;
 
-    nwg::dispatch_thread_events();
+    *app.msg_wnd.borrow_mut() = Some(msg_wnd);
+    app.publish_topology();
+    app.hook_check_timer.start();
+    app.startup_report_timer.start();
+    app.on_prune_tick();
+    app.prune_timer.start();
 
-    for hook in evt_hooks {
-        EventHook::unregister(hook);
+    // External control is optional, so carry on without it if the pipe is taken.
+    let (calls, receiver) = mpsc::channel();
+    *app.ipc_calls.borrow_mut() = Some(receiver);
+    if let Err(e) = ipc::serve(elevated, app.peers.clone(), app.ipc_notice.sender(), calls) {
+        warn!("{:?}", e.context("failed to start pipe server"));
     }
 
-    nwg::unbind_raw_event_handler(&raw_hook).unwrap();
+    if let Err(e) = scaling::watch(app.scaling_notice.sender()) {
+        warn!("{:?}", e.context("failed to watch monitor scaling"));
+    }
+
+    nwg::dispatch_thread_events();
+
+    app.hook_check_timer.stop();
+    drop(app.hooks.take());
+    drop(app.hook_cb.take());
+
+    drop(app.msg_wnd.take());
 
     Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    // RUST_LOG takes precedence over the configured level, which can change at runtime.
+    match std::env::var_os("RUST_LOG") {
+        Some(_) => env_logger::init(),
+        None => {
+            env_logger::Builder::new()
+                .filter_level(log::LevelFilter::Trace)
+                .init();
+            log::set_max_level(config::get().log_level());
+        }
+    }
 
     // Check and see if we were invoked to run a utility command.
     let args = std::env::args().collect::<Vec<_>>();
-    if args.len() > 1 {
-        let res = match args[1].as_str() {
-            "autorun" => toggle_autorun(),
-            _ => anyhow::bail!("unknown command"),
-        };
+    let command = match cli::Command::parse(&args[1..]) {
+        Ok(command) => command,
+        Err(e) => {
+            cli::attach_console();
+            return Err(e);
+        }
+    };
+    if let Some(command) = command {
+        let interactive = command.is_interactive();
+        if !interactive {
+            cli::attach_console();
+        }
 
-        return match res {
+        return match command.run() {
             Ok(_) => Ok(()),
-            Err(e) => {
+            Err(e) if interactive => {
                 nwg::error_message("Error", &format!("{e:?}"));
                 Err(e)
             }
+            Err(e) => Err(e),
         };
     }
 
@@ -651,8 +6873,60 @@ fn main() -> anyhow::Result<()> {
 
     // Display an error dialog if the run function fails (instead of logging to console, which is unavailable
     // in the Windows subsystem).
-    match run() {
+    match LaunchOptions::parse(&args[1..]).and_then(run) {
         Ok(_) => Ok(()),
         Err(e) => nwg::fatal_message("Error", &format!("{e:?}")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(rect: Rect) -> WindowDisplay {
+        WindowDisplay {
+            show: SW_SHOWNORMAL.0,
+            min: None,
+            max: None,
+            rect,
+            restore_maximized: false,
+            topmost: None,
+            dpi: None,
+            source: CaptureSource::Placement,
+        }
+    }
+
+    #[test]
+    fn rejects_records_out_of_range() {
+        let valid = display(Rect {
+            left: -WindowDisplay::MAX_COORD,
+            top: 0,
+            right: WindowDisplay::MAX_COORD,
+            bottom: 600,
+        });
+        assert!(valid.validate().is_ok());
+
+        let far = display(Rect {
+            right: WindowDisplay::MAX_COORD + 1,
+            ..valid.rect.clone()
+        });
+        assert!(far.validate().is_err());
+
+        // The magnitude of the smallest coordinate does not fit an `i32`.
+        let corrupt = display(Rect {
+            left: i32::MIN,
+            ..valid.rect.clone()
+        });
+        assert!(corrupt.validate().is_err());
+        assert!(WindowDisplay::decode(&bson::to_vec(&corrupt).unwrap()).is_err());
+
+        let corrupt = WindowDisplay {
+            min: Some(Point {
+                x: i32::MIN,
+                y: i32::MIN,
+            }),
+            ..valid
+        };
+        assert!(corrupt.validate().is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+
+/// Minimum similarity (0 to 1) between normalized titles for a `MatchTier::Similar` match.
+const SIMILAR_TITLE_THRESHOLD: f64 = 0.8;
+
+/// Weight of recency against the resemblance of titles when scoring records for a
+/// `MatchTier::Scored` match. The most recently captured record gets all of it.
+const RECENCY_WEIGHT: f64 = 0.25;
+
+/// How closely a window matched the record it was given, from closest to loosest. Titles often
+/// change slightly between sessions (unread counts, modified markers, document names), so looser
+/// tiers are tried in turn when there is no exact match.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MatchTier {
+    /// The same window, by the tag stamped on it when it was captured
+    Tagged,
+    /// Same image, class and title
+    Exact,
+    /// Same image and class, and the same title once decorations are stripped
+    Normalized,
+    /// Same image and class, for the only unclaimed record of the application
+    Application,
+    /// Same image and class, and a similar title
+    Similar,
+    /// Same image and class, for the best scoring of several records with a title sharing
+    /// words with the window's
+    Scored,
+}
+
+/// Pick the record for a window titled `title` among the unclaimed records of its application
+/// (`candidates`, as ID and title, most recent first), trying each `MatchTier` in turn. With
+/// `exact_only`, only exact matches are accepted.
+pub fn match_record(
+    title: &str,
+    candidates: &[(i64, String)],
+    exact_only: bool,
+) -> Option<(i64, MatchTier)> {
+    if let Some((id, _)) = candidates.iter().find(|(_, t)| t == title) {
+        return Some((*id, MatchTier::Exact));
+    }
+
+    if exact_only {
+        return None;
+    }
+
+    let title = normalize_title(title);
+    let normalized = candidates
+        .iter()
+        .map(|(id, t)| (*id, normalize_title(t)))
+        .collect::<Vec<_>>();
+
+    if let Some((id, _)) = normalized.iter().find(|(_, t)| *t == title) {
+        return Some((*id, MatchTier::Normalized));
+    }
+
+    // With several records to choose from, only a similar title can tell them apart.
+    if let [(id, _)] = candidates {
+        return Some((*id, MatchTier::Application));
+    }
+
+    // Among equally similar records, the most recent one wins (`max_by` keeps the last).
+    let similar = normalized
+        .iter()
+        .rev()
+        .map(|(id, t)| (*id, title_similarity(&title, t)))
+        .filter(|(_, sim)| *sim >= SIMILAR_TITLE_THRESHOLD)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b));
+    if let Some((id, _)) = similar {
+        return Some((id, MatchTier::Similar));
+    }
+
+    // Titles often keep only some of their words (e.g. the project but not the document), so
+    // score the records by the words they share and how alike they are, preferring recent ones.
+    // Records sharing no word at all are more likely another window of the application.
+    let words = title_words(&title);
+    normalized
+        .iter()
+        .enumerate()
+        .filter_map(|(rank, (id, t))| {
+            let overlap = word_overlap(&words, &title_words(t));
+            let recency = 1.0 - rank as f64 / normalized.len() as f64;
+            (overlap > 0.0).then(|| {
+                (
+                    *id,
+                    overlap + title_similarity(&title, t) + RECENCY_WEIGHT * recency,
+                )
+            })
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(id, _)| (id, MatchTier::Scored))
+}
+
+/// The distinct words of a normalized title, ignoring punctuation.
+fn title_words(title: &str) -> HashSet<&str> {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// The share of words two titles have in common, from 0 (none) to 1 (all of them).
+fn word_overlap(a: &HashSet<&str>, b: &HashSet<&str>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// Strip the parts of a window title that change while it is open: unread counts (e.g. "(3)
+/// Inbox"), unsaved markers (e.g. "*notes.txt" or "● main.rs"), case and spacing.
+pub fn normalize_title(title: &str) -> String {
+    let mut title = title.trim();
+
+    if let Some(rest) = title.strip_prefix('(') {
+        if let Some((count, rest)) = rest.split_once(')') {
+            if !count.is_empty() && count.chars().all(|c| c.is_ascii_digit() || c == '+') {
+                title = rest.trim_start();
+            }
+        }
+    }
+
+    let title = title.trim_matches(|c: char| c == '*' || c == '●' || c.is_whitespace());
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Similarity of two titles from 0 (nothing in common) to 1 (identical), as one minus their edit
+/// distance relative to the longer title.
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let len = a.len().max(b.len());
+    if len == 0 {
+        return 1.0;
+    }
+
+    // Levenshtein distance, keeping a single row of the table.
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = diag + (ca != cb) as usize;
+            diag = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(diag + 1);
+        }
+    }
+
+    1.0 - row[b.len()] as f64 / len as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(titles: &[(i64, &str)]) -> Vec<(i64, String)> {
+        titles.iter().map(|(id, t)| (*id, t.to_string())).collect()
+    }
+
+    #[test]
+    fn strips_changing_parts_of_titles() {
+        assert_eq!(normalize_title("(3) Inbox - Mail"), "inbox - mail");
+        assert_eq!(normalize_title("(99+)  Chat "), "chat");
+        assert_eq!(normalize_title("*notes.txt - Editor"), "notes.txt - editor");
+        assert_eq!(normalize_title("● main.rs  -  Code"), "main.rs - code");
+        // Only counts are stripped, not any parenthesized prefix.
+        assert_eq!(normalize_title("(draft) Letter"), "(draft) letter");
+    }
+
+    #[test]
+    fn measures_title_similarity() {
+        assert_eq!(title_similarity("", ""), 1.0);
+        assert_eq!(title_similarity("abc", "abc"), 1.0);
+        assert_eq!(title_similarity("abc", "xyz"), 0.0);
+        assert_eq!(title_similarity("kitten", "sitting"), 1.0 - 3.0 / 7.0);
+    }
+
+    #[test]
+    fn prefers_exact_matches() {
+        let candidates = candidates(&[(2, "(1) Inbox - Mail"), (1, "Inbox - Mail")]);
+        assert_eq!(
+            match_record("Inbox - Mail", &candidates, false),
+            Some((1, MatchTier::Exact))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_normalized_titles() {
+        let candidates = candidates(&[(2, "Calendar - Mail"), (1, "Inbox - Mail")]);
+        assert_eq!(
+            match_record("(3) inbox  -  Mail", &candidates, false),
+            Some((1, MatchTier::Normalized))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_similar_titles() {
+        let candidates = candidates(&[
+            (2, "budget.xlsx - Spreadsheet"),
+            (1, "report-final.docx - Word"),
+        ]);
+        assert_eq!(
+            match_record("report-final2.docx - Word", &candidates, false),
+            Some((1, MatchTier::Similar))
+        );
+
+        // Titles neither similar nor sharing a word are left for another window.
+        assert_eq!(match_record("untitled", &candidates, false), None);
+    }
+
+    #[test]
+    fn picks_the_newest_of_equally_similar_titles() {
+        let candidates = candidates(&[(2, "report 1 - Word"), (1, "report 3 - Word")]);
+        assert_eq!(
+            match_record("report 2 - Word", &candidates, false),
+            Some((2, MatchTier::Similar))
+        );
+    }
+
+    #[test]
+    fn exact_only_applications_skip_the_fallbacks() {
+        let candidates = candidates(&[(2, "report 1 - Word"), (1, "Inbox - Mail")]);
+        assert_eq!(
+            match_record("Inbox - Mail", &candidates, true),
+            Some((1, MatchTier::Exact))
+        );
+        assert_eq!(match_record("(3) Inbox - Mail", &candidates, true), None);
+        assert_eq!(match_record("report 2 - Word", &candidates, true), None);
+        assert_eq!(match_record("untitled", &candidates[..1], true), None);
+    }
+
+    #[test]
+    fn takes_the_only_record_whatever_its_title() {
+        let candidates = candidates(&[(1, "Settings")]);
+        assert_eq!(
+            match_record("main.rs - widgets - IDE", &candidates, false),
+            Some((1, MatchTier::Application))
+        );
+    }
+
+    #[test]
+    fn scores_only_records_sharing_a_word() {
+        let candidates = candidates(&[(2, "Settings"), (1, "Project Alpha - IDE")]);
+        assert_eq!(
+            match_record("main.rs - Project Alpha - IDE", &candidates, false),
+            Some((1, MatchTier::Scored))
+        );
+    }
+
+    #[test]
+    fn scores_shared_words_above_recency() {
+        let candidates = candidates(&[(2, "server - IDE"), (1, "lib.rs - widgets - IDE")]);
+        assert_eq!(
+            match_record("widgets - IDE", &candidates, false),
+            Some((1, MatchTier::Scored))
+        );
+    }
+
+    #[test]
+    fn breaks_score_ties_by_recency() {
+        let candidates = candidates(&[
+            (3, "lib.rs - widgets - IDE"),
+            (2, "lib.rs - widgets - IDE"),
+            (1, "lib.rs - widgets - IDE"),
+        ]);
+        assert_eq!(
+            match_record("widgets - IDE", &candidates, false),
+            Some((3, MatchTier::Scored))
+        );
+    }
+}
@@ -1,12 +1,23 @@
 use crate::Rect;
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
 use windows::{
     core::Error,
     Win32::{
-        Foundation::{BOOL, LPARAM, RECT},
+        Devices::Display::{
+            DisplayConfigGetDeviceInfo, GetDisplayConfigBufferSizes, QueryDisplayConfig,
+            DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+            DISPLAYCONFIG_DEVICE_INFO_HEADER, DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO,
+            DISPLAYCONFIG_SOURCE_DEVICE_NAME, DISPLAYCONFIG_TARGET_DEVICE_NAME,
+            QDC_ONLY_ACTIVE_PATHS,
+        },
+        Foundation::{BOOL, ERROR_INSUFFICIENT_BUFFER, LPARAM, POINT, RECT},
         Graphics::Gdi::{
-            EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW,
+            EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW, MonitorFromPoint,
+            MonitorFromRect, DEVMODEW, ENUM_CURRENT_SETTINGS, HDC, HMONITOR, MONITORINFO,
+            MONITORINFOEXW, MONITOR_DEFAULTTONEAREST,
         },
         UI::{
             HiDpi::{GetDpiForMonitor, MONITOR_DPI_TYPE},
@@ -47,6 +58,7 @@ pub struct MonitorDpi {
 pub trait HMonitorExt {
     fn info(&self) -> Result<MonitorInfo>;
     fn dpi(&self, ty: MonitorDpiType) -> Result<MonitorDpi>;
+    fn rotation(&self) -> Result<u32>;
 }
 
 impl HMonitorExt for HMONITOR {
@@ -58,7 +70,7 @@ impl HMonitorExt for HMONITOR {
             GetMonitorInfoW(self.clone(), &mut info as *mut _ as *mut MONITORINFO).as_bool()
         } {
             true => {
-                let name = String::from_utf16_lossy(&info.szDevice);
+                let name = from_wide(&info.szDevice);
 
                 Ok(MonitorInfo {
                     primary: (info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY) != 0,
@@ -85,6 +97,24 @@ impl HMonitorExt for HMONITOR {
 
         Ok(dpi)
     }
+
+    /// The clockwise rotation of the display, in degrees.
+    fn rotation(&self) -> Result<u32> {
+        let name = windows::core::HSTRING::from(self.info()?.name);
+
+        let mut mode = DEVMODEW {
+            dmSize: core::mem::size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+
+        match unsafe { EnumDisplaySettingsW(&name, ENUM_CURRENT_SETTINGS, &mut mode) }.as_bool() {
+            true => {
+                let orientation = unsafe { mode.Anonymous1.Anonymous2.dmDisplayOrientation };
+                Ok(orientation.0 * 90)
+            }
+            false => Err(Error::from_win32()),
+        }
+    }
 }
 
 /// Enumerate all displays attached to the system. This corresponds to `EnumDisplayMonitors`.
@@ -130,3 +160,110 @@ pub fn monitors(rect: Option<RECT>) -> Result<Vec<(HMONITOR, HDC)>> {
 
     Ok(vec)
 }
+
+/// Find the monitor that has the largest intersection with `rect`, or the nearest one if the
+/// rectangle does not intersect any monitor.
+pub fn from_rect(rect: &Rect) -> HMONITOR {
+    let rect: RECT = rect.clone().into();
+
+    unsafe { MonitorFromRect(&rect, MONITOR_DEFAULTTONEAREST) }
+}
+
+/// Find the monitor containing a point in screen coordinates, or the nearest one.
+pub fn from_point(pt: POINT) -> HMONITOR {
+    unsafe { MonitorFromPoint(pt, MONITOR_DEFAULTTONEAREST) }
+}
+
+/// Decode a nul-terminated UTF-16 buffer.
+fn from_wide(buf: &[u16]) -> String {
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..len])
+}
+
+/// Identify the monitors attached to the system, keyed by the GDI name of the display they show
+/// (as in `MonitorInfo::name`, e.g. `\\.\DISPLAY1`).
+///
+/// A monitor is identified by the manufacturer and product codes from its EDID (e.g.
+/// `DEL:4123`), which stay the same whichever port it is plugged into. Monitors without valid
+/// EDID codes are identified by their device path instead.
+pub fn monitor_ids() -> Result<HashMap<String, String>> {
+    let mut paths = Vec::new();
+    let mut modes = Vec::new();
+
+    // The configuration may change between querying the sizes and the paths.
+    loop {
+        let (mut npaths, mut nmodes) = (0u32, 0u32);
+        unsafe { GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut npaths, &mut nmodes) }
+            .ok()?;
+
+        paths.resize(npaths as usize, DISPLAYCONFIG_PATH_INFO::default());
+        modes.resize(nmodes as usize, DISPLAYCONFIG_MODE_INFO::default());
+
+        match unsafe {
+            QueryDisplayConfig(
+                QDC_ONLY_ACTIVE_PATHS,
+                &mut npaths,
+                paths.as_mut_ptr(),
+                &mut nmodes,
+                modes.as_mut_ptr(),
+                None,
+            )
+        } {
+            ERROR_INSUFFICIENT_BUFFER => continue,
+            err => err.ok()?,
+        }
+
+        paths.truncate(npaths as usize);
+        break;
+    }
+
+    let mut ids = HashMap::new();
+    for path in paths {
+        let mut source = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+            header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+                size: core::mem::size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32,
+                adapterId: path.sourceInfo.adapterId,
+                id: path.sourceInfo.id,
+            },
+            ..Default::default()
+        };
+
+        let mut target = DISPLAYCONFIG_TARGET_DEVICE_NAME {
+            header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                r#type: DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+                size: core::mem::size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32,
+                adapterId: path.targetInfo.adapterId,
+                id: path.targetInfo.id,
+            },
+            ..Default::default()
+        };
+
+        // Skip paths that disappeared since the configuration was queried.
+        if unsafe { DisplayConfigGetDeviceInfo(&mut source.header) } != 0
+            || unsafe { DisplayConfigGetDeviceInfo(&mut target.header) } != 0
+        {
+            continue;
+        }
+
+        // N.B: `edidIdsValid` is the third bit of the flags.
+        let edid_valid = unsafe { target.flags.Anonymous.Anonymous._bitfield } & 0x4 != 0;
+        let id = match edid_valid {
+            true => {
+                // The manufacturer ID is three 5-bit letters, stored big-endian.
+                let mfg = target.edidManufactureId.swap_bytes();
+                let mfg = [10, 5, 0]
+                    .iter()
+                    .map(|s| (b'A' - 1 + ((mfg >> s) & 0x1f) as u8) as char)
+                    .collect::<String>();
+
+                format!("{mfg}:{:04X}", target.edidProductCodeId)
+            }
+            false => from_wide(&target.monitorDevicePath),
+        };
+
+        ids.insert(from_wide(&source.viewGdiDeviceName), id);
+    }
+
+    Ok(ids)
+}
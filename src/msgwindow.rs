@@ -0,0 +1,291 @@
+use widestring::widecstr;
+use windows::{
+    core::{Error, PCWSTR},
+    Win32::{
+        Devices::Display::GUID_DEVINTERFACE_MONITOR,
+        Foundation::{ERROR_CLASS_ALREADY_EXISTS, HANDLE, HWND, LPARAM, LRESULT, WPARAM},
+        System::{
+            LibraryLoader::GetModuleHandleW,
+            Power::{
+                RegisterPowerSettingNotification, UnregisterPowerSettingNotification, HPOWERNOTIFY,
+            },
+            RemoteDesktop::{
+                WTSRegisterSessionNotification, WTSUnRegisterSessionNotification,
+                NOTIFY_FOR_THIS_SESSION,
+            },
+            SystemServices::{GUID_ACDC_POWER_SOURCE, GUID_CONSOLE_DISPLAY_STATE},
+        },
+        UI::Input::KeyboardAndMouse::{RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS},
+        UI::WindowsAndMessaging::{
+            ChangeWindowMessageFilterEx, CreateWindowExW, DefWindowProcW, DestroyWindow,
+            FindWindowExW, GetPropW, RegisterClassExW, RegisterDeviceNotificationW,
+            RegisterWindowMessageW, RemovePropW, SetPropW, SetWindowTextW,
+            UnregisterDeviceNotification, DBT_DEVTYP_DEVICEINTERFACE, DEVICE_NOTIFY_WINDOW_HANDLE,
+            DEV_BROADCAST_DEVICEINTERFACE_W, MSGFLT_ALLOW, WINDOW_EX_STYLE, WNDCLASSEXW,
+            WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_POPUP,
+        },
+    },
+};
+
+use widestring::{WideCStr, WideCString};
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+type Result<R> = core::result::Result<R, Error>;
+type CallbackFn = dyn Fn(HWND, u32, WPARAM, LPARAM) -> Option<LRESULT>;
+
+const CLASS_NAME: &widestring::WideCStr = widecstr!("PersistentWindowsEvents");
+
+/// Find the event windows of all running instances.
+pub fn instances() -> Vec<HWND> {
+    let mut found = Vec::new();
+    let mut prev = HWND(0);
+    loop {
+        prev = unsafe { FindWindowExW(None, prev, PCWSTR(CLASS_NAME.as_ptr()), None) };
+        if prev.0 == 0 {
+            return found;
+        }
+
+        found.push(prev);
+    }
+}
+
+/// The message the shell broadcasts to top-level windows once the taskbar has been created,
+/// which after startup means Explorer has restarted.
+pub fn taskbar_created_message() -> u32 {
+    unsafe { RegisterWindowMessageW(PCWSTR(widecstr!("TaskbarCreated").as_ptr())) }
+}
+
+/// Read a property published on the event window of an instance, or 0 if it is not set.
+pub fn property(hwnd: HWND, name: &str) -> isize {
+    let name = WideCString::from_str_truncate(name);
+    unsafe { GetPropW(hwnd, PCWSTR(name.as_ptr())) }.0
+}
+
+// Window procedures are always invoked on the thread that created the window.
+thread_local! {
+    static WINDOW_TABLE: RefCell<HashMap<isize, Rc<CallbackFn>>> = RefCell::new(HashMap::new());
+}
+
+/// A hidden window dedicated to receiving system notifications (display, session, power and
+/// device changes), independent of any UI.
+///
+/// N.B: This is intentionally _not_ a `HWND_MESSAGE` window. Message-only windows do not receive
+/// broadcast messages such as `WM_DISPLAYCHANGE` or `WM_SETTINGCHANGE`, so we instead create an
+/// unowned top-level window that is never shown.
+pub struct MessageWindow {
+    hwnd: HWND,
+    power: Vec<HPOWERNOTIFY>,
+    device: *mut core::ffi::c_void,
+    hotkeys: Vec<i32>,
+    props: Vec<WideCString>,
+}
+
+impl MessageWindow {
+    /// Create the window and register it for all of the notifications the app depends on.
+    /// The callback returns `Some` if it handled the message, or `None` to fall back to the
+    /// default window procedure.
+    pub fn create(
+        cb: impl Fn(HWND, u32, WPARAM, LPARAM) -> Option<LRESULT> + 'static,
+    ) -> Result<MessageWindow> {
+        let module = unsafe { GetModuleHandleW(None) }?;
+
+        let class = WNDCLASSEXW {
+            cbSize: core::mem::size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(wnd_proc),
+            hInstance: module,
+            lpszClassName: PCWSTR(CLASS_NAME.as_ptr()),
+            ..Default::default()
+        };
+
+        if unsafe { RegisterClassExW(&class) } == 0 {
+            // The class may have been registered by a previous instance of this window.
+            let err = Error::from_win32();
+            if err.code() != ERROR_CLASS_ALREADY_EXISTS.to_hresult() {
+                Err(err)?;
+            }
+        }
+
+        let hwnd = unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE(WS_EX_TOOLWINDOW.0 | WS_EX_NOACTIVATE.0),
+                PCWSTR(CLASS_NAME.as_ptr()),
+                PCWSTR::null(),
+                WS_POPUP,
+                0,
+                0,
+                0,
+                0,
+                None,
+                None,
+                module,
+                None,
+            )
+        };
+
+        if hwnd.0 == 0 {
+            Err(Error::from_win32())?;
+        }
+
+        WINDOW_TABLE.with(|tab| {
+            tab.borrow_mut().insert(hwnd.0, Rc::new(cb));
+        });
+
+        // From this point onwards, the window will be torn down by `Drop` if registration fails.
+        let mut wnd = MessageWindow {
+            hwnd,
+            power: Vec::new(),
+            device: core::ptr::null_mut(),
+            hotkeys: Vec::new(),
+            props: Vec::new(),
+        };
+
+        // The broadcast comes from Explorer, which runs at a lower integrity level than an
+        // elevated instance, so let it through explicitly.
+        unsafe { ChangeWindowMessageFilterEx(hwnd, taskbar_created_message(), MSGFLT_ALLOW, None) };
+
+        wnd.register_notifications()?;
+
+        Ok(wnd)
+    }
+
+    fn register_notifications(&mut self) -> Result<()> {
+        let hwnd = self.hwnd;
+
+        // WM_WTSSESSION_CHANGE
+        match unsafe { WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION) }.as_bool() {
+            true => {}
+            false => Err(Error::from_win32())?,
+        }
+
+        // WM_POWERBROADCAST (PBT_POWERSETTINGCHANGE)
+        for setting in [&GUID_CONSOLE_DISPLAY_STATE, &GUID_ACDC_POWER_SOURCE] {
+            self.power.push(unsafe {
+                RegisterPowerSettingNotification(
+                    HANDLE(hwnd.0),
+                    setting,
+                    DEVICE_NOTIFY_WINDOW_HANDLE.0,
+                )
+            }?);
+        }
+
+        // WM_DEVICECHANGE (monitor arrival/removal)
+        let mut filter = DEV_BROADCAST_DEVICEINTERFACE_W {
+            dbcc_size: core::mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as u32,
+            dbcc_devicetype: DBT_DEVTYP_DEVICEINTERFACE.0,
+            ..Default::default()
+        };
+        filter.dbcc_classguid = GUID_DEVINTERFACE_MONITOR;
+
+        self.device = unsafe {
+            RegisterDeviceNotificationW(
+                HANDLE(hwnd.0),
+                &filter as *const _ as *const _,
+                DEVICE_NOTIFY_WINDOW_HANDLE,
+            )
+        };
+        if self.device.is_null() {
+            Err(Error::from_win32())?;
+        }
+
+        Ok(())
+    }
+
+    fn unregister_notifications(&mut self) {
+        unsafe {
+            if !self.device.is_null() {
+                UnregisterDeviceNotification(self.device);
+                self.device = core::ptr::null_mut();
+            }
+
+            for hnd in self.power.drain(..) {
+                UnregisterPowerSettingNotification(hnd);
+            }
+
+            WTSUnRegisterSessionNotification(self.hwnd);
+        }
+    }
+
+    /// Drop and redo the notification registrations, for when the system may have lost track
+    /// of them (e.g. after the shell or DWM restarted). Hotkeys are unaffected.
+    pub fn reregister(&mut self) -> Result<()> {
+        self.unregister_notifications();
+        self.register_notifications()
+    }
+
+    /// Set the title of the window. The window is never shown, but its title can be read by
+    /// other programs that find it by its class (`PersistentWindowsEvents`).
+    pub fn set_title(&self, title: &str) -> Result<()> {
+        let title = WideCString::from_str_truncate(title);
+        match unsafe { SetWindowTextW(self.hwnd, PCWSTR(title.as_ptr())) }.as_bool() {
+            true => Ok(()),
+            false => Err(Error::from_win32()),
+        }
+    }
+
+    /// Attach a numeric property to the window (`SetPropW`), readable by other programs with
+    /// `GetPropW`.
+    pub fn set_property(&mut self, name: &WideCStr, value: isize) -> Result<()> {
+        if !unsafe { SetPropW(self.hwnd, PCWSTR(name.as_ptr()), HANDLE(value)) }.as_bool() {
+            Err(Error::from_win32())?;
+        }
+
+        if !self.props.iter().any(|p| p.as_ucstr() == name) {
+            self.props.push(name.to_ucstring());
+        }
+
+        Ok(())
+    }
+
+    /// Register a system-wide hotkey, delivered to the callback as `WM_HOTKEY` with `id` as the
+    /// `WPARAM`.
+    pub fn register_hotkey(
+        &mut self,
+        id: i32,
+        modifiers: HOT_KEY_MODIFIERS,
+        vk: u32,
+    ) -> Result<()> {
+        match unsafe { RegisterHotKey(self.hwnd, id, modifiers, vk) }.as_bool() {
+            true => {
+                self.hotkeys.push(id);
+                Ok(())
+            }
+            false => Err(Error::from_win32()),
+        }
+    }
+}
+
+impl std::ops::Drop for MessageWindow {
+    fn drop(&mut self) {
+        self.unregister_notifications();
+
+        unsafe {
+            for id in self.hotkeys.drain(..) {
+                UnregisterHotKey(self.hwnd, id);
+            }
+
+            // Properties must be removed before the window is destroyed.
+            for name in self.props.drain(..) {
+                let _ = RemovePropW(self.hwnd, PCWSTR(name.as_ptr()));
+            }
+
+            DestroyWindow(self.hwnd);
+        }
+
+        WINDOW_TABLE.with(|tab| {
+            tab.borrow_mut().remove(&self.hwnd.0);
+        });
+    }
+}
+
+extern "system" fn wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    // N.B: Clone the callback out of the table so it may re-enter this procedure (e.g. by
+    // pumping messages from a modal dialog) without holding the table borrowed.
+    let cb = WINDOW_TABLE.with(|tab| tab.borrow().get(&hwnd.0).cloned());
+
+    if let Some(r) = cb.and_then(|cb| (cb)(hwnd, msg, wparam, lparam)) {
+        return r;
+    }
+
+    unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+}
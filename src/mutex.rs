@@ -2,8 +2,13 @@ use widestring::WideCString;
 use windows::{
     core::{Error, PCWSTR},
     Win32::{
-        Foundation::{CloseHandle, ERROR_ALREADY_EXISTS, ERROR_INVALID_PARAMETER, HANDLE},
-        System::Threading::{CreateMutexW, OpenMutexW, SYNCHRONIZATION_SYNCHRONIZE},
+        Foundation::{
+            CloseHandle, ERROR_ALREADY_EXISTS, ERROR_INVALID_PARAMETER, HANDLE, WAIT_ABANDONED,
+            WAIT_OBJECT_0, WAIT_TIMEOUT,
+        },
+        System::Threading::{
+            CreateMutexW, OpenMutexW, WaitForSingleObject, SYNCHRONIZATION_SYNCHRONIZE,
+        },
     },
 };
 
@@ -11,6 +16,17 @@ type Result<R> = core::result::Result<R, Error>;
 
 pub struct GlobalMutex(HANDLE);
 
+/// How ownership of a mutex was acquired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acquired {
+    /// The mutex did not exist yet
+    Created,
+    /// The mutex existed but nobody owned it
+    Released,
+    /// The previous owner exited without releasing the mutex (e.g. it crashed)
+    Abandoned,
+}
+
 #[allow(dead_code)]
 impl GlobalMutex {
     pub fn create(name: &str, take_ownership: bool) -> Result<GlobalMutex> {
@@ -32,6 +48,27 @@ impl GlobalMutex {
         Ok(GlobalMutex(handle))
     }
 
+    /// Take ownership of a mutex, creating it if needed. If another thread owns it, this waits
+    /// up to `timeout_ms` for it to be released (or abandoned), then fails with
+    /// `ERROR_ALREADY_EXISTS`.
+    pub fn acquire(name: &str, timeout_ms: u32) -> Result<(GlobalMutex, Acquired)> {
+        let name = WideCString::from_str(name).map_err(|_| ERROR_INVALID_PARAMETER.to_hresult())?;
+
+        let handle = unsafe { CreateMutexW(None, true, PCWSTR::from_raw(name.as_ptr()))? };
+        if Error::from_win32().code() != ERROR_ALREADY_EXISTS.to_hresult() {
+            return Ok((GlobalMutex(handle), Acquired::Created));
+        }
+
+        // N.B: Ownership is not granted when opening an existing mutex, so wait for it.
+        let mutex = GlobalMutex(handle);
+        match unsafe { WaitForSingleObject(handle, timeout_ms) } {
+            WAIT_OBJECT_0 => Ok((mutex, Acquired::Released)),
+            WAIT_ABANDONED => Ok((mutex, Acquired::Abandoned)),
+            WAIT_TIMEOUT => Err(ERROR_ALREADY_EXISTS.to_hresult().into()),
+            _ => Err(Error::from_win32()),
+        }
+    }
+
     pub fn open(name: &str) -> Result<GlobalMutex> {
         let name = WideCString::from_str(name).map_err(|_| ERROR_INVALID_PARAMETER.to_hresult())?;
 
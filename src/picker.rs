@@ -0,0 +1,134 @@
+use widestring::widecstr;
+use windows::{
+    core::{Error, PCWSTR},
+    Win32::{
+        Foundation::{ERROR_CLASS_ALREADY_EXISTS, HWND, LPARAM, LRESULT, POINT, WPARAM},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Input::KeyboardAndMouse::VK_ESCAPE,
+            WindowsAndMessaging::{
+                CreateWindowExW, DefWindowProcW, DestroyWindow, GetCursorPos, GetSystemMetrics,
+                LoadCursorW, RegisterClassExW, SetForegroundWindow, SetLayeredWindowAttributes,
+                ShowWindow, IDC_CROSS, LWA_ALPHA, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN,
+                SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN, SW_SHOW, WINDOW_EX_STYLE, WM_KEYDOWN,
+                WM_LBUTTONUP, WM_RBUTTONUP, WNDCLASSEXW, WS_EX_LAYERED, WS_EX_TOOLWINDOW,
+                WS_EX_TOPMOST, WS_POPUP,
+            },
+        },
+    },
+};
+
+use std::cell::RefCell;
+
+type Result<R> = core::result::Result<R, Error>;
+type CallbackFn = dyn FnOnce(Option<POINT>);
+
+const CLASS_NAME: &widestring::WideCStr = widecstr!("PersistentWindowsPicker");
+
+// Window procedures are always invoked on the thread that created the window.
+thread_local! {
+    static PICKER: RefCell<Option<(HWND, Box<CallbackFn>)>> = RefCell::new(None);
+}
+
+/// Let the user pick a point on the screen with the mouse, Spy++ style.
+///
+/// This covers the virtual screen with a nearly transparent overlay showing a crosshair cursor.
+/// Clicking picks the point under the cursor (in screen coordinates), while right-clicking or
+/// pressing escape cancels. Either way, `cb` is invoked once the overlay is gone, with `None` if
+/// cancelled, so the window under the point can be found with `window::from_point`.
+///
+/// Starting a new pick cancels any pick already in progress.
+pub fn pick(cb: impl FnOnce(Option<POINT>) + 'static) -> Result<()> {
+    cancel();
+
+    let module = unsafe { GetModuleHandleW(None) }?;
+
+    let class = WNDCLASSEXW {
+        cbSize: core::mem::size_of::<WNDCLASSEXW>() as u32,
+        lpfnWndProc: Some(wnd_proc),
+        hInstance: module,
+        hCursor: unsafe { LoadCursorW(None, IDC_CROSS) }?,
+        lpszClassName: PCWSTR(CLASS_NAME.as_ptr()),
+        ..Default::default()
+    };
+
+    if unsafe { RegisterClassExW(&class) } == 0 {
+        // The class may have been registered by a previous pick.
+        let err = Error::from_win32();
+        if err.code() != ERROR_CLASS_ALREADY_EXISTS.to_hresult() {
+            Err(err)?;
+        }
+    }
+
+    let hwnd = unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE(WS_EX_LAYERED.0 | WS_EX_TOPMOST.0 | WS_EX_TOOLWINDOW.0),
+            PCWSTR(CLASS_NAME.as_ptr()),
+            PCWSTR::null(),
+            WS_POPUP,
+            GetSystemMetrics(SM_XVIRTUALSCREEN),
+            GetSystemMetrics(SM_YVIRTUALSCREEN),
+            GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            GetSystemMetrics(SM_CYVIRTUALSCREEN),
+            None,
+            None,
+            module,
+            None,
+        )
+    };
+
+    if hwnd.0 == 0 {
+        Err(Error::from_win32())?;
+    }
+
+    // N.B: A fully transparent layered window does not receive mouse input, so use the lowest
+    // visible alpha instead.
+    unsafe {
+        SetLayeredWindowAttributes(hwnd, None, 1, LWA_ALPHA);
+        ShowWindow(hwnd, SW_SHOW);
+        SetForegroundWindow(hwnd);
+    }
+
+    PICKER.with(|p| *p.borrow_mut() = Some((hwnd, Box::new(cb))));
+    Ok(())
+}
+
+/// Cancel the pick in progress, if any.
+pub fn cancel() {
+    finish(None);
+}
+
+/// Tear down the overlay and invoke the callback of the pick in progress.
+fn finish(picked: Option<POINT>) {
+    // N.B: Take the callback out first, as it may start another pick.
+    if let Some((hwnd, cb)) = PICKER.with(|p| p.borrow_mut().take()) {
+        unsafe { DestroyWindow(hwnd) };
+        cb(picked);
+    }
+}
+
+fn cursor_pos() -> Option<POINT> {
+    let mut pt = POINT::default();
+    match unsafe { GetCursorPos(&mut pt) }.as_bool() {
+        true => Some(pt),
+        false => None,
+    }
+}
+
+extern "system" fn wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match msg {
+        WM_LBUTTONUP => {
+            finish(cursor_pos());
+            LRESULT(0)
+        }
+        WM_RBUTTONUP => {
+            finish(None);
+            LRESULT(0)
+        }
+        WM_KEYDOWN if wparam.0 == VK_ESCAPE.0 as usize => {
+            finish(None);
+            LRESULT(0)
+        }
+        _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
+    }
+}
@@ -0,0 +1,360 @@
+use crate::{
+    geometry::Rect,
+    matching::{match_record, MatchTier},
+};
+
+use std::collections::{HashMap, HashSet};
+
+/// A monitor windows are restored to.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    /// The area of the monitor, in virtual screen coordinates
+    pub rect: Rect,
+    /// The area of the monitor not covered by the taskbar and docked toolbars
+    pub work: Rect,
+    /// The effective DPI of the monitor
+    pub dpi: u32,
+}
+
+/// A window to plan a restore for.
+pub struct Window {
+    pub exe: Option<String>,
+    pub class: String,
+    pub title: String,
+    /// Whether only a record with the same title may be taken, per the application's rules
+    pub exact_only: bool,
+}
+
+/// A saved record a window can be restored to.
+pub struct Record {
+    pub id: i64,
+    pub exe: Option<String>,
+    pub class: String,
+    pub title: String,
+    /// The normal rectangle of the window, in virtual screen coordinates
+    pub rect: Rect,
+    /// The effective DPI of the monitor holding the window when it was captured, if known
+    pub dpi: Option<u32>,
+}
+
+/// Where a saved rectangle lands on the monitors it is restored to.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Landing {
+    /// The index of the monitor the rectangle lands on
+    pub monitor: usize,
+    /// The rectangle, scaled to the DPI of its monitor and clamped within its work area
+    pub rect: Rect,
+    /// Whether the rectangle did not overlap any monitor
+    pub off_screen: bool,
+}
+
+/// The planned restore of one window.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Restore {
+    /// The index of the window
+    pub window: usize,
+    pub record: i64,
+    pub tier: MatchTier,
+    /// The rectangle of the record, scaled and clamped within its monitor
+    pub rect: Rect,
+    /// Whether the record did not overlap any monitor
+    pub off_screen: bool,
+}
+
+/// A restore pass over a set of windows, without touching any of them.
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// The restores of the windows landing on each monitor, in the order of the monitors
+    pub batches: Vec<Vec<Restore>>,
+    /// The indices of the windows without a record
+    pub unmatched: Vec<usize>,
+}
+
+/// Find the record to restore a window titled `title` to: the one it was stamped with, if any,
+/// or else the best match among the `candidates` no other window has claimed.
+pub fn find_match(
+    title: &str,
+    tagged: Option<i64>,
+    candidates: &[(i64, String)],
+    exact_only: bool,
+) -> Option<(i64, MatchTier)> {
+    match tagged {
+        Some(id) => Some((id, MatchTier::Tagged)),
+        None => match_record(title, candidates, exact_only),
+    }
+}
+
+/// The monitor with the largest intersection with `rect`, or the nearest one if it does not
+/// intersect any, as the system picks it. Returns the index and the area of the intersection.
+fn nearest(monitors: &[Monitor], rect: &Rect) -> (usize, u64) {
+    let distance = |m: &Monitor| {
+        let dx = (m.rect.left - rect.right)
+            .max(rect.left - m.rect.right)
+            .max(0) as i64;
+        let dy = (m.rect.top - rect.bottom)
+            .max(rect.top - m.rect.bottom)
+            .max(0) as i64;
+        dx * dx + dy * dy
+    };
+
+    monitors
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (i, rect.intersection_area(&m.rect), distance(m)))
+        .max_by_key(|(i, area, distance)| (*area, std::cmp::Reverse((*distance, *i))))
+        .map(|(i, area, _)| (i, area))
+        .expect("no monitors to plan for")
+}
+
+/// Land a rectangle saved at `dpi` on the monitors. Layouts captured at a different scale
+/// factor keep their logical size, and the rectangle is then clamped within the work area of
+/// the monitor it mostly lands on, out from under the taskbar and docked toolbars. This also
+/// matters for rotated monitors, where a size recorded in landscape can exceed the monitor in
+/// portrait.
+pub fn land(monitors: &[Monitor], rect: &Rect, dpi: Option<u32>) -> Landing {
+    let (mon, _) = nearest(monitors, rect);
+    let rect = match dpi.filter(|from| *from != 0) {
+        Some(from) => rect.scale_dpi(&monitors[mon].rect, from, monitors[mon].dpi),
+        None => rect.clone(),
+    };
+
+    let (mon, area) = nearest(monitors, &rect);
+    Landing {
+        monitor: mon,
+        rect: rect.clamp_within(&monitors[mon].work),
+        off_screen: area == 0,
+    }
+}
+
+/// Plan a restore of `windows` (in restore order) to `records` (oldest first) on `monitors`.
+///
+/// This is the planning the app does for live windows: each window is matched among the records
+/// of the application that no other window has claimed, newest first, and its record is landed
+/// and batched by the monitor it lands on.
+pub fn plan(monitors: &[Monitor], windows: &[Window], records: &[Record]) -> Plan {
+    let mut apps = HashMap::<(Option<&str>, &str), Vec<&Record>>::new();
+    for record in records.iter().rev() {
+        apps.entry((record.exe.as_deref(), &record.class))
+            .or_default()
+            .push(record);
+    }
+
+    let mut plan = Plan {
+        batches: monitors.iter().map(|_| Vec::new()).collect(),
+        unmatched: Vec::new(),
+    };
+    let mut claimed = HashSet::new();
+    for (i, window) in windows.iter().enumerate() {
+        let app = apps
+            .get(&(window.exe.as_deref(), window.class.as_str()))
+            .map_or(&[][..], Vec::as_slice);
+        let candidates = app
+            .iter()
+            .filter(|r| !claimed.contains(&r.id))
+            .map(|r| (r.id, r.title.clone()))
+            .collect::<Vec<_>>();
+
+        let Some((id, tier)) = find_match(&window.title, None, &candidates, window.exact_only)
+        else {
+            plan.unmatched.push(i);
+            continue;
+        };
+        claimed.insert(id);
+
+        let record = app.iter().find(|r| r.id == id).unwrap();
+        let landing = land(monitors, &record.rect, record.dpi);
+        plan.batches[landing.monitor].push(Restore {
+            window: i,
+            record: id,
+            tier,
+            rect: landing.rect,
+            off_screen: landing.off_screen,
+        });
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        Rect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    /// A monitor at 96 DPI whose work area is the whole monitor.
+    fn monitor(rect: Rect) -> Monitor {
+        Monitor {
+            work: rect.clone(),
+            rect,
+            dpi: 96,
+        }
+    }
+
+    fn window(title: &str) -> Window {
+        Window {
+            exe: Some(r"c:\apps\editor.exe".to_string()),
+            class: "Editor".to_string(),
+            title: title.to_string(),
+            exact_only: false,
+        }
+    }
+
+    fn record(id: i64, title: &str, rect: Rect) -> Record {
+        Record {
+            id,
+            exe: Some(r"c:\apps\editor.exe".to_string()),
+            class: "Editor".to_string(),
+            title: title.to_string(),
+            rect,
+            dpi: None,
+        }
+    }
+
+    #[test]
+    fn batches_restores_by_monitor() {
+        let monitors = [
+            monitor(rect(0, 0, 1920, 1080)),
+            monitor(rect(1920, 0, 3840, 1080)),
+        ];
+        let records = [
+            record(1, "a", rect(100, 100, 900, 700)),
+            record(2, "b", rect(2000, 100, 2800, 700)),
+            // Mostly on the second monitor, and hanging off its bottom.
+            record(3, "c", rect(1800, 900, 2600, 1300)),
+        ];
+        let plan = plan(
+            &monitors,
+            &[window("c"), window("b"), window("a")],
+            &records,
+        );
+
+        assert_eq!(plan.batches[0].len(), 1);
+        assert_eq!(plan.batches[0][0].record, 1);
+        assert_eq!(
+            plan.batches[1]
+                .iter()
+                .map(|r| (r.window, r.record))
+                .collect::<Vec<_>>(),
+            [(0, 3), (1, 2)]
+        );
+        assert_eq!(plan.batches[1][0].rect, rect(1920, 680, 2720, 1080));
+        assert!(plan.unmatched.is_empty());
+    }
+
+    #[test]
+    fn gives_each_record_to_one_window() {
+        let monitors = [monitor(rect(0, 0, 1920, 1080))];
+        let records = [
+            record(1, "notes.txt", rect(0, 0, 800, 600)),
+            record(2, "notes.txt", rect(100, 100, 900, 700)),
+        ];
+        let plan = plan(
+            &monitors,
+            &[
+                window("notes.txt"),
+                window("notes.txt"),
+                window("notes.txt"),
+            ],
+            &records,
+        );
+
+        // The newest record goes first.
+        assert_eq!(
+            plan.batches[0]
+                .iter()
+                .map(|r| (r.window, r.record, r.tier))
+                .collect::<Vec<_>>(),
+            [(0, 2, MatchTier::Exact), (1, 1, MatchTier::Exact)]
+        );
+        assert_eq!(plan.unmatched, [2]);
+    }
+
+    #[test]
+    fn only_matches_records_of_the_same_application() {
+        let monitors = [monitor(rect(0, 0, 1920, 1080))];
+        let records = [Record {
+            class: "Viewer".to_string(),
+            ..record(1, "notes.txt", rect(0, 0, 800, 600))
+        }];
+        let plan = plan(&monitors, &[window("notes.txt")], &records);
+
+        assert!(plan.batches[0].is_empty());
+        assert_eq!(plan.unmatched, [0]);
+    }
+
+    #[test]
+    fn prefers_the_tagged_record() {
+        let candidates = [(1, "notes.txt".to_string()), (2, "todo.txt".to_string())];
+
+        assert_eq!(
+            find_match("notes.txt", Some(2), &candidates, true),
+            Some((2, MatchTier::Tagged))
+        );
+        assert_eq!(
+            find_match("notes.txt", None, &candidates, true),
+            Some((1, MatchTier::Exact))
+        );
+    }
+
+    #[test]
+    fn flags_records_off_every_monitor() {
+        let monitors = [monitor(rect(0, 0, 1920, 1080))];
+        let records = [record(1, "a", rect(3000, 0, 3800, 600))];
+        let plan = plan(&monitors, &[window("a")], &records);
+
+        assert!(plan.batches[0][0].off_screen);
+        assert_eq!(plan.batches[0][0].rect, rect(1120, 0, 1920, 600));
+    }
+
+    #[test]
+    fn lands_off_screen_records_on_the_nearest_monitor() {
+        let monitors = [
+            monitor(rect(0, 0, 1920, 1080)),
+            monitor(rect(1920, 0, 3840, 1080)),
+        ];
+        let landing = land(&monitors, &rect(4000, 1200, 4400, 1500), None);
+
+        assert_eq!(landing.monitor, 1);
+        assert_eq!(landing.rect, rect(3440, 780, 3840, 1080));
+        assert!(landing.off_screen);
+    }
+
+    #[test]
+    fn clamps_records_within_the_work_area() {
+        let monitors = [Monitor {
+            work: rect(0, 0, 1920, 1040),
+            ..monitor(rect(0, 0, 1920, 1080))
+        }];
+        let landing = land(&monitors, &rect(100, 600, 900, 1080), None);
+
+        assert_eq!(landing.rect, rect(100, 560, 900, 1040));
+        assert!(!landing.off_screen);
+    }
+
+    #[test]
+    fn scales_records_captured_at_another_dpi() {
+        let monitors = [
+            monitor(rect(0, 0, 1920, 1080)),
+            Monitor {
+                dpi: 144,
+                ..monitor(rect(1920, 0, 5760, 2160))
+            },
+        ];
+        let landing = land(&monitors, &rect(2020, 100, 2820, 700), Some(96));
+
+        // The offset from the monitor and the size scale by 1.5.
+        assert_eq!(landing.monitor, 1);
+        assert_eq!(landing.rect, rect(2070, 150, 3270, 1050));
+
+        // Records without a DPI are left alone.
+        let landing = land(&monitors, &rect(2020, 100, 2820, 700), None);
+        assert_eq!(landing.rect, rect(2020, 100, 2820, 700));
+    }
+}
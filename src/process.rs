@@ -1,39 +1,72 @@
+use crate::buffer::{fill_string, Fill};
+
+use widestring::WideCString;
 use windows::{
-    core::{Error, PWSTR},
+    core::{Error, PCWSTR, PWSTR},
     Win32::{
-        Foundation::{ERROR_INTERNAL_ERROR, HANDLE},
+        Foundation::{
+            CloseHandle, ERROR_INSUFFICIENT_BUFFER, ERROR_INTERNAL_ERROR, ERROR_INVALID_PARAMETER,
+            ERROR_PARTIAL_COPY, HANDLE,
+        },
         Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY},
-        System::Threading::{
-            OpenProcess, OpenProcessToken, QueryFullProcessImageNameW, PROCESS_ACCESS_RIGHTS,
-            PROCESS_NAME_FORMAT,
+        Storage::FileSystem::{
+            CreateFileW, GetFinalPathNameByHandleW, FILE_FLAG_BACKUP_SEMANTICS,
+            FILE_NAME_NORMALIZED, FILE_READ_ATTRIBUTES, FILE_SHARE_DELETE, FILE_SHARE_READ,
+            FILE_SHARE_WRITE, OPEN_EXISTING,
+        },
+        System::{
+            Diagnostics::Debug::ReadProcessMemory,
+            Threading::{
+                NtQueryInformationProcess, OpenProcess, OpenProcessToken, ProcessBasicInformation,
+                QueryFullProcessImageNameW, PEB, PROCESS_ACCESS_RIGHTS, PROCESS_BASIC_INFORMATION,
+                PROCESS_NAME_FORMAT, RTL_USER_PROCESS_PARAMETERS,
+            },
         },
     },
 };
 
 type Result<R> = core::result::Result<R, Error>;
 
+/// The maximum length of an extended-length (`\\?\`) path, in characters.
+const MAX_LONG_PATH: usize = 32768;
+
 pub trait ProcessExt {
     fn full_image_name(&self) -> Result<String>;
     fn is_elevated(&self) -> Result<bool>;
+    /// The command line the process was started with. The handle needs
+    /// `PROCESS_QUERY_INFORMATION` and `PROCESS_VM_READ` access.
+    fn command_line(&self) -> Result<String>;
+    /// The working directory of the process, with the same access requirements as
+    /// `command_line`.
+    fn current_directory(&self) -> Result<String>;
 }
 
 impl ProcessExt for HANDLE {
     fn full_image_name(&self) -> Result<String> {
-        let mut name = [0u16; 256];
-        let mut len = name.len() as u32;
-
-        match unsafe {
-            QueryFullProcessImageNameW(
-                self.clone(),
-                PROCESS_NAME_FORMAT(0),
-                PWSTR(&mut name as *mut u16),
-                &mut len,
-            )
-            .as_bool()
-        } {
-            true => Ok(String::from_utf16(&name[..len as usize]).unwrap()),
-            false => Err(Error::from_win32()),
-        }
+        // Paths can exceed MAX_PATH, so retry with a larger buffer up to the maximum length of
+        // an extended-length path.
+        fill_string(MAX_LONG_PATH, |name| {
+            let mut len = name.len() as u32;
+
+            match unsafe {
+                QueryFullProcessImageNameW(
+                    self.clone(),
+                    PROCESS_NAME_FORMAT(0),
+                    PWSTR(name.as_mut_ptr()),
+                    &mut len,
+                )
+                .as_bool()
+            } {
+                true => Ok(Fill::Fits(len as usize)),
+                false => {
+                    let err = Error::from_win32();
+                    match err.code() == ERROR_INSUFFICIENT_BUFFER.to_hresult() {
+                        true => Ok(Fill::TooSmall),
+                        false => Err(err),
+                    }
+                }
+            }
+        })
     }
 
     fn is_elevated(&self) -> Result<bool> {
@@ -48,16 +81,21 @@ impl ProcessExt for HANDLE {
             }
         }
 
-        match unsafe {
-            GetTokenInformation(
+        let ret = unsafe {
+            let ret = GetTokenInformation(
                 token,
                 TokenElevation,
                 Some(&mut elevation as *mut _ as *mut _),
                 std::mem::size_of::<TOKEN_ELEVATION>() as u32,
                 &mut ret_len,
             )
-            .as_bool()
-        } {
+            .as_bool();
+
+            CloseHandle(token);
+            ret
+        };
+
+        match ret {
             true => {
                 // Ensure the return length is correct.
                 if ret_len != std::mem::size_of::<TOKEN_ELEVATION>() as u32 {
@@ -71,9 +109,145 @@ impl ProcessExt for HANDLE {
 
         Ok(elevation.TokenIsElevated != 0)
     }
+
+    fn command_line(&self) -> Result<String> {
+        let params = process_parameters(*self)?;
+        read_string(
+            *self,
+            params.CommandLine.Buffer.0,
+            params.CommandLine.Length,
+        )
+    }
+
+    fn current_directory(&self) -> Result<String> {
+        let params = process_parameters(*self)?;
+
+        // `CurrentDirectory.DosPath` is not part of the public definition of the structure. It
+        // is the `UNICODE_STRING` taking up the 6th and 7th reserved pointers: the lengths
+        // packed into the first, and the buffer in the second.
+        let lengths = params.Reserved2[5] as usize;
+        let buffer = params.Reserved2[6] as *const u16;
+        read_string(*self, buffer, (lengths & 0xFFFF) as u16)
+    }
+}
+
+/// Read a value out of the address space of another process.
+fn read<T: Copy>(process: HANDLE, address: *const T) -> Result<T> {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let mut read = 0usize;
+
+    let ok = unsafe {
+        ReadProcessMemory(
+            process,
+            address as *const _,
+            value.as_mut_ptr() as *mut _,
+            core::mem::size_of::<T>(),
+            Some(&mut read),
+        )
+    }
+    .as_bool();
+
+    match (ok, read == core::mem::size_of::<T>()) {
+        (true, true) => Ok(unsafe { value.assume_init() }),
+        (true, false) => Err(ERROR_PARTIAL_COPY.to_hresult().into()),
+        (false, _) => Err(Error::from_win32()),
+    }
+}
+
+/// Read a string of `len` bytes (not characters) out of another process.
+fn read_string(process: HANDLE, buffer: *const u16, len: u16) -> Result<String> {
+    let mut text = vec![0u16; len as usize / 2];
+    if text.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut read = 0usize;
+    let ok = unsafe {
+        ReadProcessMemory(
+            process,
+            buffer as *const _,
+            text.as_mut_ptr() as *mut _,
+            len as usize,
+            Some(&mut read),
+        )
+    }
+    .as_bool();
+    if !ok {
+        return Err(Error::from_win32());
+    }
+
+    text.truncate(read / 2);
+    Ok(String::from_utf16_lossy(&text))
+}
+
+/// The startup parameters of another process, found through its PEB.
+///
+/// N.B: The layouts read are those of our own bitness. For a 32-bit process on 64-bit Windows,
+/// this still finds its native (64-bit) PEB.
+fn process_parameters(process: HANDLE) -> Result<RTL_USER_PROCESS_PARAMETERS> {
+    let mut info = PROCESS_BASIC_INFORMATION::default();
+    let mut len = 0u32;
+    unsafe {
+        NtQueryInformationProcess(
+            process,
+            ProcessBasicInformation,
+            &mut info as *mut _ as *mut _,
+            core::mem::size_of::<PROCESS_BASIC_INFORMATION>() as u32,
+            &mut len,
+        )
+    }?;
+
+    if info.PebBaseAddress.is_null() {
+        Err(ERROR_INVALID_PARAMETER.to_hresult())?;
+    }
+
+    let peb: PEB = read(process, info.PebBaseAddress)?;
+    read(process, peb.ProcessParameters)
 }
 
 #[allow(dead_code)]
 pub fn open(access: u32, id: u32) -> Result<HANDLE> {
     unsafe { OpenProcess(PROCESS_ACCESS_RIGHTS(access), false, id) }
 }
+
+/// Resolve a path to its final form (following symbolic links and expanding 8.3 short names)
+/// and fold its case, so that different spellings of the same file compare equal.
+pub fn normalize_path(path: &str) -> Result<String> {
+    let path = WideCString::from_str(path).map_err(|_| ERROR_INVALID_PARAMETER.to_hresult())?;
+
+    let file = unsafe {
+        CreateFileW(
+            PCWSTR::from_raw(path.as_ptr()),
+            FILE_READ_ATTRIBUTES.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS,
+            None,
+        )
+    }?;
+
+    let mut buf = vec![0u16; 260];
+    let res = loop {
+        // On success this returns the length of the path, otherwise the required buffer size.
+        match unsafe { GetFinalPathNameByHandleW(file, &mut buf, FILE_NAME_NORMALIZED) } as usize {
+            0 => break Err(Error::from_win32()),
+            n if n > buf.len() => buf.resize(n, 0u16),
+            n => break Ok(String::from_utf16_lossy(&buf[..n])),
+        }
+    };
+
+    unsafe { CloseHandle(file) };
+    let path = res?;
+
+    // Strip the extended-length prefix that is always returned.
+    let path = if let Some(p) = path.strip_prefix("\\\\?\\UNC\\") {
+        format!("\\\\{p}")
+    } else if let Some(p) = path.strip_prefix("\\\\?\\") {
+        p.to_string()
+    } else {
+        path
+    };
+
+    Ok(path.to_lowercase())
+}
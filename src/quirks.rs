@@ -0,0 +1,131 @@
+use crate::{config, rules::Pattern, window::HwndExt};
+
+use serde::Deserialize;
+use windows::Win32::Foundation::HWND;
+
+/// The number of placement reads a capture waits for at least, for windows of toolkits that
+/// settle slowly.
+pub const SETTLE_READS: u32 = 4;
+
+/// How the windows of a UI toolkit deviate from native ones, and so need to be handled.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quirks {
+    /// The window is an invisible stand-in for the real one, e.g. to host its taskbar button.
+    /// Its placement means nothing, so it is neither captured nor restored.
+    pub proxy: bool,
+    /// The toolkit keeps its own copy of the normal bounds, updated only by moves it sees while
+    /// the window is in its normal state, and puts the window back there once it is shown
+    /// normally again. Windows restored minimized or maximized are first moved while normal.
+    pub reposition: bool,
+    /// The toolkit adjusts the placement on its own shortly after the window appears or is
+    /// activated, so captures wait for `SETTLE_READS` reads at least.
+    pub settle: bool,
+}
+
+/// Quirks applying to windows whose class matches, as written in the configuration file:
+///
+/// ```toml
+/// [[quirks]]
+/// class = "SunAwt*"      # wildcard pattern, case-insensitive
+/// reposition = true
+/// settle = true
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+pub struct QuirkRule {
+    pub class: String,
+    #[serde(flatten)]
+    pub quirks: Quirks,
+}
+
+impl QuirkRule {
+    fn matches(&self, class: &str) -> bool {
+        Pattern::Wildcard(self.class.clone()).matches(class)
+    }
+}
+
+/// A toolkit known to need quirks, recognized by the classes of its windows.
+struct Toolkit {
+    name: &'static str,
+    classes: &'static [&'static str],
+    quirks: Quirks,
+}
+
+const TOOLKITS: &[Toolkit] = &[
+    // Frames and dialogs cache their bounds in the Java peer, and reshape the window to them
+    // when it is restored or activated.
+    Toolkit {
+        name: "Java AWT/Swing",
+        classes: &["SunAwtFrame", "SunAwtDialog"],
+        quirks: Quirks {
+            proxy: false,
+            reposition: true,
+            settle: true,
+        },
+    },
+    Toolkit {
+        name: "JavaFX",
+        classes: &["GlassWndClass-GlassWindowClass-*"],
+        quirks: Quirks {
+            proxy: false,
+            reposition: false,
+            settle: true,
+        },
+    },
+    // Older Delphi and C++Builder applications own their forms with a zero-sized application
+    // window, which is the one minimized to the taskbar.
+    Toolkit {
+        name: "Delphi VCL",
+        classes: &["TApplication"],
+        quirks: Quirks {
+            proxy: true,
+            reposition: false,
+            settle: false,
+        },
+    },
+    // Client-side decorations are sized once the first frame has been drawn.
+    Toolkit {
+        name: "GTK",
+        classes: &["gdkWindowToplevel"],
+        quirks: Quirks {
+            proxy: false,
+            reposition: false,
+            settle: true,
+        },
+    },
+];
+
+/// The quirks of windows of a class. Rules from the configuration file take precedence over
+/// the built-in toolkits.
+pub fn for_class(class: &str) -> Quirks {
+    if let Some(quirks) = config::with(|c| {
+        c.quirks
+            .iter()
+            .find(|rule| rule.matches(class))
+            .map(|rule| rule.quirks)
+    }) {
+        return quirks;
+    }
+
+    toolkit(class).map(|t| t.quirks).unwrap_or_default()
+}
+
+/// The quirks of a window, or none if its class cannot be queried.
+pub fn for_window(hwnd: HWND) -> Quirks {
+    hwnd.class_name()
+        .map(|class| for_class(&class))
+        .unwrap_or_default()
+}
+
+/// The name of the built-in toolkit a class belongs to, if it is one needing quirks.
+pub fn toolkit_name(class: &str) -> Option<&'static str> {
+    toolkit(class).map(|t| t.name)
+}
+
+fn toolkit(class: &str) -> Option<&'static Toolkit> {
+    TOOLKITS.iter().find(|t| {
+        t.classes
+            .iter()
+            .any(|pattern| Pattern::Wildcard(pattern.to_string()).matches(class))
+    })
+}
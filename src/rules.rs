@@ -0,0 +1,264 @@
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// The part of a window's identity a rule looks at.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    /// Normalized image path of the owning process
+    Exe,
+    /// Window class
+    Class,
+    /// Window title
+    Title,
+}
+
+/// How a rule's pattern is matched. Every kind of pattern is case-insensitive.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// The whole value
+    Exact(String),
+    /// The whole value, where `*` matches any run of characters and `?` any one character
+    Wildcard(String),
+    /// Any part of the value, unless anchored
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Pattern::Exact(pattern) => pattern.eq_ignore_ascii_case(value),
+            Pattern::Wildcard(pattern) => wildcard_match(
+                &pattern.to_lowercase().chars().collect::<Vec<_>>(),
+                &value.to_lowercase().chars().collect::<Vec<_>>(),
+            ),
+            Pattern::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+/// A rule matching windows by one part of their identity, as written in the configuration file:
+///
+/// ```toml
+/// [[exclude_rules]]
+/// field = "title"        # "exe", "class" or "title"
+/// pattern = "* - Private*"
+/// matcher = "wildcard"   # "exact" (the default), "wildcard" or "regex"
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "RuleSpec")]
+pub struct Rule {
+    pub field: Field,
+    pub pattern: Pattern,
+}
+
+impl Rule {
+    /// A rule matching the windows of an application by its image file name (e.g.
+    /// "notepad.exe") or full path, as listed in the `exclude` option.
+    pub fn exe(name: &str) -> Self {
+        Self {
+            field: Field::Exe,
+            pattern: Pattern::Exact(name.to_string()),
+        }
+    }
+
+    /// Whether a window matches. Windows whose image is unknown never match image rules.
+    pub fn matches(&self, exe: Option<&str>, class: &str, title: &str) -> bool {
+        match self.field {
+            Field::Exe => exe.is_some_and(|exe| self.matches_exe(exe)),
+            Field::Class => self.pattern.matches(class),
+            Field::Title => self.pattern.matches(title),
+        }
+    }
+
+    /// Whether the rule matches every window of an application, by its image.
+    pub fn matches_app(&self, exe: &str) -> bool {
+        self.field == Field::Exe && self.matches_exe(exe)
+    }
+
+    /// Image paths also match patterns naming only the file (e.g. "notepad.exe"), unless the
+    /// pattern is a regex, which can match any part of the path anyway.
+    fn matches_exe(&self, exe: &str) -> bool {
+        let name = exe.rsplit('\\').next().unwrap_or_default();
+        match &self.pattern {
+            Pattern::Exact(p) | Pattern::Wildcard(p) if !p.contains('\\') => {
+                self.pattern.matches(name)
+            }
+            _ => self.pattern.matches(exe),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    field: Field,
+    pattern: String,
+    #[serde(default)]
+    matcher: Matcher,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum Matcher {
+    #[default]
+    Exact,
+    Wildcard,
+    Regex,
+}
+
+impl TryFrom<RuleSpec> for Rule {
+    type Error = regex::Error;
+
+    fn try_from(spec: RuleSpec) -> Result<Self, Self::Error> {
+        let pattern = match spec.matcher {
+            Matcher::Exact => Pattern::Exact(spec.pattern),
+            Matcher::Wildcard => Pattern::Wildcard(spec.pattern),
+            Matcher::Regex => Pattern::Regex(
+                RegexBuilder::new(&spec.pattern)
+                    .case_insensitive(true)
+                    .build()?,
+            ),
+        };
+
+        Ok(Self {
+            field: spec.field,
+            pattern,
+        })
+    }
+}
+
+/// A rewrite of the titles of an application's windows down to the part that stays the same
+/// while they are open, for applications showing the current page or document in the title
+/// (e.g. browsers and Electron apps). As written in the configuration file:
+///
+/// ```toml
+/// [[title_rules]]
+/// exe = "chrome.exe"      # wildcard patterns, both optional
+/// class = "Chrome_WidgetWin_1"
+/// strip_before = " - "    # or `strip_after`, or a regex `pattern`
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "TitleRuleSpec")]
+pub struct TitleRule {
+    exe: Option<Rule>,
+    class: Option<Pattern>,
+    rewrite: Rewrite,
+}
+
+/// How a title rule rewrites a title. Titles it does not apply to are left as they are.
+#[derive(Clone, Debug)]
+enum Rewrite {
+    /// Keep what follows the last separator, e.g. "Google Chrome" in "News - Google Chrome"
+    StripBefore(String),
+    /// Keep what precedes the first separator
+    StripAfter(String),
+    /// Keep the capture groups of a match, joined, or the whole match if there are none
+    Regex(Regex),
+}
+
+impl TitleRule {
+    /// Whether the rule is for the windows of an image and class. Windows whose image is
+    /// unknown only match rules for any image.
+    pub fn matches(&self, exe: Option<&str>, class: &str) -> bool {
+        self.exe
+            .as_ref()
+            .is_none_or(|rule| rule.matches(exe, class, ""))
+            && self.class.as_ref().is_none_or(|p| p.matches(class))
+    }
+
+    pub fn apply(&self, title: &str) -> String {
+        match &self.rewrite {
+            Rewrite::StripBefore(separator) => title
+                .rsplit_once(separator.as_str())
+                .map_or(title, |(_, rest)| rest)
+                .to_string(),
+            Rewrite::StripAfter(separator) => title
+                .split_once(separator.as_str())
+                .map_or(title, |(rest, _)| rest)
+                .to_string(),
+            Rewrite::Regex(regex) => match regex.captures(title) {
+                None => title.to_string(),
+                Some(captures) if captures.len() == 1 => captures[0].to_string(),
+                Some(captures) => captures
+                    .iter()
+                    .skip(1)
+                    .flatten()
+                    .map(|m| m.as_str())
+                    .collect(),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TitleRuleSpec {
+    exe: Option<String>,
+    class: Option<String>,
+    strip_before: Option<String>,
+    strip_after: Option<String>,
+    pattern: Option<String>,
+}
+
+impl TryFrom<TitleRuleSpec> for TitleRule {
+    type Error = String;
+
+    fn try_from(spec: TitleRuleSpec) -> Result<Self, Self::Error> {
+        let rewrite = match (spec.strip_before, spec.strip_after, spec.pattern) {
+            (Some(separator), None, None) => Rewrite::StripBefore(separator),
+            (None, Some(separator), None) => Rewrite::StripAfter(separator),
+            (None, None, Some(pattern)) => Rewrite::Regex(
+                RegexBuilder::new(&pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| e.to_string())?,
+            ),
+            _ => {
+                return Err(
+                    "a title rule needs exactly one of strip_before, strip_after or pattern"
+                        .to_string(),
+                )
+            }
+        };
+
+        Ok(Self {
+            exe: spec.exe.map(|exe| Rule {
+                field: Field::Exe,
+                pattern: Pattern::Wildcard(exe),
+            }),
+            class: spec.class.map(Pattern::Wildcard),
+            rewrite,
+        })
+    }
+}
+
+/// Match a value against a wildcard pattern, backtracking to the most recent `*` on a mismatch.
+fn wildcard_match(pattern: &[char], value: &[char]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut star = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and try again.
+                Some((sp, sv)) => {
+                    star = Some((sp, sv + 1));
+                    p = sp + 1;
+                    v = sv + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
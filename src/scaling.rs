@@ -0,0 +1,87 @@
+use anyhow::Context;
+use log::{info, warn};
+use windows::Win32::{
+    Foundation::ERROR_SUCCESS,
+    System::Registry::{
+        RegNotifyChangeKeyValue, HKEY, REG_NOTIFY_CHANGE_LAST_SET, REG_NOTIFY_CHANGE_NAME,
+    },
+};
+use winreg::{
+    enums::{HKEY_CURRENT_USER, KEY_NOTIFY, KEY_READ},
+    RegKey,
+};
+
+use std::{collections::BTreeMap, time::Duration};
+
+/// The key holding the scale chosen for each monitor, under the user's desktop settings.
+const DESKTOP_KEY: &str = r"Control Panel\Desktop";
+
+/// The subkey of `DESKTOP_KEY` with one key per monitor, each with a `DpiValue`.
+const PER_MONITOR_KEY: &str = "PerMonitorSettings";
+
+/// The scale overrides of every monitor, by monitor key. `DpiValue` is a number of steps away
+/// from the monitor's recommended scale, stored as a DWORD.
+fn overrides(desktop: &RegKey) -> BTreeMap<String, u32> {
+    let Ok(settings) = desktop.open_subkey(PER_MONITOR_KEY) else {
+        return BTreeMap::new();
+    };
+
+    settings
+        .enum_keys()
+        .filter_map(|name| name.ok())
+        .filter_map(|name| {
+            let value = settings
+                .open_subkey(&name)
+                .ok()?
+                .get_value("DpiValue")
+                .ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Watch the per-monitor scale overrides in the registry, and wake up the UI thread with
+/// `notice` whenever one of them changes.
+///
+/// Changing the scale of a monitor does not always send a display change to top-level windows,
+/// even though it changes the coordinates of every window on that monitor.
+pub fn watch(notice: nwg::NoticeSender) -> anyhow::Result<()> {
+    // N.B: The desktop key is watched rather than the per-monitor key, which does not exist
+    // until a scale is first changed.
+    let desktop = RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey_with_flags(DESKTOP_KEY, KEY_READ | KEY_NOTIFY)
+        .context("failed to open desktop settings")?;
+
+    std::thread::Builder::new()
+        .name("scaling".to_string())
+        .spawn(move || {
+            let mut last = overrides(&desktop);
+            loop {
+                let res = unsafe {
+                    RegNotifyChangeKeyValue(
+                        HKEY(desktop.raw_handle()),
+                        true,
+                        REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET,
+                        None,
+                        false,
+                    )
+                };
+                if res != ERROR_SUCCESS {
+                    warn!("failed to watch desktop settings: {res:?}");
+                    std::thread::sleep(Duration::from_secs(60));
+                    continue;
+                }
+
+                // Most changes to the desktop settings are not about scaling at all.
+                let current = overrides(&desktop);
+                if current != last {
+                    last = current;
+                    notice.notice();
+                }
+            }
+        })
+        .context("failed to start scaling thread")?;
+
+    info!("watching monitor scale overrides");
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use windows::{
+    core::{ComInterface, Error},
+    Win32::{
+        Foundation::HWND,
+        Storage::EnhancedStorage::PKEY_AppUserModel_ID,
+        System::Com::{
+            CoCreateInstance, CoTaskMemFree, StructuredStorage::PropVariantClear,
+            CLSCTX_LOCAL_SERVER, VARIANT, VT_I4,
+        },
+        UI::Shell::{
+            IShellWindows, IWebBrowser2,
+            PropertiesSystem::{
+                IPropertyStore, PropVariantToStringAlloc, SHGetPropertyStoreForWindow,
+            },
+            ShellWindows,
+        },
+    },
+};
+
+type Result<R> = core::result::Result<R, Error>;
+
+/// Window class of File Explorer windows.
+pub const EXPLORER_CLASS: &str = "CabinetWClass";
+
+/// Window class of the frames UWP apps are shown in. Every frame belongs to the same process,
+/// `ApplicationFrameHost.exe`, whatever the app.
+pub const APP_FRAME_CLASS: &str = "ApplicationFrameWindow";
+
+/// Resolve the location a File Explorer window is showing, via the shell's collection of open
+/// windows (`IShellWindows`). Returns `None` if the window is not registered with the shell, or
+/// is showing a virtual folder without a URL (e.g. "This PC").
+///
+/// N.B: COM must be initialized on the calling thread.
+pub fn explorer_location(hwnd: HWND) -> Result<Option<String>> {
+    let windows: IShellWindows =
+        unsafe { CoCreateInstance(&ShellWindows, None, CLSCTX_LOCAL_SERVER) }?;
+
+    for i in 0..unsafe { windows.Count() }? {
+        let mut index = VARIANT::default();
+        unsafe {
+            let v = &mut *index.Anonymous.Anonymous;
+            v.vt = VT_I4;
+            v.Anonymous.lVal = i;
+        }
+
+        // Other kinds of windows (e.g. Internet Explorer) may also be registered.
+        let browser = match unsafe { windows.Item(index) }.and_then(|d| d.cast::<IWebBrowser2>()) {
+            Ok(browser) => browser,
+            Err(_) => continue,
+        };
+
+        if unsafe { browser.HWND() }?.0 != hwnd.0 {
+            continue;
+        }
+
+        let url = unsafe { browser.LocationURL() }?.to_string();
+        return Ok(Some(url).filter(|url| !url.is_empty()));
+    }
+
+    Ok(None)
+}
+
+/// Query the AppUserModelID of a window (e.g. `Microsoft.WindowsCalculator_8wekyb3d8bbwe!App`),
+/// which the taskbar groups windows by. Returns `None` if the window has none.
+///
+/// N.B: COM must be initialized on the calling thread.
+pub fn app_user_model_id(hwnd: HWND) -> Result<Option<String>> {
+    let mut store: Option<IPropertyStore> = None;
+    unsafe {
+        SHGetPropertyStoreForWindow(
+            hwnd,
+            &IPropertyStore::IID,
+            &mut store as *mut _ as *mut *mut std::ffi::c_void,
+        )
+    }?;
+    let Some(store) = store else {
+        return Ok(None);
+    };
+
+    let mut value = unsafe { store.GetValue(&PKEY_AppUserModel_ID) }?;
+    let id = unsafe { PropVariantToStringAlloc(&value) };
+    let _ = unsafe { PropVariantClear(&mut value) };
+
+    let id = id?;
+    let text = String::from_utf16_lossy(unsafe { id.as_wide() });
+    unsafe { CoTaskMemFree(Some(id.0 as *const _)) };
+
+    Ok(Some(text).filter(|id| !id.is_empty()))
+}
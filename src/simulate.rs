@@ -0,0 +1,235 @@
+use crate::{
+    config, read_setting, store::LayoutStore, unix_time, workspace_path, MatchTier, Rect, Topology,
+    WindowDisplay, DEFAULT_WORKSPACE,
+};
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OpenFlags};
+use serde::Deserialize;
+
+use persistentwin::plan;
+
+use std::collections::{HashMap, HashSet};
+
+/// A synthetic display setup and set of windows to plan a restore for.
+#[derive(Deserialize)]
+struct Scenario {
+    /// The workspace whose records are matched against, or the default one
+    workspace: Option<String>,
+    /// Monitors in virtual screen coordinates
+    monitors: Vec<ScenarioMonitor>,
+    /// Windows present on the synthetic desktop, in restore order
+    windows: Vec<ScenarioWindow>,
+}
+
+/// A monitor of the synthetic display setup: its rectangle, and optionally its work area and
+/// effective DPI.
+#[derive(Deserialize)]
+struct ScenarioMonitor {
+    #[serde(flatten)]
+    rect: Rect,
+    /// The work area, or the whole monitor
+    work: Option<Rect>,
+    /// The effective DPI, or 96
+    dpi: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ScenarioWindow {
+    exe: Option<String>,
+    class: String,
+    title: String,
+}
+
+/// Developer command: feed a synthetic topology and window set from a JSON file through record
+/// matching and restore planning, and print the plan. Windows are planned by the same core as
+/// live restores, so records are scaled to the DPI of their monitor and clamped to its work
+/// area, and the record age limit applies. The workspace database is opened read-only and no
+/// real window is touched.
+///
+/// Placements are treated as screen coordinates.
+pub fn simulate(path: &str) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(path).context("failed to read scenario")?;
+    let scenario: Scenario = serde_json::from_str(&text).context("failed to parse scenario")?;
+    if scenario.monitors.is_empty() {
+        anyhow::bail!("scenario has no monitors");
+    }
+
+    let workspace = scenario.workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE);
+    let db =
+        Connection::open_with_flags(workspace_path(workspace)?, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open workspace \"{workspace}\""))?;
+
+    // Normalize the topology the same way it is captured.
+    let origin_x = scenario.monitors.iter().map(|m| m.rect.left).min().unwrap();
+    let origin_y = scenario.monitors.iter().map(|m| m.rect.top).min().unwrap();
+    let mut rects = scenario
+        .monitors
+        .iter()
+        .map(|m| m.rect.offset(-origin_x, -origin_y))
+        .collect::<Vec<_>>();
+    rects.sort_by_key(|r| (r.left, r.top, r.right, r.bottom));
+
+    // Scenarios do not describe monitor identities, so match by geometry alone.
+    let mut stmt = db
+        .prepare("SELECT id, data FROM topology")
+        .context("failed to query topology")?;
+    let mut topology = None;
+    for row in stmt
+        .query_map([], |r| {
+            Ok((r.get::<usize, usize>(0)?, r.get::<usize, Vec<u8>>(1)?))
+        })
+        .context("failed to query topology")?
+    {
+        let (id, data) = row.context("failed to query topology")?;
+        let t: Topology = bson::from_slice(&data).context("failed to decode topology")?;
+        if t.monitors == rects {
+            topology = Some(id);
+            break;
+        }
+    }
+
+    let topology = match topology {
+        Some(id) => id,
+        None => {
+            println!("topology not found in workspace \"{workspace}\", nothing would be restored");
+            return Ok(());
+        }
+    };
+
+    println!("topology {topology} (workspace \"{workspace}\")");
+
+    // As in the app, records not updated within the age limit are matched but not restored.
+    let max_age = read_setting("MaxRecordAgeDays")
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|days| *days > 0);
+    let now = unix_time()?;
+
+    // Placements are treated as screen coordinates on the scenario's monitors.
+    let mut records = Vec::new();
+    let mut displays = HashMap::new();
+    let mut stale = HashSet::new();
+    let mut skipped = Vec::new();
+    for record in db.records(topology)? {
+        if max_age.is_some_and(|days| now.saturating_sub(record.updated) > days * 24 * 60 * 60) {
+            stale.insert(record.id);
+        }
+
+        match WindowDisplay::decode(record.placement()) {
+            Ok(disp) => {
+                let disp = disp.offset(origin_x, origin_y);
+                records.push(plan::Record {
+                    id: record.id,
+                    exe: record.key.exe,
+                    class: record.key.class,
+                    title: record.key.title,
+                    rect: disp.rect.clone(),
+                    dpi: disp.dpi,
+                });
+                displays.insert(record.id, disp);
+            }
+            Err(e) => skipped.push(format!("record {} is invalid ({e})", record.id)),
+        }
+    }
+
+    let mut windows = Vec::new();
+    for wnd in &scenario.windows {
+        let excluded = db
+            .query_row(
+                "SELECT COUNT(*) FROM rule WHERE exe IS :exe AND action='exclude'",
+                named_params! { ":exe": wnd.exe },
+                |r| r.get::<usize, usize>(0),
+            )
+            .context("failed to query rules")?;
+        let configured =
+            config::with(|c| c.is_window_excluded(wnd.exe.as_deref(), &wnd.class, &wnd.title));
+        if excluded != 0 || configured {
+            skipped.push(format!(
+                "{}: excluded by rule",
+                label(wnd.exe.as_deref(), &wnd.class, &wnd.title)
+            ));
+            continue;
+        }
+
+        let exact_only = db
+            .query_row(
+                "SELECT COUNT(*) FROM rule WHERE exe IS :exe AND action='exact-match'",
+                named_params! { ":exe": wnd.exe },
+                |r| r.get::<usize, usize>(0),
+            )
+            .context("failed to query rules")?
+            != 0;
+
+        windows.push(plan::Window {
+            exe: wnd.exe.clone(),
+            class: wnd.class.clone(),
+            title: wnd.title.clone(),
+            exact_only,
+        });
+    }
+
+    let monitors = scenario
+        .monitors
+        .into_iter()
+        .map(|m| plan::Monitor {
+            work: m.work.unwrap_or_else(|| m.rect.clone()),
+            rect: m.rect,
+            dpi: m.dpi.unwrap_or(96),
+        })
+        .collect::<Vec<_>>();
+    let plan = plan::plan(&monitors, &windows, &records);
+
+    let window_label = |i: usize| {
+        let wnd = &windows[i];
+        label(wnd.exe.as_deref(), &wnd.class, &wnd.title)
+    };
+    for i in plan.unmatched {
+        skipped.push(format!("{}: no record", window_label(i)));
+    }
+
+    for (i, batch) in plan.batches.iter().enumerate() {
+        println!("\nmonitor {i} {:?}:", monitors[i].rect);
+        for restore in batch {
+            if stale.contains(&restore.record) {
+                skipped.push(format!(
+                    "{}: record {} is older than the record age limit",
+                    window_label(restore.window),
+                    restore.record
+                ));
+                continue;
+            }
+            let disp = &displays[&restore.record];
+
+            let mut line = format!(
+                "{}: record {} -> {:?} (show {})",
+                window_label(restore.window),
+                restore.record,
+                restore.rect,
+                disp.show
+            );
+            if restore.tier != MatchTier::Exact {
+                line.push_str(&format!(", matched by {:?} tier", restore.tier));
+            }
+            if restore.off_screen {
+                line.push_str(", off-screen");
+            }
+            if restore.rect != disp.rect {
+                line.push_str(&format!(", moved from {:?}", disp.rect));
+            }
+            println!("  {line}");
+        }
+    }
+
+    if !skipped.is_empty() {
+        println!("\nnot restored:");
+        for line in skipped {
+            println!("  {line}");
+        }
+    }
+
+    Ok(())
+}
+
+fn label(exe: Option<&str>, class: &str, title: &str) -> String {
+    format!("{} [{class}] \"{title}\"", exe.unwrap_or("?"))
+}
@@ -0,0 +1,439 @@
+use crate::{
+    ipc,
+    monitor::{self, HMonitorExt, MonitorInfo},
+    unix_time,
+    window::HwndExt,
+    workspace_path, Rect,
+};
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OpenFlags};
+use widestring::{widecstr, WideCString};
+use windows::{
+    core::{Error, PCWSTR},
+    Win32::{
+        Foundation::{ERROR_CLASS_ALREADY_EXISTS, HWND, LPARAM, LRESULT, WPARAM},
+        System::LibraryLoader::GetModuleHandleW,
+        UI::WindowsAndMessaging::{
+            CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, PeekMessageW,
+            RegisterClassExW, SetWindowPos, ShowWindow, TranslateMessage, MSG, PM_REMOVE,
+            SHOW_WINDOW_CMD, SWP_NOACTIVATE, SWP_NOZORDER, SW_MAXIMIZE, SW_MINIMIZE, SW_RESTORE,
+            WINDOW_EX_STYLE, WNDCLASSEXW, WS_OVERLAPPEDWINDOW, WS_VISIBLE,
+        },
+    },
+};
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+const CLASS_NAME: &widestring::WideCStr = widecstr!("PersistentWindowsSoak");
+
+/// The workspace the running instance is switched to for the duration of the run, so the
+/// synthetic windows stay out of the user's layouts.
+const WORKSPACE: &str = "Soak";
+
+/// Interval between random actions.
+const STEP: Duration = Duration::from_millis(500);
+
+/// Most synthetic windows alive at once.
+const MAX_WINDOWS: usize = 16;
+
+/// Steps between checks of the records.
+const CHECK_STEPS: u64 = 20;
+
+/// Steps between simulated topology transitions.
+const TRANSITION_STEPS: u64 = 120;
+
+/// How long the instance gets to settle and restore windows after a simulated transition.
+const TRANSITION_WAIT: Duration = Duration::from_secs(10);
+
+/// Interval between progress reports.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Locations cycled through to simulate transitions. Each one forms a distinct topology with
+/// the same monitors, so the running instance goes through a real transition without anything
+/// physically changing.
+const LOCATIONS: &[Option<&str>] = &[None, Some("Soak A"), Some("Soak B")];
+
+/// A xorshift generator, good enough to pick random actions.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn range(&mut self, lo: i32, hi: i32) -> i32 {
+        match hi > lo {
+            true => lo + (self.next() % (hi - lo) as u64) as i32,
+            false => lo,
+        }
+    }
+}
+
+struct Soak {
+    rng: Rng,
+    /// Unix time the run started at, naming its windows apart from those of earlier runs
+    run: u64,
+    monitors: Vec<MonitorInfo>,
+    windows: Vec<HWND>,
+    serial: u64,
+    location: usize,
+    transitions: u64,
+    violations: Vec<String>,
+    db: Connection,
+}
+
+/// Developer command: stress the running instance for `hours` by creating, moving and
+/// destroying synthetic windows while simulating topology transitions, and check that no window
+/// gets more than one record, no window is restored off-screen, and the instance keeps
+/// answering.
+///
+/// The instance is switched to the `Soak` workspace for the run and back afterwards.
+pub fn soak(hours: u64) -> anyhow::Result<()> {
+    let status = ipc::call(&ipc::Request::Status)?;
+    let workspace = status["workspace"]
+        .as_str()
+        .context("the running instance did not report its workspace")?
+        .to_string();
+    let location = status["location"].as_str().map(str::to_string);
+
+    ipc::call(&ipc::Request::SwitchWorkspace {
+        name: WORKSPACE.to_string(),
+    })?;
+
+    let run = unix_time()?;
+    let mut soak = Soak {
+        rng: Rng(run | 1),
+        run,
+        monitors: monitors()?,
+        windows: Vec::new(),
+        serial: 0,
+        location: 0,
+        transitions: 0,
+        violations: Vec::new(),
+        db: Connection::open_with_flags(
+            workspace_path(WORKSPACE)?,
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )
+        .context("failed to open soak workspace")?,
+    };
+
+    println!("soaking for {hours} hours (seed {run})");
+    let res = soak.run(Duration::from_secs(hours * 60 * 60));
+
+    for hwnd in soak.windows.drain(..) {
+        unsafe { DestroyWindow(hwnd) };
+    }
+
+    // Put the instance back the way it was, if it is still there.
+    let _ = ipc::call(&ipc::Request::SimulateTransition { location });
+    let _ = ipc::call(&ipc::Request::SwitchWorkspace { name: workspace });
+
+    res?;
+
+    println!(
+        "{} windows, {} transitions, {} violations",
+        soak.serial,
+        soak.transitions,
+        soak.violations.len()
+    );
+    if !soak.violations.is_empty() {
+        anyhow::bail!("invariants were violated:\n{}", soak.violations.join("\n"));
+    }
+
+    Ok(())
+}
+
+impl Soak {
+    fn run(&mut self, duration: Duration) -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut report = start;
+        let mut step = 0u64;
+        while start.elapsed() < duration {
+            step += 1;
+
+            match self.rng.below(10) {
+                0..=3 if self.windows.len() < MAX_WINDOWS => self.create()?,
+                0..=7 => self.move_window(),
+                8 => self.show_window(),
+                _ => self.destroy(),
+            }
+
+            if step.is_multiple_of(TRANSITION_STEPS) {
+                self.transition()?;
+            }
+
+            if step.is_multiple_of(CHECK_STEPS) {
+                self.check_records()?;
+            }
+
+            if report.elapsed() >= REPORT_INTERVAL {
+                report = Instant::now();
+                println!(
+                    "{} min: {} windows, {} transitions, {} violations",
+                    start.elapsed().as_secs() / 60,
+                    self.serial,
+                    self.transitions,
+                    self.violations.len()
+                );
+            }
+
+            pump(STEP);
+        }
+
+        Ok(())
+    }
+
+    fn random_rect(&mut self) -> Rect {
+        let work = self.monitors[self.rng.below(self.monitors.len())]
+            .work
+            .clone();
+        let width = self.rng.range(200, (work.width() as i32).min(800));
+        let height = self.rng.range(150, (work.height() as i32).min(600));
+        let left = self.rng.range(work.left, work.right - width);
+        let top = self.rng.range(work.top, work.bottom - height);
+
+        Rect {
+            left,
+            top,
+            right: left + width,
+            bottom: top + height,
+        }
+    }
+
+    fn create(&mut self) -> anyhow::Result<()> {
+        self.serial += 1;
+        let rect = self.random_rect();
+        let hwnd = create_window(&format!("Soak {} #{}", self.run, self.serial), &rect)?;
+
+        self.windows.push(hwnd);
+        Ok(())
+    }
+
+    fn pick(&mut self) -> Option<HWND> {
+        match self.windows.is_empty() {
+            true => None,
+            false => Some(self.windows[self.rng.below(self.windows.len())]),
+        }
+    }
+
+    fn move_window(&mut self) {
+        let Some(hwnd) = self.pick() else {
+            return;
+        };
+        let rect = self.random_rect();
+
+        unsafe {
+            SetWindowPos(
+                hwnd,
+                None,
+                rect.left,
+                rect.top,
+                rect.width() as i32,
+                rect.height() as i32,
+                SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+        };
+    }
+
+    fn show_window(&mut self) {
+        const COMMANDS: &[SHOW_WINDOW_CMD] = &[SW_MINIMIZE, SW_MAXIMIZE, SW_RESTORE];
+
+        if let Some(hwnd) = self.pick() {
+            let cmd = COMMANDS[self.rng.below(COMMANDS.len())];
+            unsafe { ShowWindow(hwnd, cmd) };
+        }
+    }
+
+    fn destroy(&mut self) {
+        if self.windows.is_empty() {
+            return;
+        }
+
+        let hwnd = self.windows.swap_remove(self.rng.below(self.windows.len()));
+        unsafe { DestroyWindow(hwnd) };
+    }
+
+    /// Move to the next location, wait for the instance to restore windows for it, and check
+    /// where they ended up.
+    fn transition(&mut self) -> anyhow::Result<()> {
+        self.location = (self.location + 1) % LOCATIONS.len();
+        self.transitions += 1;
+
+        let location = LOCATIONS[self.location].map(str::to_string);
+        if let Err(e) = ipc::call(&ipc::Request::SimulateTransition { location }) {
+            return self.lost(e);
+        }
+
+        pump(TRANSITION_WAIT);
+
+        // Monitors are unchanged, so windows outside all of them were restored there.
+        let offscreen = self
+            .windows
+            .iter()
+            .filter(|hwnd| !hwnd.is_minimized())
+            .filter_map(|hwnd| Some((hwnd.title().unwrap_or_default(), hwnd.rect().ok()?)))
+            .filter(|(_, rect)| {
+                self.monitors
+                    .iter()
+                    .all(|m| m.rect.intersection_area(rect) == 0)
+            })
+            .collect::<Vec<_>>();
+        for (title, rect) in offscreen {
+            self.violation(format!("\"{title}\" restored off-screen at {rect:?}"));
+        }
+
+        Ok(())
+    }
+
+    /// Check that no window claims a record another window claims, and that no window has
+    /// several records in a topology.
+    fn check_records(&mut self) -> anyhow::Result<()> {
+        let tracked = match ipc::call(&ipc::Request::ListWindows) {
+            Ok(tracked) => tracked,
+            Err(e) => return self.lost(e),
+        };
+
+        let mut claims = HashMap::<i64, u32>::new();
+        for window in tracked.as_array().into_iter().flatten() {
+            if let Some(record) = window["record"].as_i64() {
+                *claims.entry(record).or_default() += 1;
+            }
+        }
+        for (record, count) in claims {
+            if count > 1 {
+                self.violation(format!("record {record} is claimed by {count} windows"));
+            }
+        }
+
+        let duplicates = self
+            .db
+            .prepare(
+                "SELECT topology, title, COUNT(*) FROM appwindow
+                    WHERE class=:class AND title LIKE :prefix
+                    GROUP BY topology, title HAVING COUNT(*) > 1",
+            )
+            .context("failed to prepare query")?
+            .query_map(
+                named_params! {
+                    ":class": CLASS_NAME.to_string_lossy(),
+                    ":prefix": format!("Soak {} #%", self.run),
+                },
+                |r| {
+                    Ok((
+                        r.get::<usize, usize>(0)?,
+                        r.get::<usize, String>(1)?,
+                        r.get::<usize, usize>(2)?,
+                    ))
+                },
+            )
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        for (topology, title, count) in duplicates {
+            self.violation(format!(
+                "\"{title}\" has {count} records in topology {topology}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn violation(&mut self, message: String) {
+        println!("violation: {message}");
+        self.violations.push(message);
+    }
+
+    /// The instance stopped answering, e.g. because it panicked, which ends the run.
+    fn lost(&mut self, e: anyhow::Error) -> anyhow::Result<()> {
+        self.violation(format!("the running instance stopped answering: {e:#}"));
+        Err(e.context("lost the running instance"))
+    }
+}
+
+fn monitors() -> anyhow::Result<Vec<MonitorInfo>> {
+    monitor::monitors(None)
+        .context("failed to query monitors")?
+        .into_iter()
+        .map(|(m, _)| m.info().context("failed to query monitor info"))
+        .collect()
+}
+
+/// Create a visible top-level window of the synthetic window class, in screen coordinates.
+/// Messages for it are handled while `pump`ing.
+pub fn create_window(title: &str, rect: &Rect) -> anyhow::Result<HWND> {
+    register_class()?;
+
+    let title = WideCString::from_str_truncate(title);
+    let hwnd = unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE(0),
+            PCWSTR(CLASS_NAME.as_ptr()),
+            PCWSTR(title.as_ptr()),
+            WS_OVERLAPPEDWINDOW | WS_VISIBLE,
+            rect.left,
+            rect.top,
+            rect.width() as i32,
+            rect.height() as i32,
+            None,
+            None,
+            GetModuleHandleW(None)?,
+            None,
+        )
+    };
+    if hwnd.0 == 0 {
+        Err(Error::from_win32()).context("failed to create window")?;
+    }
+
+    Ok(hwnd)
+}
+
+fn register_class() -> anyhow::Result<()> {
+    let class = WNDCLASSEXW {
+        cbSize: core::mem::size_of::<WNDCLASSEXW>() as u32,
+        lpfnWndProc: Some(wnd_proc),
+        hInstance: unsafe { GetModuleHandleW(None) }?,
+        lpszClassName: PCWSTR(CLASS_NAME.as_ptr()),
+        ..Default::default()
+    };
+
+    if unsafe { RegisterClassExW(&class) } == 0 {
+        let err = Error::from_win32();
+        if err.code() != ERROR_CLASS_ALREADY_EXISTS.to_hresult() {
+            Err(err).context("failed to register window class")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handle the messages of the synthetic windows for a while.
+pub fn pump(duration: Duration) {
+    let end = Instant::now() + duration;
+    while Instant::now() < end {
+        let mut msg = MSG::default();
+        while unsafe { PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE) }.as_bool() {
+            unsafe {
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+extern "system" fn wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+}
@@ -0,0 +1,1538 @@
+use crate::WindowKey;
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OptionalExtension};
+
+use std::rc::Rc;
+
+/// The saved fields of a window record, as written on capture.
+pub struct RecordData<'a> {
+    pub key: &'a WindowKey,
+    /// The encoded placement of the window
+    pub disp: &'a [u8],
+    /// The encoded placement the user chose, if this capture was user-initiated
+    pub user_disp: Option<&'a [u8]>,
+    pub trigger: &'a str,
+    /// Unix time of the capture
+    pub updated: u64,
+    /// The virtual desktop of the window, if known
+    pub desktop: Option<&'a str>,
+    /// The tag stamped on the window this session, if any
+    pub tag: Option<&'a str>,
+}
+
+/// A window record, as read back from the store.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub id: i64,
+    pub key: WindowKey,
+    /// The encoded placement of the window
+    pub disp: Vec<u8>,
+    /// The encoded placement the user chose, if any
+    pub user_disp: Option<Vec<u8>>,
+    pub trigger: Option<String>,
+    /// Unix time of the last capture
+    pub updated: u64,
+    /// The virtual desktop of the window, if known
+    pub desktop: Option<String>,
+    /// Unix time the window was closed, if it was since the last capture
+    pub closed: Option<u64>,
+}
+
+impl Record {
+    /// The placement to restore the record to, preferring the one the user chose.
+    pub fn placement(&self) -> &[u8] {
+        self.user_disp.as_deref().unwrap_or(&self.disp)
+    }
+}
+
+/// An entry of the placement history of a record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Unix time of the entry
+    pub time: u64,
+    pub record: i64,
+    pub topology: usize,
+    /// What was done with the placement (`capture` or `restore`)
+    pub action: String,
+    pub trigger: String,
+    /// The encoded placement, in screen coordinates
+    pub disp: Vec<u8>,
+}
+
+/// A transaction on a layout store. Changes made through the store while it is open are undone
+/// if it is dropped without being committed.
+pub trait Transaction {
+    fn commit(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// Persistence of saved layouts: display topologies, the window records saved in each, and the
+/// placement history of the records.
+///
+/// Topologies and placements are opaque encoded blobs to the store. Records are identified by
+/// a numeric ID that stays the same for the lifetime of the record.
+///
+/// N.B: Auxiliary data (checkpoints, statistics, rules) is kept in the workspace database
+/// alongside, and is not part of this interface. Transactions on the database store cover it as
+/// well.
+pub trait LayoutStore {
+    /// Begin a transaction. Transactions do not nest.
+    fn begin(&self) -> anyhow::Result<Box<dyn Transaction + '_>>;
+
+    /// Register a topology if it is not already known, returning its ID.
+    fn register_topology(&self, data: &[u8]) -> anyhow::Result<usize>;
+    /// Replace the data of the topology `legacy` with `data`, unless `data` is already
+    /// registered. Returns whether a topology was replaced.
+    fn replace_topology(&self, legacy: &[u8], data: &[u8]) -> anyhow::Result<bool>;
+    /// The ID of a topology, if it is registered.
+    fn find_topology(&self, data: &[u8]) -> anyhow::Result<Option<usize>>;
+    /// Fold the topology `from` into `into`, which takes over the data of `from`. The records of
+    /// `from` (and those set aside) are deleted along with it, and their IDs returned.
+    fn merge_topology(&self, from: usize, into: usize) -> anyhow::Result<Vec<i64>>;
+    /// The data of a topology.
+    fn topology(&self, id: usize) -> anyhow::Result<Vec<u8>>;
+    /// The IDs of all known topologies, most recent first.
+    fn topologies(&self) -> anyhow::Result<Vec<usize>>;
+
+    /// The records of an application in a topology, as IDs and titles, most recent first.
+    fn candidates(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+    ) -> anyhow::Result<Vec<(i64, String)>>;
+    /// The record in a topology of the window stamped with a tag.
+    fn tagged_record(&self, topology: usize, tag: &str) -> anyhow::Result<Option<i64>>;
+    /// The key a record was saved with.
+    fn record_key(&self, id: i64) -> anyhow::Result<Option<WindowKey>>;
+    /// The placement to restore a record to (preferring the one the user chose) and the Unix
+    /// time it was last updated.
+    fn record_display(&self, id: i64) -> anyhow::Result<Option<(Vec<u8>, u64)>>;
+    /// The virtual desktop a record was saved on.
+    fn record_desktop(&self, id: i64) -> anyhow::Result<Option<String>>;
+    /// Every record of a topology, oldest first.
+    fn records(&self, topology: usize) -> anyhow::Result<Vec<Record>>;
+    /// The records no window has matched (nor been captured to) since Unix time `cutoff`.
+    fn stale_records(&self, cutoff: u64) -> anyhow::Result<Vec<i64>>;
+    /// The records of each topology past the `cap` most recently matched ones.
+    fn excess_records(&self, cap: usize) -> anyhow::Result<Vec<i64>>;
+    /// Insert a record, returning its ID.
+    fn insert_record(&self, topology: usize, data: &RecordData) -> anyhow::Result<i64>;
+    /// Update a record, returning false if it no longer exists.
+    fn update_record(&self, id: i64, data: &RecordData) -> anyhow::Result<bool>;
+    /// Replace the placement of a record with one its window was not captured at (e.g. copied
+    /// from another topology), dropping the one the user chose. Returns false if the record no
+    /// longer exists.
+    fn replace_placement(
+        &self,
+        id: i64,
+        disp: &[u8],
+        trigger: &str,
+        updated: u64,
+    ) -> anyhow::Result<bool>;
+    /// Delete a record, along with its history.
+    fn delete_record(&self, id: i64) -> anyhow::Result<()>;
+    /// Set a record that failed validation aside, with the reason, so it is never considered
+    /// again.
+    fn quarantine_record(&self, id: i64, reason: &str) -> anyhow::Result<()>;
+    /// Note that a window matched a record at Unix time `time`. Captures note it as well.
+    fn touch_record(&self, id: i64, time: u64) -> anyhow::Result<()>;
+    /// Mark a record as belonging to a window that was closed at Unix time `time`. The mark is
+    /// cleared by the next update.
+    fn close_record(&self, id: i64, time: u64) -> anyhow::Result<()>;
+    /// Delete the records of closed windows of an application in a topology, all but the `keep`
+    /// most recently closed, returning the IDs of the deleted records.
+    fn prune_closed(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+        keep: usize,
+    ) -> anyhow::Result<Vec<i64>>;
+
+    /// Append an entry to the history of its record, keeping only the `keep` most recent entries
+    /// of the record.
+    fn add_history(&self, entry: &HistoryEntry, keep: usize) -> anyhow::Result<()>;
+    /// The `count` most recent history entries of a set of records, newest first.
+    fn history(&self, records: &[i64], count: usize) -> anyhow::Result<Vec<HistoryEntry>>;
+    /// The placement a record was last captured at, as of Unix time `time`.
+    fn captured_placement(&self, id: i64, time: u64) -> anyhow::Result<Option<Vec<u8>>>;
+}
+
+impl Transaction for rusqlite::Transaction<'_> {
+    fn commit(self: Box<Self>) -> anyhow::Result<()> {
+        (*self).commit().context("failed to commit transaction")
+    }
+}
+
+/// The default store, backed by the workspace database.
+impl LayoutStore for Connection {
+    fn begin(&self) -> anyhow::Result<Box<dyn Transaction + '_>> {
+        Ok(Box::new(crate::db::begin(self)?))
+    }
+
+    fn register_topology(&self, data: &[u8]) -> anyhow::Result<usize> {
+        self.execute(
+            "INSERT OR IGNORE INTO topology (data) VALUES (:topology)",
+            named_params! { ":topology": data },
+        )
+        .context("failed to query database")?;
+
+        self.query_row(
+            "SELECT rowid FROM topology WHERE data=:topology",
+            named_params! { ":topology": data },
+            |row| row.get::<usize, usize>(0),
+        )
+        .context("failed to query row id")
+    }
+
+    fn replace_topology(&self, legacy: &[u8], data: &[u8]) -> anyhow::Result<bool> {
+        let n = self
+            .execute(
+                "UPDATE topology SET data=:topology WHERE data=:legacy AND NOT EXISTS \
+                 (SELECT 1 FROM topology WHERE data=:topology)",
+                named_params! { ":topology": data, ":legacy": legacy },
+            )
+            .context("failed to query database")?;
+
+        Ok(n != 0)
+    }
+
+    fn find_topology(&self, data: &[u8]) -> anyhow::Result<Option<usize>> {
+        self.query_row(
+            "SELECT id FROM topology WHERE data=:topology",
+            named_params! { ":topology": data },
+            |r| r.get::<usize, usize>(0),
+        )
+        .optional()
+        .context("failed to query topology")
+    }
+
+    fn merge_topology(&self, from: usize, into: usize) -> anyhow::Result<Vec<i64>> {
+        let data = self.topology(from)?;
+
+        let mut stmt = self
+            .prepare("SELECT id FROM appwindow WHERE topology=:topology")
+            .context("failed to prepare query")?;
+        let ids = stmt
+            .query_map(named_params! { ":topology": from }, |r| {
+                r.get::<usize, i64>(0)
+            })
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        for id in &ids {
+            self.delete_record(*id)?;
+        }
+        self.execute(
+            "DELETE FROM quarantine WHERE topology=:topology",
+            named_params! { ":topology": from },
+        )
+        .context("failed to query database")?;
+
+        // The data is unique, so it can only move once `from` is gone.
+        self.execute(
+            "DELETE FROM topology WHERE id=:id",
+            named_params! { ":id": from },
+        )
+        .context("failed to query database")?;
+        self.execute(
+            "UPDATE topology SET data=:topology WHERE id=:id",
+            named_params! { ":topology": data, ":id": into },
+        )
+        .context("failed to query database")?;
+
+        Ok(ids)
+    }
+
+    fn topology(&self, id: usize) -> anyhow::Result<Vec<u8>> {
+        self.query_row(
+            "SELECT data FROM topology WHERE id=:id",
+            named_params! { ":id": id },
+            |r| r.get::<usize, Vec<u8>>(0),
+        )
+        .context("failed to query topology")
+    }
+
+    fn topologies(&self) -> anyhow::Result<Vec<usize>> {
+        let mut stmt = self
+            .prepare("SELECT id FROM topology ORDER BY id DESC")
+            .context("failed to prepare query")?;
+
+        let ids = stmt
+            .query_map([], |r| r.get::<usize, usize>(0))
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(ids)
+    }
+
+    fn candidates(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+    ) -> anyhow::Result<Vec<(i64, String)>> {
+        let mut stmt = self
+            .prepare(
+                "SELECT id, title FROM appwindow WHERE topology=:topology
+                    AND exe IS :exe AND class=:class ORDER BY id DESC",
+            )
+            .context("failed to prepare query")?;
+
+        let rows = stmt
+            .query_map(
+                named_params! {
+                    ":topology": topology,
+                    ":exe": exe,
+                    ":class": class,
+                },
+                |r| Ok((r.get::<usize, i64>(0)?, r.get::<usize, String>(1)?)),
+            )
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(rows)
+    }
+
+    fn tagged_record(&self, topology: usize, tag: &str) -> anyhow::Result<Option<i64>> {
+        self.query_row(
+            "SELECT id FROM appwindow WHERE topology=:topology AND tag=:tag
+                ORDER BY id DESC LIMIT 1",
+            named_params! { ":topology": topology, ":tag": tag },
+            |r| r.get::<usize, i64>(0),
+        )
+        .optional()
+        .context("failed to query database")
+    }
+
+    fn record_key(&self, id: i64) -> anyhow::Result<Option<WindowKey>> {
+        self.query_row(
+            "SELECT exe, class, title FROM appwindow WHERE id=:id",
+            named_params! { ":id": id },
+            |r| {
+                Ok(WindowKey {
+                    exe: r.get(0)?,
+                    class: r.get(1)?,
+                    title: r.get(2)?,
+                })
+            },
+        )
+        .optional()
+        .context("failed to query database")
+    }
+
+    fn record_display(&self, id: i64) -> anyhow::Result<Option<(Vec<u8>, u64)>> {
+        self.query_row(
+            "SELECT IFNULL(user_disp, disp), updated FROM appwindow WHERE id=:id",
+            named_params! { ":id": id },
+            |r| Ok((r.get::<usize, Vec<u8>>(0)?, r.get::<usize, u64>(1)?)),
+        )
+        .optional()
+        .context("failed to query database")
+    }
+
+    fn record_desktop(&self, id: i64) -> anyhow::Result<Option<String>> {
+        let desktop = self
+            .query_row(
+                "SELECT desktop FROM appwindow WHERE id=:id",
+                named_params! { ":id": id },
+                |r| r.get::<usize, Option<String>>(0),
+            )
+            .optional()
+            .context("failed to query database")?;
+
+        Ok(desktop.flatten())
+    }
+
+    fn records(&self, topology: usize) -> anyhow::Result<Vec<Record>> {
+        let mut stmt = self
+            .prepare(
+                "SELECT id, exe, class, title, disp, user_disp, trigger, updated, desktop,
+                    closed FROM appwindow WHERE topology=:topology ORDER BY id",
+            )
+            .context("failed to prepare query")?;
+
+        let records = stmt
+            .query_map(named_params! { ":topology": topology }, |r| {
+                Ok(Record {
+                    id: r.get(0)?,
+                    key: WindowKey {
+                        exe: r.get(1)?,
+                        class: r.get(2)?,
+                        title: r.get(3)?,
+                    },
+                    disp: r.get(4)?,
+                    user_disp: r.get(5)?,
+                    trigger: r.get(6)?,
+                    updated: r.get(7)?,
+                    desktop: r.get(8)?,
+                    closed: r.get(9)?,
+                })
+            })
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(records)
+    }
+
+    fn stale_records(&self, cutoff: u64) -> anyhow::Result<Vec<i64>> {
+        let mut stmt = self
+            .prepare("SELECT id FROM appwindow WHERE IFNULL(seen, updated) < :cutoff")
+            .context("failed to prepare query")?;
+
+        let ids = stmt
+            .query_map(named_params! { ":cutoff": cutoff }, |r| {
+                r.get::<usize, i64>(0)
+            })
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(ids)
+    }
+
+    fn excess_records(&self, cap: usize) -> anyhow::Result<Vec<i64>> {
+        let mut stmt = self
+            .prepare(
+                "SELECT id FROM (SELECT id, ROW_NUMBER() OVER (PARTITION BY topology
+                    ORDER BY IFNULL(seen, updated) DESC, id DESC) AS n FROM appwindow)
+                    WHERE n > :cap",
+            )
+            .context("failed to prepare query")?;
+
+        let ids = stmt
+            .query_map(named_params! { ":cap": cap }, |r| r.get::<usize, i64>(0))
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(ids)
+    }
+
+    fn insert_record(&self, topology: usize, data: &RecordData) -> anyhow::Result<i64> {
+        self.execute(
+            "INSERT INTO appwindow (topology, exe, class, title, disp, user_disp, trigger,
+                updated, desktop, seen, tag)
+                VALUES (:topology, :exe, :class, :title, :disp, :user_disp, :trigger,
+                :updated, :desktop, :updated, :tag)",
+            named_params! {
+                ":topology": topology,
+                ":updated": data.updated,
+                ":exe": data.key.exe,
+                ":class": data.key.class,
+                ":title": data.key.title,
+                ":disp": data.disp,
+                ":user_disp": data.user_disp,
+                ":trigger": data.trigger,
+                ":desktop": data.desktop,
+                ":tag": data.tag,
+            },
+        )
+        .context("failed to query database")?;
+
+        Ok(self.last_insert_rowid())
+    }
+
+    fn update_record(&self, id: i64, data: &RecordData) -> anyhow::Result<bool> {
+        let n = self
+            .execute(
+                "UPDATE appwindow SET exe=:exe, class=:class, title=:title, disp=:disp,
+                    user_disp=IFNULL(:user_disp, user_disp), trigger=:trigger,
+                    updated=:updated, desktop=IFNULL(:desktop, desktop), closed=NULL,
+                    seen=:updated, tag=IFNULL(:tag, tag) WHERE id=:id",
+                named_params! {
+                    ":id": id,
+                    ":updated": data.updated,
+                    ":exe": data.key.exe,
+                    ":class": data.key.class,
+                    ":title": data.key.title,
+                    ":disp": data.disp,
+                    ":user_disp": data.user_disp,
+                    ":trigger": data.trigger,
+                    ":desktop": data.desktop,
+                    ":tag": data.tag,
+                },
+            )
+            .context("failed to query database")?;
+
+        Ok(n != 0)
+    }
+
+    fn replace_placement(
+        &self,
+        id: i64,
+        disp: &[u8],
+        trigger: &str,
+        updated: u64,
+    ) -> anyhow::Result<bool> {
+        let n = self
+            .execute(
+                "UPDATE appwindow SET disp=:disp, user_disp=NULL, trigger=:trigger,
+                    updated=:updated WHERE id=:id",
+                named_params! {
+                    ":id": id,
+                    ":disp": disp,
+                    ":trigger": trigger,
+                    ":updated": updated,
+                },
+            )
+            .context("failed to query database")?;
+
+        Ok(n != 0)
+    }
+
+    fn delete_record(&self, id: i64) -> anyhow::Result<()> {
+        self.execute(
+            "DELETE FROM appwindow WHERE id=:id",
+            named_params! { ":id": id },
+        )
+        .context("failed to query database")?;
+        self.execute(
+            "DELETE FROM history WHERE record=:id",
+            named_params! { ":id": id },
+        )
+        .context("failed to prune history")?;
+
+        Ok(())
+    }
+
+    fn quarantine_record(&self, id: i64, reason: &str) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT INTO quarantine (id, topology, disp, reason)
+                SELECT id, topology, disp, :reason FROM appwindow WHERE id=:id",
+            named_params! { ":id": id, ":reason": reason },
+        )
+        .context("failed to query database")?;
+
+        self.delete_record(id)
+    }
+
+    fn touch_record(&self, id: i64, time: u64) -> anyhow::Result<()> {
+        self.execute(
+            "UPDATE appwindow SET seen=MAX(IFNULL(seen, 0), :time) WHERE id=:id",
+            named_params! { ":id": id, ":time": time },
+        )
+        .context("failed to query database")?;
+
+        Ok(())
+    }
+
+    fn close_record(&self, id: i64, time: u64) -> anyhow::Result<()> {
+        self.execute(
+            "UPDATE appwindow SET closed=:time WHERE id=:id",
+            named_params! { ":id": id, ":time": time },
+        )
+        .context("failed to query database")?;
+
+        Ok(())
+    }
+
+    fn prune_closed(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+        keep: usize,
+    ) -> anyhow::Result<Vec<i64>> {
+        let mut stmt = self
+            .prepare(
+                "SELECT id FROM appwindow WHERE topology=:topology AND exe IS :exe
+                    AND class=:class AND closed IS NOT NULL
+                    ORDER BY closed DESC, id DESC LIMIT -1 OFFSET :keep",
+            )
+            .context("failed to prepare query")?;
+
+        let ids = stmt
+            .query_map(
+                named_params! {
+                    ":topology": topology,
+                    ":exe": exe,
+                    ":class": class,
+                    ":keep": keep,
+                },
+                |r| r.get::<usize, i64>(0),
+            )
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        for id in &ids {
+            self.delete_record(*id)?;
+        }
+
+        Ok(ids)
+    }
+
+    fn add_history(&self, entry: &HistoryEntry, keep: usize) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT INTO history (time, record, topology, action, trigger, disp)
+                VALUES (:time, :record, :topology, :action, :trigger, :disp)",
+            named_params! {
+                ":time": entry.time,
+                ":record": entry.record,
+                ":topology": entry.topology,
+                ":action": entry.action,
+                ":trigger": entry.trigger,
+                ":disp": entry.disp,
+            },
+        )
+        .context("failed to query database")?;
+
+        self.execute(
+            "DELETE FROM history WHERE record=:record AND id NOT IN
+                (SELECT id FROM history WHERE record=:record ORDER BY id DESC LIMIT :keep)",
+            named_params! { ":record": entry.record, ":keep": keep },
+        )
+        .context("failed to prune history")?;
+
+        Ok(())
+    }
+
+    fn history(&self, records: &[i64], count: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        let mut stmt = self
+            .prepare(
+                "SELECT time, record, topology, action, trigger, disp FROM history
+                    WHERE record IN (SELECT value FROM json_each(:records))
+                    ORDER BY id DESC LIMIT :count",
+            )
+            .context("failed to prepare query")?;
+
+        let entries = stmt
+            .query_map(
+                named_params! { ":records": format!("{records:?}"), ":count": count },
+                |r| {
+                    Ok(HistoryEntry {
+                        time: r.get(0)?,
+                        record: r.get(1)?,
+                        topology: r.get(2)?,
+                        action: r.get(3)?,
+                        trigger: r.get(4)?,
+                        disp: r.get(5)?,
+                    })
+                },
+            )
+            .context("failed to query database")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to read row")?;
+
+        Ok(entries)
+    }
+
+    fn captured_placement(&self, id: i64, time: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        self.query_row(
+            "SELECT disp FROM history WHERE record=:record AND action='capture'
+                AND time<=:time ORDER BY id DESC LIMIT 1",
+            named_params! { ":record": id, ":time": time },
+            |r| r.get::<usize, Vec<u8>>(0),
+        )
+        .optional()
+        .context("failed to query database")
+    }
+}
+
+/// A shared store, e.g. the workspace database also holding the auxiliary data of the app.
+impl<S: LayoutStore + ?Sized> LayoutStore for Rc<S> {
+    fn begin(&self) -> anyhow::Result<Box<dyn Transaction + '_>> {
+        (**self).begin()
+    }
+
+    fn register_topology(&self, data: &[u8]) -> anyhow::Result<usize> {
+        (**self).register_topology(data)
+    }
+
+    fn replace_topology(&self, legacy: &[u8], data: &[u8]) -> anyhow::Result<bool> {
+        (**self).replace_topology(legacy, data)
+    }
+
+    fn find_topology(&self, data: &[u8]) -> anyhow::Result<Option<usize>> {
+        (**self).find_topology(data)
+    }
+
+    fn merge_topology(&self, from: usize, into: usize) -> anyhow::Result<Vec<i64>> {
+        (**self).merge_topology(from, into)
+    }
+
+    fn topology(&self, id: usize) -> anyhow::Result<Vec<u8>> {
+        (**self).topology(id)
+    }
+
+    fn topologies(&self) -> anyhow::Result<Vec<usize>> {
+        (**self).topologies()
+    }
+
+    fn candidates(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+    ) -> anyhow::Result<Vec<(i64, String)>> {
+        (**self).candidates(topology, exe, class)
+    }
+
+    fn tagged_record(&self, topology: usize, tag: &str) -> anyhow::Result<Option<i64>> {
+        (**self).tagged_record(topology, tag)
+    }
+
+    fn record_key(&self, id: i64) -> anyhow::Result<Option<WindowKey>> {
+        (**self).record_key(id)
+    }
+
+    fn record_display(&self, id: i64) -> anyhow::Result<Option<(Vec<u8>, u64)>> {
+        (**self).record_display(id)
+    }
+
+    fn record_desktop(&self, id: i64) -> anyhow::Result<Option<String>> {
+        (**self).record_desktop(id)
+    }
+
+    fn records(&self, topology: usize) -> anyhow::Result<Vec<Record>> {
+        (**self).records(topology)
+    }
+
+    fn stale_records(&self, cutoff: u64) -> anyhow::Result<Vec<i64>> {
+        (**self).stale_records(cutoff)
+    }
+
+    fn excess_records(&self, cap: usize) -> anyhow::Result<Vec<i64>> {
+        (**self).excess_records(cap)
+    }
+
+    fn insert_record(&self, topology: usize, data: &RecordData) -> anyhow::Result<i64> {
+        (**self).insert_record(topology, data)
+    }
+
+    fn update_record(&self, id: i64, data: &RecordData) -> anyhow::Result<bool> {
+        (**self).update_record(id, data)
+    }
+
+    fn replace_placement(
+        &self,
+        id: i64,
+        disp: &[u8],
+        trigger: &str,
+        updated: u64,
+    ) -> anyhow::Result<bool> {
+        (**self).replace_placement(id, disp, trigger, updated)
+    }
+
+    fn delete_record(&self, id: i64) -> anyhow::Result<()> {
+        (**self).delete_record(id)
+    }
+
+    fn quarantine_record(&self, id: i64, reason: &str) -> anyhow::Result<()> {
+        (**self).quarantine_record(id, reason)
+    }
+
+    fn touch_record(&self, id: i64, time: u64) -> anyhow::Result<()> {
+        (**self).touch_record(id, time)
+    }
+
+    fn close_record(&self, id: i64, time: u64) -> anyhow::Result<()> {
+        (**self).close_record(id, time)
+    }
+
+    fn prune_closed(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+        keep: usize,
+    ) -> anyhow::Result<Vec<i64>> {
+        (**self).prune_closed(topology, exe, class, keep)
+    }
+
+    fn add_history(&self, entry: &HistoryEntry, keep: usize) -> anyhow::Result<()> {
+        (**self).add_history(entry, keep)
+    }
+
+    fn history(&self, records: &[i64], count: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        (**self).history(records, count)
+    }
+
+    fn captured_placement(&self, id: i64, time: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        (**self).captured_placement(id, time)
+    }
+}
+
+/// A store keeping layouts in memory, to test what builds on the store without a workspace
+/// database.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    state: std::cell::RefCell<MemoryState>,
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct MemoryState {
+    topologies: std::collections::BTreeMap<usize, Vec<u8>>,
+    records: std::collections::BTreeMap<i64, MemoryRecord>,
+    /// Records set aside, as topology, ID and reason
+    quarantine: Vec<(usize, i64, String)>,
+    /// History entries, oldest first
+    history: Vec<HistoryEntry>,
+}
+
+#[cfg(test)]
+#[derive(Clone)]
+struct MemoryRecord {
+    topology: usize,
+    record: Record,
+    /// Unix time a window last matched the record (or it was captured)
+    seen: u64,
+    tag: Option<String>,
+}
+
+/// A transaction on a `MemoryStore`, holding the state to go back to.
+#[cfg(test)]
+struct MemoryTransaction<'a> {
+    store: &'a MemoryStore,
+    snapshot: Option<MemoryState>,
+}
+
+#[cfg(test)]
+impl Transaction for MemoryTransaction<'_> {
+    fn commit(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.snapshot = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Drop for MemoryTransaction<'_> {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            *self.store.state.borrow_mut() = snapshot;
+        }
+    }
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    /// The records of a topology, newest first.
+    fn newest(&self, topology: usize) -> Vec<Record> {
+        self.state
+            .borrow()
+            .records
+            .values()
+            .rev()
+            .filter(|r| r.topology == topology)
+            .map(|r| r.record.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl LayoutStore for MemoryStore {
+    fn begin(&self) -> anyhow::Result<Box<dyn Transaction + '_>> {
+        Ok(Box::new(MemoryTransaction {
+            store: self,
+            snapshot: Some(self.state.borrow().clone()),
+        }))
+    }
+
+    fn register_topology(&self, data: &[u8]) -> anyhow::Result<usize> {
+        if let Some(id) = self.find_topology(data)? {
+            return Ok(id);
+        }
+
+        let topologies = &mut self.state.borrow_mut().topologies;
+        let id = topologies.keys().next_back().map_or(1, |id| id + 1);
+        topologies.insert(id, data.to_vec());
+        Ok(id)
+    }
+
+    fn replace_topology(&self, legacy: &[u8], data: &[u8]) -> anyhow::Result<bool> {
+        if self.find_topology(data)?.is_some() {
+            return Ok(false);
+        }
+
+        let topologies = &mut self.state.borrow_mut().topologies;
+        match topologies.values_mut().find(|d| d.as_slice() == legacy) {
+            Some(d) => {
+                *d = data.to_vec();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn find_topology(&self, data: &[u8]) -> anyhow::Result<Option<usize>> {
+        Ok(self
+            .state
+            .borrow()
+            .topologies
+            .iter()
+            .find(|(_, d)| d.as_slice() == data)
+            .map(|(id, _)| *id))
+    }
+
+    fn merge_topology(&self, from: usize, into: usize) -> anyhow::Result<Vec<i64>> {
+        let ids = self
+            .records(from)?
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        for id in &ids {
+            self.delete_record(*id)?;
+        }
+
+        let mut state = self.state.borrow_mut();
+        state
+            .quarantine
+            .retain(|(topology, _, _)| *topology != from);
+        let data = state.topologies.remove(&from).context("no such topology")?;
+        state.topologies.insert(into, data);
+
+        Ok(ids)
+    }
+
+    fn topology(&self, id: usize) -> anyhow::Result<Vec<u8>> {
+        self.state
+            .borrow()
+            .topologies
+            .get(&id)
+            .cloned()
+            .context("failed to query topology")
+    }
+
+    fn topologies(&self) -> anyhow::Result<Vec<usize>> {
+        Ok(self
+            .state
+            .borrow()
+            .topologies
+            .keys()
+            .rev()
+            .copied()
+            .collect())
+    }
+
+    fn candidates(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+    ) -> anyhow::Result<Vec<(i64, String)>> {
+        Ok(self
+            .newest(topology)
+            .into_iter()
+            .filter(|r| r.key.exe.as_deref() == exe && r.key.class == class)
+            .map(|r| (r.id, r.key.title))
+            .collect())
+    }
+
+    fn tagged_record(&self, topology: usize, tag: &str) -> anyhow::Result<Option<i64>> {
+        Ok(self
+            .state
+            .borrow()
+            .records
+            .values()
+            .rev()
+            .find(|r| r.topology == topology && r.tag.as_deref() == Some(tag))
+            .map(|r| r.record.id))
+    }
+
+    fn record_key(&self, id: i64) -> anyhow::Result<Option<WindowKey>> {
+        Ok(self
+            .state
+            .borrow()
+            .records
+            .get(&id)
+            .map(|r| r.record.key.clone()))
+    }
+
+    fn record_display(&self, id: i64) -> anyhow::Result<Option<(Vec<u8>, u64)>> {
+        Ok(self
+            .state
+            .borrow()
+            .records
+            .get(&id)
+            .map(|r| (r.record.placement().to_vec(), r.record.updated)))
+    }
+
+    fn record_desktop(&self, id: i64) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .state
+            .borrow()
+            .records
+            .get(&id)
+            .and_then(|r| r.record.desktop.clone()))
+    }
+
+    fn records(&self, topology: usize) -> anyhow::Result<Vec<Record>> {
+        let mut records = self.newest(topology);
+        records.reverse();
+        Ok(records)
+    }
+
+    fn stale_records(&self, cutoff: u64) -> anyhow::Result<Vec<i64>> {
+        Ok(self
+            .state
+            .borrow()
+            .records
+            .values()
+            .filter(|r| r.seen < cutoff)
+            .map(|r| r.record.id)
+            .collect())
+    }
+
+    fn excess_records(&self, cap: usize) -> anyhow::Result<Vec<i64>> {
+        let mut excess = Vec::new();
+        for topology in self.topologies()? {
+            let mut seen = self
+                .state
+                .borrow()
+                .records
+                .values()
+                .filter(|r| r.topology == topology)
+                .map(|r| (r.seen, r.record.id))
+                .collect::<Vec<_>>();
+            seen.sort_by(|a, b| b.cmp(a));
+            excess.extend(seen.iter().skip(cap).map(|(_, id)| *id));
+        }
+        Ok(excess)
+    }
+
+    fn insert_record(&self, topology: usize, data: &RecordData) -> anyhow::Result<i64> {
+        let records = &mut self.state.borrow_mut().records;
+        let id = records.keys().next_back().map_or(1, |id| id + 1);
+        records.insert(
+            id,
+            MemoryRecord {
+                topology,
+                record: Record {
+                    id,
+                    key: data.key.clone(),
+                    disp: data.disp.to_vec(),
+                    user_disp: data.user_disp.map(<[u8]>::to_vec),
+                    trigger: Some(data.trigger.to_string()),
+                    updated: data.updated,
+                    desktop: data.desktop.map(str::to_string),
+                    closed: None,
+                },
+                seen: data.updated,
+                tag: data.tag.map(str::to_string),
+            },
+        );
+        Ok(id)
+    }
+
+    fn update_record(&self, id: i64, data: &RecordData) -> anyhow::Result<bool> {
+        let records = &mut self.state.borrow_mut().records;
+        let Some(r) = records.get_mut(&id) else {
+            return Ok(false);
+        };
+
+        r.record.key = data.key.clone();
+        r.record.disp = data.disp.to_vec();
+        if let Some(user_disp) = data.user_disp {
+            r.record.user_disp = Some(user_disp.to_vec());
+        }
+        r.record.trigger = Some(data.trigger.to_string());
+        r.record.updated = data.updated;
+        r.seen = data.updated;
+        if let Some(desktop) = data.desktop {
+            r.record.desktop = Some(desktop.to_string());
+        }
+        r.record.closed = None;
+        if let Some(tag) = data.tag {
+            r.tag = Some(tag.to_string());
+        }
+        Ok(true)
+    }
+
+    fn replace_placement(
+        &self,
+        id: i64,
+        disp: &[u8],
+        trigger: &str,
+        updated: u64,
+    ) -> anyhow::Result<bool> {
+        let records = &mut self.state.borrow_mut().records;
+        let Some(r) = records.get_mut(&id) else {
+            return Ok(false);
+        };
+
+        r.record.disp = disp.to_vec();
+        r.record.user_disp = None;
+        r.record.trigger = Some(trigger.to_string());
+        r.record.updated = updated;
+        Ok(true)
+    }
+
+    fn delete_record(&self, id: i64) -> anyhow::Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.records.remove(&id);
+        state.history.retain(|e| e.record != id);
+        Ok(())
+    }
+
+    fn quarantine_record(&self, id: i64, reason: &str) -> anyhow::Result<()> {
+        let Some(topology) = self.state.borrow().records.get(&id).map(|r| r.topology) else {
+            return Ok(());
+        };
+
+        self.delete_record(id)?;
+        let quarantine = &mut self.state.borrow_mut().quarantine;
+        quarantine.push((topology, id, reason.to_string()));
+        Ok(())
+    }
+
+    fn touch_record(&self, id: i64, time: u64) -> anyhow::Result<()> {
+        if let Some(r) = self.state.borrow_mut().records.get_mut(&id) {
+            r.seen = r.seen.max(time);
+        }
+        Ok(())
+    }
+
+    fn close_record(&self, id: i64, time: u64) -> anyhow::Result<()> {
+        if let Some(r) = self.state.borrow_mut().records.get_mut(&id) {
+            r.record.closed = Some(time);
+        }
+        Ok(())
+    }
+
+    fn prune_closed(
+        &self,
+        topology: usize,
+        exe: Option<&str>,
+        class: &str,
+        keep: usize,
+    ) -> anyhow::Result<Vec<i64>> {
+        let mut closed = self
+            .state
+            .borrow()
+            .records
+            .values()
+            .filter(|r| {
+                r.topology == topology
+                    && r.record.key.exe.as_deref() == exe
+                    && r.record.key.class == class
+            })
+            .filter_map(|r| Some((r.record.closed?, r.record.id)))
+            .collect::<Vec<_>>();
+        closed.sort_by_key(|c| std::cmp::Reverse(*c));
+
+        let ids = closed
+            .into_iter()
+            .skip(keep)
+            .map(|(_, id)| id)
+            .collect::<Vec<_>>();
+        for id in &ids {
+            self.delete_record(*id)?;
+        }
+
+        Ok(ids)
+    }
+
+    fn add_history(&self, entry: &HistoryEntry, keep: usize) -> anyhow::Result<()> {
+        let history = &mut self.state.borrow_mut().history;
+        history.push(entry.clone());
+
+        let count = history.iter().filter(|e| e.record == entry.record).count();
+        let mut excess = count.saturating_sub(keep);
+        history.retain(|e| {
+            let drop = excess > 0 && e.record == entry.record;
+            excess -= drop as usize;
+            !drop
+        });
+        Ok(())
+    }
+
+    fn history(&self, records: &[i64], count: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+        Ok(self
+            .state
+            .borrow()
+            .history
+            .iter()
+            .rev()
+            .filter(|e| records.contains(&e.record))
+            .take(count)
+            .cloned()
+            .collect())
+    }
+
+    fn captured_placement(&self, id: i64, time: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .state
+            .borrow()
+            .history
+            .iter()
+            .rev()
+            .find(|e| e.record == id && e.action == "capture" && e.time <= time)
+            .map(|e| e.disp.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use persistentwin::matching::{match_record, MatchTier};
+
+    /// The database store and the fake, so the fake is held to the behavior of the real one.
+    fn stores() -> Vec<Box<dyn LayoutStore>> {
+        vec![
+            Box::new(crate::db::open_in_memory().unwrap()),
+            Box::<MemoryStore>::default(),
+        ]
+    }
+
+    fn key(class: &str, title: &str) -> WindowKey {
+        WindowKey {
+            exe: Some(r"c:\apps\editor.exe".to_string()),
+            class: class.to_string(),
+            title: title.to_string(),
+        }
+    }
+
+    fn data<'a>(key: &'a WindowKey, disp: &'a [u8], updated: u64) -> RecordData<'a> {
+        RecordData {
+            key,
+            disp,
+            user_disp: None,
+            trigger: "test",
+            updated,
+            desktop: None,
+            tag: None,
+        }
+    }
+
+    #[test]
+    fn registers_topologies_once() {
+        for store in stores() {
+            let a = store.register_topology(b"a").unwrap();
+            let b = store.register_topology(b"b").unwrap();
+
+            assert_ne!(a, b);
+            assert_eq!(store.register_topology(b"a").unwrap(), a);
+            assert_eq!(store.find_topology(b"b").unwrap(), Some(b));
+            assert_eq!(store.find_topology(b"c").unwrap(), None);
+            assert_eq!(store.topologies().unwrap(), vec![b, a]);
+        }
+    }
+
+    #[test]
+    fn updates_keep_what_the_capture_does_not_know() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let first = key("Editor", "a");
+            let id = store
+                .insert_record(
+                    topology,
+                    &RecordData {
+                        user_disp: Some(b"chosen"),
+                        desktop: Some("desktop"),
+                        tag: Some("tag"),
+                        ..data(&first, b"first", 10)
+                    },
+                )
+                .unwrap();
+
+            let second = key("Editor", "b");
+            assert!(store
+                .update_record(id, &data(&second, b"second", 20))
+                .unwrap());
+
+            assert_eq!(store.record_key(id).unwrap(), Some(second.clone()));
+            assert_eq!(
+                store.record_display(id).unwrap(),
+                Some((b"chosen".to_vec(), 20))
+            );
+            assert_eq!(
+                store.record_desktop(id).unwrap().as_deref(),
+                Some("desktop")
+            );
+            assert_eq!(store.tagged_record(topology, "tag").unwrap(), Some(id));
+
+            let records = store.records(topology).unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].disp, b"second");
+            assert_eq!(store.stale_records(21).unwrap(), vec![id]);
+
+            store.delete_record(id).unwrap();
+            assert!(!store
+                .update_record(id, &data(&second, b"third", 30))
+                .unwrap());
+            assert_eq!(store.record_key(id).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn replacing_a_placement_drops_the_chosen_one() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let key = key("Editor", "a");
+            let id = store
+                .insert_record(
+                    topology,
+                    &RecordData {
+                        user_disp: Some(b"chosen"),
+                        ..data(&key, b"captured", 10)
+                    },
+                )
+                .unwrap();
+
+            assert!(store.replace_placement(id, b"copied", "copy", 20).unwrap());
+            assert_eq!(
+                store.record_display(id).unwrap(),
+                Some((b"copied".to_vec(), 20))
+            );
+            assert!(!store
+                .replace_placement(id + 1, b"copied", "copy", 20)
+                .unwrap());
+        }
+    }
+
+    #[test]
+    fn candidates_are_the_application_newest_first() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let other = store.register_topology(b"o").unwrap();
+
+            let old = store
+                .insert_record(topology, &data(&key("Editor", "old"), b"d", 10))
+                .unwrap();
+            store
+                .insert_record(topology, &data(&key("Dialog", "dialog"), b"d", 10))
+                .unwrap();
+            store
+                .insert_record(other, &data(&key("Editor", "elsewhere"), b"d", 10))
+                .unwrap();
+            let new = store
+                .insert_record(topology, &data(&key("Editor", "new"), b"d", 20))
+                .unwrap();
+
+            assert_eq!(
+                store
+                    .candidates(topology, Some(r"c:\apps\editor.exe"), "Editor")
+                    .unwrap(),
+                vec![(new, "new".to_string()), (old, "old".to_string())]
+            );
+            assert!(store
+                .candidates(topology, None, "Editor")
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn prunes_all_but_the_last_closed() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let ids = (0..4)
+                .map(|_| {
+                    store
+                        .insert_record(topology, &data(&key("Editor", "a"), b"d", 10))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            store.close_record(ids[0], 30).unwrap();
+            store.close_record(ids[1], 10).unwrap();
+            store.close_record(ids[2], 20).unwrap();
+
+            let mut pruned = store
+                .prune_closed(topology, Some(r"c:\apps\editor.exe"), "Editor", 2)
+                .unwrap();
+            pruned.sort();
+            assert_eq!(pruned, vec![ids[1]]);
+            let closed = store
+                .records(topology)
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.id, r.closed))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                closed,
+                vec![(ids[0], Some(30)), (ids[2], Some(20)), (ids[3], None)]
+            );
+        }
+    }
+
+    #[test]
+    fn finds_stale_and_excess_records() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let ids = [10, 20, 30]
+                .into_iter()
+                .map(|updated| {
+                    store
+                        .insert_record(topology, &data(&key("Editor", "a"), b"d", updated))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            store.touch_record(ids[0], 40).unwrap();
+
+            assert_eq!(store.stale_records(25).unwrap(), vec![ids[1]]);
+
+            let mut excess = store.excess_records(1).unwrap();
+            excess.sort();
+            assert_eq!(excess, vec![ids[1], ids[2]]);
+        }
+    }
+
+    #[test]
+    fn merging_topologies_keeps_the_known_layout() {
+        for store in stores() {
+            let known = store.register_topology(b"old ids").unwrap();
+            let kept = store
+                .insert_record(known, &data(&key("Editor", "a"), b"d", 10))
+                .unwrap();
+            let new = store.register_topology(b"new ids").unwrap();
+            let dropped = store
+                .insert_record(new, &data(&key("Editor", "a"), b"d", 20))
+                .unwrap();
+
+            assert_eq!(store.merge_topology(new, known).unwrap(), vec![dropped]);
+            assert_eq!(store.topologies().unwrap(), vec![known]);
+            assert_eq!(store.find_topology(b"new ids").unwrap(), Some(known));
+            assert_eq!(
+                store
+                    .records(known)
+                    .unwrap()
+                    .iter()
+                    .map(|r| r.id)
+                    .collect::<Vec<_>>(),
+                vec![kept]
+            );
+        }
+    }
+
+    #[test]
+    fn quarantined_records_are_not_considered() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let id = store
+                .insert_record(topology, &data(&key("Editor", "a"), b"d", 10))
+                .unwrap();
+
+            store.quarantine_record(id, "invalid").unwrap();
+            assert!(store.records(topology).unwrap().is_empty());
+            assert_eq!(store.record_display(id).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn rolls_back_uncommitted_transactions() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+
+            let tx = store.begin().unwrap();
+            let dropped = store
+                .insert_record(topology, &data(&key("Editor", "a"), b"d", 10))
+                .unwrap();
+            drop(tx);
+            assert_eq!(store.record_key(dropped).unwrap(), None);
+
+            let tx = store.begin().unwrap();
+            let kept = store
+                .insert_record(topology, &data(&key("Editor", "a"), b"d", 10))
+                .unwrap();
+            tx.commit().unwrap();
+            assert!(store.record_key(kept).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn keeps_the_most_recent_history() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let a = store
+                .insert_record(topology, &data(&key("Editor", "a"), b"d", 10))
+                .unwrap();
+            let b = store
+                .insert_record(topology, &data(&key("Editor", "b"), b"d", 10))
+                .unwrap();
+
+            let entry = |record, time, action: &str| HistoryEntry {
+                time,
+                record,
+                topology,
+                action: action.to_string(),
+                trigger: "test".to_string(),
+                disp: format!("{record}@{time}").into_bytes(),
+            };
+            for (record, time, action) in [
+                (a, 10, "capture"),
+                (b, 15, "capture"),
+                (a, 20, "restore"),
+                (a, 30, "capture"),
+                (a, 40, "capture"),
+            ] {
+                store.add_history(&entry(record, time, action), 3).unwrap();
+            }
+
+            // The oldest entry of `a` is dropped, and `b` keeps its own.
+            assert_eq!(
+                store.history(&[a, b], 10).unwrap(),
+                vec![
+                    entry(a, 40, "capture"),
+                    entry(a, 30, "capture"),
+                    entry(a, 20, "restore"),
+                    entry(b, 15, "capture"),
+                ]
+            );
+            assert_eq!(
+                store.history(&[a], 1).unwrap(),
+                vec![entry(a, 40, "capture")]
+            );
+
+            assert_eq!(
+                store.captured_placement(a, 35).unwrap(),
+                Some(entry(a, 30, "capture").disp)
+            );
+            assert_eq!(store.captured_placement(a, 25).unwrap(), None);
+
+            store.delete_record(a).unwrap();
+            assert_eq!(
+                store.history(&[a, b], 10).unwrap(),
+                vec![entry(b, 15, "capture")]
+            );
+        }
+    }
+
+    #[test]
+    fn matches_windows_to_stored_records() {
+        for store in stores() {
+            let topology = store.register_topology(b"t").unwrap();
+            let report = store
+                .insert_record(
+                    topology,
+                    &data(&key("Editor", "report.txt - Editor"), b"d", 10),
+                )
+                .unwrap();
+            let notes = store
+                .insert_record(
+                    topology,
+                    &data(&key("Editor", "notes.txt - Editor"), b"d", 20),
+                )
+                .unwrap();
+            let candidates = store
+                .candidates(topology, Some(r"c:\apps\editor.exe"), "Editor")
+                .unwrap();
+
+            assert_eq!(
+                match_record("report.txt - Editor", &candidates, false),
+                Some((report, MatchTier::Exact))
+            );
+            assert_eq!(
+                match_record("(3) notes.txt - Editor *", &candidates, false),
+                Some((notes, MatchTier::Normalized))
+            );
+            assert_eq!(
+                match_record("(3) notes.txt - Editor *", &candidates, true),
+                None
+            );
+
+            // The only record of an application is taken whatever its title.
+            assert_eq!(
+                match_record("untitled", &candidates[..1], false),
+                Some((notes, MatchTier::Application))
+            );
+        }
+    }
+}
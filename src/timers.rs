@@ -0,0 +1,50 @@
+use windows::Win32::System::WindowsProgramming::QueryUnbiasedInterruptTime;
+
+use std::time::Duration;
+
+/// Length of an interrupt time tick.
+const TICK_NANOS: u64 = 100;
+
+/// A point in time, for debouncing, grace periods, cooldowns and retries.
+///
+/// Stamps are taken from the unbiased interrupt time, which is monotonic (unlike wall clock
+/// time, it is unaffected by clock changes) and does not advance while the system is asleep.
+/// A grace period that spans a sleep therefore resumes where it left off, rather than every
+/// pending deadline expiring at once on resume.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Stamp(u64);
+
+impl Stamp {
+    pub fn now() -> Self {
+        let mut ticks = 0u64;
+        unsafe { QueryUnbiasedInterruptTime(&mut ticks) };
+        Self(ticks)
+    }
+
+    /// Time passed since the stamp was taken, excluding time spent asleep.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().since(*self)
+    }
+
+    /// Time passed between an earlier stamp and this one, or zero if it is not earlier.
+    pub fn since(&self, earlier: Stamp) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0) * TICK_NANOS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_ticks_to_durations() {
+        assert_eq!(Stamp(10_000_000).since(Stamp(0)), Duration::from_secs(1));
+        assert_eq!(Stamp(15).since(Stamp(5)), Duration::from_micros(1));
+    }
+
+    #[test]
+    fn since_a_later_stamp_is_zero() {
+        assert_eq!(Stamp(5).since(Stamp(10)), Duration::ZERO);
+        assert_eq!(Stamp(5).since(Stamp(5)), Duration::ZERO);
+    }
+}
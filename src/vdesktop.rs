@@ -0,0 +1,65 @@
+use windows::{
+    core::{Error, GUID},
+    Win32::{
+        Foundation::HWND,
+        System::Com::{CoCreateInstance, CLSCTX_ALL},
+        UI::Shell::{IVirtualDesktopManager, VirtualDesktopManager},
+    },
+};
+
+use std::cell::RefCell;
+
+type Result<R> = core::result::Result<R, Error>;
+
+// The manager is created on first use. COM objects must stay on the thread that created them.
+thread_local! {
+    static MANAGER: RefCell<Option<IVirtualDesktopManager>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with the shell's virtual desktop manager.
+///
+/// N.B: COM must be initialized on the calling thread.
+fn with_manager<R>(f: impl FnOnce(&IVirtualDesktopManager) -> Result<R>) -> Result<R> {
+    MANAGER.with(|m| {
+        let mut manager = m.borrow_mut();
+        if manager.is_none() {
+            *manager = Some(unsafe { CoCreateInstance(&VirtualDesktopManager, None, CLSCTX_ALL) }?);
+        }
+
+        f(manager.as_ref().unwrap())
+    })
+}
+
+/// Whether a top-level window is on the virtual desktop the user is currently looking at.
+pub fn is_on_current_desktop(hwnd: HWND) -> Result<bool> {
+    with_manager(|m| Ok(unsafe { m.IsWindowOnCurrentVirtualDesktop(hwnd) }?.as_bool()))
+}
+
+/// The virtual desktop a top-level window is on, or `None` if it is not on one (e.g. it has not
+/// been shown yet).
+pub fn desktop_id(hwnd: HWND) -> Result<Option<GUID>> {
+    let id = with_manager(|m| unsafe { m.GetWindowDesktopId(hwnd) })?;
+    Ok(Some(id).filter(|id| *id != GUID::zeroed()))
+}
+
+/// Move a top-level window to another virtual desktop.
+///
+/// N.B: The shell only lets a process move its own windows this way, so this fails with
+/// `E_ACCESSDENIED` for windows of other processes on some versions of Windows.
+pub fn move_to_desktop(hwnd: HWND, id: &GUID) -> Result<()> {
+    with_manager(|m| unsafe { m.MoveWindowToDesktop(hwnd, id) })
+}
+
+/// Format a desktop ID for storage, in the usual GUID notation.
+pub fn format_id(id: &GUID) -> String {
+    format!("{id:?}")
+}
+
+/// Parse a desktop ID formatted with `format_id`.
+pub fn parse_id(s: &str) -> Option<GUID> {
+    let hex = s.replace('-', "");
+    match hex.len() {
+        32 => u128::from_str_radix(&hex, 16).ok().map(GUID::from_u128),
+        _ => None,
+    }
+}
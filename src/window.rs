@@ -1,17 +1,38 @@
+use crate::{
+    buffer::{fill_string, Fill},
+    Rect,
+};
+
+use widestring::WideCString;
 use windows::{
-    core::Error,
+    core::{Error, GUID, PCWSTR},
     Win32::{
-        Foundation::{BOOL, HWND, LPARAM},
+        Foundation::{SetLastError, BOOL, HANDLE, HWND, LPARAM, POINT, RECT, WIN32_ERROR},
+        Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED},
+        Graphics::Gdi::{RedrawWindow, RDW_ALLCHILDREN, RDW_ERASE, RDW_FRAME, RDW_INVALIDATE},
+        System::Threading::{AttachThreadInput, GetCurrentThreadId},
         UI::WindowsAndMessaging::{
-            EnumWindows, GetAncestor, GetClassNameW, GetWindowPlacement, GetWindowTextLengthW,
-            GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible, SetWindowPlacement, GA_ROOT,
-            WINDOWPLACEMENT,
+            BeginDeferWindowPos, DeferWindowPos, EndDeferWindowPos, EnumWindows, GetAncestor,
+            GetClassNameW, GetForegroundWindow, GetPropW, GetWindow, GetWindowLongW,
+            GetWindowPlacement, GetWindowRect, GetWindowTextLengthW, GetWindowTextW,
+            GetWindowThreadProcessId, IsIconic, IsWindowVisible, IsZoomed, SetForegroundWindow,
+            SetPropW, SetWindowPlacement, SetWindowPos, SystemParametersInfoW, WindowFromPoint,
+            ANIMATIONINFO, GA_ROOT, GWL_EXSTYLE, GW_OWNER, HWND_NOTOPMOST, HWND_TOPMOST,
+            SPI_GETANIMATION, SPI_SETANIMATION, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOOWNERZORDER,
+            SWP_NOREDRAW, SWP_NOSIZE, SWP_NOZORDER, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+            WINDOWPLACEMENT, WINDOW_EX_STYLE,
         },
     },
 };
 
 type Result<R> = core::result::Result<R, Error>;
 
+/// A buffer long enough for any class name, which are limited to 256 characters.
+const MAX_CLASS_NAME: usize = 1024;
+
+/// Names of the window properties holding the high and low halves of a window's tag.
+const TAG_PROPERTIES: [&str; 2] = ["PersistentWindows.Tag.Hi", "PersistentWindows.Tag.Lo"];
+
 pub struct OwnerInfo {
     pub process_id: u32,
     pub thread_id: u32,
@@ -25,20 +46,39 @@ pub trait HwndExt {
     fn is_top_level(&self) -> bool;
     fn owner(&self) -> Result<OwnerInfo>;
     fn is_visible(&self) -> bool;
+    fn is_minimized(&self) -> bool;
+    fn is_maximized(&self) -> bool;
+    fn ex_style(&self) -> WINDOW_EX_STYLE;
+    fn owner_window(&self) -> Option<HWND>;
+    fn rect(&self) -> Result<Rect>;
+    /// Whether DWM hides the window even though it is visible, e.g. because it is on another
+    /// virtual desktop or is the frame of a suspended UWP app.
+    fn is_cloaked(&self) -> bool;
+    /// The tag stamped on the window with `set_tag`, if any.
+    fn tag(&self) -> Option<GUID>;
+    /// Stamp the window with a tag (as window properties), which stays with it until it is
+    /// destroyed. Windows of more privileged processes cannot be stamped.
+    fn set_tag(&self, tag: GUID) -> Result<()>;
 }
 
 impl HwndExt for HWND {
     fn class_name(&self) -> Result<String> {
-        let mut buf: Vec<u16> = Vec::new();
-        buf.resize(256, 0u16);
-
-        match unsafe { GetClassNameW(self.clone(), &mut buf) } {
-            n if n > 0 => Ok(String::from_utf16(&buf[..n as usize]).unwrap()),
-            _ => Err(Error::from_win32()),
-        }
+        fill_string(MAX_CLASS_NAME, |buf| {
+            match unsafe { GetClassNameW(self.clone(), buf) } {
+                // The name is silently truncated if the buffer is too small, so grow it
+                // until the name (plus its terminator) fits.
+                n if n as usize >= buf.len() - 1 => Ok(Fill::TooSmall),
+                n if n > 0 => Ok(Fill::Fits(n as usize)),
+                _ => Err(Error::from_win32()),
+            }
+        })
     }
 
     fn title(&self) -> Result<String> {
+        // N.B: The last error is not cleared on success, so reset it to tell an empty title
+        // apart from a failure.
+        unsafe { SetLastError(WIN32_ERROR(0)) };
+
         let len = unsafe { GetWindowTextLengthW(self.clone()) };
         if len <= 0 {
             // Check if the title is just empty.
@@ -58,7 +98,7 @@ impl HwndExt for HWND {
             Err(Error::from_win32())?;
         }
 
-        Ok(String::from_utf16(&buf).unwrap())
+        Ok(String::from_utf16_lossy(&buf[..len as usize]))
     }
 
     fn placement(&self) -> Result<WINDOWPLACEMENT> {
@@ -99,6 +139,217 @@ impl HwndExt for HWND {
     fn is_visible(&self) -> bool {
         unsafe { IsWindowVisible(self.clone()) }.as_bool()
     }
+
+    fn is_minimized(&self) -> bool {
+        unsafe { IsIconic(self.clone()) }.as_bool()
+    }
+
+    fn is_maximized(&self) -> bool {
+        unsafe { IsZoomed(self.clone()) }.as_bool()
+    }
+
+    fn ex_style(&self) -> WINDOW_EX_STYLE {
+        WINDOW_EX_STYLE(unsafe { GetWindowLongW(self.clone(), GWL_EXSTYLE) } as u32)
+    }
+
+    fn owner_window(&self) -> Option<HWND> {
+        match unsafe { GetWindow(self.clone(), GW_OWNER) } {
+            HWND(0) => None,
+            owner => Some(owner),
+        }
+    }
+
+    fn rect(&self) -> Result<Rect> {
+        let mut rect = RECT::default();
+        match unsafe { GetWindowRect(self.clone(), &mut rect) }.as_bool() {
+            true => Ok(rect.into()),
+            false => Err(Error::from_win32()),
+        }
+    }
+
+    fn is_cloaked(&self) -> bool {
+        let mut cloaked = 0u32;
+        let res = unsafe {
+            DwmGetWindowAttribute(
+                *self,
+                DWMWA_CLOAKED,
+                &mut cloaked as *mut _ as *mut _,
+                std::mem::size_of::<u32>() as u32,
+            )
+        };
+
+        res.is_ok() && cloaked != 0
+    }
+
+    fn tag(&self) -> Option<GUID> {
+        let [hi, lo] = TAG_PROPERTIES.map(|name| {
+            let name = WideCString::from_str_truncate(name);
+            unsafe { GetPropW(*self, PCWSTR(name.as_ptr())) }.0 as u64
+        });
+
+        // N.B: A property that is not set reads as 0, and no tag has a zero half.
+        match hi != 0 && lo != 0 {
+            true => Some(GUID::from_u128((hi as u128) << 64 | lo as u128)),
+            false => None,
+        }
+    }
+
+    fn set_tag(&self, tag: GUID) -> Result<()> {
+        let value = tag.to_u128();
+        let halves = [(value >> 64) as u64, value as u64];
+
+        for (name, half) in TAG_PROPERTIES.iter().zip(halves) {
+            let name = WideCString::from_str_truncate(*name);
+            if !unsafe { SetPropW(*self, PCWSTR(name.as_ptr()), HANDLE(half as isize)) }.as_bool() {
+                return Err(Error::from_win32());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Move and resize a set of windows in a single operation (`BeginDeferWindowPos`), with
+/// coordinates specified in screen space. Nothing is repainted until all windows have moved,
+/// at which point the whole desktop is redrawn once.
+pub fn set_positions(positions: &[(HWND, Rect)]) -> Result<()> {
+    if positions.is_empty() {
+        return Ok(());
+    }
+
+    let mut hdwp = unsafe { BeginDeferWindowPos(positions.len() as i32) }?;
+    for (hwnd, rect) in positions {
+        hdwp = unsafe {
+            DeferWindowPos(
+                hdwp,
+                *hwnd,
+                None,
+                rect.left,
+                rect.top,
+                rect.width() as i32,
+                rect.height() as i32,
+                SWP_NOZORDER | SWP_NOOWNERZORDER | SWP_NOACTIVATE | SWP_NOREDRAW,
+            )
+        }?;
+    }
+
+    if !unsafe { EndDeferWindowPos(hdwp) }.as_bool() {
+        Err(Error::from_win32())?;
+    }
+
+    unsafe {
+        RedrawWindow(
+            None,
+            None,
+            None,
+            RDW_INVALIDATE | RDW_ERASE | RDW_FRAME | RDW_ALLCHILDREN,
+        );
+    }
+
+    Ok(())
+}
+
+/// Place a window in the topmost z-band (always on top), or take it out of it.
+pub fn set_topmost(hwnd: HWND, topmost: bool) -> Result<()> {
+    let after = match topmost {
+        true => HWND_TOPMOST,
+        false => HWND_NOTOPMOST,
+    };
+
+    match unsafe {
+        SetWindowPos(
+            hwnd,
+            after,
+            0,
+            0,
+            0,
+            0,
+            SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE,
+        )
+    }
+    .as_bool()
+    {
+        true => Ok(()),
+        false => Err(Error::from_win32()),
+    }
+}
+
+/// Whether windows animate when minimized, maximized or restored.
+pub fn animations() -> Result<bool> {
+    let mut info = ANIMATIONINFO {
+        cbSize: core::mem::size_of::<ANIMATIONINFO>() as u32,
+        iMinAnimate: 0,
+    };
+
+    match unsafe {
+        SystemParametersInfoW(
+            SPI_GETANIMATION,
+            info.cbSize,
+            Some(&mut info as *mut _ as *mut _),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )
+    }
+    .as_bool()
+    {
+        true => Ok(info.iMinAnimate != 0),
+        false => Err(Error::from_win32()),
+    }
+}
+
+/// Turn window animations on or off. The change is not persisted, so it does not outlive the
+/// session even if it is never undone.
+pub fn set_animations(enabled: bool) -> Result<()> {
+    let mut info = ANIMATIONINFO {
+        cbSize: core::mem::size_of::<ANIMATIONINFO>() as u32,
+        iMinAnimate: enabled as i32,
+    };
+
+    match unsafe {
+        SystemParametersInfoW(
+            SPI_SETANIMATION,
+            info.cbSize,
+            Some(&mut info as *mut _ as *mut _),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )
+    }
+    .as_bool()
+    {
+        true => Ok(()),
+        false => Err(Error::from_win32()),
+    }
+}
+
+/// The window the user is currently working with.
+pub fn foreground() -> HWND {
+    unsafe { GetForegroundWindow() }
+}
+
+/// Bring a window to the foreground.
+///
+/// The system only lets the thread that received the last input change the foreground window,
+/// so this briefly attaches to the input queue of the current foreground thread.
+pub fn set_foreground(hwnd: HWND) -> bool {
+    let current = unsafe { GetCurrentThreadId() };
+    let target = unsafe { GetWindowThreadProcessId(GetForegroundWindow(), None) };
+    let attached = target != 0
+        && target != current
+        && unsafe { AttachThreadInput(current, target, true) }.as_bool();
+
+    let res = unsafe { SetForegroundWindow(hwnd) }.as_bool();
+
+    if attached {
+        unsafe { AttachThreadInput(current, target, false) };
+    }
+
+    res
+}
+
+/// Find the top-level window at a point in screen coordinates.
+pub fn from_point(pt: POINT) -> Option<HWND> {
+    match unsafe { WindowFromPoint(pt) } {
+        HWND(0) => None,
+        hwnd => Some(unsafe { GetAncestor(hwnd, GA_ROOT) }),
+    }
 }
 
 /// Enumerate all windows present on the system. This corresponds to `EnumWindows`.